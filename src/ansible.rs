@@ -0,0 +1,162 @@
+//! Exporting to [Ansible](https://www.ansible.com/) tasks.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dotfile::AbsDotfile;
+use crate::facts::Facts;
+use crate::template;
+use crate::template::TemplateError;
+use crate::util::yaml_string;
+
+/// An Ansible tasks file which installs `dotfiles`, e.g. to be included with
+/// `ansible.builtin.include_tasks`. Each dotfile's parent directory is
+/// created, then the dotfile is linked with `ansible.builtin.file`; templates
+/// are rendered with `facts` and written with `ansible.builtin.copy`, since
+/// Ansible's `template` module only understands Jinja. Paths in `home` are
+/// written relative to `~`, so the tasks install into the remote user's
+/// home directory, which must have the dotfile repository at the same path.
+pub fn export(
+    dotfiles: &[AbsDotfile],
+    home: &Path,
+    facts: &Facts,
+) -> Result<String, TemplateError> {
+    let mut tasks = String::from("# Generated by dotfile-manager.\n");
+    if dotfiles.is_empty() {
+        tasks.push_str("[]\n");
+        return Ok(tasks);
+    }
+    let dirs = dotfiles
+        .iter()
+        .filter_map(|d| d.installed.parent())
+        .filter(|dir| *dir != home && *dir != Path::new("/"))
+        .collect::<BTreeSet<_>>();
+    for dir in dirs {
+        let dir = home_relative(dir, home);
+        writeln!(tasks, "- name: Create {}", dir.display()).unwrap();
+        writeln!(tasks, "  ansible.builtin.file:").unwrap();
+        writeln!(tasks, "    path: {}", yaml_path(&dir)).unwrap();
+        writeln!(tasks, "    state: directory").unwrap();
+    }
+    for d in dotfiles {
+        let dest = home_relative(&d.installed, home);
+        if d.template {
+            let content = template::render(&fs::read_to_string(&d.repo)?, facts)?;
+            writeln!(tasks, "- name: Render {}", dest.display()).unwrap();
+            writeln!(tasks, "  ansible.builtin.copy:").unwrap();
+            writeln!(tasks, "    dest: {}", yaml_path(&dest)).unwrap();
+            // `!unsafe` stops Ansible from treating `{{` in the rendered
+            // file as Jinja.
+            writeln!(tasks, "    content: !unsafe {}", yaml_string(&content)).unwrap();
+        } else {
+            writeln!(tasks, "- name: Link {}", dest.display()).unwrap();
+            writeln!(tasks, "  ansible.builtin.file:").unwrap();
+            writeln!(
+                tasks,
+                "    src: {}",
+                yaml_path(&home_relative(&d.repo, home))
+            )
+            .unwrap();
+            writeln!(tasks, "    dest: {}", yaml_path(&dest)).unwrap();
+            writeln!(tasks, "    state: link").unwrap();
+        }
+    }
+    Ok(tasks)
+}
+
+/// `path` relative to `~`, if it's in `home`.
+fn home_relative(path: &Path, home: &Path) -> PathBuf {
+    match path.strip_prefix(home) {
+        Ok(rel) => Path::new("~").join(rel),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+fn yaml_path(path: &Path) -> String {
+    yaml_string(&path.to_string_lossy())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn export_tasks() {
+        let cwd = env::current_dir().unwrap();
+        let dotfile = |repo: &str, installed: &str| AbsDotfile {
+            repo: repo.into(),
+            installed: installed.into(),
+            ..Default::default()
+        };
+        let facts = Facts::new().with_vars(
+            &vec![("email".to_string(), "me@example.com".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        let template = cwd.join("test-data/template.txt");
+        assert_eq!(
+            export(
+                &[
+                    dotfile("/home/me/.dotfiles/bashrc", "/home/me/.bashrc"),
+                    dotfile("/nix/store/abc-nvim", "/home/me/.config/nvim"),
+                    dotfile("/home/me/.dotfiles/hosts", "/etc/hosts"),
+                    AbsDotfile {
+                        template: true,
+                        ..dotfile(template.to_str().unwrap(), "/home/me/.config/git/config")
+                    },
+                ],
+                Path::new("/home/me"),
+                &facts,
+            )
+            .unwrap(),
+            indoc!(
+                r#"
+                # Generated by dotfile-manager.
+                - name: Create /etc
+                  ansible.builtin.file:
+                    path: "/etc"
+                    state: directory
+                - name: Create ~/.config
+                  ansible.builtin.file:
+                    path: "~/.config"
+                    state: directory
+                - name: Create ~/.config/git
+                  ansible.builtin.file:
+                    path: "~/.config/git"
+                    state: directory
+                - name: Link ~/.bashrc
+                  ansible.builtin.file:
+                    src: "~/.dotfiles/bashrc"
+                    dest: "~/.bashrc"
+                    state: link
+                - name: Link ~/.config/nvim
+                  ansible.builtin.file:
+                    src: "/nix/store/abc-nvim"
+                    dest: "~/.config/nvim"
+                    state: link
+                - name: Link /etc/hosts
+                  ansible.builtin.file:
+                    src: "~/.dotfiles/hosts"
+                    dest: "/etc/hosts"
+                    state: link
+                - name: Render ~/.config/git/config
+                  ansible.builtin.copy:
+                    dest: "~/.config/git/config"
+                    content: !unsafe "[user]\n  email = me@example.com\n"
+                "#
+            )
+            .trim_start()
+        );
+        assert_eq!(
+            export(&[], Path::new("/home/me"), &facts).unwrap(),
+            "# Generated by dotfile-manager.\n[]\n"
+        );
+    }
+}
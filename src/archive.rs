@@ -0,0 +1,262 @@
+//! Fetching a dotfile repository as an archive, e.g. a GitHub archive URL
+//! like `https://github.com/me/dotfiles/archive/main.tar.gz`, for machines
+//! without git.
+//!
+//! Downloading, checksumming and unpacking use whichever of `curl` or
+//! `wget`, `sha256sum` or `shasum`, and `tar` or `unzip` are installed.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("none of {} found; install one to fetch archives", .0.join(", "))]
+    NoTool(Vec<&'static str>),
+
+    #[error("`{tool}` failed: {message}")]
+    Tool { tool: String, message: String },
+
+    #[error("{url} has SHA-256 checksum {actual}, not {expected}")]
+    Checksum {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("{0} isn't a `.tar.gz`, `.tgz`, `.tar` or `.zip` archive")]
+    UnknownFormat(String),
+}
+
+/// The kinds of archives which can be unpacked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    TarGz,
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// The format of the archive at `url`, from its extension.
+    pub fn of(url: &str) -> Option<Self> {
+        // Ignore a query string or fragment, e.g. `?raw=true`.
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if path.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if path.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Download the archive at `url`, check its SHA-256 checksum against
+/// `sha256` if given, and unpack it into `dest`, which mustn't exist yet. If
+/// the archive holds a single directory, like GitHub's archives do, its
+/// contents are unpacked into `dest` rather than the directory itself.
+pub fn fetch(url: &str, sha256: Option<&str>, dest: &Path) -> Result<(), ArchiveError> {
+    let format = ArchiveFormat::of(url).ok_or_else(|| ArchiveError::UnknownFormat(url.into()))?;
+    let parent = dest.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(parent)?;
+    let work = download_dir(dest);
+    if work.exists() {
+        fs::remove_dir_all(&work)?;
+    }
+    fs::create_dir_all(&work)?;
+    let res = fetch_in(url, format, sha256, &work, dest);
+    // The download is only needed while it's unpacked.
+    let _ = fs::remove_dir_all(&work);
+    res
+}
+
+fn fetch_in(
+    url: &str,
+    format: ArchiveFormat,
+    sha256: Option<&str>,
+    work: &Path,
+    dest: &Path,
+) -> Result<(), ArchiveError> {
+    let archive = work.join("archive");
+    download(url, &archive)?;
+    if let Some(expected) = sha256 {
+        let actual = sha256sum(&archive)?;
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(ArchiveError::Checksum {
+                url: url.into(),
+                expected: expected.trim().into(),
+                actual,
+            });
+        }
+    }
+    let unpacked = work.join("unpacked");
+    fs::create_dir_all(&unpacked)?;
+    unpack(&archive, format, &unpacked)?;
+    let entries = fs::read_dir(&unpacked)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    let root = match entries.as_slice() {
+        [dir] if dir.is_dir() => dir.clone(),
+        _ => unpacked,
+    };
+    fs::rename(root, dest)?;
+    Ok(())
+}
+
+/// Run the first of `commands` whose program is installed, e.g. to use
+/// `wget` if `curl` isn't.
+fn run_first(commands: Vec<(&'static str, Vec<&OsStr>)>) -> Result<String, ArchiveError> {
+    let tools = commands.iter().map(|(tool, _)| *tool).collect();
+    for (tool, args) in commands {
+        let output = match Command::new(tool).args(args).stdin(Stdio::null()).output() {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            output => output?,
+        };
+        if !output.status.success() {
+            return Err(ArchiveError::Tool {
+                tool: tool.into(),
+                message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    Err(ArchiveError::NoTool(tools))
+}
+
+fn download(url: &str, dest: &Path) -> Result<(), ArchiveError> {
+    let url = url.as_ref();
+    run_first(vec![
+        (
+            "curl",
+            vec!["-fsSL".as_ref(), "-o".as_ref(), dest.as_os_str(), url],
+        ),
+        (
+            "wget",
+            vec!["-q".as_ref(), "-O".as_ref(), dest.as_os_str(), url],
+        ),
+    ])?;
+    Ok(())
+}
+
+/// The SHA-256 checksum of `path`, in lowercase hexadecimal.
+fn sha256sum(path: &Path) -> Result<String, ArchiveError> {
+    let output = run_first(vec![
+        ("sha256sum", vec![path.as_os_str()]),
+        (
+            "shasum",
+            vec!["-a".as_ref(), "256".as_ref(), path.as_os_str()],
+        ),
+    ])?;
+    Ok(output
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase())
+}
+
+fn unpack(archive: &Path, format: ArchiveFormat, dest: &Path) -> Result<(), ArchiveError> {
+    let archive = archive.as_os_str();
+    let dest = dest.as_os_str();
+    match format {
+        ArchiveFormat::TarGz => run_first(vec![(
+            "tar",
+            vec!["-xzf".as_ref(), archive, "-C".as_ref(), dest],
+        )]),
+        ArchiveFormat::Tar => run_first(vec![(
+            "tar",
+            vec!["-xf".as_ref(), archive, "-C".as_ref(), dest],
+        )]),
+        ArchiveFormat::Zip => run_first(vec![(
+            "unzip",
+            vec!["-q".as_ref(), archive, "-d".as_ref(), dest],
+        )]),
+    }?;
+    Ok(())
+}
+
+/// The directory `fetch` downloads and unpacks into, next to `dest` so the
+/// result can be renamed into place.
+fn download_dir(dest: &Path) -> PathBuf {
+    dest.parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(format!(
+            ".{}.download",
+            dest.file_name().unwrap_or_default().to_string_lossy()
+        ))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn archive_format() {
+        assert_eq!(
+            ArchiveFormat::of("https://github.com/me/dotfiles/archive/main.tar.gz"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::of("https://example.com/dotfiles.zip?raw=true"),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(
+            ArchiveFormat::of("dotfiles.tgz"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(ArchiveFormat::of("dotfiles.tar"), Some(ArchiveFormat::Tar));
+        assert_eq!(ArchiveFormat::of("git@github.com:me/dotfiles.git"), None);
+    }
+
+    #[test]
+    fn archive_fetch() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let src = root.join("dotfiles-main");
+        fs::create_dir_all(src.join(".config")).unwrap();
+        fs::write(src.join(".bashrc"), "bashrc").unwrap();
+        fs::write(src.join(".config/dotfiles.json"), "[]").unwrap();
+        let archive = root.join("main.tar.gz");
+        Command::new("tar")
+            .arg("-czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(root)
+            .arg("dotfiles-main")
+            .status()
+            .unwrap();
+        let url = format!("file://{}", archive.display());
+        let checksum = sha256sum(&archive).unwrap();
+
+        let dest = root.join("home/.dotfiles");
+        let wrong = fetch(&url, Some("0123"), &dest);
+        let wrong_left = dest.exists() || download_dir(&dest).exists();
+        let fetched = fetch(&url, Some(&checksum.to_uppercase()), &dest);
+        let bashrc = fs::read_to_string(dest.join(".bashrc"));
+        let list = fs::read_to_string(dest.join(".config/dotfiles.json"));
+        let download_left = download_dir(&dest).exists();
+        let not_an_archive = fetch("https://example.com/dotfiles.git", None, &dest);
+
+        assert!(matches!(wrong, Err(ArchiveError::Checksum { .. })));
+        assert!(!wrong_left);
+        fetched.unwrap();
+        assert_eq!(bashrc.unwrap(), "bashrc");
+        assert_eq!(list.unwrap(), "[]");
+        assert!(!download_left);
+        assert!(matches!(
+            not_an_archive,
+            Err(ArchiveError::UnknownFormat(_))
+        ));
+    }
+}
@@ -0,0 +1,254 @@
+//! Async versions of `DotfileManager`'s methods, with the `async` feature,
+//! for programs like daemons or TUIs which run on tokio. dfm's work is
+//! blocking (reading files, running `nix` and fact commands), so each call
+//! runs on tokio's blocking thread pool rather than stalling the runtime.
+//!
+//! Facts are collected once, concurrently, by the first call, and shared by
+//! every later call and every clone of the manager; see
+//! `AsyncDotfileManager::with_facts_cache`.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use tokio::task;
+
+use crate::config::Config;
+use crate::dotfile::{AbsDotfile, InstallStatus};
+use crate::engine::Action;
+use crate::error::Error;
+use crate::facts::{Facts, FactsError, FactsModel};
+use crate::manager::DotfileManager;
+use crate::prompt::{ConflictPrompt, PromptOptions};
+use crate::report::LinkReport;
+use crate::warning::Warnings;
+
+/// Like `DotfileManager`, but async. Cloning it is cheap, and clones share
+/// their warnings.
+#[derive(Debug, Clone)]
+pub struct AsyncDotfileManager {
+    config: Arc<Config>,
+    /// A facts cache file and how long facts in it are used for.
+    facts_cache: Option<(PathBuf, Duration)>,
+    /// The facts collected so far; `None` until the first call.
+    facts: Arc<Mutex<Option<Facts>>>,
+    warnings: Arc<Mutex<Warnings>>,
+}
+
+impl AsyncDotfileManager {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config: Arc::new(config),
+            facts_cache: None,
+            facts: Default::default(),
+            warnings: Default::default(),
+        }
+    }
+
+    /// Use `facts` rather than collecting them, e.g. facts from
+    /// `Facts::collect_async` or with overrides.
+    pub fn with_facts(self, facts: Facts) -> Self {
+        *lock(&self.facts) = Some(facts);
+        self
+    }
+
+    /// Read facts from the cache file at `path` if they were collected less
+    /// than `ttl` ago, and write the facts calls collect to it; see
+    /// `Facts::cached`.
+    pub fn with_facts_cache(mut self, path: impl Into<PathBuf>, ttl: Duration) -> Self {
+        self.facts_cache = Some((path.into(), ttl));
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The warnings collected so far, leaving none.
+    pub fn take_warnings(&self) -> Warnings {
+        std::mem::take(&mut lock(&self.warnings))
+    }
+
+    /// The facts collected so far, collecting every category of them if this
+    /// is the first call. This blocks, and other calls wait for it.
+    fn shared_facts(&self) -> Facts {
+        lock(&self.facts)
+            .get_or_insert_with(|| {
+                let facts = match &self.facts_cache {
+                    Some((path, ttl)) => Facts::cached(path, *ttl),
+                    None => Facts::new(),
+                }
+                .with_commands(&self.config.facts, self.config.fact_command_timeout)
+                .with_vars(&self.config.vars);
+                // Facts which can't be collected are tried again, and their
+                // errors reported, when they're needed.
+                let _ = facts.collect_missing();
+                facts
+            })
+            .clone()
+    }
+
+    /// Run `f` with a `DotfileManager` on the blocking thread pool, keeping
+    /// its warnings and the facts it collected.
+    async fn run<T, E>(
+        &self,
+        f: impl FnOnce(&DotfileManager) -> Result<T, E> + Send + 'static,
+    ) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        E: Into<Error> + Send + 'static,
+    {
+        let this = self.clone();
+        task::spawn_blocking(move || {
+            let manager =
+                DotfileManager::new(Config::clone(&this.config)).with_facts(this.shared_facts());
+            let res = f(&manager);
+            if let Some((path, _)) = &this.facts_cache {
+                manager.facts().write_cache(path);
+            }
+            *lock(&this.facts) = Some(manager.facts().clone());
+            let mut warnings = lock(&this.warnings);
+            for warning in manager.take_warnings().iter() {
+                warnings.push(warning.clone());
+            }
+            res.map_err(Into::into)
+        })
+        .await?
+    }
+
+    /// Collect every fact; see `Facts::model`.
+    pub async fn facts(&self) -> Result<FactsModel, Error> {
+        self.run(|manager| manager.facts().model()).await
+    }
+
+    /// See `DotfileManager::list`.
+    pub async fn list(&self) -> Result<Vec<AbsDotfile>, Error> {
+        self.run(|manager| manager.list()).await
+    }
+
+    /// See `DotfileManager::status`; statuses which couldn't be checked are
+    /// `None`.
+    pub async fn status(&self) -> Result<Vec<(AbsDotfile, Option<InstallStatus>)>, Error> {
+        self.run(|manager| {
+            Ok::<_, Error>(
+                manager
+                    .status()?
+                    .into_iter()
+                    .map(|(d, status)| (d, status.ok()))
+                    .collect(),
+            )
+        })
+        .await
+    }
+
+    /// See `DotfileManager::plan`.
+    pub async fn plan(&self) -> Result<Vec<(AbsDotfile, Action)>, Error> {
+        self.run(|manager| manager.plan()).await
+    }
+
+    /// See `DotfileManager::link`. Conflicts are resolved with `prompts`'s
+    /// `on_conflict`; if that's `ask`, the questions are asked on the
+    /// terminal, on the blocking thread.
+    pub async fn link(&self, prompts: PromptOptions) -> Result<LinkReport, Error> {
+        self.run(move |manager| manager.link(&mut ConflictPrompt::new(&prompts)))
+            .await
+    }
+}
+
+impl Facts {
+    /// Like `Facts::collect`, on tokio's blocking thread pool. Providers are
+    /// synchronous, so this doesn't save a thread, but it doesn't stall the
+    /// runtime either.
+    pub async fn collect_async() -> Result<Self, Error> {
+        Ok(task::spawn_blocking(Facts::collect)
+            .await?
+            .map_err(FactsError::from)?)
+    }
+}
+
+/// Lock `mutex`, even if a thread panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+    use tokio::runtime::Builder;
+
+    use super::*;
+    use crate::facts::{Nic, PlatformInfo, User};
+    use crate::provider::{default_provider, FactsProvider, ProviderError};
+
+    #[test]
+    fn async_plan() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(
+            root.join("dotfiles.json"),
+            r#"{"dotfiles": [{"repo": "bashrc", "installed": ".dfm-async-test", "tempalte": true}]}"#,
+        )
+        .unwrap();
+        let manager =
+            AsyncDotfileManager::new(Config::builder().dotfile_repo(root).build().unwrap());
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let plan = runtime.block_on(manager.clone().plan());
+
+        assert_eq!(
+            plan.unwrap()
+                .into_iter()
+                .map(|(d, action)| (d.repo, action))
+                .collect::<Vec<_>>(),
+            vec![(root.join("bashrc"), Action::Link)]
+        );
+        assert!(!manager.take_warnings().is_empty());
+        assert!(manager.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn async_facts_shared() {
+        /// Collects the default provider's facts, counting how many times.
+        #[derive(Debug)]
+        struct Counting(Arc<AtomicUsize>);
+
+        impl FactsProvider for Counting {
+            fn users(&self) -> Result<HashMap<String, User>, ProviderError> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                default_provider().users()
+            }
+
+            fn networks(&self) -> Result<HashMap<String, Nic>, ProviderError> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                default_provider().networks()
+            }
+
+            fn platform(&self) -> Result<PlatformInfo, ProviderError> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                default_provider().platform()
+            }
+        }
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let manager = AsyncDotfileManager::new(Config::builder().build().unwrap())
+            .with_facts(Facts::new().with_provider(Counting(count.clone())));
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let first = runtime.block_on(manager.clone().facts()).unwrap();
+        let second = runtime.block_on(manager.facts()).unwrap();
+
+        assert_eq!(first.platform, second.platform);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            runtime
+                .block_on(Facts::collect_async())
+                .unwrap()
+                .hostname()
+                .unwrap(),
+            first.platform.hostname
+        );
+    }
+}
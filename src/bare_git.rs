@@ -0,0 +1,225 @@
+//! Migrating from a bare git repository whose work tree is the home
+//! directory, i.e. dotfiles managed with an alias like
+//!
+//! ```sh
+//! alias config='git --git-dir=$HOME/.cfg --work-tree=$HOME'
+//! ```
+//!
+//! The tracked files are moved into the dotfile repository at the same
+//! relative paths and linked back to where they were, and the bare
+//! repository becomes the dotfile repository's `.git`, so its history and
+//! index carry over unchanged.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use thiserror::Error;
+
+use crate::dotfile::{AbsDotfile, Dotfile, DotfileError};
+
+#[derive(Error, Debug)]
+pub enum BareGitError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("{0}")]
+    Dotfile(#[from] DotfileError),
+
+    #[error("`git {args}` failed: {stderr}")]
+    Git { args: String, stderr: String },
+
+    /// A file would be moved over one already in the dotfile repository.
+    #[error("{0} already exists; move it out of the way first")]
+    Exists(PathBuf),
+}
+
+/// A bare git repository and the work tree it tracks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BareRepo {
+    /// The repository, e.g. `~/.cfg`.
+    pub git_dir: PathBuf,
+    /// The work tree, usually the home directory.
+    pub work_tree: PathBuf,
+}
+
+/// A tracked file to move into the dotfile repository.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Move {
+    /// The file's path in the work tree.
+    pub from: PathBuf,
+    /// The file's path in the dotfile repository.
+    pub to: PathBuf,
+}
+
+impl BareRepo {
+    fn git(&self, args: &[&str]) -> Result<Vec<u8>, BareGitError> {
+        let output = Command::new("git")
+            .arg("--git-dir")
+            .arg(&self.git_dir)
+            .arg("--work-tree")
+            .arg(&self.work_tree)
+            .args(args)
+            .output()?;
+        if !output.status.success() {
+            return Err(BareGitError::Git {
+                args: args.join(" "),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(output.stdout)
+    }
+
+    /// The tracked files which exist in the work tree, relative to it.
+    pub fn tracked_files(&self) -> Result<Vec<PathBuf>, BareGitError> {
+        Ok(self
+            .git(&["ls-files", "-z"])?
+            .split(|&b| b == 0)
+            .filter(|path| !path.is_empty())
+            .map(|path| PathBuf::from(String::from_utf8_lossy(path).into_owned()))
+            .filter(|path| fs::symlink_metadata(self.work_tree.join(path)).is_ok())
+            .collect())
+    }
+
+    /// The files `migrate` would move into `dotfile_repo`, and the dotfiles
+    /// list linking them back. Fails if anything is in the way.
+    pub fn plan(&self, dotfile_repo: &Path) -> Result<(Vec<Move>, Vec<Dotfile>), BareGitError> {
+        let files = self.tracked_files()?;
+        let git_dir = dotfile_repo.join(".git");
+        if git_dir.exists() {
+            return Err(BareGitError::Exists(git_dir));
+        }
+        let mut moves = Vec::new();
+        for file in &files {
+            let to = dotfile_repo.join(file);
+            if fs::symlink_metadata(&to).is_ok() {
+                return Err(BareGitError::Exists(to));
+            }
+            moves.push(Move {
+                from: self.work_tree.join(file),
+                to,
+            });
+        }
+        Ok((moves, files.into_iter().map(Dotfile::from).collect()))
+    }
+
+    /// Move each file into the dotfile repository and link it back, then
+    /// make the bare repository `dotfile_repo`'s `.git`.
+    pub fn migrate(&self, moves: &[Move], dotfile_repo: &Path) -> Result<(), BareGitError> {
+        for m in moves {
+            if let Some(parent) = m.to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            move_file(&m.from, &m.to)?;
+            AbsDotfile {
+                repo: m.to.clone(),
+                installed: m.from.clone(),
+                ..Default::default()
+            }
+            .link()?;
+        }
+        fs::create_dir_all(dotfile_repo)?;
+        let git_dir = dotfile_repo.join(".git");
+        move_file(&self.git_dir, &git_dir)?;
+        let repo = BareRepo {
+            git_dir,
+            work_tree: dotfile_repo.to_path_buf(),
+        };
+        repo.git(&["config", "core.bare", "false"])?;
+        // Some setups set the work tree in the repository's configuration
+        // rather than with an alias.
+        match repo.git(&["config", "--unset", "core.worktree"]) {
+            Err(BareGitError::Git { .. }) | Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Rename `from` to `to`, copying it if they're on different filesystems.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    let status = Command::new("cp").arg("-RP").arg(from).arg(to).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "couldn't move {} to {}",
+            from.display(),
+            to.display()
+        )));
+    }
+    if from.is_dir() && !fs::symlink_metadata(from)?.file_type().is_symlink() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn bare_git_migrate() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let home = root.join("home");
+        fs::create_dir_all(home.join(".config/git")).unwrap();
+        fs::write(home.join(".bashrc"), "bashrc").unwrap();
+        fs::write(home.join(".config/git/config"), "gitconfig").unwrap();
+        fs::write(home.join(".untracked"), "").unwrap();
+        let repo = BareRepo {
+            git_dir: home.join(".cfg"),
+            work_tree: home.clone(),
+        };
+        Command::new("git")
+            .args(["init", "--quiet", "--bare"])
+            .arg(&repo.git_dir)
+            .status()
+            .unwrap();
+        repo.git(&["add", ".bashrc", ".config/git/config"]).unwrap();
+        repo.git(&[
+            "-c",
+            "user.name=me",
+            "-c",
+            "user.email=me@example.com",
+            "commit",
+            "--quiet",
+            "--message=dotfiles",
+        ])
+        .unwrap();
+
+        let dotfile_repo = home.join(".dotfiles");
+        let (moves, dotfiles) = repo.plan(&dotfile_repo).unwrap();
+        assert_eq!(
+            dotfiles,
+            vec![
+                Dotfile::from(PathBuf::from(".bashrc")),
+                Dotfile::from(PathBuf::from(".config/git/config")),
+            ]
+        );
+        fs::create_dir_all(&dotfile_repo).unwrap();
+        fs::write(dotfile_repo.join(".bashrc"), "").unwrap();
+        assert!(matches!(
+            repo.plan(&dotfile_repo),
+            Err(BareGitError::Exists(_))
+        ));
+        fs::remove_file(dotfile_repo.join(".bashrc")).unwrap();
+
+        repo.migrate(&moves, &dotfile_repo).unwrap();
+        let bashrc = fs::read_to_string(home.join(".bashrc"));
+        let link = fs::read_link(home.join(".config/git/config"));
+        let status = Command::new("git")
+            .args(["status", "--porcelain"])
+            .current_dir(&dotfile_repo)
+            .output()
+            .unwrap();
+        assert_eq!(bashrc.unwrap(), "bashrc");
+        assert_eq!(link.unwrap(), dotfile_repo.join(".config/git/config"));
+        assert_eq!(String::from_utf8_lossy(&status.stdout), "");
+    }
+}
@@ -0,0 +1,839 @@
+//! A small expression language for deciding whether a dotfile applies to the
+//! current machine, e.g.
+//!
+//! ```text
+//! os == 'linux' && hostname =~ 'work-.*' && env('SSH_CONNECTION') == null
+//! ```
+//!
+//! Identifiers are looked up with `Facts::get`; string literals may use single
+//! or double quotes. These functions are also available:
+//!
+//! - `env(name)`: the environment variable `name`, or null if it's unset.
+//! - `command_exists(name)`: whether `name` is on the `PATH`.
+//! - `in_subnet(cidr)`: whether any interface has an address in `cidr`, e.g.
+//!   `in_subnet('10.42.0.0/16')`.
+//! - `interface_up(name)`: whether an interface whose name matches the regex
+//!   `name` (anchored, like `=~`) is up, e.g. `interface_up('wg0|utun[0-9]+')`.
+//! - `glob(value, pattern)`: whether `value` matches the shell-style glob
+//!   `pattern`, e.g. `glob(hostname, 'work-*')`. `*` matches any run of
+//!   characters, `?` matches any one character, and `[abc]` / `[!abc]` match
+//!   one character in / not in a set.
+//!
+//! Instead of an expression, a condition may also be a table of facts and
+//! the glob patterns they must match, e.g.
+//!
+//! ```toml
+//! when = { os = "linux", hostname = "work-*", is_wsl = false }
+//! ```
+//!
+//! A key ending in `_regex` matches the fact without the suffix against a
+//! regex instead (`hostname_regex = "db[0-9]+"`); see `Condition`.
+//!
+//! With the `rhai` feature, a condition can also be a Rhai script which
+//! returns a boolean, prefixed with `script:`; see the `script` module.
+//!
+//! `<`, `<=`, `>`, and `>=` compare their operands as version strings (see the
+//! `version` module), e.g. `os == 'macos' && os_version >= '13.0'`; they're
+//! false if either side is null.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::facts::{FactValue, Facts, FactsError};
+use crate::provider::ProviderError;
+use crate::subnet::{Subnet, SubnetParseError};
+use crate::version;
+
+/// An error while parsing or evaluating a condition.
+#[derive(Error, Debug)]
+pub enum ConditionError {
+    /// The condition isn't syntactically valid.
+    #[error("invalid condition at offset {offset}: {message}")]
+    Parse { offset: usize, message: String },
+
+    /// The condition refers to a fact that doesn't exist.
+    #[error("unknown fact `{0}`")]
+    UnknownFact(String),
+
+    /// The condition calls a function that doesn't exist.
+    #[error("unknown function `{0}`")]
+    UnknownFunction(String),
+
+    /// A function was called with the wrong number or type of arguments.
+    #[error("bad arguments to `{name}`: {message}")]
+    BadArguments { name: String, message: String },
+
+    /// The right-hand side of `=~` or `!~` isn't a valid regex.
+    #[error("invalid regex: {0}")]
+    Regex(#[from] regex::Error),
+
+    /// A fact couldn't be collected.
+    #[error("{0}")]
+    Facts(#[from] FactsError),
+
+    /// Network information couldn't be collected.
+    #[error("{0}")]
+    Provider(#[from] ProviderError),
+
+    /// A `script:` condition failed.
+    #[cfg(feature = "rhai")]
+    #[error("{0}")]
+    Script(#[from] crate::script::ScriptError),
+
+    /// The condition is a `script:`, but this build doesn't support them.
+    #[error("`script:` conditions need the `rhai` feature")]
+    NoScripts,
+}
+
+/// A condition as written in a dotfiles list: either an expression or a table
+/// of patterns which must all match.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Condition {
+    Expr(String),
+    Table(BTreeMap<String, Pattern>),
+}
+
+/// A value in a `Condition::Table`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Pattern {
+    /// The fact must equal this boolean.
+    Bool(bool),
+    /// The fact must match this glob, or this regex if the key ends with
+    /// `_regex`.
+    String(String),
+}
+
+impl Condition {
+    pub fn parse(&self) -> Result<Expr, ConditionError> {
+        match self {
+            Condition::Expr(expr) => Expr::parse(expr),
+            Condition::Table(table) => Ok(table
+                .iter()
+                .map(|(key, pattern)| {
+                    let (fact, op, rhs) = match (key.strip_suffix("_regex"), pattern) {
+                        (_, Pattern::Bool(b)) => (key.as_str(), CompareOp::Eq, (*b).into()),
+                        (Some(fact), Pattern::String(regex)) => {
+                            (fact, CompareOp::Match, regex.as_str().into())
+                        }
+                        (None, Pattern::String(glob)) => {
+                            (key.as_str(), CompareOp::Match, glob_to_regex(glob).into())
+                        }
+                    };
+                    Expr::Compare(
+                        Box::new(Expr::Fact(fact.to_string())),
+                        op,
+                        Box::new(Expr::Literal(rhs)),
+                    )
+                })
+                .fold(Expr::Literal(true.into()), |acc, expr| match acc {
+                    Expr::Literal(FactValue::Bool(true)) => expr,
+                    acc => Expr::And(Box::new(acc), Box::new(expr)),
+                })),
+        }
+    }
+
+    pub fn eval(&self, facts: &Facts) -> Result<bool, ConditionError> {
+        match self {
+            Condition::Expr(expr) if expr.starts_with(SCRIPT_PREFIX) => {
+                eval_script(&expr[SCRIPT_PREFIX.len()..], facts)
+            }
+            _ => self.parse()?.eval(facts),
+        }
+    }
+}
+
+/// The prefix for conditions which are Rhai scripts rather than expressions.
+const SCRIPT_PREFIX: &str = "script:";
+
+#[cfg(feature = "rhai")]
+fn eval_script(script: &str, facts: &Facts) -> Result<bool, ConditionError> {
+    Ok(crate::script::eval_condition(script, facts)?)
+}
+
+#[cfg(not(feature = "rhai"))]
+fn eval_script(_script: &str, _facts: &Facts) -> Result<bool, ConditionError> {
+    Err(ConditionError::NoScripts)
+}
+
+impl From<&str> for Condition {
+    fn from(expr: &str) -> Self {
+        Condition::Expr(expr.to_string())
+    }
+}
+
+/// Translate a shell-style glob into an (unanchored) regex.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = glob.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                let negated = chars.as_str().starts_with('!');
+                // A `]` straight after `[` or `[!` is part of the set rather
+                // than closing it.
+                let start = usize::from(negated) + 1;
+                match chars.clone().skip(start).position(|c| c == ']') {
+                    Some(len) => {
+                        let set: Vec<char> = chars
+                            .by_ref()
+                            .take(start + len)
+                            .skip(usize::from(negated))
+                            .collect();
+                        chars.next(); // The closing `]`.
+                        regex.push('[');
+                        if negated {
+                            regex.push('^');
+                        }
+                        push_glob_set(&mut regex, &set);
+                        regex.push(']');
+                    }
+                    // An unterminated `[` is literal.
+                    None => regex.push_str(r"\["),
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex
+}
+
+/// Add the characters in a glob's `[...]` set to a regex character class.
+/// Everything but ranges like `a-z` is literal, so characters the regex
+/// syntax gives a meaning, including the `&&`, `--` and `~~` set operations,
+/// are escaped.
+fn push_glob_set(regex: &mut String, set: &[char]) {
+    // Whether the last character ended a range, and whether it was a range's
+    // `-`; a `-` after either is literal, as is one at either end.
+    let mut ended_range = false;
+    let mut in_range = false;
+    for (i, &c) in set.iter().enumerate() {
+        if c == '-' && i > 0 && i + 1 < set.len() && !ended_range && !in_range {
+            regex.push('-');
+            in_range = true;
+            continue;
+        }
+        if matches!(c, '\\' | '[' | ']' | '^' | '&' | '-' | '~') {
+            regex.push('\\');
+        }
+        regex.push(c);
+        ended_range = in_range;
+        in_range = false;
+    }
+}
+
+/// A comparison operator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    /// `==`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `=~`; the right-hand side is a regex which must match the entire
+    /// left-hand side.
+    Match,
+    /// `!~`
+    NotMatch,
+    /// `<`; this and the other orderings compare versions.
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+}
+
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!=",
+            CompareOp::Match => "=~",
+            CompareOp::NotMatch => "!~",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+        })
+    }
+}
+
+/// A parsed condition expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(FactValue),
+    Fact(String),
+    Call(String, Vec<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Result<Self, ConditionError> {
+        let mut parser = Parser::new(s)?;
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some((offset, tok)) => Err(ConditionError::Parse {
+                offset: *offset,
+                message: format!("unexpected {}", tok),
+            }),
+        }
+    }
+
+    /// Evaluate this expression to a value.
+    pub fn value(&self, facts: &Facts) -> Result<FactValue, ConditionError> {
+        Ok(match self {
+            Expr::Literal(v) => v.clone(),
+            Expr::Fact(name) => facts
+                .get(name)?
+                .ok_or_else(|| ConditionError::UnknownFact(name.clone()))?,
+            Expr::Call(name, args) => call(facts, name, args)?,
+            Expr::Not(e) => (!e.value(facts)?.truthy()).into(),
+            Expr::And(l, r) => (l.eval(facts)? && r.eval(facts)?).into(),
+            Expr::Or(l, r) => (l.eval(facts)? || r.eval(facts)?).into(),
+            Expr::Compare(l, op, r) => compare(&l.value(facts)?, *op, &r.value(facts)?)?.into(),
+        })
+    }
+
+    /// Evaluate this expression as a boolean.
+    pub fn eval(&self, facts: &Facts) -> Result<bool, ConditionError> {
+        Ok(self.value(facts)?.truthy())
+    }
+}
+
+fn compare(l: &FactValue, op: CompareOp, r: &FactValue) -> Result<bool, ConditionError> {
+    Ok(match op {
+        CompareOp::Eq => l == r,
+        CompareOp::Ne => l != r,
+        CompareOp::Match | CompareOp::NotMatch => {
+            let matched = match l {
+                // Nothing matches null.
+                FactValue::Null => false,
+                l => full_regex(&r.to_string())?.is_match(&l.to_string()),
+            };
+            matched == (op == CompareOp::Match)
+        }
+        CompareOp::Lt | CompareOp::Le | CompareOp::Gt | CompareOp::Ge => match (l, r) {
+            (FactValue::Null, _) | (_, FactValue::Null) => false,
+            (l, r) => {
+                let ordering = version::compare(&l.to_string(), &r.to_string());
+                match op {
+                    CompareOp::Lt => ordering == Ordering::Less,
+                    CompareOp::Le => ordering != Ordering::Greater,
+                    CompareOp::Gt => ordering == Ordering::Greater,
+                    _ => ordering != Ordering::Less,
+                }
+            }
+        },
+    })
+}
+
+/// A regex which must match an entire string.
+fn full_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+fn call(facts: &Facts, name: &str, args: &[Expr]) -> Result<FactValue, ConditionError> {
+    let bad_args = |message: &str| ConditionError::BadArguments {
+        name: name.to_string(),
+        message: message.to_string(),
+    };
+    let string_arg = || match args {
+        [arg] => match arg.value(facts)? {
+            FactValue::String(s) => Ok(s),
+            _ => Err(bad_args("expected a string")),
+        },
+        _ => Err(bad_args("expected 1 argument")),
+    };
+    match name {
+        "env" => Ok(facts.env(&string_arg()?).into()),
+        "command_exists" => Ok(facts.command_exists(&string_arg()?).into()),
+        "in_subnet" => {
+            let subnet: Subnet = string_arg()?
+                .parse()
+                .map_err(|e: SubnetParseError| bad_args(&e.to_string()))?;
+            Ok(facts.in_subnet(&subnet)?.into())
+        }
+        "glob" => match args {
+            [value, pattern] => match (value.value(facts)?, pattern.value(facts)?) {
+                (FactValue::Null, _) => Ok(false.into()),
+                (value, FactValue::String(pattern)) => Ok(full_regex(&glob_to_regex(&pattern))?
+                    .is_match(&value.to_string())
+                    .into()),
+                _ => Err(bad_args("expected a string pattern")),
+            },
+            _ => Err(bad_args("expected 2 arguments")),
+        },
+        "interface_up" => {
+            let name = full_regex(&string_arg()?)?;
+            Ok(facts.interface_up(|iface| name.is_match(iface))?.into())
+        }
+        _ => Err(ConditionError::UnknownFunction(name.to_string())),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    LParen,
+    RParen,
+    Comma,
+    Not,
+    And,
+    Or,
+    Op(CompareOp),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(s) => write!(f, "`{}`", s),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Not => write!(f, "`!`"),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Op(op) => write!(f, "`{}`", op),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, ConditionError> {
+    let mut chars: Peekable<CharIndices> = s.char_indices().peekable();
+    let mut tokens = Vec::new();
+    let err = |offset, message: &str| ConditionError::Parse {
+        offset,
+        message: message.to_string(),
+    };
+    while let Some((i, c)) = chars.next() {
+        let mut next_is = |want: char| {
+            if chars.peek().map(|(_, c)| *c) == Some(want) {
+                chars.next();
+                true
+            } else {
+                false
+            }
+        };
+        let tok = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '=' if next_is('=') => Token::Op(CompareOp::Eq),
+            '=' if next_is('~') => Token::Op(CompareOp::Match),
+            '!' if next_is('=') => Token::Op(CompareOp::Ne),
+            '!' if next_is('~') => Token::Op(CompareOp::NotMatch),
+            '!' => Token::Not,
+            '<' if next_is('=') => Token::Op(CompareOp::Le),
+            '<' => Token::Op(CompareOp::Lt),
+            '>' if next_is('=') => Token::Op(CompareOp::Ge),
+            '>' => Token::Op(CompareOp::Gt),
+            '\'' | '"' => {
+                let mut lit = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(err(i, "unterminated string")),
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => lit.push(escaped),
+                            None => return Err(err(i, "unterminated string")),
+                        },
+                        Some((_, end)) if end == c => break,
+                        Some((_, other)) => lit.push(other),
+                    }
+                }
+                Token::Str(lit)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some((_, c)) = chars.peek() {
+                    if c.is_alphanumeric() || *c == '_' {
+                        ident.push(*c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Ident(ident)
+            }
+            c => return Err(err(i, &format!("unexpected character {:?}", c))),
+        };
+        tokens.push((i, tok));
+    }
+    Ok(tokens)
+}
+
+/// A recursive-descent parser; precedence from loosest to tightest is `||`,
+/// `&&`, `!`, comparisons.
+struct Parser {
+    tokens: Peekable<std::vec::IntoIter<(usize, Token)>>,
+    len: usize,
+}
+
+impl Parser {
+    fn new(s: &str) -> Result<Self, ConditionError> {
+        Ok(Self {
+            tokens: tokenize(s)?.into_iter().peekable(),
+            len: s.len(),
+        })
+    }
+
+    fn peek(&mut self) -> Option<&(usize, Token)> {
+        self.tokens.peek()
+    }
+
+    fn eat(&mut self, tok: &Token) -> bool {
+        if self.peek().map(|(_, t)| t) == Some(tok) {
+            self.tokens.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tok: Token) -> Result<(), ConditionError> {
+        match self.tokens.next() {
+            Some((_, t)) if t == tok => Ok(()),
+            Some((offset, t)) => Err(ConditionError::Parse {
+                offset,
+                message: format!("expected {} but found {}", tok, t),
+            }),
+            None => Err(ConditionError::Parse {
+                offset: self.len,
+                message: format!("expected {} but found end of input", tok),
+            }),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, ConditionError> {
+        let mut expr = self.and()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ConditionError> {
+        let mut expr = self.not()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, ConditionError> {
+        if self.eat(&Token::Not) {
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.compare()
+        }
+    }
+
+    fn compare(&mut self) -> Result<Expr, ConditionError> {
+        let lhs = self.primary()?;
+        match self.peek() {
+            Some((_, Token::Op(op))) => {
+                let op = *op;
+                self.tokens.next();
+                Ok(Expr::Compare(Box::new(lhs), op, Box::new(self.primary()?)))
+            }
+            _ => Ok(lhs),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ConditionError> {
+        match self.tokens.next() {
+            Some((_, Token::Str(s))) => Ok(Expr::Literal(s.into())),
+            Some((_, Token::LParen)) => {
+                let expr = self.or()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some((_, Token::Ident(ident))) => match ident.as_str() {
+                "null" => Ok(Expr::Literal(FactValue::Null)),
+                "true" => Ok(Expr::Literal(true.into())),
+                "false" => Ok(Expr::Literal(false.into())),
+                _ if self.eat(&Token::LParen) => {
+                    let mut args = Vec::new();
+                    if !self.eat(&Token::RParen) {
+                        loop {
+                            args.push(self.or()?);
+                            if self.eat(&Token::RParen) {
+                                break;
+                            }
+                            self.expect(Token::Comma)?;
+                        }
+                    }
+                    Ok(Expr::Call(ident, args))
+                }
+                _ => Ok(Expr::Fact(ident)),
+            },
+            Some((offset, tok)) => Err(ConditionError::Parse {
+                offset,
+                message: format!("expected a value but found {}", tok),
+            }),
+            None => Err(ConditionError::Parse {
+                offset: self.len,
+                message: "expected a value but found end of input".to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn fact(s: &str) -> Box<Expr> {
+        Box::new(Expr::Fact(s.to_string()))
+    }
+
+    fn lit(s: &str) -> Box<Expr> {
+        Box::new(Expr::Literal(s.into()))
+    }
+
+    #[test]
+    fn parse_precedence() {
+        assert_eq!(
+            Expr::parse("os == 'linux' || !a && b").unwrap(),
+            Expr::Or(
+                Box::new(Expr::Compare(fact("os"), CompareOp::Eq, lit("linux"))),
+                Box::new(Expr::And(Box::new(Expr::Not(fact("a"))), fact("b"))),
+            )
+        );
+
+        assert_eq!(
+            Expr::parse("(a || b) && env(\"X\") != null").unwrap(),
+            Expr::And(
+                Box::new(Expr::Or(fact("a"), fact("b"))),
+                Box::new(Expr::Compare(
+                    Box::new(Expr::Call("env".into(), vec![*lit("X")])),
+                    CompareOp::Ne,
+                    Box::new(Expr::Literal(FactValue::Null)),
+                )),
+            )
+        );
+
+        assert_eq!(
+            Expr::parse("os_version >= '13.0' && os_release<'5'").unwrap(),
+            Expr::And(
+                Box::new(Expr::Compare(
+                    fact("os_version"),
+                    CompareOp::Ge,
+                    lit("13.0")
+                )),
+                Box::new(Expr::Compare(fact("os_release"), CompareOp::Lt, lit("5"))),
+            )
+        );
+
+        assert_eq!(
+            Expr::parse(r"hostname =~ 'it\'s'").unwrap(),
+            Expr::Compare(fact("hostname"), CompareOp::Match, lit("it's"))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            format!("{}", Expr::parse("os == ").unwrap_err()),
+            "invalid condition at offset 6: expected a value but found end of input"
+        );
+        assert_eq!(
+            format!("{}", Expr::parse("os 'linux'").unwrap_err()),
+            "invalid condition at offset 3: unexpected \"linux\""
+        );
+        assert_eq!(
+            format!("{}", Expr::parse("(a").unwrap_err()),
+            "invalid condition at offset 2: expected `)` but found end of input"
+        );
+        assert_eq!(
+            format!("{}", Expr::parse("a = b").unwrap_err()),
+            "invalid condition at offset 2: unexpected character '='"
+        );
+        assert!(matches!(
+            Expr::parse("'abc"),
+            Err(ConditionError::Parse { offset: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn glob() {
+        let matches = |glob: &str, s: &str| full_regex(&glob_to_regex(glob)).unwrap().is_match(s);
+        assert!(matches("work-*", "work-laptop"));
+        assert!(matches("work-*", "work-"));
+        assert!(!matches("work-*", "home-work-laptop"));
+        assert!(matches("db?", "db1"));
+        assert!(!matches("db?", "db10"));
+        assert!(matches("db[0-9]", "db7"));
+        assert!(!matches("db[!0-9]", "db7"));
+        assert!(matches("db[!0-9]", "dbx"));
+        assert!(matches("a.b", "a.b"));
+        assert!(!matches("a.b", "axb"));
+        assert!(matches("[abc", "[abc"));
+        // Regex set operations are literal.
+        assert!(matches("[a&&b]", "&"));
+        assert!(!matches("[a&&b]", "c"));
+        assert!(matches("[a-c--]", "-"));
+        assert!(matches("[a-c--]", "b"));
+        assert!(!matches("[a-c--]", "d"));
+        assert!(matches("[a~~b]", "~"));
+        assert!(matches("[-a]", "-"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[a-c-e]", "-"));
+        assert!(!matches("[a-c-e]", "d"));
+        // A `]` first in a set is part of it.
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[]a]", "a"));
+        assert!(!matches("[!]a]", "]"));
+        assert!(matches("[!]a]", "b"));
+        assert!(matches("x[]", "x[]"));
+    }
+
+    #[test]
+    fn condition_table() {
+        let table: Condition = toml::from_str::<BTreeMap<String, Condition>>(
+            r#"when = { os = "linux", hostname_regex = "db[0-9]+", is_wsl = false }"#,
+        )
+        .unwrap()
+        .remove("when")
+        .unwrap();
+        assert_eq!(
+            table.parse().unwrap(),
+            Expr::And(
+                Box::new(Expr::And(
+                    Box::new(Expr::Compare(
+                        fact("hostname"),
+                        CompareOp::Match,
+                        lit("db[0-9]+")
+                    )),
+                    Box::new(Expr::Compare(
+                        fact("is_wsl"),
+                        CompareOp::Eq,
+                        Box::new(Expr::Literal(false.into()))
+                    )),
+                )),
+                Box::new(Expr::Compare(fact("os"), CompareOp::Match, lit("linux"))),
+            )
+        );
+        assert_eq!(
+            Condition::Table(BTreeMap::new()).parse().unwrap(),
+            Expr::Literal(true.into())
+        );
+    }
+
+    #[test]
+    fn eval_synthetic() {
+        use crate::facts::{Nic, PlatformInfo};
+
+        let facts = Facts::from_parts(
+            PlatformInfo {
+                system: "Linux".into(),
+                release: "5.4.0-42-generic".into(),
+                version: "#46-Ubuntu SMP".into(),
+                hostname: "db12".into(),
+                arch: "x86_64".into(),
+            },
+            vec![],
+            vec![Nic {
+                name: "wg0".into(),
+                addresses: vec!["10.42.3.4".parse().unwrap()],
+                is_up: true,
+                is_loopback: false,
+            }],
+        );
+        let eval = |s: &str| Expr::parse(s).unwrap().eval(&facts).unwrap();
+
+        assert!(eval("os == 'linux' && hostname =~ 'db[0-9]+'"));
+        assert!(eval("glob(hostname, 'db*') && os_release >= '5.4'"));
+        assert!(!eval("os_release >= '5.10'"));
+        assert!(eval(
+            "in_subnet('10.42.0.0/16') && interface_up('wg[0-9]+')"
+        ));
+        assert!(!eval(
+            "in_subnet('192.168.0.0/16') || interface_up('utun.*')"
+        ));
+    }
+
+    #[test]
+    fn eval() {
+        let facts = Facts::new();
+        let eval = |s: &str| Expr::parse(s).unwrap().eval(&facts);
+
+        assert!(eval(&format!("hostname == '{}'", facts.hostname().unwrap())).unwrap());
+        assert!(eval(&format!("os == '{}'", facts.os().unwrap())).unwrap());
+        assert!(!eval("os == 'plan9' && true").unwrap());
+        assert!(eval("os == 'plan9' || true").unwrap());
+        assert!(eval("hostname =~ '.+'").unwrap());
+        assert!(!eval("hostname =~ ''").unwrap());
+        assert!(eval("hostname !~ ''").unwrap());
+        assert!(eval("env('DOTFILE_MANAGER_SURELY_UNSET') == null").unwrap());
+        assert!(!eval("env('DOTFILE_MANAGER_SURELY_UNSET')").unwrap());
+        assert!(eval("!null").unwrap());
+        assert!(!eval("command_exists('dotfile-manager-surely-nonexistent')").unwrap());
+        assert!(eval(&format!(
+            "glob(hostname, '{}*')",
+            &facts.hostname().unwrap()[..1]
+        ))
+        .unwrap());
+        assert!(!eval("glob(env('DOTFILE_MANAGER_SURELY_UNSET'), '*')").unwrap());
+        assert!(!eval("in_subnet('0.0.0.0/32')").unwrap());
+        assert!(!eval("interface_up('dotfile-manager-surely-nonexistent')").unwrap());
+        assert!(eval("'10.10' > '10.9'").unwrap());
+        assert!(eval("'13' >= '13.0' && '13' <= '13.0'").unwrap());
+        assert!(!eval("'13' < '13.0'").unwrap());
+        assert!(!eval("env('DOTFILE_MANAGER_SURELY_UNSET') < '1'").unwrap());
+        assert!(!eval("env('DOTFILE_MANAGER_SURELY_UNSET') >= '1'").unwrap());
+
+        assert!(matches!(
+            eval("kernel == 'linux'"),
+            Err(ConditionError::UnknownFact(_))
+        ));
+        assert!(matches!(
+            eval("getenv('HOME')"),
+            Err(ConditionError::UnknownFunction(_))
+        ));
+        assert!(matches!(
+            eval("env('A', 'B')"),
+            Err(ConditionError::BadArguments { .. })
+        ));
+        assert!(matches!(
+            eval("in_subnet('10.0.0.0/99')"),
+            Err(ConditionError::BadArguments { .. })
+        ));
+        assert!(matches!(
+            eval("hostname =~ '('"),
+            Err(ConditionError::Regex(_))
+        ));
+    }
+
+    #[test]
+    fn script_condition() {
+        let res = Condition::from("script: 1 < 2").eval(&Facts::new());
+        if cfg!(feature = "rhai") {
+            assert!(res.unwrap());
+        } else {
+            assert!(matches!(res, Err(ConditionError::NoScripts)));
+        }
+    }
+}
@@ -1,25 +1,45 @@
+use std::collections::BTreeMap;
 use std::convert::{TryFrom, TryInto};
+use std::error::Error;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io;
-use std::io::{BufReader, ErrorKind};
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use dirs;
 use lazy_static::lazy_static;
 use serde::Deserialize;
 use thiserror::Error;
 
-use crate::dotfile::{Dotfile, SerdeDotfile};
+use crate::defaults::Defaults;
+use crate::diagnostic::{Diagnose, Diagnostic};
+use crate::discover::App;
+#[cfg(feature = "nix")]
+use crate::dotfile::SerdeDotfile;
+use crate::dotfile::{AbsDotfile, Dotfile};
+use crate::facts::{Facts, FactsError, DEFAULT_COMMAND_TIMEOUT};
+use crate::git::GitOptions;
+use crate::kdl::KdlError;
+use crate::loader::{Evaluated, ListLoader, ListLoaders, SerdeDotfileList};
+use crate::log;
+#[cfg(feature = "nix")]
 use crate::nix;
-use crate::nix::NixEvalError;
-use crate::util::file_to_string;
+#[cfg(feature = "nix")]
+use crate::nix::{NixEvalError, NixOptions};
+use crate::prompt::PromptOptions;
+use crate::strategy::LinkStrategies;
+use crate::util::{file_to_string, make_abs};
+use crate::vscode::VsCodeOptions;
+use crate::warning::{Warning, WarningKind};
 
 lazy_static! {
     static ref CONFIG_DIR_NAME: &'static Path = Path::new("dotfile-manager");
     static ref DEFAULT_DOTFILE_REPO_NAME: &'static Path = Path::new(".dotfiles");
     static ref CONFIG_FILE_NAME: &'static Path = Path::new("dotfile-manager.toml");
-    pub static ref CONFIG: Config = { Config::try_default().unwrap() };
+    static ref FACTS_CACHE_FILE_NAME: &'static Path = Path::new("facts.json");
 }
 
 /// Configuration directory, e.g. ~/.config/dotfile-manager on Linux.
@@ -41,30 +61,76 @@ pub fn config_file() -> io::Result<PathBuf> {
         .collect::<PathBuf>())
 }
 
-/// A wrapper struct for use when deserializing a dotfile list.
-#[derive(Deserialize)]
-struct SerdeDotfileList {
-    /// Allow a `$schema` identifier for formats/programs that support it (mostly
-    /// JSON).
-    #[allow(dead_code)]
-    #[serde(rename = "$schema")]
-    schema: Option<String>,
-    dotfiles: Vec<SerdeDotfile>,
+/// A configuration file kept in the dotfile repository, e.g.
+/// ~/.dotfiles/dotfile-manager.toml, which `dfm init` installs.
+pub fn repo_config_file(dotfile_repo: &Path) -> PathBuf {
+    dotfile_repo.join(*CONFIG_FILE_NAME)
 }
 
-impl From<Vec<SerdeDotfile>> for SerdeDotfileList {
-    fn from(v: Vec<SerdeDotfile>) -> Self {
-        Self {
-            schema: None,
-            dotfiles: v,
-        }
-    }
+/// Cache directory, e.g. ~/.cache/dotfile-manager on Linux.
+fn cache_dir() -> io::Result<PathBuf> {
+    Ok([
+        &dirs::cache_dir()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Cache directory not found."))?,
+        *CONFIG_DIR_NAME,
+    ]
+    .iter()
+    .collect::<PathBuf>())
 }
 
-impl SerdeDotfileList {
-    fn dotfiles(&self) -> Vec<Dotfile> {
-        self.dotfiles.iter().cloned().map(Into::into).collect()
-    }
+/// Facts cache file path, e.g. ~/.cache/dotfile-manager/facts.json on Linux.
+pub fn facts_cache_file() -> io::Result<PathBuf> {
+    Ok([&cache_dir()?, *FACTS_CACHE_FILE_NAME]
+        .iter()
+        .collect::<PathBuf>())
+}
+
+/// Where `dfm status --short` saves the last status it checked, e.g.
+/// ~/.cache/dotfile-manager/short-status on Linux.
+pub fn short_status_cache_file() -> io::Result<PathBuf> {
+    Ok(cache_dir()?.join("short-status"))
+}
+
+/// Where hashes of rendered templates are kept, so a previous render can be
+/// told apart from a file the user put in a template's place, e.g.
+/// ~/.cache/dotfile-manager/rendered on Linux; see `template::is_rendered`.
+pub fn rendered_dir() -> io::Result<PathBuf> {
+    Ok(cache_dir()?.join("rendered"))
+}
+
+/// Directory for cached Nix evaluation results, e.g.
+/// ~/.cache/dotfile-manager/nix on Linux.
+pub fn nix_cache_dir() -> io::Result<PathBuf> {
+    Ok(cache_dir()?.join("nix"))
+}
+
+/// Check that `list`, e.g. an evaluated Nix dotfiles list, is a list of
+/// dotfiles, and describe each problem with it. `SerdeDotfile`'s own errors
+/// don't say which element or field is wrong, so each element is checked
+/// separately.
+pub fn check_dotfiles_list(list: &serde_json::Value) -> Vec<String> {
+    use serde_json::Value;
+    let elements = match list {
+        Value::Array(elements) => elements,
+        _ => return vec![format!("expected a list of dotfiles, found {}", list)],
+    };
+    elements
+        .iter()
+        .enumerate()
+        .filter_map(|(i, element)| {
+            let err = match element {
+                Value::String(_) => return None,
+                Value::Object(_) => serde_json::from_value::<Dotfile>(element.clone()).err()?,
+                _ => {
+                    return Some(format!(
+                        "element {}: expected a path or an object, found {}",
+                        i, element
+                    ))
+                }
+            };
+            Some(format!("element {}: {}", i, err))
+        })
+        .collect()
 }
 
 /// An error when reading/deserializing a dotfiles list file.
@@ -83,7 +149,12 @@ pub enum DotfilesReadError {
     #[error("failed to parse as JSON / incorrect schema")]
     SerdeJSON(#[from] serde_json::Error),
 
+    /// Deserialization error (JSON5, or JSON with comments).
+    #[error("failed to parse as JSON5 / incorrect schema")]
+    SerdeJSON5(#[from] json5::Error),
+
     /// Deserialization error (YAML).
+    #[cfg(feature = "yaml")]
     #[error("failed to parse as YAML / incorrect schema")]
     SerdeYAML(#[from] serde_yaml::Error),
 
@@ -91,31 +162,210 @@ pub enum DotfilesReadError {
     #[error("failed to parse as TOML / incorrect schema")]
     SerdeTOML(#[from] toml::de::Error),
 
+    /// Parse error or incorrect schema (KDL).
+    #[error("failed to parse as KDL / incorrect schema")]
+    KDL(#[from] KdlError),
+
+    /// A program needed to read the dotfiles list, like `dhall-to-json`,
+    /// isn't installed.
+    #[error("`{0}` not found; is it installed?")]
+    ProgramNotFound(String),
+
+    /// A program reading the dotfiles list failed or timed out.
+    #[error("`{program}` failed: {stderr}")]
+    ProgramFailed { program: String, stderr: String },
+
+    /// Error while running a dotfiles list written in Rhai.
+    #[cfg(feature = "rhai")]
+    #[error("{0}")]
+    Script(#[from] crate::script::ScriptError),
+
+    /// Error while evaluating a dotfiles list written in Jsonnet.
+    #[cfg(feature = "jsonnet")]
+    #[error("{0}")]
+    Jsonnet(String),
+
+    /// Error while running a dotfiles list written in Starlark.
+    #[cfg(feature = "starlark")]
+    #[error("{0}")]
+    Starlark(String),
+
+    /// Error while running a dotfiles list written in Lua.
+    #[cfg(feature = "lua")]
+    #[error("{0}")]
+    Lua(#[from] mlua::Error),
+
+    /// Deserialization error from a `ListLoader` for another format.
+    #[error("failed to parse as {format} / incorrect schema")]
+    Parse {
+        format: String,
+        source: Box<dyn Error + Send + Sync>,
+    },
+
     /// Evaluation error (Nix expression language).
+    #[cfg(feature = "nix")]
     #[error("{0}")]
     NixEval(#[from] NixEvalError),
+
+    /// The dotfiles list is written in Nix, or a dotfile is built with Nix,
+    /// but this build doesn't support it.
+    #[error("evaluating Nix needs the `nix` feature")]
+    NoNix,
+
+    /// Error while collecting facts to pass to the Nix expression language.
+    #[error("{0}")]
+    Facts(#[from] FactsError),
+
+    /// `dotfiles_format` isn't the name of a format.
+    #[error("unknown dotfiles list format `{0}`")]
+    UnknownFormat(String),
+
+    /// A dotfile's `link` isn't the name of a strategy; see the `strategy`
+    /// module.
+    #[error("{}: unknown link strategy `{name}`", .repo.display())]
+    UnknownLinkStrategy {
+        repo: PathBuf,
+        name: String,
+        known: Vec<String>,
+    },
+
+    /// The dotfiles list's format can't be told from its extension.
+    #[error("can't tell which format {0} is written in; set `dotfiles_format`")]
+    NoFormat(PathBuf),
+
+    /// A dotfiles list written in Nix was to be read from standard input.
+    #[error("dotfiles lists written in Nix can't be read from standard input")]
+    NixStdin,
+
+    /// More than one dotfiles list file exists and `ambiguous_dotfiles` is
+    /// `error`.
+    #[error(
+        "found more than one dotfiles list: {}; remove all but one, or set `ambiguous_dotfiles = \"first\"` to read the first",
+        .0.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    Ambiguous(Vec<PathBuf>),
+
+    /// The dotfiles list needed to be written in the Nix expression language
+    /// but isn't.
+    #[error("{0} isn't written in Nix")]
+    NotNix(PathBuf),
+
+    /// An error reading the dotfiles list file `path`.
+    #[error("{source}")]
+    InFile {
+        path: PathBuf,
+        source: Box<DotfilesReadError>,
+    },
+}
+
+impl DotfilesReadError {
+    /// This error, read from the dotfiles list file `path`.
+    fn in_file(self, path: &Path) -> Self {
+        DotfilesReadError::InFile {
+            path: path.to_path_buf(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Where a parse error is in the dotfiles list, as a 1-based line and
+    /// column; see `Diagnostic::position`.
+    fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            DotfilesReadError::SerdeJSON(err) if err.line() > 0 => Some((err.line(), err.column())),
+            DotfilesReadError::SerdeJSON5(json5::Error::Message {
+                location: Some(location),
+                ..
+            }) => Some((location.line, location.column)),
+            #[cfg(feature = "yaml")]
+            DotfilesReadError::SerdeYAML(err) => err
+                .location()
+                .map(|location| (location.line(), location.column())),
+            DotfilesReadError::SerdeTOML(err) => {
+                err.line_col().map(|(line, column)| (line + 1, column + 1))
+            }
+            DotfilesReadError::KDL(err) => Some((err.line, 0)),
+            _ => None,
+        }
+    }
+}
+
+impl Diagnose for DotfilesReadError {
+    fn diagnostic(&self) -> Diagnostic {
+        match self {
+            DotfilesReadError::InFile { path, source } => {
+                let diagnostic = source.diagnostic().in_file(path);
+                match source.position() {
+                    Some((line, column)) => diagnostic.at(line, column),
+                    None => diagnostic,
+                }
+            }
+            DotfilesReadError::NoneFound => Diagnostic::from_error(self).help(
+                "write a dotfiles list like `dotfiles.toml` in the dotfile repository, or run `dfm init` to set one up",
+            ),
+            DotfilesReadError::ProgramNotFound(program) => Diagnostic::from_error(self).help(
+                format!("install `{}`, or write the dotfiles list in another format", program),
+            ),
+            #[cfg(feature = "nix")]
+            DotfilesReadError::NixEval(err) => err.diagnostic(),
+            DotfilesReadError::NoNix => Diagnostic::from_error(self).help(
+                "rebuild dfm with the `nix` feature, or write the dotfiles list in another format, like TOML",
+            ),
+            DotfilesReadError::UnknownLinkStrategy { known, .. } => Diagnostic::from_error(self)
+                .help(format!(
+                    "use one of {}",
+                    known
+                        .iter()
+                        .map(|name| format!("`{}`", name))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            _ => Diagnostic::from_error(self),
+        }
+    }
+}
+
+#[cfg(feature = "nix")]
+fn nix_eval_error(err: NixEvalError) -> DotfilesReadError {
+    match err {
+        // Don't use multiple json serde error types
+        NixEvalError::SerdeJSON(err) => DotfilesReadError::SerdeJSON(err),
+        err => DotfilesReadError::NixEval(err),
+    }
 }
 
 /// The file format of a dotfiles list file.
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 enum DotfileListFiletype {
+    /// The Nix expression language, which is evaluated rather than parsed; see
+    /// `Config::load_dotfiles`.
     Nix,
-    JSON,
-    TOML,
-    YAML,
+    Loader(Arc<dyn ListLoader>),
 }
 
 impl DotfileListFiletype {
-    fn extensions(self) -> Vec<PathBuf> {
+    fn name(&self) -> &str {
         match self {
-            DotfileListFiletype::Nix => vec!["nix".into()],
-            DotfileListFiletype::JSON => vec!["json".into()],
-            DotfileListFiletype::TOML => vec!["toml".into()],
-            DotfileListFiletype::YAML => vec!["yaml".into(), "yml".into()],
+            DotfileListFiletype::Nix => "nix",
+            DotfileListFiletype::Loader(loader) => loader.name(),
         }
     }
 }
 
+/// What to do when more than one dotfiles list file exists, e.g. both
+/// `dotfiles.toml` and `dotfiles.yaml`.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AmbiguousDotfiles {
+    /// Read the first one, in the order they're looked for (Nix first, then
+    /// each of `Config::list_loaders`), and warn about the others.
+    #[default]
+    Warn,
+    /// Read the first one silently.
+    First,
+    /// Refuse to read any of them.
+    Error,
+}
+
 #[derive(Error, Debug)]
 pub enum ConfigReadError {
     #[error("dirs crate failed to find home directory")]
@@ -128,20 +378,92 @@ pub enum ConfigReadError {
     File(#[from] io::Error),
 
     #[error("failed to parse config file as TOML / incorrect schema")]
-    SerdeTOML(#[from] toml::de::Error),
+    SerdeTOML {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("`{0}` is defined in both `facts` and `vars`")]
+    DuplicateFact(String),
+
+    #[error("unknown dotfiles list format `{0}`")]
+    UnknownFormat(String),
+
+    #[error("`dotfiles_basename` is empty")]
+    EmptyBasename,
+
+    #[error("failed to parse config file as TOML to edit it: {0}")]
+    Edit(#[from] toml_edit::TomlError),
+}
+
+impl Diagnose for ConfigReadError {
+    fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::from_error(self);
+        match self {
+            ConfigReadError::NoHome => diagnostic.help("set `HOME` to your home directory"),
+            ConfigReadError::NotFound(_) => diagnostic
+                .help("run `dfm init` to set up a dotfile repository and configuration file"),
+            ConfigReadError::SerdeTOML { path, source } => {
+                let diagnostic = diagnostic.in_file(path);
+                match source.line_col() {
+                    Some((line, column)) => diagnostic.at(line + 1, column + 1),
+                    None => diagnostic,
+                }
+            }
+            ConfigReadError::DuplicateFact(_) => diagnostic
+                .help("rename one of them; facts and variables are looked up by the same names"),
+            ConfigReadError::UnknownFormat(_) => diagnostic
+                .help("use `nix` or the name of one of the dotfiles list loaders, e.g. `yaml`"),
+            ConfigReadError::EmptyBasename => diagnostic,
+            ConfigReadError::File(_) => diagnostic,
+            ConfigReadError::Edit(_) => diagnostic,
+        }
+    }
 }
 
 #[derive(Deserialize, Default)]
 #[serde(deny_unknown_fields)]
 struct SerdeConfig {
     dotfile_repo: Option<PathBuf>,
+    dotfile_repo_url: Option<String>,
+    dotfile_repo_sha256: Option<String>,
     dotfiles_basename: Option<PathBuf>,
+    dotfiles_list: Option<PathBuf>,
+    dotfiles_format: Option<String>,
+    ambiguous_dotfiles: Option<AmbiguousDotfiles>,
+    dotfiles_flake: Option<String>,
+    dotfiles_attr: Option<String>,
+    facts_cache_ttl: Option<u64>,
+    facts: Option<BTreeMap<String, String>>,
+    fact_command_timeout: Option<u64>,
+    vars: Option<BTreeMap<String, String>>,
+    #[cfg(feature = "nix")]
+    nix: Option<NixOptions>,
+    /// Accepted, so configuration files shared with builds which have the
+    /// `nix` feature can be read.
+    #[cfg(not(feature = "nix"))]
+    #[allow(dead_code)]
+    nix: Option<toml::Value>,
+    git: Option<GitOptions>,
+    apps: Option<BTreeMap<String, App>>,
+    brewfile: Option<PathBuf>,
+    log_file: Option<PathBuf>,
+    log_file_max_size: Option<u64>,
+    defaults: Option<Defaults>,
+    vscode: Option<VsCodeOptions>,
+    prompts: Option<PromptOptions>,
+    plugins: Option<bool>,
 }
 
 impl TryFrom<SerdeConfig> for Config {
     type Error = ConfigReadError;
 
     fn try_from(cfg: SerdeConfig) -> Result<Self, ConfigReadError> {
+        if let (Some(facts), Some(vars)) = (&cfg.facts, &cfg.vars) {
+            if let Some(name) = vars.keys().find(|name| facts.contains_key(*name)) {
+                return Err(ConfigReadError::DuplicateFact(name.clone()));
+            }
+        }
         Ok(Config {
             dotfile_repo: cfg
                 .dotfile_repo
@@ -154,7 +476,153 @@ impl TryFrom<SerdeConfig> for Config {
                     .iter()
                     .collect())
                 })?,
+            dotfile_repo_url: cfg.dotfile_repo_url,
+            dotfile_repo_sha256: cfg.dotfile_repo_sha256,
             dotfiles_basename: cfg.dotfiles_basename.unwrap_or_else(|| "dotfiles".into()),
+            dotfiles_list: cfg.dotfiles_list,
+            dotfiles_format: cfg.dotfiles_format,
+            ambiguous_dotfiles: cfg.ambiguous_dotfiles.unwrap_or_default(),
+            dotfiles_flake: cfg.dotfiles_flake,
+            dotfiles_attr: cfg.dotfiles_attr,
+            facts_cache_ttl: Duration::from_secs(cfg.facts_cache_ttl.unwrap_or(5 * 60)),
+            facts: cfg.facts.unwrap_or_default(),
+            fact_command_timeout: cfg
+                .fact_command_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_COMMAND_TIMEOUT),
+            vars: cfg.vars.unwrap_or_default(),
+            #[cfg(feature = "nix")]
+            nix: cfg.nix.unwrap_or_default(),
+            git: cfg.git.unwrap_or_default(),
+            apps: cfg.apps.unwrap_or_default(),
+            brewfile: cfg.brewfile,
+            log_file: cfg.log_file,
+            log_file_max_size: cfg.log_file_max_size.unwrap_or(log::DEFAULT_MAX_SIZE),
+            defaults: cfg.defaults.unwrap_or_default(),
+            vscode: cfg.vscode.unwrap_or_default(),
+            prompts: cfg.prompts.unwrap_or_default(),
+            plugins: cfg.plugins.unwrap_or(true),
+            list_loaders: ListLoaders::default(),
+            link_strategies: LinkStrategies::default(),
+        })
+    }
+}
+
+/// Builds a `Config`; see `Config::builder`. Anything which isn't set has
+/// the same default as in the configuration file.
+#[derive(Default)]
+pub struct ConfigBuilder {
+    cfg: SerdeConfig,
+    list_loaders: ListLoaders,
+    link_strategies: LinkStrategies,
+}
+
+impl ConfigBuilder {
+    /// See `Config::dotfile_repo`.
+    pub fn dotfile_repo(mut self, dotfile_repo: impl Into<PathBuf>) -> Self {
+        self.cfg.dotfile_repo = Some(dotfile_repo.into());
+        self
+    }
+
+    /// See `Config::dotfiles_basename`.
+    pub fn dotfiles_basename(mut self, dotfiles_basename: impl Into<PathBuf>) -> Self {
+        self.cfg.dotfiles_basename = Some(dotfiles_basename.into());
+        self
+    }
+
+    /// See `Config::dotfiles_list`.
+    pub fn dotfiles_list(mut self, dotfiles_list: impl Into<PathBuf>) -> Self {
+        self.cfg.dotfiles_list = Some(dotfiles_list.into());
+        self
+    }
+
+    /// See `Config::dotfiles_format`.
+    pub fn dotfiles_format(mut self, dotfiles_format: impl Into<String>) -> Self {
+        self.cfg.dotfiles_format = Some(dotfiles_format.into());
+        self
+    }
+
+    /// See `Config::dotfiles_attr`.
+    pub fn dotfiles_attr(mut self, dotfiles_attr: impl Into<String>) -> Self {
+        self.cfg.dotfiles_attr = Some(dotfiles_attr.into());
+        self
+    }
+
+    /// See `Config::facts_cache_ttl`; it's rounded down to whole seconds.
+    pub fn facts_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cfg.facts_cache_ttl = Some(ttl.as_secs());
+        self
+    }
+
+    /// Add a user-defined fact; see `Config::facts`.
+    pub fn fact(mut self, name: impl Into<String>, command: impl Into<String>) -> Self {
+        self.cfg
+            .facts
+            .get_or_insert_with(Default::default)
+            .insert(name.into(), command.into());
+        self
+    }
+
+    /// Add a user-defined fact with a static value; see `Config::vars`.
+    pub fn var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.cfg
+            .vars
+            .get_or_insert_with(Default::default)
+            .insert(name.into(), value.into());
+        self
+    }
+
+    /// See `Config::nix`.
+    #[cfg(feature = "nix")]
+    pub fn nix(mut self, nix: NixOptions) -> Self {
+        self.cfg.nix = Some(nix);
+        self
+    }
+
+    /// See `Config::log_file`.
+    pub fn log_file(mut self, log_file: impl Into<PathBuf>) -> Self {
+        self.cfg.log_file = Some(log_file.into());
+        self
+    }
+
+    /// See `Config::prompts`.
+    pub fn prompts(mut self, prompts: PromptOptions) -> Self {
+        self.cfg.prompts = Some(prompts);
+        self
+    }
+
+    /// See `Config::list_loaders`; the built-in loaders by default.
+    pub fn list_loaders(mut self, list_loaders: ListLoaders) -> Self {
+        self.list_loaders = list_loaders;
+        self
+    }
+
+    /// See `Config::link_strategies`; the built-in strategies by default.
+    pub fn link_strategies(mut self, link_strategies: LinkStrategies) -> Self {
+        self.link_strategies = link_strategies;
+        self
+    }
+
+    /// The configuration, if it's valid: `dotfiles_basename` isn't empty,
+    /// `dotfiles_format` is a known format, and no fact is also a variable.
+    pub fn build(self) -> Result<Config, ConfigReadError> {
+        if self
+            .cfg
+            .dotfiles_basename
+            .as_ref()
+            .is_some_and(|basename| basename.as_os_str().is_empty())
+        {
+            return Err(ConfigReadError::EmptyBasename);
+        }
+        if let Some(format) = &self.cfg.dotfiles_format {
+            if format != "nix" && self.list_loaders.get(format).is_none() {
+                return Err(ConfigReadError::UnknownFormat(format.clone()));
+            }
+        }
+        Ok(Config {
+            list_loaders: self.list_loaders,
+            link_strategies: self.link_strategies,
+            ..self.cfg.try_into()?
         })
     }
 }
@@ -165,9 +633,79 @@ pub struct Config {
     /// The directory where dotfiles are stored; if not absolute, interpreted as
     /// relative to the user's home directory.
     pub dotfile_repo: PathBuf,
+    /// Where `dfm init` gets `dotfile_repo` from if it isn't given `--from`:
+    /// a git repository, or a `.tar.gz`, `.tgz`, `.tar` or `.zip` archive of
+    /// one (e.g. a GitHub archive URL) for machines without git.
+    pub dotfile_repo_url: Option<String>,
+    /// The SHA-256 checksum `dotfile_repo_url` must have, if it's an archive.
+    pub dotfile_repo_sha256: Option<String>,
     /// Basename of the dotfiles list file; default `dotfiles`. Relative to
     /// `dotfile_repo`.
     pub dotfiles_basename: PathBuf,
+    /// The dotfiles list file, relative to `dotfile_repo`, if it shouldn't be
+    /// looked for with `dotfiles_basename`; `-` reads standard input.
+    pub dotfiles_list: Option<PathBuf>,
+    /// The format the dotfiles list is written in, e.g. `yaml`, regardless of
+    /// its extension: `nix` or the name of one of `list_loaders`. If set,
+    /// `dotfiles_basename` is also looked for without an extension.
+    pub dotfiles_format: Option<String>,
+    /// What to do when more than one dotfiles list file exists; default
+    /// `warn`.
+    pub ambiguous_dotfiles: AmbiguousDotfiles,
+    /// A flake output to read the dotfiles list from instead of a file, e.g.
+    /// `github:me/dotfiles#dotfiles`. Relative paths like `.#dotfiles` are
+    /// relative to `dotfile_repo`.
+    pub dotfiles_flake: Option<String>,
+    /// An attribute path to select from a dotfiles list written in Nix, e.g.
+    /// `hosts.laptop`, so one file can hold a list for each machine.
+    pub dotfiles_attr: Option<String>,
+    /// How long collected system facts are cached for; default 5 minutes. Set
+    /// to 0 (seconds) to always collect facts.
+    pub facts_cache_ttl: Duration,
+    /// User-defined facts; maps fact names to shell commands whose output is
+    /// the fact's value.
+    pub facts: BTreeMap<String, String>,
+    /// How long commands for user-defined facts may run; default 10 seconds.
+    pub fact_command_timeout: Duration,
+    /// User-defined facts with static values, e.g. an email address to use on
+    /// this machine.
+    pub vars: BTreeMap<String, String>,
+    /// Options for evaluating a dotfiles list written in the Nix expression
+    /// language, e.g. extra arguments to pass to it.
+    #[cfg(feature = "nix")]
+    pub nix: NixOptions,
+    /// Options for the dotfile repository's git repository, e.g. whether to
+    /// commit changes dfm makes to it.
+    pub git: GitOptions,
+    /// Applications for `dfm discover` to look for, besides the bundled
+    /// ones; see `discover::apps`.
+    pub apps: BTreeMap<String, App>,
+    /// A Homebrew `Brewfile`, relative to `dotfile_repo`, for `dfm brew` to
+    /// install with `brew bundle`; `dfm link` installs it too.
+    pub brewfile: Option<PathBuf>,
+    /// A file, relative to the home directory, to append a record of each
+    /// run's actions and results to; see the `log` module.
+    pub log_file: Option<PathBuf>,
+    /// How large `log_file` may grow, in bytes, before it's moved to
+    /// `log_file` with `.1` appended and a new one is started; default 1
+    /// MiB.
+    pub log_file_max_size: u64,
+    /// macOS preferences for `dfm link` to set with `defaults write`; see
+    /// the `defaults` module.
+    pub defaults: Defaults,
+    /// VS Code settings to install and extensions for `dfm link` to install.
+    pub vscode: VsCodeOptions,
+    /// How interactive prompts look, and their default answers to
+    /// questions which replace files; see the `prompt` module.
+    pub prompts: PromptOptions,
+    /// Whether to use plugins on the `PATH`; see the `plugin` module.
+    pub plugins: bool,
+    /// The formats dotfiles lists may be written in, besides Nix.
+    #[serde(skip)]
+    pub list_loaders: ListLoaders,
+    /// The ways dotfiles may be installed; see `Dotfile::link`.
+    #[serde(skip)]
+    pub link_strategies: LinkStrategies,
 }
 
 impl TryFrom<&Path> for Config {
@@ -177,7 +715,12 @@ impl TryFrom<&Path> for Config {
         if !p.exists() {
             return Err(ConfigReadError::NotFound(p.to_path_buf()));
         }
-        toml::from_str::<SerdeConfig>(&file_to_string(&mut File::open(p)?)?)?.try_into()
+        toml::from_str::<SerdeConfig>(&file_to_string(&mut File::open(p)?)?)
+            .map_err(|source| ConfigReadError::SerdeTOML {
+                path: p.to_path_buf(),
+                source,
+            })?
+            .try_into()
     }
 }
 
@@ -186,6 +729,63 @@ impl Config {
         SerdeConfig::default().try_into()
     }
 
+    /// Build a configuration without a configuration file, e.g.
+    ///
+    /// ```
+    /// # use dotfile_manager::config::Config;
+    /// let cfg = Config::builder()
+    ///     .dotfile_repo("/home/me/dotfiles")
+    ///     .dotfiles_basename("hosts/laptop")
+    ///     .var("email", "me@example.com")
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(cfg.dotfiles_basename.to_str(), Some("hosts/laptop"));
+    /// ```
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Make a local flake reference like `./nix#dotfiles` relative to the
+    /// dotfile repository; other references are returned as-is.
+    #[cfg(feature = "nix")]
+    fn resolve_flake_ref(&self, flake_ref: &str) -> String {
+        let (path, attr) = match flake_ref.find('#') {
+            Some(i) => flake_ref.split_at(i),
+            None => (flake_ref, ""),
+        };
+        if path.starts_with('.') {
+            format!(
+                "{}{}",
+                make_abs(&self.dotfile_repo, Path::new(path)).display(),
+                attr
+            )
+        } else {
+            flake_ref.to_string()
+        }
+    }
+
+    /// The options for evaluating Nix: `nix`, with search path entries
+    /// relative to the dotfile repository and results cached in
+    /// `nix_cache_dir` unless another `cache_dir` is set.
+    #[cfg(feature = "nix")]
+    pub fn nix_options(&self) -> NixOptions {
+        let opts = self.nix.clone().with_include_base(&self.dotfile_repo);
+        NixOptions {
+            cache_dir: opts.cache_dir.clone().or_else(|| nix_cache_dir().ok()),
+            ..opts
+        }
+    }
+
+    /// The options for evaluating the dotfiles list: `nix_options`, with
+    /// facts as arguments and `dotfiles_attr` selected.
+    #[cfg(feature = "nix")]
+    fn dotfiles_nix_options(&self, facts: &Facts) -> Result<NixOptions, FactsError> {
+        Ok(NixOptions {
+            attr: self.dotfiles_attr.clone(),
+            ..self.nix_options().with_default_argstrs(facts.nix_args()?)
+        })
+    }
+
     fn dotfiles_basename_extension<S: AsRef<OsStr>>(&self, extension: S) -> PathBuf {
         let mut dotfiles_filename = self.dotfiles_basename.clone();
         dotfiles_filename.set_extension(extension);
@@ -201,63 +801,250 @@ impl Config {
         .collect::<PathBuf>()
     }
 
-    fn dotfiles_paths(&self) -> Vec<(PathBuf, DotfileListFiletype)> {
-        [
-            DotfileListFiletype::Nix,
-            DotfileListFiletype::JSON,
-            DotfileListFiletype::TOML,
-            DotfileListFiletype::YAML,
-        ]
-        .iter()
-        .map(|filetype| {
-            filetype
-                .extensions()
-                .iter()
-                .map(|ext| self.dotfiles_filename(ext))
-                .map(|filename| (filename, *filetype))
-                .collect::<Vec<_>>()
-        })
-        .flatten()
-        .collect()
+    /// The format named `name`; see `dotfiles_format`.
+    fn filetype_named(&self, name: &str) -> Result<DotfileListFiletype, DotfilesReadError> {
+        match name {
+            "nix" => Ok(DotfileListFiletype::Nix),
+            _ => self
+                .list_loaders
+                .get(name)
+                .map(|loader| DotfileListFiletype::Loader(Arc::clone(loader)))
+                .ok_or_else(|| DotfilesReadError::UnknownFormat(name.to_string())),
+        }
     }
 
-    fn dotfiles_path(&self) -> Result<(PathBuf, File, DotfileListFiletype), DotfilesReadError> {
-        self.dotfiles_paths()
-            .iter()
-            .find(|(path, _)| path.exists())
-            .map(Result::Ok)
-            .unwrap_or(Err(DotfilesReadError::NoneFound))
-            .and_then(|(path, filetype)| {
-                Ok(File::open(path).map(|file| (path.clone(), file, *filetype))?)
-            })
+    /// The format of `path`, according to its extension.
+    fn filetype_of(&self, path: &Path) -> Result<DotfileListFiletype, DotfilesReadError> {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("nix") => Ok(DotfileListFiletype::Nix),
+            Some(ext) => self
+                .list_loaders
+                .for_extension(ext)
+                .map(|loader| DotfileListFiletype::Loader(Arc::clone(loader)))
+                .ok_or_else(|| DotfilesReadError::NoFormat(path.to_path_buf())),
+            None => Err(DotfilesReadError::NoFormat(path.to_path_buf())),
+        }
     }
 
-    pub fn dotfiles(&self) -> Result<Vec<Dotfile>, DotfilesReadError> {
-        let (path, mut file, filetype) = self.dotfiles_path()?;
-        match filetype {
-            DotfileListFiletype::JSON => Ok(serde_json::from_reader::<_, SerdeDotfileList>(
-                BufReader::new(file),
-            )?
-            .dotfiles()),
-            DotfileListFiletype::YAML => Ok(serde_yaml::from_reader::<_, SerdeDotfileList>(
-                BufReader::new(file),
-            )?
-            .dotfiles()),
-            DotfileListFiletype::TOML => {
-                Ok(toml::from_str::<SerdeDotfileList>(&file_to_string(&mut file)?)?.dotfiles())
+    /// The dotfiles list files to look for, in order, and their formats.
+    fn dotfiles_paths(&self) -> Result<Vec<(PathBuf, DotfileListFiletype)>, DotfilesReadError> {
+        let format = match &self.dotfiles_format {
+            Some(name) => Some(self.filetype_named(name)?),
+            None => None,
+        };
+        if let Some(list) = &self.dotfiles_list {
+            let filetype = match format {
+                Some(filetype) => filetype,
+                None => self.filetype_of(list)?,
+            };
+            if list == Path::new("-") {
+                return match filetype {
+                    DotfileListFiletype::Nix => Err(DotfilesReadError::NixStdin),
+                    filetype => Ok(vec![(list.clone(), filetype)]),
+                };
+            }
+            return Ok(vec![(make_abs(&self.dotfile_repo, list), filetype)]);
+        }
+        let mut paths = vec![(self.dotfiles_filename("nix"), DotfileListFiletype::Nix)];
+        for loader in self.list_loaders.iter() {
+            for ext in loader.extensions() {
+                paths.push((
+                    self.dotfiles_filename(ext),
+                    DotfileListFiletype::Loader(Arc::clone(loader)),
+                ));
             }
-            DotfileListFiletype::Nix => {
-                let list: SerdeDotfileList = nix::eval_file::<Vec<SerdeDotfile>>(&path)
-                    .map_err(|err| match err {
-                        // Don't use multiple json serde error types
-                        NixEvalError::SerdeJSON(err) => DotfilesReadError::SerdeJSON(err),
-                        err => DotfilesReadError::NixEval(err),
-                    })?
-                    .into();
-                Ok(list.dotfiles())
+        }
+        if let Some(format) = format {
+            paths.retain(|(_, filetype)| filetype.name() == format.name());
+            paths.push((self.dotfile_repo.join(&self.dotfiles_basename), format));
+        }
+        Ok(paths)
+    }
+
+    /// The dotfiles list file which would be read, if there is one; it's
+    /// ignored if `dotfiles_flake` is set.
+    pub fn dotfiles_file(&self) -> Option<PathBuf> {
+        self.dotfiles_path().ok().map(|(path, _)| path)
+    }
+
+    /// The dotfiles list files which exist, in the order they're looked for.
+    fn dotfiles_candidates(
+        &self,
+    ) -> Result<Vec<(PathBuf, DotfileListFiletype)>, DotfilesReadError> {
+        Ok(self
+            .dotfiles_paths()?
+            .into_iter()
+            .filter(|(path, _)| path == Path::new("-") || path.is_file())
+            .collect())
+    }
+
+    fn dotfiles_path(&self) -> Result<(PathBuf, DotfileListFiletype), DotfilesReadError> {
+        let mut candidates = self.dotfiles_candidates()?;
+        if candidates.len() > 1 && self.ambiguous_dotfiles == AmbiguousDotfiles::Error {
+            return Err(DotfilesReadError::Ambiguous(
+                candidates.into_iter().map(|(path, _)| path).collect(),
+            ));
+        }
+        if candidates.is_empty() {
+            return Err(DotfilesReadError::NoneFound);
+        }
+        Ok(candidates.swap_remove(0))
+    }
+
+    /// A warning if more than one dotfiles list file exists and
+    /// `ambiguous_dotfiles` is `warn`, saying which one is read.
+    pub fn ambiguous_dotfiles_warning(&self) -> Option<Warning> {
+        if self.ambiguous_dotfiles != AmbiguousDotfiles::Warn || self.dotfiles_flake.is_some() {
+            return None;
+        }
+        let candidates = self.dotfiles_candidates().ok()?;
+        match candidates.as_slice() {
+            [(read, _), ignored @ ..] if !ignored.is_empty() => Some(Warning::new(
+                WarningKind::AmbiguousList,
+                format!(
+                    "Reading {} and ignoring {}; remove the files you don't use, or set `ambiguous_dotfiles` to `first` or `error`",
+                    read.display(),
+                    ignored
+                        .iter()
+                        .map(|(path, _)| path.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Read the dotfiles list. If it's written in the Nix expression language,
+    /// it may be a function which takes facts as arguments (see
+    /// `Facts::nix_args`) along with the arguments in `nix`, which take
+    /// precedence.
+    pub fn dotfiles(&self, facts: &Facts) -> Result<Vec<Dotfile>, DotfilesReadError> {
+        Ok(self.load_dotfiles(facts)?.value)
+    }
+
+    /// Resolve `d` to absolute paths. If it has a `build` installable, it's
+    /// built first, with a garbage collector root for its output in the Nix
+    /// cache directory, if there is one.
+    pub fn resolve_dotfile(&self, d: &Dotfile) -> Result<AbsDotfile, DotfilesReadError> {
+        match &d.build {
+            #[cfg(feature = "nix")]
+            Some(installable) => {
+                let installable = self.resolve_flake_ref(installable);
+                let opts = self.nix_options();
+                let out_link = opts.cache_dir.as_ref().map(|cache_dir| {
+                    cache_dir.join("gcroots").join(
+                        installable
+                            .chars()
+                            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+                            .collect::<String>(),
+                    )
+                });
+                let out =
+                    nix::build(&installable, out_link.as_deref(), &opts).map_err(nix_eval_error)?;
+                AbsDotfile::new_in(d, &out, &self.link_strategies)
             }
+            #[cfg(not(feature = "nix"))]
+            Some(_) => Err(DotfilesReadError::NoNix),
+            None => AbsDotfile::new(d, self),
+        }
+    }
+
+    /// Evaluate the dotfiles list, which must be written in the Nix
+    /// expression language, without checking that the result is a list of
+    /// dotfiles; see `check_dotfiles_list`. Cached results aren't used.
+    #[cfg(feature = "nix")]
+    pub fn eval_nix_dotfiles(
+        &self,
+        facts: &Facts,
+    ) -> Result<Evaluated<serde_json::Value>, DotfilesReadError> {
+        let opts = self.dotfiles_nix_options(facts)?;
+        if let Some(flake_ref) = &self.dotfiles_flake {
+            return Ok(nix::eval_flake(&self.resolve_flake_ref(flake_ref), &opts)?);
+        }
+        match self.dotfiles_path()? {
+            (path, DotfileListFiletype::Nix) => Ok(nix::eval_file(&path, &opts)?),
+            (path, _) => Err(DotfilesReadError::NotNix(path)),
+        }
+    }
+
+    /// Read the dotfiles list like `dotfiles`, keeping any warnings Nix
+    /// printed while evaluating it.
+    pub fn load_dotfiles(
+        &self,
+        facts: &Facts,
+    ) -> Result<Evaluated<Vec<Dotfile>>, DotfilesReadError> {
+        let no_warnings = |dotfiles| Evaluated {
+            value: dotfiles,
+            warnings: Vec::new(),
+        };
+        if let Some(flake_ref) = &self.dotfiles_flake {
+            return self.eval_dotfiles_flake(flake_ref, facts);
+        }
+        let (path, filetype) = self.dotfiles_path()?;
+        match filetype {
+            DotfileListFiletype::Loader(loader) if path == Path::new("-") => Ok(no_warnings(
+                SerdeDotfileList::from(loader.parse(&mut io::stdin().lock())?).dotfiles(),
+            )),
+            DotfileListFiletype::Loader(loader) => Ok(no_warnings(
+                SerdeDotfileList::from(
+                    loader
+                        .load(&path, facts)
+                        .map_err(|err| err.in_file(&path))?,
+                )
+                .dotfiles(),
+            )),
+            DotfileListFiletype::Nix => self.eval_dotfiles_file(&path, facts),
         }
     }
+
+    /// Evaluate the dotfiles list from the flake output `flake_ref`.
+    #[cfg(feature = "nix")]
+    fn eval_dotfiles_flake(
+        &self,
+        flake_ref: &str,
+        facts: &Facts,
+    ) -> Result<Evaluated<Vec<Dotfile>>, DotfilesReadError> {
+        Ok(nix::eval_flake::<Vec<SerdeDotfile>>(
+            &self.resolve_flake_ref(flake_ref),
+            &self.dotfiles_nix_options(facts)?,
+        )
+        .map_err(nix_eval_error)?
+        .map(|list| SerdeDotfileList::from(list).dotfiles()))
+    }
+
+    #[cfg(not(feature = "nix"))]
+    fn eval_dotfiles_flake(
+        &self,
+        _flake_ref: &str,
+        _facts: &Facts,
+    ) -> Result<Evaluated<Vec<Dotfile>>, DotfilesReadError> {
+        Err(DotfilesReadError::NoNix)
+    }
+
+    /// Evaluate the dotfiles list written in Nix at `path`, or use the
+    /// cached result if it hasn't changed.
+    #[cfg(feature = "nix")]
+    fn eval_dotfiles_file(
+        &self,
+        path: &Path,
+        facts: &Facts,
+    ) -> Result<Evaluated<Vec<Dotfile>>, DotfilesReadError> {
+        Ok(
+            nix::eval_file_cached::<Vec<SerdeDotfile>>(path, &self.dotfiles_nix_options(facts)?)
+                .map_err(nix_eval_error)?
+                .map(|list| SerdeDotfileList::from(list).dotfiles()),
+        )
+    }
+
+    #[cfg(not(feature = "nix"))]
+    fn eval_dotfiles_file(
+        &self,
+        _path: &Path,
+        _facts: &Facts,
+    ) -> Result<Evaluated<Vec<Dotfile>>, DotfilesReadError> {
+        Err(DotfilesReadError::NoNix)
+    }
 }
 
 #[cfg(test)]
@@ -266,6 +1053,9 @@ mod test {
 
     use pretty_assertions::assert_eq;
 
+    use crate::defaults::DefaultsValue;
+    use crate::prompt::PromptStyles;
+
     use super::*;
 
     #[test]
@@ -275,53 +1065,76 @@ mod test {
     }
 
     #[test]
-    fn serde_dotfile_list() {
-        let dotfiles: SerdeDotfileList = serde_json::from_str(
-            r#"
-                {
-                    "$schema": "...",
-                    "dotfiles": [
-                        "ok",
-                        {
-                            "repo": "repo-path",
-                            "installed": "installed-path"
-                        },
-                        "great"
-                    ]
-                }
-                "#,
-        )
-        .unwrap();
-
+    fn check_list() {
         assert_eq!(
-            dotfiles.dotfiles,
+            check_dotfiles_list(&serde_json::json!([
+                ".bashrc",
+                { "repo": "nvim", "installed": ".config/nvim" },
+                { "installed": ".vimrc" },
+                { "repo": "git", "template": "yes" },
+                42,
+            ])),
             vec![
-                SerdeDotfile::Path("ok".into()),
-                SerdeDotfile::Advanced(Dotfile {
-                    repo: "repo-path".into(),
-                    installed: Some("installed-path".into()),
-                }),
-                SerdeDotfile::Path("great".into()),
+                "element 2: missing field `repo`",
+                "element 3: invalid type: string \"yes\", expected a boolean",
+                "element 4: expected a path or an object, found 42",
             ]
         );
+        assert_eq!(
+            check_dotfiles_list(&serde_json::json!({ "repo": ".bashrc" })),
+            vec![r#"expected a list of dotfiles, found {"repo":".bashrc"}"#]
+        );
+    }
 
+    #[test]
+    fn test_facts_cache_file() {
+        let cache = facts_cache_file().unwrap();
+        assert!(cache.ends_with("dotfile-manager/facts.json"));
+    }
+
+    #[test]
+    fn config_builder() {
+        let cfg = Config::builder()
+            .dotfile_repo("test-data")
+            .dotfiles_basename("dotfiles-json")
+            .facts_cache_ttl(Duration::from_millis(90_500))
+            .fact("gpu", "lspci | grep -q NVIDIA && echo nvidia")
+            .var("email", "me@example.com")
+            .build()
+            .unwrap();
         assert_eq!(
-            dotfiles.dotfiles(),
-            vec![
-                Dotfile {
-                    repo: "ok".into(),
-                    installed: None
-                },
-                Dotfile {
-                    repo: "repo-path".into(),
-                    installed: Some("installed-path".into()),
-                },
-                Dotfile {
-                    repo: "great".into(),
-                    installed: None
-                },
-            ]
+            cfg,
+            Config {
+                dotfile_repo: "test-data".into(),
+                dotfiles_basename: "dotfiles-json".into(),
+                facts_cache_ttl: Duration::from_secs(90),
+                facts: vec![("gpu".into(), "lspci | grep -q NVIDIA && echo nvidia".into())]
+                    .into_iter()
+                    .collect(),
+                vars: vec![("email".into(), "me@example.com".into())]
+                    .into_iter()
+                    .collect(),
+                ..Config::try_default().unwrap()
+            }
         );
+        assert_eq!(cfg.dotfiles(&Facts::new()).unwrap().len(), 4);
+
+        assert!(matches!(
+            Config::builder().dotfiles_basename("").build(),
+            Err(ConfigReadError::EmptyBasename)
+        ));
+        assert!(Config::builder().dotfiles_format("toml").build().is_ok());
+        assert!(matches!(
+            Config::builder()
+                .dotfiles_format("toml")
+                .list_loaders(ListLoaders::empty())
+                .build(),
+            Err(ConfigReadError::UnknownFormat(format)) if format == "toml"
+        ));
+        assert!(matches!(
+            Config::builder().fact("os", "uname").var("os", "plan9").build(),
+            Err(ConfigReadError::DuplicateFact(name)) if name == "os"
+        ));
     }
 
     #[test]
@@ -333,7 +1146,72 @@ mod test {
             cfg,
             Config {
                 dotfile_repo: ".dotfiles".into(),
+                dotfile_repo_url: Some("https://github.com/me/dotfiles/archive/main.tar.gz".into()),
+                dotfile_repo_sha256: Some(
+                    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".into()
+                ),
                 dotfiles_basename: "dotfiles_list".into(),
+                dotfiles_list: None,
+                dotfiles_format: None,
+                ambiguous_dotfiles: AmbiguousDotfiles::Error,
+                dotfiles_flake: None,
+                dotfiles_attr: Some("hosts.laptop".into()),
+                facts_cache_ttl: Duration::from_secs(60),
+                facts: vec![("gpu".into(), "lspci | grep -q NVIDIA && echo nvidia".into())]
+                    .into_iter()
+                    .collect(),
+                fact_command_timeout: Duration::from_secs(2),
+                vars: vec![("email".into(), "me@example.com".into())]
+                    .into_iter()
+                    .collect(),
+                #[cfg(feature = "nix")]
+                nix: NixOptions {
+                    timeout: Some(30),
+                    include: vec!["mylib=./nix/lib".into()],
+                    ..Default::default()
+                }
+                .arg("pkgs", "import <nixpkgs> {}")
+                .argstr("profile", "work"),
+                git: GitOptions {
+                    auto_commit: true,
+                    commit_message: Some("{action} {paths}".into()),
+                },
+                apps: vec![(
+                    "helix".into(),
+                    App {
+                        name: "Helix".into(),
+                        files: vec![".config/helix".into()],
+                    },
+                )]
+                .into_iter()
+                .collect(),
+                brewfile: Some("Brewfile".into()),
+                log_file: Some(".local/state/dfm/log.jsonl".into()),
+                log_file_max_size: 4096,
+                defaults: vec![(
+                    "com.apple.dock".into(),
+                    vec![("autohide".into(), DefaultsValue::Bool(true))]
+                        .into_iter()
+                        .collect(),
+                )]
+                .into_iter()
+                .collect(),
+                vscode: VsCodeOptions {
+                    settings: Some("vscode/settings.json".into()),
+                    keybindings: None,
+                    extensions: vec!["vscodevim.vim".into()],
+                },
+                prompts: PromptOptions {
+                    destructive_default: Some(false),
+                    styles: PromptStyles {
+                        defaults: Some("white".parse().unwrap()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                plugins: true,
+                list_loaders: ListLoaders::default(),
+                link_strategies: LinkStrategies::default(),
             }
         );
 
@@ -348,12 +1226,126 @@ mod test {
             .try_into()
             .unwrap();
         assert_eq!(cfg, Config::try_default().unwrap());
+
+        let cfg_res: Result<Config, ConfigReadError> = toml::from_str::<SerdeConfig>(
+            r#"
+            facts = { theme = "echo gruvbox" }
+            vars = { theme = "solarized" }
+            "#,
+        )
+        .unwrap()
+        .try_into();
+        assert_eq!(
+            format!("{}", cfg_res.unwrap_err()),
+            "`theme` is defined in both `facts` and `vars`"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "nix")]
+    fn config_flake_ref() {
+        let cfg = test_config();
+        let repo = std::env::current_dir().unwrap().join("test-data");
+        assert_eq!(
+            cfg.resolve_flake_ref(".#dotfiles"),
+            format!("{}#dotfiles", repo.display())
+        );
+        assert_eq!(
+            cfg.resolve_flake_ref("./flake#dotfiles"),
+            format!("{}#dotfiles", repo.join("flake").display())
+        );
+        assert_eq!(
+            cfg.resolve_flake_ref("github:me/dotfiles#dotfiles"),
+            "github:me/dotfiles#dotfiles"
+        );
+    }
+
+    #[test]
+    fn config_dotfiles_format() {
+        let cfg = |list: Option<&str>, format: Option<&str>| Config {
+            dotfiles_basename: "dotfiles-noext".into(),
+            dotfiles_list: list.map(Into::into),
+            dotfiles_format: format.map(Into::into),
+            ..test_config()
+        };
+        let dotfiles = |list, format| cfg(list, format).dotfiles(&Facts::new());
+
+        assert!(matches!(
+            dotfiles(None, None),
+            Err(DotfilesReadError::NoneFound)
+        ));
+        #[cfg(feature = "yaml")]
+        assert_eq!(dotfiles(None, Some("yaml")).unwrap(), sample_dotfiles());
+        assert_eq!(
+            cfg(None, Some("toml")).dotfiles_file(),
+            Some(PathBuf::from("test-data/dotfiles-noext"))
+        );
+        assert_eq!(
+            dotfiles(Some("dotfiles-json.json"), None).unwrap(),
+            sample_dotfiles()
+        );
+        #[cfg(feature = "yaml")]
+        assert_eq!(
+            dotfiles(Some("dotfiles-noext"), Some("yaml")).unwrap(),
+            sample_dotfiles()
+        );
+        assert_eq!(
+            dotfiles(Some("dotfiles-noext"), None)
+                .unwrap_err()
+                .to_string(),
+            "can't tell which format dotfiles-noext is written in; set `dotfiles_format`"
+        );
+        assert_eq!(
+            dotfiles(None, Some("xml")).unwrap_err().to_string(),
+            "unknown dotfiles list format `xml`"
+        );
+        assert!(matches!(
+            dotfiles(Some("-"), Some("nix")),
+            Err(DotfilesReadError::NixStdin)
+        ));
+    }
+
+    #[test]
+    fn config_ambiguous_dotfiles() {
+        let cfg = |ambiguous_dotfiles| Config {
+            dotfiles_basename: "dotfiles-both".into(),
+            ambiguous_dotfiles,
+            ..test_config()
+        };
+
+        let warn = cfg(AmbiguousDotfiles::Warn);
+        assert_eq!(warn.dotfiles(&Facts::new()).unwrap(), sample_dotfiles());
+        assert_eq!(
+            warn.dotfiles_file(),
+            Some(PathBuf::from("test-data/dotfiles-both.json"))
+        );
+        assert_eq!(
+            warn.ambiguous_dotfiles_warning().unwrap().message,
+            "Reading test-data/dotfiles-both.json and ignoring test-data/dotfiles-both.toml; \
+            remove the files you don't use, or set `ambiguous_dotfiles` to `first` or `error`"
+        );
+
+        let first = cfg(AmbiguousDotfiles::First);
+        assert_eq!(first.dotfiles(&Facts::new()).unwrap(), sample_dotfiles());
+        assert_eq!(first.ambiguous_dotfiles_warning(), None);
+
+        let error = cfg(AmbiguousDotfiles::Error);
+        assert_eq!(
+            error.dotfiles(&Facts::new()).unwrap_err().to_string(),
+            "found more than one dotfiles list: test-data/dotfiles-both.json, \
+            test-data/dotfiles-both.toml; remove all but one, or set \
+            `ambiguous_dotfiles = \"first\"` to read the first"
+        );
+        assert_eq!(error.dotfiles_file(), None);
+
+        assert_eq!(test_config().ambiguous_dotfiles_warning(), None);
     }
 
     fn test_config() -> Config {
         Config {
             dotfile_repo: "test-data/".into(),
             dotfiles_basename: "dotfiles".into(),
+            ..Config::try_default().unwrap()
         }
     }
 
@@ -362,18 +1354,22 @@ mod test {
             Dotfile {
                 repo: ".bash_profile".into(),
                 installed: None,
+                ..Default::default()
             },
             Dotfile {
                 repo: ".bashrc".into(),
                 installed: None,
+                ..Default::default()
             },
             Dotfile {
                 repo: ".curlrc".into(),
                 installed: None,
+                ..Default::default()
             },
             Dotfile {
                 repo: ".config/fisher_local/fishfile".into(),
                 installed: Some(".config/fish/fishfile".into()),
+                ..Default::default()
             },
         ]
     }
@@ -385,14 +1381,32 @@ mod test {
                 dotfiles_basename: format!("dotfiles-{}", ext).into(),
                 ..test_config()
             }
-            .dotfiles()
+            .dotfiles(&Facts::new())
             .unwrap()
         };
 
         assert_eq!(cfg_dotfiles("json"), sample_dotfiles());
+        assert_eq!(cfg_dotfiles("json5"), sample_dotfiles());
+        #[cfg(feature = "yaml")]
         assert_eq!(cfg_dotfiles("yaml"), sample_dotfiles());
+        #[cfg(feature = "yaml")]
         assert_eq!(cfg_dotfiles("yml"), sample_dotfiles());
         assert_eq!(cfg_dotfiles("toml"), sample_dotfiles());
+        assert_eq!(cfg_dotfiles("kdl"), sample_dotfiles());
+        #[cfg(feature = "nix")]
         assert_eq!(cfg_dotfiles("nix"), sample_dotfiles());
     }
+
+    #[test]
+    #[cfg(not(feature = "nix"))]
+    fn config_dotfiles_no_nix() {
+        let cfg = Config {
+            dotfiles_basename: "dotfiles-nix".into(),
+            ..test_config()
+        };
+        assert!(matches!(
+            cfg.dotfiles(&Facts::new()),
+            Err(DotfilesReadError::NoNix)
+        ));
+    }
 }
@@ -0,0 +1,139 @@
+//! Scheduling dotfiles with cron; see `Dotfile::cron`.
+//!
+//! dfm owns a block of the user's crontab between `BEGIN` and `END` lines,
+//! which it replaces each time the dotfiles are linked; the rest of the
+//! crontab is left alone.
+
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use thiserror::Error;
+
+use crate::util;
+
+const BEGIN: &str = "# BEGIN dotfile-manager: changes between these lines are overwritten";
+const END: &str = "# END dotfile-manager";
+
+#[derive(Error, Debug)]
+pub enum CronError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("`crontab {args}` failed: {message}")]
+    Failed { args: String, message: String },
+}
+
+/// A crontab line running `command` on `schedule`, e.g. `0 3 * * *`.
+pub fn entry(schedule: &str, command: &Path) -> String {
+    // cron runs commands with `sh`, and `%` starts standard input.
+    let command =
+        format!("'{}'", command.to_string_lossy().replace('\'', r"'\''")).replace('%', r"\%");
+    format!("{} {}", schedule.trim(), command)
+}
+
+/// `crontab` with dfm's block replaced by `entries`, or removed if there
+/// are none.
+pub fn with_block(crontab: &str, entries: &[String]) -> String {
+    util::with_block(crontab, BEGIN, END, entries)
+}
+
+/// The user's crontab; empty if they don't have one.
+pub fn read() -> Result<String, CronError> {
+    let output = Command::new("crontab")
+        .arg("-l")
+        .stdin(Stdio::null())
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else if stderr.contains("no crontab") {
+        Ok(String::new())
+    } else {
+        Err(CronError::Failed {
+            args: "-l".into(),
+            message: stderr.trim().to_string(),
+        })
+    }
+}
+
+/// Replace the user's crontab with `crontab`.
+pub fn write(crontab: &str) -> Result<(), CronError> {
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("crontab's input is piped")
+        .write_all(crontab.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(CronError::Failed {
+            args: "-".into(),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn cron_with_block() {
+        let entries = vec![
+            entry("0 3 * * *", Path::new("/home/me/bin/backup")),
+            entry(" @daily ", Path::new("/home/me/bin/it's 100%")),
+        ];
+        assert_eq!(
+            entries,
+            vec![
+                "0 3 * * * '/home/me/bin/backup'",
+                r"@daily '/home/me/bin/it'\''s 100\%'",
+            ]
+        );
+
+        let crontab = "MAILTO=me@example.com\n*/5 * * * * fetchmail\n";
+        let updated = with_block(crontab, &entries);
+        assert_eq!(
+            updated,
+            [
+                "MAILTO=me@example.com",
+                "*/5 * * * * fetchmail",
+                BEGIN,
+                "0 3 * * * '/home/me/bin/backup'",
+                r"@daily '/home/me/bin/it'\''s 100\%'",
+                END,
+                "",
+            ]
+            .join("\n")
+        );
+        assert_eq!(with_block(&updated, &entries), updated);
+        assert_eq!(with_block(&updated, &[]), crontab);
+        assert_eq!(with_block("", &[]), "");
+
+        // Without an `END`, nothing after `BEGIN` is removed.
+        let truncated = [BEGIN, "0 4 * * * mine", ""].join("\n");
+        let updated = with_block(&truncated, &entries[..1]);
+        assert_eq!(
+            updated,
+            [
+                BEGIN,
+                "0 4 * * * mine",
+                BEGIN,
+                "0 3 * * * '/home/me/bin/backup'",
+                END,
+                "",
+            ]
+            .join("\n")
+        );
+        assert_eq!(with_block(&updated, &entries[..1]), updated);
+    }
+}
@@ -0,0 +1,179 @@
+//! macOS preferences, set with the `defaults` command; the `[defaults]`
+//! table in the config file, e.g.
+//!
+//! ```toml
+//! [defaults."com.apple.dock"]
+//! autohide = true
+//! tilesize = 36
+//! ```
+//!
+//! Only booleans, integers, floats and strings are supported.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Preferences to set: domains, e.g. `com.apple.dock`, to keys to values.
+pub type Defaults = BTreeMap<String, BTreeMap<String, DefaultsValue>>;
+
+#[derive(Error, Debug)]
+pub enum DefaultsError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("`defaults {args}` failed: {message}")]
+    Failed { args: String, message: String },
+}
+
+/// A preference's value.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum DefaultsValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl fmt::Display for DefaultsValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DefaultsValue::Bool(b) => write!(f, "{}", b),
+            DefaultsValue::Int(i) => write!(f, "{}", i),
+            DefaultsValue::Float(x) => write!(f, "{}", x),
+            DefaultsValue::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl DefaultsValue {
+    /// The type flag and value for `defaults write`, e.g. `-bool true`.
+    fn write_args(&self) -> [String; 2] {
+        let flag = match self {
+            DefaultsValue::Bool(_) => "-bool",
+            DefaultsValue::Int(_) => "-int",
+            DefaultsValue::Float(_) => "-float",
+            DefaultsValue::String(_) => "-string",
+        };
+        [flag.to_string(), self.to_string()]
+    }
+
+    /// Whether `read`, the output of `defaults read`, is this value.
+    /// Booleans are read as `1` or `0`.
+    fn matches(&self, read: &str) -> bool {
+        match self {
+            DefaultsValue::Bool(b) => read == if *b { "1" } else { "0" },
+            DefaultsValue::Int(i) => read.parse() == Ok(*i),
+            DefaultsValue::Float(x) => read.parse() == Ok(*x),
+            DefaultsValue::String(s) => read == s,
+        }
+    }
+}
+
+/// A preference which isn't set to its declared value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub domain: String,
+    pub key: String,
+    pub declared: DefaultsValue,
+    /// The current value, as `defaults read` prints it, or `None` if it's
+    /// unset.
+    pub current: Option<String>,
+}
+
+fn defaults(args: &[&str]) -> Result<Option<String>, DefaultsError> {
+    let output = Command::new("defaults")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if output.status.success() {
+        Ok(Some(
+            String::from_utf8_lossy(&output.stdout)
+                .trim_end()
+                .to_string(),
+        ))
+    } else if args[0] == "read" {
+        // The key doesn't exist.
+        Ok(None)
+    } else {
+        Err(DefaultsError::Failed {
+            args: args.join(" "),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
+    }
+}
+
+/// The preferences in `declared` which aren't set to their declared values.
+pub fn diff(declared: &Defaults) -> Result<Vec<Difference>, DefaultsError> {
+    let mut differences = Vec::new();
+    for (domain, values) in declared {
+        for (key, value) in values {
+            let current = defaults(&["read", domain, key])?;
+            if current
+                .as_deref()
+                .is_some_and(|current| value.matches(current))
+            {
+                continue;
+            }
+            differences.push(Difference {
+                domain: domain.clone(),
+                key: key.clone(),
+                declared: value.clone(),
+                current,
+            });
+        }
+    }
+    Ok(differences)
+}
+
+/// Set a preference to its declared value.
+pub fn write(difference: &Difference) -> Result<(), DefaultsError> {
+    let [flag, value] = difference.declared.write_args();
+    defaults(&["write", &difference.domain, &difference.key, &flag, &value])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn defaults_values() {
+        let declared: Defaults = toml::from_str(indoc!(
+            r#"
+            ["com.apple.dock"]
+            autohide = true
+            tilesize = 36
+            magnification-scale = 1.5
+            orientation = "left"
+            "#
+        ))
+        .unwrap();
+        let dock = &declared["com.apple.dock"];
+        assert_eq!(dock["autohide"], DefaultsValue::Bool(true));
+        assert_eq!(dock["tilesize"], DefaultsValue::Int(36));
+        assert_eq!(dock["magnification-scale"], DefaultsValue::Float(1.5));
+        assert_eq!(dock["orientation"], DefaultsValue::String("left".into()));
+
+        assert!(dock["autohide"].matches("1"));
+        assert!(!dock["autohide"].matches("0"));
+        assert!(dock["tilesize"].matches("36"));
+        assert!(dock["magnification-scale"].matches("1.5"));
+        assert!(!dock["orientation"].matches("bottom"));
+        assert_eq!(
+            dock["autohide"].write_args(),
+            ["-bool".to_string(), "true".to_string()]
+        );
+        assert_eq!(
+            dock["orientation"].write_args(),
+            ["-string".to_string(), "left".to_string()]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Installing dotfiles on another machine over SSH.
+//!
+//! The dotfile repository is copied to the same path relative to the
+//! remote home directory with `tar` piped through `ssh`, then either a
+//! shell script linking the dotfiles, generated here, or a `dfm` installed
+//! on the remote machine is run there.
+
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+
+use thiserror::Error;
+
+use crate::dotfile::{AbsDotfile, Dotfile};
+use crate::facts::Facts;
+use crate::template;
+use crate::template::TemplateError;
+
+#[derive(Error, Debug)]
+pub enum DeployError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("{0}")]
+    Template(#[from] TemplateError),
+
+    #[error("`{command}` failed: {status}")]
+    Failed { command: String, status: ExitStatus },
+
+    #[error("{0}: a generated script would decide `when` conditions and render templates with this machine's facts; give the remote machine's with `--fact`, or use `--remote-dfm`")]
+    LocalFacts(String),
+}
+
+/// Functions the script generated by `link_script` uses.
+const PRELUDE: &str = r#"#!/bin/sh
+# Generated by dotfile-manager.
+set -eu
+
+# Link $2 to $1, moving anything but a link at $2 out of the way, without
+# replacing earlier backups.
+link() {
+    mkdir -p "$(dirname "$2")"
+    if [ -e "$2" ] && [ ! -L "$2" ]; then
+        backup="$2.dfm-backup"
+        n=1
+        while [ -e "$backup" ] || [ -L "$backup" ]; do
+            backup="$2.dfm-backup.$n"
+            n=$((n + 1))
+        done
+        mv "$2" "$backup"
+        echo "Moved $2 to $backup"
+    fi
+    ln -sfn "$1" "$2"
+}
+
+# Write standard input to $1.
+render() {
+    mkdir -p "$(dirname "$1")"
+    if [ -L "$1" ]; then
+        rm "$1"
+    fi
+    cat > "$1"
+}
+
+"#;
+
+/// A POSIX shell script which installs `dotfiles` on a machine whose home
+/// directory holds the dotfile repository at the same path relative to it
+/// as locally. Templates are rendered here, with `facts`.
+pub fn link_script(
+    dotfiles: &[AbsDotfile],
+    home: &Path,
+    facts: &Facts,
+) -> Result<String, TemplateError> {
+    let mut script = String::from(PRELUDE);
+    for d in dotfiles {
+        let installed = home_path(&d.installed, home);
+        if d.template {
+            let content = template::render(&fs::read_to_string(&d.repo)?, facts)?;
+            writeln!(
+                script,
+                "printf '%s' {} | render {}",
+                sh_quote(&content),
+                installed
+            )
+            .unwrap();
+        } else {
+            writeln!(script, "link {} {}", home_path(&d.repo, home), installed).unwrap();
+        }
+    }
+    Ok(script)
+}
+
+/// Check that `link_script` can install `dotfiles` without knowing the
+/// remote machine's facts: that none of them has a `when` condition or is a
+/// template, unless `facts_given` says the remote machine's facts were given
+/// with `--fact`.
+pub fn check_facts(dotfiles: &[Dotfile], facts_given: bool) -> Result<(), DeployError> {
+    let depends: Vec<_> = dotfiles
+        .iter()
+        .filter(|d| d.when.is_some() || d.template)
+        .map(|d| d.repo.display().to_string())
+        .collect();
+    if facts_given || depends.is_empty() {
+        Ok(())
+    } else {
+        Err(DeployError::LocalFacts(depends.join(", ")))
+    }
+}
+
+/// `path` as a shell word, relative to `$HOME` if it's in `home`.
+fn home_path(path: &Path, home: &Path) -> String {
+    match path.strip_prefix(home) {
+        Ok(rel) => format!("\"$HOME\"/{}", sh_quote(&rel.to_string_lossy())),
+        Err(_) => sh_quote(&path.to_string_lossy()),
+    }
+}
+
+/// `s` single-quoted for a POSIX shell.
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// The path to copy `dotfile_repo` to on the remote machine: relative to the
+/// remote home directory if it's in `home`.
+pub fn remote_repo(dotfile_repo: &Path, home: &Path) -> PathBuf {
+    dotfile_repo
+        .strip_prefix(home)
+        .unwrap_or(dotfile_repo)
+        .to_path_buf()
+}
+
+/// Copy the contents of `dotfile_repo`, except for `.git`, into `dest` on
+/// `host`, e.g. `me@server`.
+pub fn copy_repo(dotfile_repo: &Path, host: &str, dest: &Path) -> Result<(), DeployError> {
+    let mut tar = Command::new("tar")
+        .arg("-C")
+        .arg(dotfile_repo)
+        .args(["--exclude=./.git", "-cf", "-", "."])
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let dest = sh_quote(&dest.to_string_lossy());
+    let remote = format!("mkdir -p {} && tar -C {} -xf -", dest, dest);
+    let ssh = Command::new("ssh")
+        .args(["--", host, &remote])
+        .stdin(tar.stdout.take().expect("tar's output is piped"))
+        .status()?;
+    let tar = tar.wait()?;
+    check("tar", tar)?;
+    check(&format!("ssh -- {} {}", host, remote), ssh)
+}
+
+/// Run `script` with `sh` on `host`.
+pub fn run_script(host: &str, script: &str) -> Result<(), DeployError> {
+    let mut ssh = Command::new("ssh")
+        .args(["--", host, "sh -s"])
+        .stdin(Stdio::piped())
+        .spawn()?;
+    io::Write::write_all(
+        &mut ssh.stdin.take().expect("ssh's input is piped"),
+        script.as_bytes(),
+    )?;
+    let status = ssh.wait()?;
+    check(&format!("ssh -- {} sh -s", host), status)
+}
+
+/// Run `command` on `host` with a terminal, so it can prompt.
+pub fn run_command(host: &str, command: &str) -> Result<(), DeployError> {
+    let status = Command::new("ssh")
+        .args(["-t", "--", host, command])
+        .status()?;
+    check(&format!("ssh -t -- {} {}", host, command), status)
+}
+
+fn check(command: &str, status: ExitStatus) -> Result<(), DeployError> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(DeployError::Failed {
+            command: command.into(),
+            status,
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::env;
+
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn deploy_link_script() {
+        let cwd = env::current_dir().unwrap();
+        let dotfile = |repo: PathBuf, installed: PathBuf| AbsDotfile {
+            repo,
+            installed,
+            ..Default::default()
+        };
+        let facts = Facts::new().with_vars(
+            &vec![("email".to_string(), "me@example.com".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let home = root.join("home");
+        let repo = home.join(".dotfiles");
+        fs::create_dir_all(&repo).unwrap();
+        fs::write(repo.join("bashrc"), "bashrc").unwrap();
+        fs::write(repo.join("it's"), "").unwrap();
+        fs::write(home.join(".bashrc"), "old").unwrap();
+        fs::write(home.join(".bashrc.dfm-backup"), "older").unwrap();
+        let template = cwd.join("test-data/template.txt");
+        let dotfiles_in = |home: &Path| {
+            vec![
+                dotfile(home.join(".dotfiles/bashrc"), home.join(".bashrc")),
+                dotfile(home.join(".dotfiles/it's"), home.join(".config/it's")),
+                dotfile("/nix/store/abc-nvim".into(), home.join(".config/nvim")),
+                AbsDotfile {
+                    template: true,
+                    ..dotfile(template.clone(), home.join(".gitconfig"))
+                },
+            ]
+        };
+
+        let script = link_script(
+            &dotfiles_in(Path::new("/home/me")),
+            Path::new("/home/me"),
+            &facts,
+        )
+        .unwrap();
+        assert!(script.starts_with(PRELUDE));
+        assert_eq!(
+            &script[PRELUDE.len()..],
+            indoc!(
+                r#"
+                link "$HOME"/'.dotfiles/bashrc' "$HOME"/'.bashrc'
+                link "$HOME"/'.dotfiles/it'\''s' "$HOME"/'.config/it'\''s'
+                link '/nix/store/abc-nvim' "$HOME"/'.config/nvim'
+                printf '%s' '[user]
+                  email = me@example.com
+                ' | render "$HOME"/'.gitconfig'
+                "#
+            )
+            .trim_start()
+        );
+
+        // Run the script as it would be on the remote machine, without
+        // the link out of the home directory.
+        let dotfiles = dotfiles_in(&home)
+            .into_iter()
+            .filter(|d| d.repo.starts_with(&home) || d.template)
+            .collect::<Vec<_>>();
+        let script = link_script(&dotfiles, &home, &facts).unwrap();
+        let status = Command::new("sh")
+            .args(["-c", &script])
+            .env("HOME", &home)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        let bashrc = fs::read_to_string(home.join(".bashrc"));
+        let older = fs::read_to_string(home.join(".bashrc.dfm-backup"));
+        let backup = fs::read_to_string(home.join(".bashrc.dfm-backup.1"));
+        let gitconfig = fs::read_to_string(home.join(".gitconfig"));
+        let link = fs::read_link(home.join(".config/it's"));
+
+        assert!(status.success());
+        assert_eq!(bashrc.unwrap(), "bashrc");
+        assert_eq!(older.unwrap(), "older");
+        assert_eq!(backup.unwrap(), "old");
+        assert_eq!(gitconfig.unwrap(), "[user]\n  email = me@example.com\n");
+        assert_eq!(link.unwrap(), repo.join("it's"));
+        assert_eq!(
+            remote_repo(Path::new("/home/me/.dotfiles"), Path::new("/home/me")),
+            PathBuf::from(".dotfiles")
+        );
+    }
+
+    #[test]
+    fn deploy_check_facts() {
+        let dotfile = |repo: &str| Dotfile {
+            repo: repo.into(),
+            ..Default::default()
+        };
+        let dotfiles = vec![
+            dotfile("bashrc"),
+            Dotfile {
+                when: Some("os == 'linux'".into()),
+                ..dotfile("i3")
+            },
+            Dotfile {
+                template: true,
+                ..dotfile("gitconfig")
+            },
+        ];
+        assert!(check_facts(&dotfiles[..1], false).is_ok());
+        assert!(check_facts(&dotfiles, true).is_ok());
+        assert_eq!(
+            check_facts(&dotfiles, false).unwrap_err().to_string(),
+            "i3, gitconfig: a generated script would decide `when` conditions and render \
+             templates with this machine's facts; give the remote machine's with `--fact`, \
+             or use `--remote-dfm`"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Errors explained for people, like rustc's diagnostics: what went wrong,
+//! the file involved with the offending line, what caused it, and a hint
+//! about what to do, e.g.
+//!
+//! ```text
+//! error: failed to parse config file as TOML / incorrect schema
+//!   --> /home/me/.config/dotfile-manager/dotfile-manager.toml:1:1
+//!    |
+//!  1 | dotfiles_repo = "~/.dotfiles"
+//!    | ^
+//! caused by: unknown field `dotfiles_repo`, expected one of `dotfile_repo`, ...
+//! ```
+//!
+//! Errors from `Error` also show their code, e.g. `error[config]:`.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use console::style;
+
+/// An error, ready to print; see `Diagnose`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Diagnostic {
+    /// The error's code, e.g. `nix`; see `Error::code`.
+    pub code: Option<String>,
+    pub message: String,
+    /// The file the error is in.
+    pub file: Option<PathBuf>,
+    /// The 1-based line and column in `file` the error is at. A column of 0
+    /// means only the line is known.
+    pub position: Option<(usize, usize)>,
+    /// The messages of the errors which caused this one, outermost first.
+    pub causes: Vec<String>,
+    /// What to do about the error.
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A diagnostic for `err`, with the errors which caused it; messages
+    /// which only repeat the one before are skipped.
+    pub fn from_error(err: &(dyn Error + 'static)) -> Self {
+        let mut diagnostic = Self::new(err.to_string());
+        let mut source = err.source();
+        while let Some(cause) = source {
+            diagnostic.push_cause(cause.to_string());
+            source = cause.source();
+        }
+        diagnostic
+    }
+
+    fn push_cause(&mut self, cause: String) {
+        let last = self.causes.last().unwrap_or(&self.message);
+        if !last.contains(&cause) {
+            self.causes.push(cause);
+        }
+    }
+
+    pub fn code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    pub fn in_file(mut self, file: &Path) -> Self {
+        self.file = Some(file.to_path_buf());
+        self
+    }
+
+    pub fn at(mut self, line: usize, column: usize) -> Self {
+        self.position = Some((line, column));
+        self
+    }
+
+    pub fn help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// The diagnostic as lines of text, colored if `color` is true.
+    pub fn render(&self, color: bool) -> String {
+        let heading = match &self.code {
+            Some(code) => format!("error[{}]:", code),
+            None => "error:".to_string(),
+        };
+        let mut rendered = format!(
+            "{} {}",
+            style(heading).red().bold().force_styling(color),
+            self.message
+        );
+        let arrow = style("-->").blue().force_styling(color);
+        match (&self.file, self.position) {
+            (Some(file), Some((line, column))) => {
+                let location = match column {
+                    0 => format!("{}:{}", file.display(), line),
+                    column => format!("{}:{}:{}", file.display(), line, column),
+                };
+                rendered.push_str(&format!("\n  {} {}", arrow, location));
+                let source = fs::read_to_string(file).ok().and_then(|source| {
+                    source.lines().nth(line.saturating_sub(1)).map(String::from)
+                });
+                if let Some(source) = source {
+                    let number = line.to_string();
+                    let gutter = " ".repeat(number.len());
+                    let bar = style("|").blue().force_styling(color);
+                    rendered.push_str(&format!(
+                        "\n {} {}\n {} {} {}",
+                        gutter,
+                        bar,
+                        style(number).blue().force_styling(color),
+                        bar,
+                        source
+                    ));
+                    if column > 0 {
+                        rendered.push_str(&format!(
+                            "\n {} {} {}{}",
+                            gutter,
+                            bar,
+                            " ".repeat(column - 1),
+                            style("^").red().bold().force_styling(color)
+                        ));
+                    }
+                }
+            }
+            (Some(file), None) => rendered.push_str(&format!("\n  {} {}", arrow, file.display())),
+            (None, _) => {}
+        }
+        for cause in &self.causes {
+            rendered.push_str(&format!("\ncaused by: {}", cause));
+        }
+        if let Some(help) = &self.help {
+            rendered.push_str(&format!(
+                "\n{} {}",
+                style("help:").cyan().bold().force_styling(color),
+                help
+            ));
+        }
+        rendered
+    }
+}
+
+/// Errors which can explain themselves as a `Diagnostic`. By default,
+/// that's the error's message and its causes.
+pub trait Diagnose: Error + Sized + 'static {
+    fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::from_error(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use thiserror::Error;
+
+    use super::*;
+
+    #[derive(Error, Debug)]
+    enum TestError {
+        #[error("{0}")]
+        Io(#[from] std::io::Error),
+
+        #[error("couldn't read the list")]
+        List(#[source] std::io::Error),
+    }
+
+    impl Diagnose for TestError {}
+
+    #[test]
+    fn diagnostic_render() {
+        let not_found = || std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        assert_eq!(
+            TestError::Io(not_found()).diagnostic().render(false),
+            "error: no such file"
+        );
+        assert_eq!(
+            TestError::List(not_found())
+                .diagnostic()
+                .in_file(Path::new("test-data/string-function.nix"))
+                .at(1, 3)
+                .help("create it")
+                .render(false),
+            indoc!(
+                r#"
+                error: couldn't read the list
+                  --> test-data/string-function.nix:1:3
+                   |
+                 1 | { name ? "default", ... }: [ "foo" name ]
+                   |   ^
+                caused by: no such file
+                help: create it"#
+            )
+            .trim_start()
+        );
+        assert_eq!(
+            Diagnostic::new("oops")
+                .in_file(Path::new("test-data/string-function.nix"))
+                .at(1, 0)
+                .render(false),
+            indoc!(
+                r#"
+                error: oops
+                  --> test-data/string-function.nix:1
+                   |
+                 1 | { name ? "default", ... }: [ "foo" name ]"#
+            )
+            .trim_start()
+        );
+        assert_eq!(
+            Diagnostic::new("oops")
+                .in_file(Path::new("missing.toml"))
+                .render(false),
+            "error: oops\n  --> missing.toml"
+        );
+    }
+}
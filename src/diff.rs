@@ -0,0 +1,319 @@
+//! Rendering the differences between two versions of a file, for `dfm
+//! diff` and the prompt to overwrite an installed file.
+//!
+//! Diffs are computed line by line and shown either in the unified format,
+//! with 3 lines of context around each change, or side by side, fitted to
+//! the terminal's width.
+
+use std::env;
+use std::ops::Range;
+use std::str::FromStr;
+
+use console::{style, Term};
+use difference::{Changeset, Difference};
+
+use crate::table;
+
+/// Unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+/// When to color output; the `--color` option.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Always,
+    Never,
+    /// Color output to a terminal, unless `NO_COLOR` is set.
+    Auto,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            "auto" => Ok(ColorChoice::Auto),
+            _ => Err(format!(
+                "expected `always`, `never` or `auto` but found `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl ColorChoice {
+    /// Whether output should be colored.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => env::var_os("NO_COLOR").is_none() && console::colors_enabled(),
+        }
+    }
+}
+
+/// The terminal's width in columns, or 80 if standard output isn't a
+/// terminal.
+pub fn terminal_width() -> usize {
+    Term::stdout()
+        .size_checked()
+        .map_or(80, |(_rows, columns)| columns.into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tag {
+    Same,
+    Removed,
+    Added,
+}
+
+/// The lines of `old` and `new`, tagged with whether they were removed,
+/// added, or are in both.
+fn lines(old: &str, new: &str) -> Vec<(Tag, String)> {
+    // Each file's last newline would otherwise be an empty last line.
+    let trim = |text: &'_ str| text.strip_suffix('\n').unwrap_or(text).to_string();
+    Changeset::new(&trim(old), &trim(new), "\n")
+        .diffs
+        .into_iter()
+        .flat_map(|diff| {
+            let (tag, chunk) = match diff {
+                Difference::Same(chunk) => (Tag::Same, chunk),
+                Difference::Rem(chunk) => (Tag::Removed, chunk),
+                Difference::Add(chunk) => (Tag::Added, chunk),
+            };
+            chunk
+                .split('\n')
+                .map(|line| (tag, line.to_string()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The ranges of `lines` to show: each change with `CONTEXT` lines around
+/// it, merged where they overlap.
+fn hunks(lines: &[(Tag, String)]) -> Vec<Range<usize>> {
+    let mut hunks: Vec<Range<usize>> = Vec::new();
+    for (i, (tag, _)) in lines.iter().enumerate() {
+        if *tag == Tag::Same {
+            continue;
+        }
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + 1 + CONTEXT).min(lines.len());
+        match hunks.last_mut() {
+            Some(last) if last.end >= start => last.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+    hunks
+}
+
+/// A hunk's header, e.g. `@@ -10,7 +10,8 @@`.
+fn header(lines: &[(Tag, String)], hunk: &Range<usize>) -> String {
+    // The first line number and number of lines on one side.
+    let span = |excluded: Tag| {
+        let count = |range: Range<usize>| {
+            lines[range]
+                .iter()
+                .filter(|(tag, _)| *tag != excluded)
+                .count()
+        };
+        let len = count(hunk.clone());
+        let start = count(0..hunk.start) + if len == 0 { 0 } else { 1 };
+        format!("{},{}", start, len)
+    };
+    format!("@@ -{} +{} @@", span(Tag::Added), span(Tag::Removed))
+}
+
+/// A unified diff from `old` to `new`, colored if `color` is true; empty
+/// if they're the same.
+pub fn unified(old: &str, new: &str, color: bool) -> String {
+    let lines = lines(old, new);
+    let mut diff = String::new();
+    for hunk in hunks(&lines) {
+        diff.push_str(&format!(
+            "{}\n",
+            style(header(&lines, &hunk)).cyan().force_styling(color)
+        ));
+        for (tag, line) in &lines[hunk] {
+            let line = match tag {
+                Tag::Same => style(format!(" {}", line)),
+                Tag::Removed => style(format!("-{}", line)).red(),
+                Tag::Added => style(format!("+{}", line)).green(),
+            };
+            diff.push_str(&format!("{}\n", line.force_styling(color)));
+        }
+    }
+    diff
+}
+
+/// One row of a side-by-side diff.
+fn row(
+    left: Option<&str>,
+    marker: char,
+    right: Option<&str>,
+    column: usize,
+    color: bool,
+) -> String {
+    let expand = |line: Option<&str>| line.unwrap_or_default().replace('\t', "    ");
+    let left_text = table::fit(&expand(left), column);
+    let right_text = table::fit(&expand(right), column);
+    let (left_style, right_style) = match marker {
+        ' ' => (style(left_text), style(right_text)),
+        _ => (style(left_text).red(), style(right_text).green()),
+    };
+    let row = format!(
+        "{} {} {}",
+        left_style.force_styling(color),
+        marker,
+        right_style.force_styling(color)
+    );
+    format!("{}\n", row.trim_end())
+}
+
+/// A diff from `old` to `new` with the two in columns side by side,
+/// `width` columns wide in all, colored if `color` is true; empty if
+/// they're the same.
+///
+/// Changed lines are marked with `|` between the columns, removed lines
+/// with `<` and added lines with `>`. Lines too long for their column are
+/// cut off.
+pub fn side_by_side(old: &str, new: &str, width: usize, color: bool) -> String {
+    let column = (width.saturating_sub(3) / 2).max(1);
+    let lines = lines(old, new);
+    let mut diff = String::new();
+    for hunk in hunks(&lines) {
+        diff.push_str(&format!(
+            "{}\n",
+            style(header(&lines, &hunk)).cyan().force_styling(color)
+        ));
+        let mut hunk = lines[hunk].iter().peekable();
+        while let Some((tag, line)) = hunk.next() {
+            if *tag == Tag::Same {
+                diff.push_str(&row(Some(line), ' ', Some(line), column, color));
+                continue;
+            }
+            // Pair a run of removed lines with the run of added lines after
+            // it.
+            let (mut removed, mut added) = match tag {
+                Tag::Removed => (vec![line], Vec::new()),
+                _ => (Vec::new(), vec![line]),
+            };
+            while let Some((tag, line)) = hunk.next_if(|(tag, _)| {
+                *tag == Tag::Added || (*tag == Tag::Removed && added.is_empty())
+            }) {
+                match tag {
+                    Tag::Removed => removed.push(line),
+                    _ => added.push(line),
+                }
+            }
+            for i in 0..removed.len().max(added.len()) {
+                let left = removed.get(i).map(|line| line.as_str());
+                let right = added.get(i).map(|line| line.as_str());
+                let marker = match (left, right) {
+                    (Some(_), Some(_)) => '|',
+                    (Some(_), None) => '<',
+                    _ => '>',
+                };
+                diff.push_str(&row(left, marker, right, column, color));
+            }
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const OLD: &str = indoc!(
+        "
+        [user]
+        name = Me
+        email = me@example.com
+        [core]
+        editor = vim
+        pager = less
+        autocrlf = false
+        whitespace = trailing-space
+        "
+    );
+
+    const NEW: &str = indoc!(
+        "
+        [user]
+        name = Me
+        email = me@example.com
+        [core]
+        editor = nvim
+        pager = less
+        autocrlf = false
+        whitespace = trailing-space
+        [init]
+        defaultBranch = main
+        "
+    );
+
+    #[test]
+    fn diff_unified() {
+        assert_eq!(unified(OLD, OLD, false), "");
+        assert_eq!(
+            unified(OLD, NEW, false),
+            indoc!(
+                "
+                @@ -2,7 +2,9 @@
+                 name = Me
+                 email = me@example.com
+                 [core]
+                -editor = vim
+                +editor = nvim
+                 pager = less
+                 autocrlf = false
+                 whitespace = trailing-space
+                +[init]
+                +defaultBranch = main
+                "
+            )
+        );
+        assert_eq!(unified("", "a\n", false), "@@ -0,0 +1,1 @@\n+a\n");
+        assert_eq!(
+            unified("a\n", "b\n", true),
+            "\u{1b}[36m@@ -1,1 +1,1 @@\u{1b}[0m\n\u{1b}[31m-a\u{1b}[0m\n\u{1b}[32m+b\u{1b}[0m\n"
+        );
+    }
+
+    #[test]
+    fn diff_side_by_side() {
+        assert_eq!(
+            side_by_side(OLD, NEW, 51, false),
+            [
+                "@@ -2,7 +2,9 @@",
+                "name = Me                  name = Me",
+                "email = me@example.com     email = me@example.com",
+                "[core]                     [core]",
+                "editor = vim             | editor = nvim",
+                "pager = less               pager = less",
+                "autocrlf = false           autocrlf = false",
+                "whitespace = trailing-s…   whitespace = trailing-s…",
+                "                         > [init]",
+                "                         > defaultBranch = main",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn diff_color_choice() {
+        assert_eq!("never".parse(), Ok(ColorChoice::Never));
+        assert_eq!(
+            "sometimes".parse::<ColorChoice>(),
+            Err("expected `always`, `never` or `auto` but found `sometimes`".to_string())
+        );
+        assert!(ColorChoice::Always.enabled());
+        assert!(!ColorChoice::Never.enabled());
+    }
+}
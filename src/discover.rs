@@ -0,0 +1,128 @@
+//! Finding well-known application configuration files in the home directory
+//! which aren't managed yet, like [Mackup](https://github.com/lra/mackup)'s
+//! application database.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// The bundled application database; see `apps`.
+const APPS: &str = include_str!("apps.toml");
+
+/// An application whose configuration files `discover` looks for.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct App {
+    /// The application's name, e.g. `Neovim`.
+    pub name: String,
+    /// Its configuration files or directories, relative to the home
+    /// directory.
+    pub files: Vec<PathBuf>,
+}
+
+/// An application's configuration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Found {
+    /// The application's name.
+    pub app: String,
+    /// The file, relative to the home directory.
+    pub path: PathBuf,
+}
+
+/// The bundled application database, keyed by an identifier like `neovim`,
+/// with `extra` added; applications in `extra` replace bundled ones with the
+/// same identifier.
+pub fn apps(extra: &BTreeMap<String, App>) -> BTreeMap<String, App> {
+    let mut apps: BTreeMap<String, App> =
+        toml::from_str(APPS).expect("the bundled application database is valid");
+    apps.extend(extra.clone());
+    apps
+}
+
+/// The configuration files of `apps` which exist in `home` but aren't
+/// `managed`, i.e. aren't one of (or in one of) the installed paths of the
+/// dotfiles, and aren't links into `dotfile_repo`.
+pub fn discover(
+    apps: &BTreeMap<String, App>,
+    home: &Path,
+    dotfile_repo: &Path,
+    managed: &[PathBuf],
+) -> Vec<Found> {
+    let mut found = Vec::new();
+    for app in apps.values() {
+        for file in &app.files {
+            let path = home.join(file);
+            if fs::symlink_metadata(&path).is_err()
+                || managed.iter().any(|installed| path.starts_with(installed))
+                || fs::read_link(&path)
+                    .is_ok_and(|target| link_target(&path, &target).starts_with(dotfile_repo))
+            {
+                continue;
+            }
+            found.push(Found {
+                app: app.name.clone(),
+                path: file.clone(),
+            });
+        }
+    }
+    found
+}
+
+/// A symlink's `target`, relative to the directory containing `link`.
+fn link_target(link: &Path, target: &Path) -> PathBuf {
+    link.parent().unwrap_or(link).join(target)
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn discover_apps() {
+        let extra = vec![(
+            "vim".to_string(),
+            App {
+                name: "My Vim".into(),
+                files: vec![".vimrc".into()],
+            },
+        )]
+        .into_iter()
+        .collect();
+        let apps = apps(&extra);
+        assert_eq!(apps["vim"], extra["vim"]);
+        assert_eq!(apps["zsh"].name, "Zsh");
+
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let home = root.join("home");
+        let dotfile_repo = home.join(".dotfiles");
+        fs::create_dir_all(home.join(".config/nvim")).unwrap();
+        fs::create_dir_all(&dotfile_repo).unwrap();
+        for file in &[".vimrc", ".zshrc", ".gitconfig", ".config/nvim/init.lua"] {
+            fs::write(home.join(file), "").unwrap();
+        }
+        fs::write(dotfile_repo.join("bashrc"), "").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(".dotfiles/bashrc", home.join(".bashrc")).unwrap();
+
+        let found = discover(
+            &apps,
+            &home,
+            &dotfile_repo,
+            &[home.join(".gitconfig"), home.join(".config")],
+        );
+        let found_file = |app: &str, path: &str| Found {
+            app: app.into(),
+            path: path.into(),
+        };
+        assert_eq!(
+            found,
+            vec![found_file("My Vim", ".vimrc"), found_file("Zsh", ".zshrc")]
+        );
+    }
+}
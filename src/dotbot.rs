@@ -0,0 +1,447 @@
+//! Interoperation with [dotbot](https://github.com/anishathalye/dotbot).
+//!
+//! dotbot's `install.conf.yaml` is a list of directives; only `link`
+//! directives (and `defaults` for them) are imported or exported. A link's `if` shell
+//! command becomes a user-defined fact which the dotfile's `when` condition
+//! checks. Other options which dfm has no equivalent for are reported, not
+//! converted. Importing needs the `yaml` feature.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+#[cfg(feature = "yaml")]
+use std::path::PathBuf;
+
+#[cfg(feature = "yaml")]
+use serde::Deserialize;
+#[cfg(feature = "yaml")]
+use serde_yaml::Mapping;
+use thiserror::Error;
+
+#[cfg(feature = "yaml")]
+use crate::condition::Condition;
+use crate::dotfile::{AbsDotfile, Dotfile};
+use crate::util::yaml_string;
+
+#[derive(Error, Debug)]
+pub enum DotbotError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[cfg(feature = "yaml")]
+    #[error("failed to parse dotbot configuration as YAML / incorrect schema: {0}")]
+    SerdeYAML(#[from] serde_yaml::Error),
+
+    /// This build can't read YAML.
+    #[error("importing dotbot configurations needs the `yaml` feature")]
+    NoYaml,
+}
+
+/// A dotbot configuration with a `link` directive for each of `dotfiles`,
+/// to be saved in `dotfile_repo`. Templates can't be rendered by dotbot, so
+/// they're left out with a comment.
+pub fn export(dotfiles: &[AbsDotfile], dotfile_repo: &Path, home: &Path) -> String {
+    let mut config = String::from("# Generated by dotfile-manager.\n- link:");
+    if dotfiles.iter().all(|d| d.template) {
+        config.push_str(" {}");
+    }
+    config.push('\n');
+    for d in dotfiles {
+        let target = match d.installed.strip_prefix(home) {
+            Ok(target) => Path::new("~").join(target),
+            Err(_) => d.installed.clone(),
+        };
+        if d.template {
+            writeln!(
+                config,
+                "    # Skipped {}: it's a template, which dotbot can't render.",
+                target.display()
+            )
+            .unwrap();
+            continue;
+        }
+        let source = d.repo.strip_prefix(dotfile_repo).unwrap_or(&d.repo);
+        writeln!(
+            config,
+            "    {}: {}",
+            yaml_string(&target.to_string_lossy()),
+            yaml_string(&source.to_string_lossy())
+        )
+        .unwrap();
+    }
+    config
+}
+
+/// A `link` entry's options, or the defaults for them.
+#[cfg(feature = "yaml")]
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+struct LinkOptions {
+    path: Option<PathBuf>,
+    #[serde(rename = "if")]
+    condition: Option<String>,
+    create: Option<bool>,
+    relink: Option<bool>,
+    force: Option<bool>,
+    relative: Option<bool>,
+    glob: Option<bool>,
+    #[serde(rename = "type")]
+    link_type: Option<String>,
+}
+
+#[cfg(feature = "yaml")]
+impl LinkOptions {
+    /// These options, with unset ones taken from `defaults`.
+    fn or(self, defaults: &LinkOptions) -> Self {
+        let defaults = defaults.clone();
+        Self {
+            path: self.path.or(defaults.path),
+            condition: self.condition.or(defaults.condition),
+            create: self.create.or(defaults.create),
+            relink: self.relink.or(defaults.relink),
+            force: self.force.or(defaults.force),
+            relative: self.relative.or(defaults.relative),
+            glob: self.glob.or(defaults.glob),
+            link_type: self.link_type.or(defaults.link_type),
+        }
+    }
+}
+
+/// A value in a `link` directive: a source path, options, or null to use
+/// the target's file name without a leading `.`.
+#[cfg(feature = "yaml")]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum Link {
+    Path(PathBuf),
+    Options(LinkOptions),
+}
+
+/// A dotfiles list converted from a dotbot configuration.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Imported {
+    pub dotfiles: Vec<Dotfile>,
+    /// User-defined facts for the configuration file's `facts` table, for
+    /// the links' `if` commands.
+    pub facts: BTreeMap<String, String>,
+    /// Directives and options which weren't converted, and why.
+    pub notes: Vec<String>,
+}
+
+/// Convert the `link` directives in the dotbot configuration at `config`
+/// (e.g. `install.conf.yaml`) to a dotfiles list. Sources are relative to
+/// `config`'s directory, like dotbot's, and are made relative to
+/// `dotfile_repo` if they're inside it; targets are made relative to `home`.
+pub fn import(config: &Path, dotfile_repo: &Path, home: &Path) -> Result<Imported, DotbotError> {
+    let config = config.canonicalize()?;
+    let base = config.parent().unwrap_or_else(|| Path::new("/"));
+    let dotfile_repo = dotfile_repo
+        .canonicalize()
+        .unwrap_or_else(|_| dotfile_repo.to_path_buf());
+    to_dotfiles(&fs::read_to_string(&config)?, base, &dotfile_repo, home)
+}
+
+#[cfg(not(feature = "yaml"))]
+fn to_dotfiles(
+    _config: &str,
+    _base: &Path,
+    _dotfile_repo: &Path,
+    _home: &Path,
+) -> Result<Imported, DotbotError> {
+    Err(DotbotError::NoYaml)
+}
+
+#[cfg(feature = "yaml")]
+fn to_dotfiles(
+    config: &str,
+    base: &Path,
+    dotfile_repo: &Path,
+    home: &Path,
+) -> Result<Imported, DotbotError> {
+    let directives: Vec<Mapping> = serde_yaml::from_str(config)?;
+    let mut imported = Imported::default();
+    let mut defaults = LinkOptions::default();
+    // Options dfm has no equivalent for, and the targets they're set on.
+    let mut unsupported: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (name, value) in directives.iter().flat_map(|directive| directive.iter()) {
+        match name.as_str() {
+            Some("defaults") => {
+                if let Some(link) = value.get("link") {
+                    defaults = serde_yaml::from_value(link.clone())?;
+                }
+            }
+            Some("link") => {
+                let links: Mapping = serde_yaml::from_value(value.clone())?;
+                for (target, link) in links {
+                    let target: String = serde_yaml::from_value(target)?;
+                    let link: Option<Link> = serde_yaml::from_value(link)?;
+                    let options = match link {
+                        Some(Link::Path(path)) => LinkOptions {
+                            path: Some(path),
+                            ..Default::default()
+                        },
+                        Some(Link::Options(options)) => options,
+                        None => LinkOptions::default(),
+                    }
+                    .or(&defaults);
+                    for (option, set) in &[
+                        ("create", options.create),
+                        ("relink", options.relink),
+                        ("force", options.force),
+                        ("relative", options.relative),
+                        ("glob", options.glob),
+                    ] {
+                        if *set == Some(true) {
+                            unsupported.entry(option).or_default().push(target.clone());
+                        }
+                    }
+                    if options.link_type.as_deref() == Some("hardlink") {
+                        unsupported.entry("type").or_default().push(target.clone());
+                    }
+                    if options.glob == Some(true) {
+                        continue;
+                    }
+                    imported.dotfiles.push(to_dotfile(
+                        &target,
+                        options,
+                        base,
+                        dotfile_repo,
+                        home,
+                        &mut imported.facts,
+                    ));
+                }
+            }
+            Some(name) => imported
+                .notes
+                .push(format!("Skipped the `{}` directive", name)),
+            None => {}
+        }
+    }
+    for (option, targets) in unsupported {
+        let why = match option {
+            "create" => "dfm doesn't create missing parent directories",
+            "relink" | "force" => "dfm asks before replacing existing files",
+            "relative" => "dfm always links to absolute paths",
+            "glob" => "globs aren't supported, so these links were skipped",
+            _ => "dfm only makes symlinks",
+        };
+        imported.notes.push(format!(
+            "Ignored `{}` on {}: {}",
+            option,
+            targets.join(", "),
+            why
+        ));
+    }
+    Ok(imported)
+}
+
+#[cfg(feature = "yaml")]
+fn to_dotfile(
+    target: &str,
+    options: LinkOptions,
+    base: &Path,
+    dotfile_repo: &Path,
+    home: &Path,
+    facts: &mut BTreeMap<String, String>,
+) -> Dotfile {
+    let installed = expand_home(target, home);
+    let source = options.path.unwrap_or_else(|| {
+        let name = installed.file_name().unwrap_or_default().to_string_lossy();
+        name.strip_prefix('.').unwrap_or(&name).into()
+    });
+    let source = base.join(source);
+    let repo = source.strip_prefix(dotfile_repo).unwrap_or(&source);
+    let installed = installed.strip_prefix(home).unwrap_or(&installed);
+    let when = options.condition.map(|command| {
+        let next = format!("dotbot_if_{}", facts.len());
+        let command = format!("if {}; then echo true; fi", command);
+        let name = match facts.iter().find(|(_, c)| **c == command) {
+            Some((name, _)) => name.clone(),
+            None => {
+                facts.insert(next.clone(), command);
+                next
+            }
+        };
+        Condition::from(name.as_str())
+    });
+    Dotfile {
+        installed: if installed == repo {
+            None
+        } else {
+            Some(installed.to_path_buf())
+        },
+        repo: repo.to_path_buf(),
+        when,
+        ..Default::default()
+    }
+}
+
+/// `path` with a leading `~` or `$HOME` replaced with `home`.
+#[cfg(feature = "yaml")]
+fn expand_home(path: &str, home: &Path) -> PathBuf {
+    for prefix in &["~", "$HOME", "${HOME}"] {
+        if let Some(rest) = path.strip_prefix(prefix) {
+            if rest.is_empty() {
+                return home.to_path_buf();
+            }
+            if let Some(rest) = rest.strip_prefix('/') {
+                return home.join(rest);
+            }
+        }
+    }
+    path.into()
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn import_links() {
+        let config = indoc!(
+            r#"
+            - defaults:
+                link:
+                  relink: true
+            - clean: ['~']
+            - link:
+                ~/.bashrc: bash/bashrc
+                ~/.vimrc:
+                ~/.config/nvim:
+                  path: nvim
+                  create: true
+                  if: '[ "$(uname)" = Linux ]'
+                ~/.config/i3:
+                  path: i3
+                  if: '[ "$(uname)" = Linux ]'
+                $HOME/.npmrc:
+                  if: command -v npm
+                  relink: false
+                ~/.local/bin/:
+                  path: bin/*
+                  glob: true
+                /etc/hosts: /srv/hosts
+            - shell:
+              - git submodule update --init
+            "#
+        );
+        let dotfile = |repo: &str, installed: Option<&str>, when: Option<&str>| Dotfile {
+            repo: repo.into(),
+            installed: installed.map(PathBuf::from),
+            when: when.map(Condition::from),
+            ..Default::default()
+        };
+        assert_eq!(
+            to_dotfiles(
+                config,
+                Path::new("/home/me/dotfiles"),
+                Path::new("/home/me/dotfiles"),
+                Path::new("/home/me"),
+            )
+            .unwrap(),
+            Imported {
+                dotfiles: vec![
+                    dotfile("bash/bashrc", Some(".bashrc"), None),
+                    dotfile("vimrc", Some(".vimrc"), None),
+                    dotfile("nvim", Some(".config/nvim"), Some("dotbot_if_0")),
+                    dotfile("i3", Some(".config/i3"), Some("dotbot_if_0")),
+                    dotfile("npmrc", Some(".npmrc"), Some("dotbot_if_1")),
+                    dotfile("/srv/hosts", Some("/etc/hosts"), None),
+                ],
+                facts: vec![
+                    (
+                        "dotbot_if_0".to_string(),
+                        r#"if [ "$(uname)" = Linux ]; then echo true; fi"#.to_string()
+                    ),
+                    (
+                        "dotbot_if_1".to_string(),
+                        "if command -v npm; then echo true; fi".to_string()
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+                notes: vec![
+                    "Skipped the `clean` directive".to_string(),
+                    "Skipped the `shell` directive".to_string(),
+                    "Ignored `create` on ~/.config/nvim: dfm doesn't create missing parent directories"
+                        .to_string(),
+                    "Ignored `glob` on ~/.local/bin/: globs aren't supported, so these links were skipped"
+                        .to_string(),
+                    "Ignored `relink` on ~/.bashrc, ~/.vimrc, ~/.config/nvim, ~/.config/i3, ~/.local/bin/, /etc/hosts: dfm asks before replacing existing files"
+                        .to_string(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn export_links() {
+        let dotfile = |repo: &str, installed: &str| AbsDotfile {
+            repo: repo.into(),
+            installed: installed.into(),
+            ..Default::default()
+        };
+        let dotfiles = vec![
+            dotfile("/home/me/dotfiles/bashrc", "/home/me/.bashrc"),
+            dotfile("/nix/store/abc-nvim", "/home/me/.config/nvim"),
+            dotfile("/home/me/dotfiles/hosts", "/etc/hosts"),
+            AbsDotfile {
+                template: true,
+                ..dotfile("/home/me/dotfiles/gitconfig", "/home/me/.gitconfig")
+            },
+        ];
+        let config = export(
+            &dotfiles,
+            Path::new("/home/me/dotfiles"),
+            Path::new("/home/me"),
+        );
+        assert_eq!(
+            config,
+            indoc!(
+                r#"
+                # Generated by dotfile-manager.
+                - link:
+                    "~/.bashrc": "bashrc"
+                    "~/.config/nvim": "/nix/store/abc-nvim"
+                    "/etc/hosts": "hosts"
+                    # Skipped ~/.gitconfig: it's a template, which dotbot can't render.
+                "#
+            )
+            .trim_start()
+        );
+        #[cfg(feature = "yaml")]
+        assert_eq!(
+            to_dotfiles(
+                &config,
+                Path::new("/home/me/dotfiles"),
+                Path::new("/home/me/dotfiles"),
+                Path::new("/home/me"),
+            )
+            .unwrap()
+            .dotfiles
+            .len(),
+            3
+        );
+        assert_eq!(
+            export(&[], Path::new("/home/me/dotfiles"), Path::new("/home/me")),
+            "# Generated by dotfile-manager.\n- link: {}\n"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn dotbot_expand_home() {
+        let home = Path::new("/home/me");
+        assert_eq!(expand_home("~", home), home);
+        assert_eq!(expand_home("~/.bashrc", home), home.join(".bashrc"));
+        assert_eq!(expand_home("${HOME}/.bashrc", home), home.join(".bashrc"));
+        assert_eq!(expand_home("~me/.bashrc", home), Path::new("~me/.bashrc"));
+        assert_eq!(expand_home("/etc/hosts", home), Path::new("/etc/hosts"));
+    }
+}
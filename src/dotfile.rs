@@ -1,12 +1,27 @@
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 
-use dialoguer::{theme::ColorfulTheme, Confirmation};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use symlink;
+use thiserror::Error;
 
-use crate::config::Config;
+use crate::condition::{Condition, ConditionError};
+use crate::config::{Config, DotfilesReadError};
+use crate::diff;
+use crate::error::Error;
+use crate::facts::Facts;
+use crate::log;
+use crate::prompt::{ConflictAction, ConflictPrompt};
+use crate::shell;
+use crate::strategy::{LinkStrategies, Strategy};
+use crate::systemd;
+use crate::systemd::SystemdUnit;
+use crate::template;
+use crate::template::TemplateError;
 use crate::util::{home_dir, make_abs};
 
 #[derive(Deserialize, Debug, Clone, PartialEq)]
@@ -16,13 +31,62 @@ pub enum SerdeDotfile {
     Advanced(Dotfile),
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
 pub struct Dotfile {
     /// The dotfile's path, relative to the dotfile repository.
     pub repo: PathBuf,
     /// The dotfile's path, relative to your home directory. If left unspecified,
-    /// this is the same as `repo`.
+    /// this is the same as `repo`, or for a systemd unit, its file name in
+    /// `systemd::USER_UNIT_DIR`, or for a shell snippet, see
+    /// `shell::snippet_path`.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub installed: Option<PathBuf>,
+    /// A condition which must be true for this dotfile to be installed; see
+    /// the `condition` module for the syntax.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub when: Option<Condition>,
+    /// If true, the dotfile is a handlebars template which is rendered to the
+    /// installed path rather than linked; see the `template` module.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub template: bool,
+    /// How the dotfile is installed, e.g. `copy`; a symbolic link by
+    /// default, or if `template` is true, a rendered template. See the
+    /// `strategy` module.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    /// If true, installing this dotfile needs administrative privileges
+    /// (e.g. it's installed to a system path), so it's skipped when we aren't
+    /// running with them; see `Facts::is_elevated`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub privileged: bool,
+    /// A flake installable to build with `nix build`, e.g. `.#nvim-config`;
+    /// `repo` is then relative to the build's output rather than the
+    /// dotfile repository, so use `.` to install the whole output. See
+    /// `Config::resolve_dotfile`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+    /// If set, the dotfile is a systemd user unit: once it's linked, `dfm
+    /// link` reloads systemd's configuration, and if this is `enabled`,
+    /// enables and starts it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub systemd: Option<SystemdUnit>,
+    /// A cron schedule, e.g. `0 3 * * *`, to run the installed file on; `dfm
+    /// link` keeps these in a block of the user's crontab. See the `cron`
+    /// module.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    /// If set, the dotfile is a shell startup snippet, sourced by `.bashrc`
+    /// and `.zshrc` in order of this number; see the `shell` module.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<u32>,
+    /// Fields which aren't any of the above, e.g. misspellings of them;
+    /// they're ignored, with a warning.
+    #[serde(flatten, skip_serializing)]
+    pub unknown: BTreeMap<String, Value>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
 }
 
 impl From<SerdeDotfile> for Dotfile {
@@ -38,7 +102,7 @@ impl From<PathBuf> for Dotfile {
     fn from(p: PathBuf) -> Self {
         Self {
             repo: p,
-            installed: None,
+            ..Default::default()
         }
     }
 }
@@ -48,67 +112,372 @@ impl Dotfile {
         &self.repo
     }
 
-    pub fn installed(&self) -> &Path {
-        &self.installed.as_ref().unwrap_or(&self.repo)
+    pub fn installed(&self) -> PathBuf {
+        match (&self.installed, self.systemd, self.shell) {
+            (Some(installed), _, _) => installed.clone(),
+            (None, Some(_), _) => {
+                Path::new(systemd::USER_UNIT_DIR).join(self.repo.file_name().unwrap_or_default())
+            }
+            (None, None, Some(order)) => shell::snippet_path(&self.repo, order),
+            (None, None, None) => self.repo.clone(),
+        }
+    }
+
+    /// Whether this dotfile should be installed on this machine, according to
+    /// its `when` condition.
+    pub fn enabled(&self, facts: &Facts) -> Result<bool, ConditionError> {
+        match &self.when {
+            Some(when) => when.eval(facts),
+            None => Ok(true),
+        }
+    }
+
+    /// The name of the strategy this dotfile is installed with; see `link`.
+    pub fn strategy(&self) -> &str {
+        match &self.link {
+            Some(link) => link,
+            None if self.template => "template",
+            None => "symlink",
+        }
+    }
+}
+
+/// `installed` made absolute relative to `home`, like `make_abs`, but
+/// without following a link at `installed` itself: once a dotfile's linked,
+/// that would resolve to the dotfile in the repository.
+fn installed_path(home: &Path, installed: &Path) -> PathBuf {
+    let abs = home.join(installed);
+    match (abs.parent(), abs.file_name()) {
+        (Some(parent), Some(name)) => parent
+            .canonicalize()
+            .unwrap_or_else(|_| parent.to_path_buf())
+            .join(name),
+        _ => make_abs(home, installed),
+    }
+}
+
+/// What was being done to a dotfile when something went wrong; see
+/// `DotfileError`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DotfileAction {
+    Linking,
+    /// Removing what's in the dotfile's place, to overwrite it.
+    Replacing,
+    BackingUp,
+    Checking,
+}
+
+impl fmt::Display for DotfileAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            DotfileAction::Linking => "linking",
+            DotfileAction::Replacing => "replacing",
+            DotfileAction::BackingUp => "backing up",
+            DotfileAction::Checking => "checking",
+        };
+        write!(f, "{}", action)
+    }
+}
+
+/// An I/O error installing or checking a dotfile, with what was being done
+/// to which file.
+#[derive(Error, Debug)]
+#[error("while {action} `{}`", .path.display())]
+pub struct DotfileError {
+    pub action: DotfileAction,
+    /// The dotfile's installed path.
+    pub path: PathBuf,
+    #[source]
+    pub source: io::Error,
+}
+
+impl DotfileError {
+    /// The kind of the underlying I/O error; e.g. `AlreadyExists` if
+    /// something else is in the dotfile's place and it wasn't overwritten.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.source.kind()
     }
 }
 
 /// A `Dotfile` struct fully resolved to canonical paths.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct AbsDotfile {
     /// The dotfile's path in the dotfile repository.
     pub repo: PathBuf,
     /// The dotfile's path in the user environment.
     pub installed: PathBuf,
+    /// Whether the dotfile is a template; see `Dotfile::template`.
+    pub template: bool,
+    /// How the dotfile is installed; see `Dotfile::link`.
+    pub strategy: Strategy,
+    /// Whether the dotfile is a systemd user unit; see `Dotfile::systemd`.
+    pub systemd: Option<SystemdUnit>,
+    /// When to run the dotfile; see `Dotfile::cron`.
+    pub cron: Option<String>,
+    /// Whether the dotfile is a shell snippet; see `Dotfile::shell`.
+    pub shell: bool,
 }
 
 impl AbsDotfile {
-    pub fn new(d: &Dotfile, cfg: &Config) -> io::Result<Self> {
+    pub fn new(d: &Dotfile, cfg: &Config) -> Result<Self, DotfilesReadError> {
+        Self::new_in(d, &cfg.dotfile_repo, &cfg.link_strategies)
+    }
+
+    /// Like `new`, but with `d.repo` relative to `repo` rather than the
+    /// dotfile repository, e.g. the output of a Nix build, and its strategy
+    /// one of `strategies`.
+    pub fn new_in(
+        d: &Dotfile,
+        repo: &Path,
+        strategies: &LinkStrategies,
+    ) -> Result<Self, DotfilesReadError> {
+        let strategy =
+            strategies
+                .get(d.strategy())
+                .ok_or_else(|| DotfilesReadError::UnknownLinkStrategy {
+                    repo: d.repo.clone(),
+                    name: d.strategy().to_string(),
+                    known: strategies.names(),
+                })?;
         Ok(Self {
-            repo: make_abs(&cfg.dotfile_repo, d.repo()),
-            installed: make_abs(home_dir()?.as_path(), d.installed()),
+            repo: make_abs(repo, d.repo()),
+            installed: installed_path(&home_dir()?, &d.installed()),
+            template: strategy.name() == "template",
+            strategy: strategy.clone(),
+            systemd: d.systemd,
+            cron: d.cron.clone(),
+            shell: d.shell.is_some(),
         })
     }
 
-    pub fn link(&self) -> io::Result<()> {
+    /// Render this dotfile's template to its installed path.
+    pub fn render(&self, facts: &Facts) -> Result<(), TemplateError> {
+        template::render_file(&self.repo, &self.installed, facts)
+    }
+
+    /// Wrap an I/O error from doing `action` to this dotfile.
+    pub(crate) fn error(
+        &self,
+        action: DotfileAction,
+    ) -> impl FnOnce(io::Error) -> DotfileError + '_ {
+        move |source| DotfileError {
+            action,
+            path: self.installed.clone(),
+            source,
+        }
+    }
+
+    /// Install this dotfile with its strategy.
+    pub fn install(&self, facts: &Facts) -> Result<(), Error> {
+        self.strategy.install(self, facts)
+    }
+
+    /// Link this dotfile to its installed path with a symbolic link,
+    /// whatever its strategy.
+    pub fn link(&self) -> Result<(), DotfileError> {
         if cfg!(unix) || self.repo.is_file() {
             symlink::symlink_file(&self.repo, &self.installed)
         } else {
             symlink::symlink_dir(&self.repo, &self.installed)
         }
+        .map_err(self.error(DotfileAction::Linking))
     }
 
-    fn should_overwrite(&self) -> io::Result<bool> {
-        // TODO: More choices, not y/n
-        // - verbose help
-        // - diff the two files
-        // - check if the files are the same (before this...?)
-        Confirmation::with_theme(&ColorfulTheme::default())
-            .with_text(&format!(
-                "Overwrite {} with a link to {}?",
+    /// Ask what to do with the file in this dotfile's place, showing how
+    /// it differs from the dotfile.
+    fn resolve_conflict(
+        &self,
+        conflicts: &mut ConflictPrompt,
+    ) -> Result<ConflictAction, DotfileError> {
+        // TODO: verbose help
+        eprintln!("{}", self.describe_installed()?);
+        if let (Ok(installed), Ok(repo)) = (
+            fs::read_to_string(&self.installed),
+            fs::read_to_string(&self.repo),
+        ) {
+            eprint!(
+                "{}",
+                diff::unified(&installed, &repo, console::colors_enabled())
+            );
+        }
+        conflicts
+            .ask(&format!(
+                "Overwrite {} with {}?",
                 self.installed.display(),
-                self.repo.display()
+                self.strategy.describe(self)
             ))
-            .interact()
+            .map_err(self.error(DotfileAction::Linking))
+    }
+
+    /// What's in this dotfile's place, so conflicts can be resolved without
+    /// looking, e.g. `~/.bashrc is a 1.2 KiB file, modified
+    /// 2024-03-01T03:00:00Z; its contents differ from ~/.dotfiles/bashrc`.
+    pub fn describe_installed(&self) -> Result<String, DotfileError> {
+        let metadata =
+            fs::symlink_metadata(&self.installed).map_err(self.error(DotfileAction::Checking))?;
+        let installed = self.installed.display();
+        if metadata.file_type().is_symlink() {
+            let target =
+                fs::read_link(&self.installed).map_err(self.error(DotfileAction::Checking))?;
+            let broken = if self.installed.exists() {
+                ""
+            } else {
+                ", which doesn't exist"
+            };
+            return Ok(format!(
+                "{} is a symlink to {}{}",
+                installed,
+                target.display(),
+                broken
+            ));
+        }
+        let modified = metadata
+            .modified()
+            .map(|time| format!(", modified {}", log::timestamp(time)))
+            .unwrap_or_default();
+        if metadata.is_dir() {
+            return Ok(format!("{} is a directory{}", installed, modified));
+        }
+        let same = match (fs::read(&self.installed), fs::read(&self.repo)) {
+            (Ok(installed), Ok(repo)) => installed == repo,
+            _ => false,
+        };
+        Ok(format!(
+            "{} is a {} file{}; its contents {} {}",
+            installed,
+            file_size(metadata.len()),
+            modified,
+            if same {
+                "are the same as"
+            } else {
+                "differ from"
+            },
+            self.repo.display()
+        ))
     }
 
-    pub fn link_interactive(&self) -> io::Result<()> {
+    /// Move the file in this dotfile's place aside, to the same path with
+    /// `.bak` appended (or `.bak.1`, `.bak.2`, and so on, if that's
+    /// taken), and return where it went.
+    pub fn back_up(&self) -> Result<PathBuf, DotfileError> {
+        let with_suffix = |suffix: String| {
+            let mut path = self.installed.as_os_str().to_owned();
+            path.push(suffix);
+            PathBuf::from(path)
+        };
+        let mut backup = with_suffix(".bak".into());
+        let mut n = 1;
+        while fs::symlink_metadata(&backup).is_ok() {
+            backup = with_suffix(format!(".bak.{}", n));
+            n += 1;
+        }
+        fs::rename(&self.installed, &backup).map_err(self.error(DotfileAction::BackingUp))?;
+        Ok(backup)
+    }
+
+    /// Install this dotfile, asking `conflicts` what to do with anything in
+    /// its place.
+    pub fn link_interactive(
+        &self,
+        conflicts: &mut ConflictPrompt,
+        facts: &Facts,
+    ) -> Result<(), Error> {
         if self.installed.exists() {
-            if self.should_overwrite()? {
-                if self.installed.is_dir() {
-                    fs::remove_dir(&self.installed)?;
-                } else {
-                    fs::remove_file(&self.installed)?;
+            match self.resolve_conflict(conflicts)? {
+                ConflictAction::Overwrite if self.installed.is_dir() => {
+                    fs::remove_dir(&self.installed).map_err(self.error(DotfileAction::Replacing))?
+                }
+                ConflictAction::Overwrite => fs::remove_file(&self.installed)
+                    .map_err(self.error(DotfileAction::Replacing))?,
+                ConflictAction::Backup => {
+                    let backup = self.back_up()?;
+                    eprintln!("Moved {} to {}", self.installed.display(), backup.display());
+                }
+                ConflictAction::Skip | ConflictAction::Ask => {
+                    return Err(self.error(DotfileAction::Linking)(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        "Link source already exists",
+                    ))
+                    .into());
                 }
-            } else {
-                return Err(io::Error::new(
-                    io::ErrorKind::AlreadyExists,
-                    "Link source already exists",
-                ));
             }
         }
-        self.link()
+        self.install(facts)
     }
+
+    /// Whether this dotfile is installed, according to its strategy.
+    pub fn status(&self) -> Result<InstallStatus, DotfileError> {
+        match fs::symlink_metadata(&self.installed) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(InstallStatus::Missing),
+            Err(err) => Err(self.error(DotfileAction::Checking)(err)),
+            Ok(metadata) => self
+                .strategy
+                .status(self, &metadata)
+                .map_err(self.error(DotfileAction::Checking)),
+        }
+    }
+
+    /// Short descriptions of what else this dotfile is, e.g. `template`
+    /// and `cron`; a strategy other than `symlink`, like `copy`, is one.
+    pub fn tags(&self) -> Vec<&str> {
+        let strategy = self.strategy.name();
+        [
+            (strategy != "symlink", strategy),
+            (self.systemd.is_some(), "systemd"),
+            (self.cron.is_some(), "cron"),
+            (self.shell, "shell"),
+        ]
+        .iter()
+        .filter(|(is, _)| *is)
+        .map(|(_, tag)| *tag)
+        .collect()
+    }
+}
+
+/// Whether a dotfile is installed; see `AbsDotfile::status`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallStatus {
+    /// The installed path is a link to the dotfile.
+    Linked,
+    /// The template has been rendered to the installed path.
+    Rendered,
+    /// A copy of the dotfile is at the installed path.
+    Copied,
+    /// Nothing is at the installed path.
+    Missing,
+    /// Something else is at the installed path, so `dfm link` will ask to
+    /// overwrite it.
+    Conflict,
+}
+
+impl fmt::Display for InstallStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            InstallStatus::Linked => "linked",
+            InstallStatus::Rendered => "rendered",
+            InstallStatus::Copied => "copied",
+            InstallStatus::Missing => "missing",
+            InstallStatus::Conflict => "conflict",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// `bytes` in a human-readable unit, e.g. `1.2 KiB`.
+fn file_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in &["B", "KiB", "MiB"] {
+        if size < 1024.0 {
+            return if *unit == "B" {
+                format!("{} B", bytes)
+            } else {
+                format!("{:.1} {}", size, unit)
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} GiB", size)
 }
 
 #[cfg(test)]
@@ -117,8 +486,11 @@ mod test {
     use std::path::PathBuf;
 
     use pretty_assertions::assert_eq;
+    use regex::Regex;
+    use tempfile::TempDir;
 
     use super::*;
+    use crate::strategy;
 
     #[test]
     fn dotfile_installed() {
@@ -126,6 +498,7 @@ mod test {
             Dotfile {
                 repo: "foo".into(),
                 installed: Some("bar".into()),
+                ..Default::default()
             }
             .installed(),
             Path::new("bar"),
@@ -135,6 +508,7 @@ mod test {
             Dotfile {
                 repo: "baz".into(),
                 installed: None,
+                ..Default::default()
             }
             .installed(),
             Path::new("baz"),
@@ -144,10 +518,158 @@ mod test {
             Dotfile {
                 repo: "baz".into(),
                 installed: None,
+                ..Default::default()
             }
             .repo(),
             Path::new("baz"),
         );
+
+        let unit: Dotfile =
+            serde_json::from_str(r#"{"repo": "systemd/backup.timer", "systemd": "enabled"}"#)
+                .unwrap();
+        assert_eq!(unit.systemd, Some(SystemdUnit::Enabled));
+        assert_eq!(
+            unit.installed(),
+            Path::new(".config/systemd/user/backup.timer")
+        );
+        assert_eq!(
+            Dotfile {
+                repo: "shell/aliases.sh".into(),
+                shell: Some(10),
+                ..Default::default()
+            }
+            .installed(),
+            Path::new(".config/dfm/shell.d/10-aliases.sh")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn absdotfile_status() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(root.join("bashrc"), "").unwrap();
+        fs::write(root.join(".profile"), "").unwrap();
+        std::os::unix::fs::symlink(root.join("bashrc"), root.join(".bashrc")).unwrap();
+        let dotfile = |repo: &str, installed: &str, template: bool| AbsDotfile {
+            repo: root.join(repo),
+            installed: root.join(installed),
+            template,
+            strategy: if template {
+                Strategy::new(strategy::Template)
+            } else {
+                Strategy::default()
+            },
+            ..Default::default()
+        };
+        let statuses = vec![
+            dotfile("bashrc", ".bashrc", false).status().unwrap(),
+            dotfile("profile", ".profile", false).status().unwrap(),
+            dotfile("profile", ".profile", true).status().unwrap(),
+            dotfile("inputrc", ".inputrc", false).status().unwrap(),
+        ];
+
+        assert_eq!(
+            statuses,
+            vec![
+                InstallStatus::Linked,
+                InstallStatus::Conflict,
+                // dfm didn't render it.
+                InstallStatus::Conflict,
+                InstallStatus::Missing,
+            ]
+        );
+        assert_eq!(
+            AbsDotfile {
+                template: true,
+                strategy: Strategy::new(strategy::Template),
+                cron: Some("@daily".into()),
+                ..Default::default()
+            }
+            .tags(),
+            vec!["template", "cron"]
+        );
+        assert_eq!(
+            AbsDotfile {
+                strategy: Strategy::new(strategy::Hardlink),
+                ..Default::default()
+            }
+            .tags(),
+            vec!["hardlink"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn absdotfile_describe_installed() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join(".vim")).unwrap();
+        fs::write(root.join("bashrc"), "same").unwrap();
+        fs::write(root.join(".bashrc"), "same").unwrap();
+        fs::write(root.join(".profile"), "different").unwrap();
+        std::os::unix::fs::symlink(root.join("inputrc"), root.join(".inputrc")).unwrap();
+        // Modification times vary.
+        let modified = Regex::new(r", modified [0-9T:-]+Z").unwrap();
+        let describe = |installed: &str| {
+            let description = AbsDotfile {
+                repo: root.join("bashrc"),
+                installed: root.join(installed),
+                ..Default::default()
+            }
+            .describe_installed()
+            .unwrap();
+            modified.replace(&description, "").into_owned()
+        };
+        let descriptions = vec![
+            describe(".bashrc"),
+            describe(".profile"),
+            describe(".inputrc"),
+            describe(".vim"),
+        ];
+
+        assert_eq!(
+            descriptions,
+            vec![
+                format!(
+                    "{} is a 4 B file; its contents are the same as {}",
+                    root.join(".bashrc").display(),
+                    root.join("bashrc").display()
+                ),
+                format!(
+                    "{} is a 9 B file; its contents differ from {}",
+                    root.join(".profile").display(),
+                    root.join("bashrc").display()
+                ),
+                format!(
+                    "{} is a symlink to {}, which doesn't exist",
+                    root.join(".inputrc").display(),
+                    root.join("inputrc").display()
+                ),
+                format!("{} is a directory", root.join(".vim").display()),
+            ]
+        );
+        assert_eq!(file_size(1234), "1.2 KiB");
+        assert_eq!(file_size(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn absdotfile_back_up() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".bashrc.bak"), "older").unwrap();
+        fs::write(root.join(".bashrc"), "old").unwrap();
+        let dotfile = AbsDotfile {
+            installed: root.join(".bashrc"),
+            ..Default::default()
+        };
+        let backup = dotfile.back_up();
+        let contents = fs::read_to_string(root.join(".bashrc.bak.1"));
+        let exists = root.join(".bashrc").exists();
+
+        assert_eq!(backup.unwrap(), root.join(".bashrc.bak.1"));
+        assert_eq!(contents.unwrap(), "old");
+        assert!(!exists);
     }
 
     #[test]
@@ -157,6 +679,7 @@ mod test {
             Dotfile {
                 repo: "xxx".into(),
                 installed: None,
+                ..Default::default()
             }
         );
     }
@@ -167,6 +690,7 @@ mod test {
         let cfg = Config {
             dotfile_repo: cwd.clone(),
             dotfiles_basename: "dotfiles".into(),
+            ..Config::try_default().unwrap()
         };
 
         assert_eq!(
@@ -174,6 +698,7 @@ mod test {
                 &Dotfile {
                     repo: ".bashrc_fake".into(),
                     installed: None,
+                    ..Default::default()
                 },
                 &cfg
             )
@@ -181,6 +706,7 @@ mod test {
             AbsDotfile {
                 repo: cwd.join(".bashrc_fake"),
                 installed: home_dir().unwrap().join(".bashrc_fake"),
+                ..Default::default()
             }
         );
 
@@ -190,6 +716,7 @@ mod test {
                     repo: ".bashrc_fake".into(),
                     // Don't do this:
                     installed: Some(".bash_profile_fake".into()),
+                    ..Default::default()
                 },
                 &cfg
             )
@@ -197,6 +724,7 @@ mod test {
             AbsDotfile {
                 repo: cwd.join(".bashrc_fake"),
                 installed: home_dir().unwrap().join(".bash_profile_fake"),
+                ..Default::default()
             }
         );
 
@@ -206,6 +734,7 @@ mod test {
                     repo: ".bashrc_fake".into(),
                     // REALLY don't do this
                     installed: Some("/tmp".into()),
+                    ..Default::default()
                 },
                 &cfg
             )
@@ -213,7 +742,25 @@ mod test {
             AbsDotfile {
                 repo: cwd.join(".bashrc_fake"),
                 installed: "/tmp".into(),
+                ..Default::default()
             }
         );
+
+        let linked = |link: &str| {
+            AbsDotfile::new(
+                &Dotfile {
+                    repo: ".bashrc_fake".into(),
+                    link: Some(link.into()),
+                    ..Default::default()
+                },
+                &cfg,
+            )
+        };
+        assert_eq!(linked("copy").unwrap().strategy.name(), "copy");
+        assert!(linked("template").unwrap().template);
+        assert_eq!(
+            linked("reflink").unwrap_err().to_string(),
+            ".bashrc_fake: unknown link strategy `reflink`"
+        );
     }
 }
@@ -0,0 +1,364 @@
+//! The engine which installs dotfiles: planning what to do with each one
+//! (`plan`), then doing it (`install_all`) and counting what happened in a
+//! `LinkReport`. `DotfileManager` and `dfm link` are both built on it; the
+//! dotfiles themselves are modeled in the `dotfile` module.
+
+use std::io;
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::cron;
+use crate::dotfile::{AbsDotfile, InstallStatus};
+use crate::error::Error;
+use crate::facts::Facts;
+use crate::observer::{ExecutionObserver, Outcome};
+use crate::prompt::ConflictPrompt;
+use crate::report::LinkReport;
+use crate::vscode;
+use crate::warning::{Warning, WarningKind, Warnings};
+
+/// What installing a dotfile involves; see `action`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// It's already installed and up to date, e.g. linked, or a template
+    /// whose rendered output hasn't changed.
+    AlreadyOk,
+    /// Install it, asking what to do with anything in its place.
+    Link,
+    /// Install it over what an earlier install left in its place, like
+    /// re-rendering a template; see `LinkStrategy::overwrites`.
+    Render,
+}
+
+/// Install each of `dotfiles` which isn't already installed, asking
+/// `conflicts` what to do with files in their places, counting what
+/// happened in `report` and telling `observer`. A dotfile with something
+/// in its place which isn't overwritten is counted as a conflict, with a
+/// warning in `warnings`, rather than failing. In a dry run, nothing is
+/// installed, but what would be is counted.
+pub fn install_all(
+    dotfiles: &[AbsDotfile],
+    facts: &Facts,
+    conflicts: &mut ConflictPrompt,
+    dry_run: bool,
+    report: &mut LinkReport,
+    warnings: &mut Warnings,
+    observer: &mut dyn ExecutionObserver,
+) -> Result<(), Error> {
+    for (index, d) in dotfiles.iter().enumerate() {
+        let action = action(d, facts)?;
+        observer.entry_started(d, action, index, dotfiles.len());
+        let outcome = match install(d, action, facts, conflicts, dry_run, observer) {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                observer.entry_finished(d, &Outcome::Failed(err.to_string()));
+                return Err(err);
+            }
+        };
+        match (&outcome, action) {
+            (Outcome::Conflict, _) => {
+                warnings.push(Warning::new(
+                    WarningKind::Skipped,
+                    format!(
+                        "{}: something else is there and wasn't overwritten",
+                        d.installed.display()
+                    ),
+                ));
+                report.conflicts += 1;
+            }
+            (_, Action::AlreadyOk) => report.already_ok += 1,
+            (_, Action::Render) => report.rendered += 1,
+            (_, Action::Link) => report.linked += 1,
+        }
+        observer.entry_finished(d, &outcome);
+    }
+    Ok(())
+}
+
+/// Install `d` with `action`, unless this is a dry run.
+fn install(
+    d: &AbsDotfile,
+    action: Action,
+    facts: &Facts,
+    conflicts: &mut ConflictPrompt,
+    dry_run: bool,
+    observer: &mut dyn ExecutionObserver,
+) -> Result<Outcome, Error> {
+    match action {
+        Action::AlreadyOk => return Ok(Outcome::AlreadyOk),
+        _ if dry_run => return Ok(Outcome::DryRun),
+        Action::Render => d.install(facts)?,
+        Action::Link => {
+            if d.installed.exists() {
+                observer.conflict_encountered(d);
+            }
+            match d.link_interactive(conflicts, facts) {
+                Err(Error::Dotfile(err)) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    return Ok(Outcome::Conflict);
+                }
+                res => res?,
+            }
+        }
+    }
+    observer.action_performed(d, action);
+    Ok(Outcome::Installed)
+}
+
+/// The dotfiles in `config`'s list whose conditions are true on this
+/// machine, counting the ones which are skipped in `report` and recording
+/// problems in `warnings`.
+pub fn enabled_dotfiles(
+    config: &Config,
+    facts: &Facts,
+    report: &mut LinkReport,
+    warnings: &mut Warnings,
+) -> Result<Vec<AbsDotfile>, Error> {
+    let mut dotfiles = Vec::new();
+    if let Some(warning) = config.ambiguous_dotfiles_warning() {
+        warnings.push(warning);
+    }
+    let list = config.load_dotfiles(facts)?;
+    for warning in list.warnings {
+        warnings.push(Warning::new(WarningKind::Nix, warning));
+    }
+    for d in &list.value {
+        if !d.unknown.is_empty() {
+            warnings.push(Warning::new(
+                WarningKind::UnknownField,
+                format!(
+                    "{}: {}",
+                    d.repo.display(),
+                    d.unknown
+                        .keys()
+                        .map(|field| format!("`{}`", field))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ));
+        }
+    }
+    let vscode = vscode::user_dir()
+        .map(|user_dir| config.vscode.dotfiles(&user_dir))
+        .unwrap_or_default();
+    for d in list.value.into_iter().chain(vscode) {
+        if !d.enabled(facts)? {
+            report.skipped_conditions += 1;
+            continue;
+        }
+        // Look the fact up by name so it can be overridden with `--fact`.
+        let elevated = matches!(facts.get("is_elevated")?, Some(v) if v.truthy());
+        if d.privileged && !elevated {
+            warnings.push(Warning::new(
+                WarningKind::Skipped,
+                format!(
+                    "{}: it needs administrative privileges; rerun as root to install it",
+                    d.installed().display()
+                ),
+            ));
+            report.skipped_privileged += 1;
+            continue;
+        }
+        dotfiles.push(d);
+    }
+    Ok(dotfiles
+        .iter()
+        .map(|d| config.resolve_dotfile(d))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// dfm's crontab entries for the enabled dotfiles, `enabled`; see
+/// `cron::with_block`. These come from every enabled dotfile, not just the
+/// ones being installed, so installing a few of them, e.g. with
+/// `dfm link --interactive`, doesn't remove the others' entries.
+pub fn cron_entries(enabled: &[AbsDotfile]) -> Vec<String> {
+    enabled
+        .iter()
+        .filter_map(|d| Some(cron::entry(d.cron.as_ref()?, &d.installed)))
+        .collect()
+}
+
+/// Whether any of the enabled dotfiles, `enabled`, is a shell snippet, so
+/// the shell's rc files should source them; see `shell::rc_updates`. Like
+/// `cron_entries`, this looks at every enabled dotfile, not just the ones
+/// being installed.
+pub fn has_shell_snippets(enabled: &[AbsDotfile]) -> bool {
+    enabled.iter().any(|d| d.shell)
+}
+
+/// What to do with each of `dotfiles` to install it.
+pub fn plan(dotfiles: Vec<AbsDotfile>, facts: &Facts) -> Result<Vec<(AbsDotfile, Action)>, Error> {
+    dotfiles
+        .into_iter()
+        .map(|d| {
+            let action = action(&d, facts)?;
+            Ok((d, action))
+        })
+        .collect()
+}
+
+/// What to do to install `d`, according to its strategy.
+pub fn action(d: &AbsDotfile, facts: &Facts) -> Result<Action, Error> {
+    if d.strategy.is_current(d, facts)? {
+        Ok(Action::AlreadyOk)
+    } else if d.strategy.overwrites() && d.status()? != InstallStatus::Conflict {
+        Ok(Action::Render)
+    } else {
+        Ok(Action::Link)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::prompt::{ConflictAction, PromptOptions};
+    use crate::shell;
+    use crate::strategy::{Strategy, Template};
+
+    #[test]
+    fn engine_install_all() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(root.join("bashrc"), "").unwrap();
+        fs::write(root.join("vimrc"), "").unwrap();
+        symlink::symlink_file(root.join("vimrc"), root.join(".vimrc")).unwrap();
+        let dotfile = |name: &str| AbsDotfile {
+            repo: root.join(name),
+            installed: root.join(format!(".{}", name)),
+            ..Default::default()
+        };
+        let dotfiles = vec![dotfile("bashrc"), dotfile("vimrc")];
+        let facts = Facts::new();
+        let plan = plan(dotfiles.clone(), &facts)
+            .unwrap()
+            .into_iter()
+            .map(|(_, action)| action)
+            .collect::<Vec<_>>();
+        let install = |dry_run| {
+            let mut report = LinkReport::default();
+            install_all(
+                &dotfiles,
+                &facts,
+                &mut ConflictPrompt::new(&PromptOptions::default()),
+                dry_run,
+                &mut report,
+                &mut Warnings::default(),
+                &mut (),
+            )
+            .map(|_| (report.linked, report.already_ok))
+        };
+        let dry_run = install(true);
+        let dry_run_linked = root.join(".bashrc").exists();
+        let linked = install(false);
+        let target = fs::read_link(root.join(".bashrc"));
+
+        assert_eq!(plan, vec![Action::Link, Action::AlreadyOk]);
+        assert_eq!(dry_run.unwrap(), (1, 1));
+        assert!(!dry_run_linked);
+        assert_eq!(linked.unwrap(), (1, 1));
+        assert_eq!(target.unwrap(), root.join("bashrc"));
+    }
+
+    #[test]
+    fn engine_template_conflict() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(root.join("gitconfig"), "# on {{ facts.os }}\n").unwrap();
+        fs::write(root.join(".gitconfig"), "# mine\n").unwrap();
+        let d = AbsDotfile {
+            repo: root.join("gitconfig"),
+            installed: root.join(".gitconfig"),
+            strategy: Strategy::new(Template),
+            ..Default::default()
+        };
+        let facts = Facts::new();
+        let status = d.status();
+        let action = action(&d, &facts);
+        let mut report = LinkReport::default();
+        let installed = install_all(
+            &[d],
+            &facts,
+            &mut ConflictPrompt::new(&PromptOptions {
+                on_conflict: ConflictAction::Skip,
+                ..Default::default()
+            }),
+            false,
+            &mut report,
+            &mut Warnings::default(),
+            &mut (),
+        );
+        let contents = fs::read_to_string(root.join(".gitconfig"));
+
+        // A file dfm didn't render is a conflict, not overwritten unasked.
+        assert_eq!(status.unwrap(), InstallStatus::Conflict);
+        assert_eq!(action.unwrap(), Action::Link);
+        installed.unwrap();
+        assert_eq!((report.conflicts, report.rendered), (1, 0));
+        assert_eq!(contents.unwrap(), "# mine\n");
+    }
+
+    #[test]
+    fn engine_install_subset() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let home = root.join("home");
+        fs::create_dir_all(&home).unwrap();
+        for name in ["backup", "aliases.sh", "vimrc"] {
+            fs::write(root.join(name), "").unwrap();
+        }
+        let enabled = vec![
+            AbsDotfile {
+                repo: root.join("backup"),
+                installed: home.join(".local/bin/backup"),
+                cron: Some("0 3 * * *".into()),
+                ..Default::default()
+            },
+            AbsDotfile {
+                repo: root.join("aliases.sh"),
+                installed: home.join(".config/dfm/shell.d/10-aliases.sh"),
+                shell: true,
+                ..Default::default()
+            },
+            AbsDotfile {
+                repo: root.join("vimrc"),
+                installed: home.join(".vimrc"),
+                ..Default::default()
+            },
+        ];
+        let crontab = cron::with_block("MAILTO=me\n", &cron_entries(&enabled));
+        fs::write(home.join(".bashrc"), "export EDITOR=vim\n").unwrap();
+        for (rc, contents) in shell::rc_updates(&home, true).unwrap() {
+            fs::write(rc, contents).unwrap();
+        }
+
+        // Install just `vimrc`, like picking it with `dfm link --interactive`.
+        let chosen = &enabled[2..];
+        let installed = install_all(
+            chosen,
+            &Facts::new(),
+            &mut ConflictPrompt::new(&PromptOptions::default()),
+            false,
+            &mut LinkReport::default(),
+            &mut Warnings::default(),
+            &mut (),
+        );
+        let crontab_after = cron::with_block(&crontab, &cron_entries(&enabled));
+        let rc_updates = shell::rc_updates(&home, has_shell_snippets(&enabled));
+        let chosen_rc_updates = shell::rc_updates(&home, has_shell_snippets(chosen));
+        let vimrc = fs::read_link(home.join(".vimrc"));
+
+        installed.unwrap();
+        assert_eq!(vimrc.unwrap(), root.join("vimrc"));
+        assert_eq!(crontab_after, crontab);
+        assert!(rc_updates.unwrap().is_empty());
+        // Going by the chosen dotfiles alone would drop the others' blocks.
+        assert_ne!(cron::with_block(&crontab, &cron_entries(chosen)), crontab);
+        assert_eq!(chosen_rc_updates.unwrap().len(), 1);
+    }
+}
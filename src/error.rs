@@ -0,0 +1,208 @@
+//! `Error`, for everything which can go wrong in dfm, wrapping each
+//! module's own error type. Each kind of error has a stable code, shown in
+//! diagnostics like `error[dotfile]: while linking `/home/me/.bashrc``, so
+//! scripts and bug reports can tell errors apart without matching messages.
+//! Codes are never changed or reused.
+
+use std::io;
+
+use thiserror::Error;
+
+use crate::archive::ArchiveError;
+use crate::bare_git::BareGitError;
+use crate::condition::ConditionError;
+use crate::config::{ConfigReadError, DotfilesReadError};
+use crate::cron::CronError;
+use crate::defaults::DefaultsError;
+use crate::deploy::DeployError;
+use crate::diagnostic::{Diagnose, Diagnostic};
+use crate::dotbot::DotbotError;
+use crate::dotfile::DotfileError;
+use crate::facts::FactsError;
+use crate::git::GitError;
+#[cfg(feature = "nix")]
+use crate::nix::NixEvalError;
+use crate::packages::PackagesError;
+use crate::plugin::PluginError;
+use crate::stow::StowError;
+use crate::systemd::SystemdError;
+use crate::template::TemplateError;
+use crate::vscode::VsCodeError;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("{0}")]
+    ConfigRead(#[from] ConfigReadError),
+
+    #[error("{0}")]
+    DotfilesRead(#[from] DotfilesReadError),
+
+    #[error("{0}")]
+    Dotfile(#[from] DotfileError),
+
+    #[error("{0}")]
+    Condition(#[from] ConditionError),
+
+    #[error("{0}")]
+    Facts(#[from] FactsError),
+
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Template(#[from] TemplateError),
+
+    #[cfg(feature = "nix")]
+    #[error("{0}")]
+    NixEval(#[from] NixEvalError),
+
+    #[error("{0}")]
+    Stow(#[from] StowError),
+
+    #[error("{0}")]
+    Dotbot(#[from] DotbotError),
+
+    #[error("{0}")]
+    BareGit(#[from] BareGitError),
+
+    #[error("{0}")]
+    Git(#[from] GitError),
+
+    #[error("{0}")]
+    Archive(#[from] ArchiveError),
+
+    #[error("{0}")]
+    Deploy(#[from] DeployError),
+
+    #[error("{0}")]
+    Packages(#[from] PackagesError),
+
+    #[error("{0}")]
+    Systemd(#[from] SystemdError),
+
+    #[error("{0}")]
+    Defaults(#[from] DefaultsError),
+
+    #[error("{0}")]
+    Cron(#[from] CronError),
+
+    #[error("{0}")]
+    VsCode(#[from] VsCodeError),
+
+    #[error("{0}")]
+    Plugin(#[from] PluginError),
+
+    /// A task on tokio's blocking thread pool panicked or was cancelled;
+    /// see the `asynchronous` module.
+    #[cfg(feature = "async")]
+    #[error("{0}")]
+    Join(#[from] tokio::task::JoinError),
+
+    #[error("no Brewfile to install")]
+    NoBrewfile,
+
+    #[error("no repository to set up from")]
+    NoRepoUrl,
+
+    #[error("the dotfiles list has {0} invalid elements")]
+    InvalidList(usize),
+
+    #[error("couldn't install {0} of the dotfiles: something else is in their place")]
+    Conflicts(usize),
+
+    /// A question needed answering, but this build can't ask it.
+    #[error("asking questions needs the `interactive` feature")]
+    NotInteractive,
+}
+
+impl Error {
+    /// The error's code, e.g. `nix` for errors evaluating Nix.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "io",
+            Error::ConfigRead(_) => "config",
+            Error::DotfilesRead(_) => "dotfiles-list",
+            Error::Dotfile(_) => "dotfile",
+            Error::Condition(_) => "condition",
+            Error::Facts(_) => "facts",
+            Error::Json(_) => "json",
+            Error::Template(_) => "template",
+            #[cfg(feature = "nix")]
+            Error::NixEval(_) => "nix",
+            Error::Stow(_) => "stow",
+            Error::Dotbot(_) => "dotbot",
+            Error::BareGit(_) => "bare-git",
+            Error::Git(_) => "git",
+            Error::Archive(_) => "archive",
+            Error::Deploy(_) => "deploy",
+            Error::Packages(_) => "packages",
+            Error::Systemd(_) => "systemd",
+            Error::Defaults(_) => "defaults",
+            Error::Cron(_) => "cron",
+            Error::VsCode(_) => "vscode",
+            Error::Plugin(_) => "plugin",
+            #[cfg(feature = "async")]
+            Error::Join(_) => "async",
+            Error::NoBrewfile => "no-brewfile",
+            Error::NoRepoUrl => "no-repo-url",
+            Error::InvalidList(_) => "invalid-list",
+            Error::Conflicts(_) => "conflicts",
+            Error::NotInteractive => "not-interactive",
+        }
+    }
+}
+
+impl Diagnose for Error {
+    fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = match self {
+            Error::ConfigRead(err) => err.diagnostic(),
+            Error::DotfilesRead(err) => err.diagnostic(),
+            #[cfg(feature = "nix")]
+            Error::NixEval(err) => err.diagnostic(),
+            Error::NoBrewfile => Diagnostic::from_error(self)
+                .help("set `brewfile` in the configuration file to the Brewfile's path"),
+            Error::NoRepoUrl => Diagnostic::from_error(self)
+                .help("pass `--from`, or set `dotfile_repo_url` in the configuration file"),
+            Error::InvalidList(_) => Diagnostic::from_error(self)
+                .help("each element must be a path or an object with a `repo` field"),
+            Error::Conflicts(_) => Diagnostic::from_error(self).help(
+                "see how they differ with `dfm diff`, then move them aside or overwrite them with `dfm link`",
+            ),
+            Error::NotInteractive => Diagnostic::from_error(self)
+                .help("rebuild dfm with the `interactive` feature, or leave out `--interactive`"),
+            _ => Diagnostic::from_error(self),
+        };
+        diagnostic.code(self.code())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::dotfile::DotfileAction;
+
+    #[test]
+    fn error_diagnostic() {
+        let err = Error::from(DotfileError {
+            action: DotfileAction::Linking,
+            path: PathBuf::from("/home/me/.config/fish/fishfile"),
+            source: io::Error::new(io::ErrorKind::PermissionDenied, "permission denied"),
+        });
+        assert_eq!(err.code(), "dotfile");
+        assert_eq!(
+            err.diagnostic().render(false),
+            "error[dotfile]: while linking `/home/me/.config/fish/fishfile`\ncaused by: permission denied"
+        );
+        assert_eq!(
+            Error::Conflicts(2).diagnostic().render(false),
+            "error[conflicts]: couldn't install 2 of the dotfiles: something else is in their place\nhelp: see how they differ with `dfm diff`, then move them aside or overwrite them with `dfm link`"
+        );
+    }
+}
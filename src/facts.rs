@@ -1,73 +1,809 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use futures::executor::{block_on, block_on_stream};
-use heim::host::{Arch, Platform as HeimPlatform, User as HeimUser};
-use heim::net::{Address, Nic};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize, Serializer};
+use thiserror::Error;
 
-#[derive(Debug)]
-pub struct User(HeimUser);
+use crate::provider::{default_provider, FactsProvider, ProviderError};
+use crate::spinner::Spinner;
+use crate::subnet::Subnet;
+use crate::util;
+use crate::util::{output_with_timeout, shell_command};
+
+/// The names of the built-in scalar facts; see `Facts::get`.
+pub const BUILTIN_FACTS: &[&str] = &[
+    "os",
+    "os_release",
+    "os_version",
+    "arch",
+    "hostname",
+    "user",
+    "is_wsl",
+    "has_brew",
+    "has_apt",
+    "has_pacman",
+    "display_server",
+    "desktop",
+    "shell",
+    "terminal",
+    "is_ssh",
+    "is_container",
+    "is_virtual_machine",
+    "has_battery",
+    "machine_class",
+    "distro_id",
+    "distro_version",
+    "id_like",
+    "is_root",
+    "is_elevated",
+    "native_arch",
+    "libc",
+    "is_rosetta",
+    "target_triple",
+];
+
+/// How long commands for user-defined facts may run by default.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A user logged in to the system.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+}
 
-impl From<HeimUser> for User {
-    fn from(u: HeimUser) -> Self {
-        User(u)
+impl From<&str> for User {
+    fn from(username: &str) -> Self {
+        User {
+            username: username.to_string(),
+        }
     }
 }
 
+/// A network interface and its addresses.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Nic {
+    pub name: String,
+    pub addresses: Vec<IpAddr>,
+    pub is_up: bool,
+    pub is_loopback: bool,
+}
+
+/// Operating system information.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlatformInfo {
+    pub system: String,
+    pub release: String,
+    pub version: String,
+    pub hostname: String,
+    pub arch: String,
+}
+
+/// An error while collecting facts.
+#[derive(Error, Debug)]
+pub enum FactsError {
+    #[error("failed to collect system facts: {0}")]
+    Provider(#[from] ProviderError),
+
+    #[error("failed to run command for fact `{name}`: {source}")]
+    Command { name: String, source: io::Error },
+
+    #[error("command for fact `{name}` failed ({status}): {stderr}")]
+    CommandFailed {
+        name: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+
+    #[error("command for fact `{name}` timed out after {timeout:?}")]
+    CommandTimeout { name: String, timeout: Duration },
+}
+
+/// Every fact, as collected by `Facts::model`. This is what `dotfile-manager
+/// facts --json` prints, what templates see as `facts`, and what Nix files
+/// receive as the `facts` argument (without `env`).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FactsModel {
+    /// The facts in `BUILTIN_FACTS`, as they appear in conditions.
+    #[serde(flatten)]
+    pub builtin: BTreeMap<String, FactValue>,
+    /// The names of the logged-in users.
+    pub users: Vec<String>,
+    /// Every address of every network interface.
+    pub addresses: Vec<IpAddr>,
+    /// Network interfaces, by name.
+    pub networks: BTreeMap<String, Nic>,
+    pub platform: PlatformInfo,
+    /// Environment variables; omitted if empty.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// User-defined static facts.
+    pub vars: BTreeMap<String, String>,
+    /// The output of user-defined fact commands.
+    pub custom: BTreeMap<String, String>,
+}
+
+impl Serialize for Facts {
+    /// Serialize every fact; see `Facts::model`.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.model()
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+/// A user-defined fact whose value is the output of a shell command.
+#[derive(Debug, Clone)]
+struct CommandFact {
+    command: String,
+    output: OnceCell<String>,
+}
+
+/// A cached `CommandFact`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedCommandFact {
+    command: String,
+    output: String,
+}
+
 /// System facts to be used for deciding dotfile status.
+///
+/// Each category of facts (users, networks, platform) is only collected the
+/// first time it's needed. Facts can be shared between threads, and clones
+/// keep whatever has been collected so far.
+#[derive(Debug, Clone)]
 pub struct Facts {
+    /// Where system facts come from.
+    provider: Arc<dyn FactsProvider>,
+    /// When these facts were collected.
+    collected: SystemTime,
     /// Map from usernames to user info.
-    users: HashMap<String, HeimUser>,
+    users: OnceCell<HashMap<String, User>>,
     /// Map from network interface names to interface info.
-    networks: HashMap<String, Nic>,
-    platform: HeimPlatform,
+    networks: OnceCell<HashMap<String, Nic>>,
+    platform: OnceCell<PlatformInfo>,
+    /// User-defined facts, by name.
+    commands: HashMap<String, CommandFact>,
+    /// How long commands for user-defined facts may run.
+    command_timeout: Duration,
+    /// User-defined static values, by name.
+    vars: BTreeMap<String, String>,
+    /// Values which replace facts of the same name; see `with_overrides`.
+    overrides: BTreeMap<String, FactValue>,
+}
+
+/// The on-disk format of the facts cache; categories which were never
+/// collected are omitted.
+#[derive(Serialize, Deserialize)]
+struct CachedFacts {
+    collected: SystemTime,
+    users: Option<HashMap<String, User>>,
+    networks: Option<HashMap<String, Nic>>,
+    platform: Option<PlatformInfo>,
+    #[serde(default)]
+    commands: HashMap<String, CachedCommandFact>,
+}
+
+impl From<CachedFacts> for Facts {
+    fn from(c: CachedFacts) -> Self {
+        fn cell<T>(value: Option<T>) -> OnceCell<T> {
+            value.map(OnceCell::from).unwrap_or_default()
+        }
+        Facts {
+            provider: default_provider().into(),
+            collected: c.collected,
+            users: cell(c.users),
+            networks: cell(c.networks),
+            platform: cell(c.platform),
+            commands: c
+                .commands
+                .into_iter()
+                .map(|(name, c)| {
+                    (
+                        name,
+                        CommandFact {
+                            command: c.command,
+                            output: c.output.into(),
+                        },
+                    )
+                })
+                .collect(),
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            vars: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for Facts {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Facts {
-    pub fn new() -> heim::Result<Self> {
-        Ok(Self {
-            users: block_on_stream(heim::host::users())
-                .map(|user| user.map(|u| (u.username().to_string(), u)))
-                .collect::<heim::Result<_>>()?,
-            networks: block_on_stream(heim::net::nic())
-                .map(|nic| nic.map(|n| (n.name().to_string(), n)))
-                .collect::<heim::Result<_>>()?,
-            platform: block_on(heim::host::platform())?,
+    pub fn new() -> Self {
+        Self {
+            provider: default_provider().into(),
+            collected: SystemTime::now(),
+            users: OnceCell::new(),
+            networks: OnceCell::new(),
+            platform: OnceCell::new(),
+            commands: HashMap::new(),
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            vars: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+        }
+    }
+
+    /// Facts made from the given values rather than collected from the
+    /// system, e.g. for tests. Combine with `with_overrides` to pin down the
+    /// facts which aren't covered here, like `is_wsl`.
+    pub fn from_parts(
+        platform: PlatformInfo,
+        users: impl IntoIterator<Item = User>,
+        networks: impl IntoIterator<Item = Nic>,
+    ) -> Self {
+        Self {
+            platform: platform.into(),
+            users: users
+                .into_iter()
+                .map(|user| (user.username.clone(), user))
+                .collect::<HashMap<_, _>>()
+                .into(),
+            networks: networks
+                .into_iter()
+                .map(|nic| (nic.name.clone(), nic))
+                .collect::<HashMap<_, _>>()
+                .into(),
+            ..Self::new()
+        }
+    }
+
+    /// Define user-defined facts whose values are the (trimmed) standard
+    /// output of the given shell commands, which are run at most once and are
+    /// killed after `timeout`. Cached output is kept only if the command
+    /// hasn't changed.
+    pub fn with_commands(mut self, commands: &BTreeMap<String, String>, timeout: Duration) -> Self {
+        let mut cached = std::mem::take(&mut self.commands);
+        self.commands = commands
+            .iter()
+            .map(|(name, command)| {
+                let fact = match cached.remove(name) {
+                    Some(fact) if &fact.command == command => fact,
+                    _ => CommandFact {
+                        command: command.clone(),
+                        output: OnceCell::new(),
+                    },
+                };
+                (name.clone(), fact)
+            })
+            .collect();
+        self.command_timeout = timeout;
+        self
+    }
+
+    /// Load facts from the cache file at `path` if it was written less than
+    /// `ttl` ago; otherwise, start from scratch.
+    ///
+    /// The cache is best-effort: an unreadable cache is ignored.
+    pub fn cached(path: &Path, ttl: Duration) -> Self {
+        Self::read_cache(path)
+            .filter(|facts| {
+                facts
+                    .collected
+                    .elapsed()
+                    .map(|age| age < ttl)
+                    .unwrap_or(false)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Collect system facts with `provider` rather than the default for this
+    /// build.
+    pub fn with_provider(mut self, provider: impl FactsProvider + 'static) -> Self {
+        self.provider = Arc::new(provider);
+        self
+    }
+
+    /// Define user-defined facts with static values.
+    pub fn with_vars(mut self, vars: &BTreeMap<String, String>) -> Self {
+        self.vars = vars.clone();
+        self
+    }
+
+    /// Pretend facts have the given values, e.g. to check which dotfiles
+    /// would be installed on another machine. The values `true` and `false`
+    /// are booleans; everything else is a string.
+    ///
+    /// Overrides apply wherever facts are looked up by name: in conditions,
+    /// templates, and Nix arguments.
+    pub fn with_overrides(mut self, overrides: &BTreeMap<String, String>) -> Self {
+        self.overrides = overrides
+            .iter()
+            .map(|(name, value)| {
+                let value = match value.as_str() {
+                    "true" => true.into(),
+                    "false" => false.into(),
+                    value => value.into(),
+                };
+                (name.clone(), value)
+            })
+            .collect();
+        self
+    }
+
+    fn read_cache(path: &Path) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        serde_json::from_reader::<_, CachedFacts>(BufReader::new(file))
+            .ok()
+            .map(Into::into)
+    }
+
+    /// Write the facts collected so far to the cache file at `path`; see
+    /// `util::write_cache`.
+    pub fn write_cache(&self, path: &Path) {
+        let cached = CachedFacts {
+            collected: self.collected,
+            users: self.users.get().cloned(),
+            networks: self.networks.get().cloned(),
+            platform: self.platform.get().cloned(),
+            commands: self
+                .commands
+                .iter()
+                .filter_map(|(name, fact)| {
+                    fact.output.get().map(|output| {
+                        (
+                            name.clone(),
+                            CachedCommandFact {
+                                command: fact.command.clone(),
+                                output: output.clone(),
+                            },
+                        )
+                    })
+                })
+                .collect(),
+        };
+        if let Ok(json) = serde_json::to_vec(&cached) {
+            util::write_cache(path, &json);
+        }
+    }
+
+    /// Collect every category of facts up front, concurrently; see
+    /// `FactsProvider::collect`.
+    pub fn collect() -> Result<Self, ProviderError> {
+        let facts = Self::new();
+        facts.collect_missing()?;
+        Ok(facts)
+    }
+
+    /// Collect every category of facts now, concurrently, unless they've all
+    /// been collected already, e.g. read from a cache.
+    pub fn collect_missing(&self) -> Result<(), ProviderError> {
+        if self.users.get().is_some()
+            && self.networks.get().is_some()
+            && self.platform.get().is_some()
+        {
+            return Ok(());
+        }
+        let (users, networks, platform) = {
+            let _spinner = Spinner::start("Collecting system facts");
+            self.provider.collect()?
+        };
+        // Categories which were already collected keep their values.
+        let _ = self.users.set(users);
+        let _ = self.networks.set(networks);
+        let _ = self.platform.set(platform);
+        Ok(())
+    }
+
+    fn platform(&self) -> Result<&PlatformInfo, ProviderError> {
+        self.platform.get_or_try_init(|| {
+            let _spinner = Spinner::start("Collecting system facts");
+            self.provider.platform()
+        })
+    }
+
+    fn networks(&self) -> Result<&HashMap<String, Nic>, ProviderError> {
+        self.networks.get_or_try_init(|| {
+            let _spinner = Spinner::start("Collecting system facts");
+            self.provider.networks()
+        })
+    }
+
+    pub fn os(&self) -> Result<OsType, ProviderError> {
+        Ok(self.platform()?.system.as_str().into())
+    }
+
+    pub fn os_release(&self) -> Result<&str, ProviderError> {
+        Ok(&self.platform()?.release)
+    }
+
+    pub fn os_version(&self) -> Result<&str, ProviderError> {
+        Ok(&self.platform()?.version)
+    }
+
+    pub fn arch(&self) -> Result<&str, ProviderError> {
+        Ok(&self.platform()?.arch)
+    }
+
+    /// The hardware's architecture, named like in Rust target triples (e.g.
+    /// `aarch64` rather than `arm64`). Unlike `arch`, this sees through
+    /// Rosetta.
+    pub fn native_arch(&self) -> Result<&str, ProviderError> {
+        if self.is_rosetta() {
+            Ok("aarch64")
+        } else {
+            Ok(normalize_arch(self.arch()?))
+        }
+    }
+
+    /// The C library on Linux: `gnu` or `musl`; `None` on other systems.
+    pub fn libc(&self) -> Result<Option<&'static str>, ProviderError> {
+        Ok(match self.os()? {
+            OsType::Linux => Some(detect_libc(
+                fs::read_dir("/lib")
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned()),
+            )),
+            _ => None,
         })
     }
 
-    pub fn os(&self) -> OsType {
-        self.platform.system().into()
+    /// Whether we're an x86_64 process being translated by Rosetta on an
+    /// Apple Silicon Mac.
+    pub fn is_rosetta(&self) -> bool {
+        cfg!(target_os = "macos")
+            && std::process::Command::new("sysctl")
+                .args(["-n", "sysctl.proc_translated"])
+                .output()
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim() == "1")
+                .unwrap_or(false)
+    }
+
+    /// The Rust-style target triple of this machine, e.g.
+    /// `x86_64-unknown-linux-musl` or `aarch64-apple-darwin`; `None` for
+    /// unrecognized operating systems.
+    pub fn target_triple(&self) -> Result<Option<String>, ProviderError> {
+        Ok(target_triple(
+            self.native_arch()?,
+            &self.os()?,
+            self.libc()?,
+        ))
     }
 
-    pub fn os_release(&self) -> &str {
-        self.platform.release()
+    pub fn hostname(&self) -> Result<&str, ProviderError> {
+        Ok(&self.platform()?.hostname)
     }
 
-    pub fn os_version(&self) -> &str {
-        self.platform.version()
+    /// Map from usernames to info about users logged in to the system.
+    pub fn users(&self) -> Result<&HashMap<String, User>, ProviderError> {
+        self.users.get_or_try_init(|| {
+            let _spinner = Spinner::start("Collecting system facts");
+            self.provider.users()
+        })
     }
 
-    pub fn arch(&self) -> Arch {
-        self.platform.architecture()
+    pub fn network(&self, interface: &str) -> Result<Option<&Nic>, ProviderError> {
+        Ok(self.networks()?.get(interface))
     }
 
-    pub fn hostname(&self) -> &str {
-        self.platform.hostname()
+    pub fn addresses(&self) -> Result<Vec<IpAddr>, ProviderError> {
+        Ok(self
+            .networks()?
+            .values()
+            .flat_map(|nic| nic.addresses.iter().cloned())
+            .collect())
     }
 
-    pub fn network(&self, interface: &str) -> Option<&Nic> {
-        self.networks.get(interface)
+    /// Whether any interface has an address in `subnet`.
+    pub fn in_subnet(&self, subnet: &Subnet) -> Result<bool, ProviderError> {
+        Ok(self.addresses()?.iter().any(|addr| subnet.contains(addr)))
     }
 
-    pub fn addresses(&self) -> Vec<Address> {
-        self.networks.values().map(|nic| nic.address()).collect()
+    /// Whether any interface whose name satisfies `matches` is up.
+    pub fn interface_up(&self, matches: impl Fn(&str) -> bool) -> Result<bool, ProviderError> {
+        Ok(self
+            .networks()?
+            .values()
+            .any(|nic| nic.is_up && matches(&nic.name)))
     }
 
     pub fn env(&self, var: &str) -> Option<String> {
         env::var(var).ok()
     }
 
+    /// Whether we're running under the Windows Subsystem for Linux.
+    pub fn is_wsl(&self) -> bool {
+        detect_wsl(
+            fs::read_to_string("/proc/sys/kernel/osrelease")
+                .ok()
+                .as_deref(),
+            |var| env::var_os(var).is_some(),
+        )
+    }
+
+    /// The display server of the current session: `wayland`, `x11`, or `None`
+    /// if there's no graphical session (or we can't tell).
+    pub fn display_server(&self) -> Option<&'static str> {
+        detect_display_server(|var| env::var(var).ok())
+    }
+
+    /// The desktop environment or window manager of the current session,
+    /// lowercased; e.g. `gnome`, `kde`, `sway`, or `None` if there isn't one
+    /// (or we can't tell).
+    pub fn desktop(&self) -> Option<String> {
+        detect_desktop(|var| env::var(var).ok())
+    }
+
+    /// The fields of the Linux distribution's `os-release` file, e.g. `ID`,
+    /// `VERSION_ID`, and `ID_LIKE`; empty on other systems.
+    pub fn distro(&self) -> BTreeMap<String, String> {
+        ["/etc/os-release", "/usr/lib/os-release"]
+            .iter()
+            .find_map(|path| fs::read_to_string(path).ok())
+            .map(|contents| parse_os_release(&contents))
+            .unwrap_or_default()
+    }
+
+    fn distro_field(&self, field: &str) -> Option<String> {
+        self.distro().remove(field)
+    }
+
+    /// The path of the current user's login shell, from `/etc/passwd` or
+    /// `$SHELL`.
+    pub fn login_shell(&self) -> Option<PathBuf> {
+        fs::read_to_string("/etc/passwd")
+            .ok()
+            .and_then(|passwd| passwd_shell(&passwd, &self.user()))
+            .or_else(|| env::var_os("SHELL").map(PathBuf::from))
+    }
+
+    /// The name of the current user's login shell, e.g. `fish` or `zsh`.
+    pub fn shell(&self) -> Option<String> {
+        self.login_shell().and_then(|shell| {
+            shell
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+    }
+
+    /// The terminal emulator we're running in, lowercased, e.g. `kitty` or
+    /// `iterm.app`; `None` if we can't tell.
+    pub fn terminal(&self) -> Option<String> {
+        detect_terminal(|var| env::var(var).ok())
+    }
+
+    /// Whether we're in a session started over SSH.
+    pub fn is_ssh(&self) -> bool {
+        env::var_os("SSH_CONNECTION").is_some() || env::var_os("SSH_TTY").is_some()
+    }
+
+    /// Whether we're running inside a container (Docker, Podman, LXC,
+    /// Kubernetes, ...).
+    pub fn is_container(&self) -> bool {
+        detect_container(
+            |path| Path::new(path).exists(),
+            fs::read_to_string("/proc/1/cgroup").ok().as_deref(),
+            |var| env::var_os(var).is_some(),
+        )
+    }
+
+    /// Whether we're running under a hypervisor.
+    pub fn is_virtual_machine(&self) -> bool {
+        detect_virtual_machine(fs::read_to_string("/proc/cpuinfo").ok().as_deref())
+    }
+
+    /// Whether this machine has a battery.
+    pub fn has_battery(&self) -> bool {
+        fs::read_dir("/sys/class/power_supply")
+            .map(|entries| {
+                entries.filter_map(Result::ok).any(|entry| {
+                    fs::read_to_string(entry.path().join("type"))
+                        .map(|kind| kind.trim() == "Battery")
+                        .unwrap_or(false)
+                })
+            })
+            .unwrap_or(false)
+    }
+
+    /// A rough guess at what kind of machine this is: `laptop`, `desktop`,
+    /// `server`, or `container`.
+    ///
+    /// This uses the chassis type reported by the firmware where it's
+    /// available, and otherwise guesses from the presence of a battery and a
+    /// graphical session; a headless machine is assumed to be a server.
+    pub fn machine_class(&self) -> &'static str {
+        classify_machine(&MachineHints {
+            is_container: self.is_container(),
+            has_battery: self.has_battery(),
+            chassis_type: fs::read_to_string("/sys/class/dmi/id/chassis_type")
+                .ok()
+                .and_then(|chassis| chassis.trim().parse().ok()),
+            is_graphical: self.display_server().is_some() && !self.is_ssh(),
+        })
+    }
+
+    /// Whether we're running as root, i.e. with an effective user ID of 0.
+    /// Always false on Windows; see `is_elevated`.
+    pub fn is_root(&self) -> bool {
+        #[cfg(unix)]
+        return unsafe { libc::geteuid() } == 0;
+        #[cfg(not(unix))]
+        return false;
+    }
+
+    /// Whether we're running with administrative privileges: as root on Unix,
+    /// or from an elevated process on Windows.
+    pub fn is_elevated(&self) -> bool {
+        #[cfg(windows)]
+        return windows_is_elevated();
+        #[cfg(not(windows))]
+        return self.is_root();
+    }
+
+    /// Whether an executable named `name` is on the `PATH`.
+    pub fn command_exists(&self, name: &str) -> bool {
+        which::which(name).is_ok()
+    }
+
+    /// The name of the user running dotfile-manager.
+    pub fn user(&self) -> String {
+        whoami::username()
+    }
+
+    /// The value of the user-defined fact `name`, running its command if it
+    /// hasn't been run yet; `None` if there's no user-defined fact by that
+    /// name.
+    pub fn command(&self, name: &str) -> Result<Option<&str>, FactsError> {
+        let fact = match self.commands.get(name) {
+            Some(fact) => fact,
+            None => return Ok(None),
+        };
+        fact.output
+            .get_or_try_init(|| {
+                let _spinner =
+                    Spinner::start(&format!("Running the command for the fact `{}`", name));
+                let output =
+                    output_with_timeout(&mut shell_command(&fact.command), self.command_timeout)
+                        .map_err(|source| FactsError::Command {
+                            name: name.to_string(),
+                            source,
+                        })?
+                        .ok_or_else(|| FactsError::CommandTimeout {
+                            name: name.to_string(),
+                            timeout: self.command_timeout,
+                        })?;
+                if output.status.success() {
+                    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+                } else {
+                    Err(FactsError::CommandFailed {
+                        name: name.to_string(),
+                        status: output.status,
+                        stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                    })
+                }
+            })
+            .map(|output| Some(output.as_str()))
+    }
+
+    /// The value of the user-defined static fact `name`.
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+
+    /// Facts to pass to Nix dotfiles lists as `--argstr` arguments: `hostname`,
+    /// `os`, `arch`, `user`, and user-defined static facts.
+    pub fn nix_args(&self) -> Result<BTreeMap<String, String>, FactsError> {
+        let mut args = self.vars.clone();
+        for name in &["hostname", "os", "arch", "user"] {
+            if let Some(value) = self.get(name)? {
+                args.insert(name.to_string(), value.to_string());
+            }
+        }
+        // Everything else is passed as a single JSON object, for use with
+        // `builtins.fromJSON`. Nix can read the environment itself.
+        let model = FactsModel {
+            env: BTreeMap::new(),
+            ..self.model()?
+        };
+        args.insert(
+            "facts".to_string(),
+            serde_json::to_string(&model).expect("facts are always serializable"),
+        );
+        Ok(args)
+    }
+
+    /// Every fact, as a single structured value. This collects every category
+    /// of facts and runs every user-defined fact's command.
+    pub fn model(&self) -> Result<FactsModel, FactsError> {
+        let mut builtin = BTreeMap::new();
+        for name in BUILTIN_FACTS {
+            if let Some(value) = self.get(name)? {
+                builtin.insert(name.to_string(), value);
+            }
+        }
+        let mut custom = BTreeMap::new();
+        for name in self.commands.keys() {
+            if let Some(output) = self.command(name)? {
+                custom.insert(name.clone(), output.to_string());
+            }
+        }
+        let mut users: Vec<String> = self.users()?.keys().cloned().collect();
+        users.sort();
+        Ok(FactsModel {
+            builtin,
+            users,
+            addresses: self.addresses()?,
+            networks: self.networks()?.clone().into_iter().collect(),
+            platform: self.platform()?.clone(),
+            env: env::vars().collect(),
+            vars: self.vars.clone(),
+            custom,
+        })
+    }
+
+    /// All facts as a JSON object, for use in templates; see `model`.
+    pub fn template_context(&self) -> Result<serde_json::Value, FactsError> {
+        Ok(serde_json::to_value(self.model()?).expect("facts are always serializable"))
+    }
+
+    /// Look up a fact by the name used in conditions; `None` if there's no
+    /// fact by that name. Overrides take precedence over everything, built-in
+    /// facts take precedence over user-defined facts, and facts defined by
+    /// commands take precedence over static ones.
+    pub fn get(&self, name: &str) -> Result<Option<FactValue>, FactsError> {
+        if let Some(value) = self.overrides.get(name) {
+            return Ok(Some(value.clone()));
+        }
+        Ok(match name {
+            "os" => Some(self.os()?.to_string().into()),
+            "os_release" => Some(self.os_release()?.into()),
+            "os_version" => Some(self.os_version()?.into()),
+            "arch" => Some(self.arch()?.into()),
+            "hostname" => Some(self.hostname()?.into()),
+            "user" => Some(self.user().into()),
+            "is_wsl" => Some(self.is_wsl().into()),
+            "has_brew" => Some(self.command_exists("brew").into()),
+            "has_apt" => Some(self.command_exists("apt").into()),
+            "has_pacman" => Some(self.command_exists("pacman").into()),
+            "display_server" => Some(self.display_server().into()),
+            "desktop" => Some(self.desktop().into()),
+            "shell" => Some(self.shell().into()),
+            "terminal" => Some(self.terminal().into()),
+            "is_ssh" => Some(self.is_ssh().into()),
+            "is_container" => Some(self.is_container().into()),
+            "is_virtual_machine" => Some(self.is_virtual_machine().into()),
+            "has_battery" => Some(self.has_battery().into()),
+            "machine_class" => Some(self.machine_class().into()),
+            "distro_id" => Some(self.distro_field("ID").into()),
+            "distro_version" => Some(self.distro_field("VERSION_ID").into()),
+            "id_like" => Some(self.distro_field("ID_LIKE").into()),
+            "is_root" => Some(self.is_root().into()),
+            "is_elevated" => Some(self.is_elevated().into()),
+            "native_arch" => Some(self.native_arch()?.into()),
+            "libc" => Some(self.libc()?.into()),
+            "is_rosetta" => Some(self.is_rosetta().into()),
+            "target_triple" => Some(self.target_triple()?.into()),
+            name => self
+                .command(name)?
+                .or_else(|| self.var(name))
+                .map(Into::into),
+        })
+    }
+
     // pub fn load<'vm>(&'vm self) -> impl Fn(&'vm Thread) -> vm::Result<vm::ExternModule> {
     //     |vm| {
     //         vm::ExternModule::new(
@@ -81,6 +817,273 @@ impl Facts {
     // }
 }
 
+/// WSL kernels have "microsoft" in their release string (e.g.
+/// `4.19.104-microsoft-standard`), and WSL sets `WSL_DISTRO_NAME` in the
+/// environment.
+fn detect_wsl(kernel_release: Option<&str>, env_is_set: impl Fn(&str) -> bool) -> bool {
+    kernel_release
+        .map(|release| release.to_lowercase().contains("microsoft"))
+        .unwrap_or(false)
+        || env_is_set("WSL_DISTRO_NAME")
+        || env_is_set("WSL_INTEROP")
+}
+
+fn detect_display_server(env: impl Fn(&str) -> Option<String>) -> Option<&'static str> {
+    match env("XDG_SESSION_TYPE").as_deref() {
+        Some("wayland") => return Some("wayland"),
+        Some("x11") => return Some("x11"),
+        _ => {}
+    }
+    if env("WAYLAND_DISPLAY").is_some() {
+        Some("wayland")
+    } else if env("DISPLAY").is_some() {
+        Some("x11")
+    } else {
+        None
+    }
+}
+
+fn detect_desktop(env: impl Fn(&str) -> Option<String>) -> Option<String> {
+    // `XDG_CURRENT_DESKTOP` is a colon-separated list, e.g. `ubuntu:GNOME`;
+    // the last entry is the most generic.
+    if let Some(desktop) = env("XDG_CURRENT_DESKTOP")
+        .and_then(|desktops| desktops.rsplit(':').next().map(str::to_lowercase))
+        .filter(|desktop| !desktop.is_empty())
+    {
+        return Some(desktop);
+    }
+    // Standalone window managers often don't set `XDG_CURRENT_DESKTOP`, but
+    // they do advertise their IPC sockets.
+    for (var, wm) in &[
+        ("SWAYSOCK", "sway"),
+        ("I3SOCK", "i3"),
+        ("HYPRLAND_INSTANCE_SIGNATURE", "hyprland"),
+    ] {
+        if env(var).is_some() {
+            return Some(wm.to_string());
+        }
+    }
+    env("DESKTOP_SESSION")
+        .map(|session| session.to_lowercase())
+        .filter(|session| !session.is_empty())
+}
+
+/// Docker and Podman leave marker files in the container's root, systemd
+/// expects container managers to set `$container`, and everything else
+/// usually shows up in init's cgroup.
+fn detect_container(
+    exists: impl Fn(&str) -> bool,
+    init_cgroup: Option<&str>,
+    env_is_set: impl Fn(&str) -> bool,
+) -> bool {
+    exists("/.dockerenv")
+        || exists("/run/.containerenv")
+        || env_is_set("container")
+        || init_cgroup
+            .map(|cgroup| {
+                ["docker", "kubepods", "lxc", "containerd", "libpod"]
+                    .iter()
+                    .any(|runtime| cgroup.contains(runtime))
+            })
+            .unwrap_or(false)
+}
+
+/// Guests get the `hypervisor` CPU flag.
+fn detect_virtual_machine(cpuinfo: Option<&str>) -> bool {
+    cpuinfo
+        .map(|cpuinfo| {
+            cpuinfo
+                .lines()
+                .filter(|line| line.starts_with("flags"))
+                .any(|line| line.split_whitespace().any(|flag| flag == "hypervisor"))
+        })
+        .unwrap_or(false)
+}
+
+struct MachineHints {
+    is_container: bool,
+    has_battery: bool,
+    /// The SMBIOS chassis type, if the firmware reports one.
+    chassis_type: Option<u32>,
+    is_graphical: bool,
+}
+
+fn classify_machine(hints: &MachineHints) -> &'static str {
+    if hints.is_container {
+        return "container";
+    }
+    // See the SMBIOS spec, section 7.4.1.
+    match hints.chassis_type {
+        Some(8..=10) | Some(14) | Some(30..=32) => return "laptop",
+        Some(3..=7) | Some(13) | Some(15) | Some(16) | Some(24) | Some(35) | Some(36) => {
+            return "desktop"
+        }
+        Some(17) | Some(23) | Some(25) | Some(28) | Some(29) => return "server",
+        _ => {}
+    }
+    if hints.has_battery {
+        "laptop"
+    } else if hints.is_graphical {
+        "desktop"
+    } else {
+        "server"
+    }
+}
+
+#[cfg(windows)]
+fn windows_is_elevated() -> bool {
+    use std::mem;
+    use std::ptr;
+
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+    use winapi::um::securitybaseapi::GetTokenInformation;
+    use winapi::um::winnt::{TokenElevation, HANDLE, TOKEN_ELEVATION, TOKEN_QUERY};
+
+    unsafe {
+        let mut token: HANDLE = ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return false;
+        }
+        let mut elevation: TOKEN_ELEVATION = mem::zeroed();
+        let mut size = mem::size_of::<TOKEN_ELEVATION>() as u32;
+        let ok = GetTokenInformation(
+            token,
+            TokenElevation,
+            &mut elevation as *mut TOKEN_ELEVATION as *mut _,
+            size,
+            &mut size,
+        );
+        CloseHandle(token);
+        ok != 0 && elevation.TokenIsElevated != 0
+    }
+}
+
+/// Normalize the many names for each architecture to the ones used in Rust
+/// target triples.
+fn normalize_arch(arch: &str) -> &str {
+    match arch {
+        "x86_64" | "amd64" | "x64" => "x86_64",
+        "aarch64" | "arm64" => "aarch64",
+        "i386" | "i486" | "i586" | "i686" | "x86" => "i686",
+        "armv7" | "armv7l" => "armv7",
+        "armv6" | "armv6l" | "arm" => "arm",
+        "riscv64" => "riscv64gc",
+        "ppc64le" | "powerpc64le" => "powerpc64le",
+        arch => arch,
+    }
+}
+
+/// musl's dynamic loader is `/lib/ld-musl-<arch>.so.1`.
+fn detect_libc(lib_entries: impl IntoIterator<Item = String>) -> &'static str {
+    if lib_entries
+        .into_iter()
+        .any(|entry| entry.starts_with("ld-musl-"))
+    {
+        "musl"
+    } else {
+        "gnu"
+    }
+}
+
+fn target_triple(arch: &str, os: &OsType, libc: Option<&str>) -> Option<String> {
+    match os {
+        OsType::Linux => {
+            // 32-bit ARM uses the hard-float EABI.
+            let abi = if arch.starts_with("arm") {
+                "eabihf"
+            } else {
+                ""
+            };
+            Some(format!(
+                "{}-unknown-linux-{}{}",
+                arch,
+                libc.unwrap_or("gnu"),
+                abi
+            ))
+        }
+        OsType::MacOS => Some(format!("{}-apple-darwin", arch)),
+        OsType::Windows => Some(format!("{}-pc-windows-msvc", arch)),
+        OsType::Other(os) if os.eq_ignore_ascii_case("FreeBSD") => {
+            Some(format!("{}-unknown-freebsd", arch))
+        }
+        OsType::Other(_) => None,
+    }
+}
+
+/// Parse an `os-release` file: newline-separated `KEY=value` assignments,
+/// where values may be quoted like in a shell script. See `os-release(5)`.
+fn parse_os_release(contents: &str) -> BTreeMap<String, String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let i = line.find('=')?;
+            let (key, value) = (&line[..i], &line[i + 1..]);
+            let value = match value.chars().next() {
+                Some(quote @ '"') | Some(quote @ '\'')
+                    if value.len() > 1 && value.ends_with(quote) =>
+                {
+                    let value = &value[1..value.len() - 1];
+                    if quote == '"' {
+                        unescape_os_release(value)
+                    } else {
+                        value.to_string()
+                    }
+                }
+                _ => value.to_string(),
+            };
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+/// Unescape the backslash escapes allowed in double-quoted `os-release`
+/// values.
+fn unescape_os_release(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// The login shell of `user` in the `/etc/passwd`-formatted `passwd`.
+fn passwd_shell(passwd: &str, user: &str) -> Option<PathBuf> {
+    passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() == 7 && fields[0] == user)
+        .map(|fields| PathBuf::from(fields[6]))
+        .filter(|shell| !shell.as_os_str().is_empty())
+}
+
+fn detect_terminal(env: impl Fn(&str) -> Option<String>) -> Option<String> {
+    if let Some(program) = env("TERM_PROGRAM").filter(|program| !program.is_empty()) {
+        return Some(program.to_lowercase());
+    }
+    for (var, terminal) in &[
+        ("KITTY_WINDOW_ID", "kitty"),
+        ("ALACRITTY_SOCKET", "alacritty"),
+        ("ALACRITTY_LOG", "alacritty"),
+        ("WEZTERM_PANE", "wezterm"),
+        ("KONSOLE_VERSION", "konsole"),
+        ("GNOME_TERMINAL_SCREEN", "gnome-terminal"),
+        ("TILIX_ID", "tilix"),
+        ("WT_SESSION", "windows-terminal"),
+    ] {
+        if env(var).is_some() {
+            return Some(terminal.to_string());
+        }
+    }
+    None
+}
+
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum Platform {
@@ -120,6 +1123,7 @@ impl From<whoami::Platform> for Platform {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum OsType {
     Linux,
     MacOS,
@@ -137,3 +1141,560 @@ impl<S: AsRef<str>> From<S> for OsType {
         }
     }
 }
+
+impl fmt::Display for OsType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OsType::Linux => write!(f, "linux"),
+            OsType::MacOS => write!(f, "macos"),
+            OsType::Windows => write!(f, "windows"),
+            OsType::Other(s) => write!(f, "{}", s.to_lowercase()),
+        }
+    }
+}
+
+/// The value of a single fact, as seen by conditions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FactValue {
+    Null,
+    Bool(bool),
+    String(String),
+}
+
+impl FactValue {
+    /// Whether this value counts as true in a condition; `null`, `false`, and
+    /// the empty string are false.
+    pub fn truthy(&self) -> bool {
+        match self {
+            FactValue::Null => false,
+            FactValue::Bool(b) => *b,
+            FactValue::String(s) => !s.is_empty(),
+        }
+    }
+}
+
+impl Serialize for FactValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            FactValue::Null => serializer.serialize_none(),
+            FactValue::Bool(b) => serializer.serialize_bool(*b),
+            FactValue::String(s) => serializer.serialize_str(s),
+        }
+    }
+}
+
+impl fmt::Display for FactValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FactValue::Null => write!(f, "null"),
+            FactValue::Bool(b) => write!(f, "{}", b),
+            FactValue::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<bool> for FactValue {
+    fn from(b: bool) -> Self {
+        FactValue::Bool(b)
+    }
+}
+
+impl From<String> for FactValue {
+    fn from(s: String) -> Self {
+        FactValue::String(s)
+    }
+}
+
+impl From<&str> for FactValue {
+    fn from(s: &str) -> Self {
+        FactValue::String(s.to_string())
+    }
+}
+
+impl<T: Into<FactValue>> From<Option<T>> for FactValue {
+    fn from(o: Option<T>) -> Self {
+        o.map(Into::into).unwrap_or(FactValue::Null)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn facts_lazy() {
+        let facts = Facts::new();
+        assert!(facts.platform.get().is_none());
+        assert!(facts.users.get().is_none());
+        assert!(!facts.hostname().unwrap().is_empty());
+        assert!(facts.platform.get().is_some());
+        assert!(facts.users.get().is_none());
+        assert!(facts.networks.get().is_none());
+    }
+
+    #[test]
+    fn facts_from_parts() {
+        let facts = Facts::from_parts(
+            PlatformInfo {
+                system: "Darwin".into(),
+                release: "22.1.0".into(),
+                version: "Darwin Kernel Version 22.1.0".into(),
+                hostname: "work-laptop".into(),
+                arch: "arm64".into(),
+            },
+            vec!["becca".into()],
+            vec![Nic {
+                name: "utun3".into(),
+                addresses: vec!["10.42.0.7".parse().unwrap()],
+                is_up: true,
+                is_loopback: false,
+            }],
+        );
+        assert_eq!(facts.os().unwrap(), OsType::MacOS);
+        assert_eq!(facts.hostname().unwrap(), "work-laptop");
+        assert_eq!(facts.native_arch().unwrap(), "aarch64");
+        assert!(facts.users().unwrap().contains_key("becca"));
+        assert_eq!(
+            facts.addresses().unwrap(),
+            vec!["10.42.0.7".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn facts_collect() {
+        let facts = Facts::collect().unwrap();
+        assert!(facts.platform.get().is_some());
+        assert!(facts.users.get().is_some());
+        assert!(facts.networks.get().is_some());
+        assert_eq!(facts.hostname().unwrap(), Facts::new().hostname().unwrap());
+
+        let facts = Facts::from_parts(
+            PlatformInfo {
+                hostname: "cached".into(),
+                ..facts.platform().unwrap().clone()
+            },
+            vec![],
+            vec![],
+        );
+        facts.collect_missing().unwrap();
+        assert_eq!(facts.hostname().unwrap(), "cached");
+
+        fn send_sync<T: Send + Sync>() {}
+        send_sync::<Facts>();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn facts_commands() {
+        let commands = vec![
+            ("greeting".to_string(), "echo '  hello '".to_string()),
+            ("hostname".to_string(), "echo shadowed".to_string()),
+            ("broken".to_string(), "echo bad >&2; exit 3".to_string()),
+            ("slow".to_string(), "sleep 10".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let facts = Facts::new().with_commands(&commands, Duration::from_millis(200));
+
+        assert_eq!(facts.get("greeting").unwrap(), Some("hello".into()));
+        assert_ne!(facts.get("hostname").unwrap(), Some("shadowed".into()));
+        assert_eq!(facts.get("nonexistent").unwrap(), None);
+        assert_eq!(
+            format!("{}", facts.get("broken").unwrap_err()),
+            "command for fact `broken` failed (exit status: 3): bad"
+        );
+        assert!(matches!(
+            facts.get("slow"),
+            Err(FactsError::CommandTimeout { .. })
+        ));
+    }
+
+    #[test]
+    fn facts_vars() {
+        let vars = vec![
+            ("theme".to_string(), "gruvbox".to_string()),
+            ("os".to_string(), "shadowed".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let facts = Facts::new().with_vars(&vars);
+        assert_eq!(facts.get("theme").unwrap(), Some("gruvbox".into()));
+        assert_eq!(facts.var("theme"), Some("gruvbox"));
+        assert_ne!(facts.get("os").unwrap(), Some("shadowed".into()));
+        assert_eq!(facts.var("nonexistent"), None);
+    }
+
+    #[test]
+    fn facts_overrides() {
+        let overrides = vec![
+            ("os".to_string(), "windows".to_string()),
+            ("is_wsl".to_string(), "true".to_string()),
+            ("work".to_string(), "yes".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let facts = Facts::new().with_overrides(&overrides);
+        assert_eq!(facts.get("os").unwrap(), Some("windows".into()));
+        assert_eq!(facts.get("is_wsl").unwrap(), Some(true.into()));
+        assert_eq!(facts.get("work").unwrap(), Some("yes".into()));
+        assert_eq!(facts.nix_args().unwrap()["os"], "windows");
+        assert_eq!(facts.template_context().unwrap()["os"], "windows");
+    }
+
+    #[test]
+    fn facts_nix_args() {
+        let vars = vec![
+            ("theme".to_string(), "gruvbox".to_string()),
+            ("os".to_string(), "shadowed".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let args = Facts::new().with_vars(&vars).nix_args().unwrap();
+        assert_eq!(
+            args.keys().collect::<Vec<_>>(),
+            vec!["arch", "facts", "hostname", "os", "theme", "user"]
+        );
+        assert_eq!(args["theme"], "gruvbox");
+        assert_ne!(args["os"], "shadowed");
+        let facts: serde_json::Value = serde_json::from_str(&args["facts"]).unwrap();
+        assert_eq!(facts["hostname"], args["hostname"]);
+        assert_eq!(facts["vars"]["theme"], "gruvbox");
+        assert!(facts.get("env").is_none());
+    }
+
+    #[test]
+    fn facts_template_context() {
+        let vars = vec![("theme".to_string(), "gruvbox".to_string())]
+            .into_iter()
+            .collect();
+        let commands = vec![("gpu".to_string(), "echo none".to_string())]
+            .into_iter()
+            .collect();
+        let facts = Facts::new()
+            .with_vars(&vars)
+            .with_commands(&commands, DEFAULT_COMMAND_TIMEOUT);
+        let context = facts.template_context().unwrap();
+        assert_eq!(context["hostname"], facts.hostname().unwrap());
+        assert_eq!(context["vars"]["theme"], "gruvbox");
+        assert_eq!(context["custom"]["gpu"], "none");
+        assert_eq!(context["is_wsl"], facts.is_wsl());
+        assert_eq!(context["env"]["PATH"], env::var("PATH").unwrap());
+        assert!(context["addresses"].is_array());
+        assert_eq!(context["platform"]["hostname"], facts.hostname().unwrap());
+        assert!(context["networks"].is_object());
+        assert_eq!(serde_json::to_value(&facts).unwrap(), context);
+    }
+
+    #[test]
+    fn wsl() {
+        let unset = |_: &str| false;
+        assert!(detect_wsl(Some("4.19.104-microsoft-standard\n"), unset));
+        assert!(detect_wsl(Some("4.4.0-18362-Microsoft"), unset));
+        assert!(!detect_wsl(Some("5.4.0-42-generic"), unset));
+        assert!(!detect_wsl(None, unset));
+        assert!(detect_wsl(None, |var| var == "WSL_DISTRO_NAME"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn facts_command_exists() {
+        let facts = Facts::new();
+        assert!(facts.command_exists("sh"));
+        assert!(!facts.command_exists("dotfile-manager-surely-nonexistent"));
+        assert_eq!(
+            facts.get("has_apt").unwrap(),
+            Some(facts.command_exists("apt").into())
+        );
+    }
+
+    fn fake_env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |var| vars.get(var).cloned()
+    }
+
+    #[test]
+    fn display_server() {
+        assert_eq!(detect_display_server(fake_env(&[])), None);
+        assert_eq!(
+            detect_display_server(fake_env(&[("DISPLAY", ":0")])),
+            Some("x11")
+        );
+        assert_eq!(
+            detect_display_server(fake_env(&[
+                ("DISPLAY", ":0"),
+                ("WAYLAND_DISPLAY", "wayland-0")
+            ])),
+            Some("wayland")
+        );
+        assert_eq!(
+            detect_display_server(fake_env(&[
+                ("XDG_SESSION_TYPE", "x11"),
+                ("WAYLAND_DISPLAY", "wayland-0")
+            ])),
+            Some("x11")
+        );
+    }
+
+    #[test]
+    fn desktop() {
+        assert_eq!(detect_desktop(fake_env(&[])), None);
+        assert_eq!(
+            detect_desktop(fake_env(&[("XDG_CURRENT_DESKTOP", "ubuntu:GNOME")])),
+            Some("gnome".to_string())
+        );
+        assert_eq!(
+            detect_desktop(fake_env(&[("XDG_CURRENT_DESKTOP", "KDE")])),
+            Some("kde".to_string())
+        );
+        assert_eq!(
+            detect_desktop(fake_env(&[("SWAYSOCK", "/run/user/1000/sway-ipc.sock")])),
+            Some("sway".to_string())
+        );
+        assert_eq!(
+            detect_desktop(fake_env(&[("DESKTOP_SESSION", "xfce")])),
+            Some("xfce".to_string())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn root() {
+        let facts = Facts::new();
+        let uid = String::from_utf8(
+            std::process::Command::new("id")
+                .arg("-u")
+                .output()
+                .unwrap()
+                .stdout,
+        )
+        .unwrap();
+        assert_eq!(facts.is_root(), uid.trim() == "0");
+        assert_eq!(facts.is_elevated(), facts.is_root());
+    }
+
+    #[test]
+    fn triples() {
+        assert_eq!(normalize_arch("arm64"), "aarch64");
+        assert_eq!(normalize_arch("amd64"), "x86_64");
+        assert_eq!(normalize_arch("armv7l"), "armv7");
+        assert_eq!(normalize_arch("mips"), "mips");
+        assert_eq!(detect_libc(vec!["ld-musl-x86_64.so.1".to_string()]), "musl");
+        assert_eq!(detect_libc(vec!["x86_64-linux-gnu".to_string()]), "gnu");
+        assert_eq!(
+            target_triple("x86_64", &OsType::Linux, Some("musl")).unwrap(),
+            "x86_64-unknown-linux-musl"
+        );
+        assert_eq!(
+            target_triple("armv7", &OsType::Linux, Some("gnu")).unwrap(),
+            "armv7-unknown-linux-gnueabihf"
+        );
+        assert_eq!(
+            target_triple("aarch64", &OsType::MacOS, None).unwrap(),
+            "aarch64-apple-darwin"
+        );
+        assert_eq!(
+            target_triple("x86_64", &OsType::Other("Plan9".into()), None),
+            None
+        );
+
+        #[cfg(all(target_os = "linux", target_arch = "x86_64", target_env = "gnu"))]
+        assert_eq!(
+            Facts::new().target_triple().unwrap().unwrap(),
+            "x86_64-unknown-linux-gnu"
+        );
+    }
+
+    #[test]
+    fn os_release() {
+        let distro = parse_os_release(indoc!(
+            r#"
+            NAME="Ubuntu"
+            VERSION="20.04 LTS (Focal Fossa)"
+            ID=ubuntu
+            ID_LIKE=debian
+            PRETTY_NAME="Ubuntu \"Focal\" 20.04"
+            VERSION_ID='20.04'
+            # A comment
+            BROKEN
+            "#
+        ));
+        assert_eq!(distro["ID"], "ubuntu");
+        assert_eq!(distro["ID_LIKE"], "debian");
+        assert_eq!(distro["VERSION_ID"], "20.04");
+        assert_eq!(distro["NAME"], "Ubuntu");
+        assert_eq!(distro["PRETTY_NAME"], r#"Ubuntu "Focal" 20.04"#);
+        assert_eq!(distro.len(), 6);
+    }
+
+    #[test]
+    fn login_shell() {
+        let passwd = indoc!(
+            "
+            root:x:0:0:root:/root:/bin/bash
+            # A comment
+            becca:x:1000:100::/home/becca:/run/current-system/sw/bin/fish
+            nologin:x:1001:100::/home/nologin:
+            "
+        );
+        assert_eq!(
+            passwd_shell(passwd, "becca"),
+            Some(PathBuf::from("/run/current-system/sw/bin/fish"))
+        );
+        assert_eq!(
+            passwd_shell(passwd, "root"),
+            Some(PathBuf::from("/bin/bash"))
+        );
+        assert_eq!(passwd_shell(passwd, "nologin"), None);
+        assert_eq!(passwd_shell(passwd, "nobody"), None);
+    }
+
+    #[test]
+    fn terminal() {
+        assert_eq!(detect_terminal(fake_env(&[])), None);
+        assert_eq!(
+            detect_terminal(fake_env(&[("TERM_PROGRAM", "iTerm.app")])),
+            Some("iterm.app".to_string())
+        );
+        assert_eq!(
+            detect_terminal(fake_env(&[("KITTY_WINDOW_ID", "1")])),
+            Some("kitty".to_string())
+        );
+    }
+
+    #[test]
+    fn container() {
+        let missing = |_: &str| false;
+        let unset = |_: &str| false;
+        assert!(!detect_container(missing, None, unset));
+        assert!(detect_container(|path| path == "/.dockerenv", None, unset));
+        assert!(detect_container(missing, None, |var| var == "container"));
+        assert!(detect_container(
+            missing,
+            Some("0::/kubepods/besteffort/pod1234\n"),
+            unset
+        ));
+        assert!(!detect_container(missing, Some("0::/init.scope\n"), unset));
+    }
+
+    #[test]
+    fn virtual_machine() {
+        assert!(detect_virtual_machine(Some(indoc!(
+            "
+            processor\t: 0
+            flags\t\t: fpu vme de pse tsc hypervisor lahf_lm
+            "
+        ))));
+        assert!(!detect_virtual_machine(Some(
+            "flags\t\t: fpu vme de pse tsc\n"
+        )));
+        assert!(!detect_virtual_machine(None));
+    }
+
+    #[test]
+    fn machine_class() {
+        let hints = MachineHints {
+            is_container: false,
+            has_battery: false,
+            chassis_type: None,
+            is_graphical: false,
+        };
+        assert_eq!(classify_machine(&hints), "server");
+        assert_eq!(
+            classify_machine(&MachineHints {
+                is_graphical: true,
+                ..hints
+            }),
+            "desktop"
+        );
+        assert_eq!(
+            classify_machine(&MachineHints {
+                has_battery: true,
+                ..hints
+            }),
+            "laptop"
+        );
+        // A laptop with its lid closed, driving an external monitor, is still
+        // a laptop.
+        assert_eq!(
+            classify_machine(&MachineHints {
+                chassis_type: Some(10),
+                ..hints
+            }),
+            "laptop"
+        );
+        // Desktops with a UPS report a battery.
+        assert_eq!(
+            classify_machine(&MachineHints {
+                has_battery: true,
+                chassis_type: Some(3),
+                ..hints
+            }),
+            "desktop"
+        );
+        assert_eq!(
+            classify_machine(&MachineHints {
+                chassis_type: Some(23),
+                is_graphical: true,
+                ..hints
+            }),
+            "server"
+        );
+        assert_eq!(
+            classify_machine(&MachineHints {
+                is_container: true,
+                has_battery: true,
+                ..hints
+            }),
+            "container"
+        );
+    }
+
+    #[test]
+    fn facts_cached() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("facts.json");
+        let ttl = Duration::from_secs(60 * 60);
+
+        let commands: BTreeMap<_, _> = vec![("x".to_string(), "echo 1".to_string())]
+            .into_iter()
+            .collect();
+        let first = Facts::cached(&path, ttl).with_commands(&commands, DEFAULT_COMMAND_TIMEOUT);
+        first.hostname().unwrap();
+        first.get("x").unwrap();
+        first.write_cache(&path);
+
+        // Only the collected categories are cached.
+        let second = Facts::cached(&path, ttl).with_commands(&commands, DEFAULT_COMMAND_TIMEOUT);
+        assert_eq!(first.collected, second.collected);
+        assert_eq!(first.platform.get(), second.platform.get());
+        assert!(second.users.get().is_none());
+        assert_eq!(second.commands["x"].output.get().unwrap(), "1");
+
+        // Cached command output is discarded if the command changes.
+        let changed: BTreeMap<_, _> = vec![("x".to_string(), "echo 2".to_string())]
+            .into_iter()
+            .collect();
+        let changed = Facts::cached(&path, ttl).with_commands(&changed, DEFAULT_COMMAND_TIMEOUT);
+        assert!(changed.commands["x"].output.get().is_none());
+
+        // An expired cache is ignored.
+        let third = Facts::cached(&path, Duration::from_secs(0));
+        assert!(third.collected > first.collected);
+        assert!(third.platform.get().is_none());
+
+        // A corrupt cache is ignored.
+        fs::write(&path, "not json").unwrap();
+        assert!(Facts::read_cache(&path).is_none());
+        assert!(Facts::cached(&path, ttl).platform.get().is_none());
+    }
+}
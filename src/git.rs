@@ -0,0 +1,513 @@
+//! Running git on the dotfile repository.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// The default for `GitOptions::commit_message`.
+const DEFAULT_COMMIT_MESSAGE: &str = "dfm {action}: {paths}";
+
+#[derive(Error, Debug)]
+pub enum GitError {
+    #[error("`git` not found; is it installed?")]
+    NotInstalled,
+
+    #[error("{0}")]
+    Io(io::Error),
+
+    #[error("`git {args}` failed: {message}")]
+    Failed { args: String, message: String },
+}
+
+impl From<io::Error> for GitError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => GitError::NotInstalled,
+            _ => GitError::Io(err),
+        }
+    }
+}
+
+/// Options for the dotfile repository's git repository; the `[git]` table in
+/// the config file.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitOptions {
+    /// Commit the files dfm changes in the dotfile repository with
+    /// `commit_message`, e.g. the submodules `dfm sync --remote` moves to
+    /// newer commits; see `auto_commit`.
+    pub auto_commit: bool,
+    /// The message for automatic commits: `{action}` is replaced with what
+    /// dfm did, e.g. `add`, and `{paths}` with the changed paths, separated
+    /// by spaces. Default `dfm {action}: {paths}`.
+    pub commit_message: Option<String>,
+}
+
+impl GitOptions {
+    /// The message for an automatic commit; see `commit_message`.
+    pub fn commit_message(&self, action: &str, paths: &[&Path]) -> String {
+        let paths = paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.commit_message
+            .as_deref()
+            .unwrap_or(DEFAULT_COMMIT_MESSAGE)
+            .replace("{action}", action)
+            .replace("{paths}", &paths)
+    }
+}
+
+/// If `opts.auto_commit` is set and `dir` is a git repository, commit the
+/// changes to `paths` (relative to `dir`), and nothing else, after dfm did
+/// `action` to them. Returns whether a commit was made; there's nothing to
+/// commit if `paths` haven't changed.
+pub fn auto_commit(
+    dir: &Path,
+    opts: &GitOptions,
+    action: &str,
+    paths: &[&Path],
+) -> Result<bool, GitError> {
+    if !opts.auto_commit || paths.is_empty() || !dir.join(".git").exists() {
+        return Ok(false);
+    }
+    let paths_args = paths
+        .iter()
+        .map(|path| path.to_string_lossy())
+        .collect::<Vec<_>>();
+    let git_on_paths = |args: &[&str]| {
+        let mut args = args.to_vec();
+        args.push("--");
+        args.extend(paths_args.iter().map(|path| path.as_ref()));
+        git(dir, &args)
+    };
+    git_on_paths(&["add", "--all"])?;
+    let mut diff_args = vec!["diff", "--cached", "--quiet", "--"];
+    diff_args.extend(paths_args.iter().map(|path| path.as_ref()));
+    let diff = Command::new("git")
+        .args(&diff_args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()?;
+    // `--quiet` exits with 1 if there are staged changes; anything else but
+    // 0 is a failure.
+    match diff.status.code() {
+        Some(0) => return Ok(false),
+        Some(1) => {}
+        _ => {
+            return Err(GitError::Failed {
+                args: diff_args.join(" "),
+                message: String::from_utf8_lossy(&diff.stderr).trim().to_string(),
+            })
+        }
+    }
+    git_on_paths(&[
+        "commit",
+        "--quiet",
+        "--message",
+        &opts.commit_message(action, paths),
+    ])?;
+    Ok(true)
+}
+
+/// The state of a git repository's work tree and current branch.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RepoStatus {
+    /// The current branch, or `None` if `HEAD` is detached.
+    pub branch: Option<String>,
+    /// The branch's upstream, e.g. `origin/main`.
+    pub upstream: Option<String>,
+    /// How many commits the branch is ahead of its upstream.
+    pub ahead: u64,
+    /// How many commits the branch is behind its upstream.
+    pub behind: u64,
+    /// Files with uncommitted changes, including untracked files, relative
+    /// to the repository's root.
+    pub changed: Vec<PathBuf>,
+}
+
+impl RepoStatus {
+    /// Whether anything hasn't been committed.
+    pub fn is_dirty(&self) -> bool {
+        !self.changed.is_empty()
+    }
+
+    /// Whether `path` (relative to the repository's root), or anything in
+    /// it if it's a directory, has uncommitted changes.
+    pub fn has_changes(&self, path: &Path) -> bool {
+        self.changed.iter().any(|changed| changed.starts_with(path))
+    }
+
+    /// Parse the output of `git status --porcelain=v2 --branch -z`.
+    fn parse(output: &str) -> Self {
+        let mut status = RepoStatus::default();
+        let mut entries = output.split('\0').filter(|entry| !entry.is_empty());
+        while let Some(entry) = entries.next() {
+            let mut fields = entry.splitn(2, ' ');
+            let kind = fields.next().unwrap_or_default();
+            let rest = fields.next().unwrap_or_default();
+            // The number of space-separated fields before the path; see
+            // git-status(1).
+            let path_at = match kind {
+                "#" => {
+                    let (key, value) = rest.split_at(rest.find(' ').unwrap_or(rest.len()));
+                    let value = value.trim_start();
+                    match key {
+                        "branch.head" if value != "(detached)" => {
+                            status.branch = Some(value.to_string())
+                        }
+                        "branch.upstream" => status.upstream = Some(value.to_string()),
+                        "branch.ab" => {
+                            for count in value.split(' ') {
+                                if let Some(ahead) = count.strip_prefix('+') {
+                                    status.ahead = ahead.parse().unwrap_or_default();
+                                } else if let Some(behind) = count.strip_prefix('-') {
+                                    status.behind = behind.parse().unwrap_or_default();
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+                "1" => 7,
+                // Renames and copies are followed by the original path.
+                "2" => {
+                    entries.next();
+                    8
+                }
+                "u" => 9,
+                _ => 0,
+            };
+            if let Some(path) = rest.splitn(path_at + 1, ' ').nth(path_at) {
+                status.changed.push(path.into());
+            }
+        }
+        status
+    }
+}
+
+/// The status of the git repository at `dir`, or `None` if `dir` isn't the
+/// root of one.
+pub fn status(dir: &Path) -> Result<Option<RepoStatus>, GitError> {
+    if !dir.join(".git").exists() {
+        return Ok(None);
+    }
+    let output = git(dir, &["status", "--porcelain=v2", "--branch", "-z"])?;
+    Ok(Some(RepoStatus::parse(&output)))
+}
+
+/// A submodule's state, from the first column of `git submodule status`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubmoduleState {
+    /// Checked out at the commit the repository records.
+    Current,
+    /// Never initialized, so its directory is empty.
+    Uninitialized,
+    /// Checked out at a different commit than the repository records.
+    Modified,
+    /// Has merge conflicts.
+    Conflicted,
+}
+
+/// A submodule of a git repository, possibly nested in another submodule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Submodule {
+    /// The submodule's path, relative to the repository's root.
+    pub path: PathBuf,
+    pub state: SubmoduleState,
+}
+
+impl Submodule {
+    /// Parse the output of `git submodule status --recursive`.
+    fn parse(output: &str) -> Vec<Self> {
+        output
+            .lines()
+            .filter_map(|line| {
+                let mut chars = line.chars();
+                let state = match chars.next()? {
+                    '-' => SubmoduleState::Uninitialized,
+                    '+' => SubmoduleState::Modified,
+                    'U' => SubmoduleState::Conflicted,
+                    _ => SubmoduleState::Current,
+                };
+                // `<commit> <path>`, followed by ` (<describe>)` if it's
+                // checked out.
+                let (_, path) = chars.as_str().split_once(' ')?;
+                let path = match path.rfind(" (") {
+                    Some(i) if path.ends_with(')') && state != SubmoduleState::Uninitialized => {
+                        &path[..i]
+                    }
+                    _ => path,
+                };
+                Some(Submodule {
+                    path: path.into(),
+                    state,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The submodules of the git repository at `dir`, recursively; empty if
+/// `dir` isn't the root of one or has no `.gitmodules`.
+pub fn submodules(dir: &Path) -> Result<Vec<Submodule>, GitError> {
+    if !dir.join(".git").exists() || !dir.join(".gitmodules").exists() {
+        return Ok(Vec::new());
+    }
+    let output = git(dir, &["submodule", "status", "--recursive"])?;
+    Ok(Submodule::parse(&output))
+}
+
+/// Initialize and check out the submodules of the git repository at `dir`,
+/// recursively, at the commits it records, or at the latest commits of
+/// their remote branches if `remote` is set. Like `clone`, git's progress
+/// and prompts go to the terminal, on standard error, which leaves
+/// standard output for dfm's results.
+pub fn update_submodules(dir: &Path, remote: bool) -> Result<(), GitError> {
+    // Pick up URLs changed in `.gitmodules` first.
+    git(dir, &["submodule", "sync", "--recursive", "--quiet"])?;
+    let mut args = vec!["submodule", "update", "--init", "--recursive"];
+    if remote {
+        args.push("--remote");
+    }
+    let status = Command::new("git")
+        .args(&args)
+        .current_dir(dir)
+        .stdout(io::stderr())
+        .status()?;
+    if !status.success() {
+        return Err(GitError::Failed {
+            args: args.join(" "),
+            message: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Run git with `args` in `dir` and return its standard output.
+pub fn git(dir: &Path, args: &[&str]) -> Result<String, GitError> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(GitError::Failed {
+            args: args.join(" "),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Clone `url` (anything `git clone` accepts, e.g. an HTTPS or SSH URL)
+/// into `dest`, with its submodules. git's progress and any prompts, e.g.
+/// for an SSH key's passphrase, go to the terminal.
+pub fn clone(url: &str, dest: &Path) -> Result<(), GitError> {
+    let status = Command::new("git")
+        .args(["clone", "--recurse-submodules", url])
+        .arg(dest)
+        .status()?;
+    if !status.success() {
+        return Err(GitError::Failed {
+            args: format!("clone {}", url),
+            message: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn git_status_parse() {
+        let output = [
+            "# branch.oid 0123abc",
+            "# branch.head main",
+            "# branch.upstream origin/main",
+            "# branch.ab +2 -1",
+            "1 .M N... 100644 100644 100644 0123abc 0123abc .bashrc",
+            "2 R. N... 100644 100644 100644 0123abc 0123abc R100 nvim/init.lua",
+            "nvim/init.vim",
+            "u UU N... 100644 100644 100644 100644 0123abc 0123abc 0123abc git/my config",
+            "? new file",
+            "",
+        ]
+        .join("\0");
+        let status = RepoStatus::parse(&output);
+        assert_eq!(
+            status,
+            RepoStatus {
+                branch: Some("main".into()),
+                upstream: Some("origin/main".into()),
+                ahead: 2,
+                behind: 1,
+                changed: vec![
+                    ".bashrc".into(),
+                    "nvim/init.lua".into(),
+                    "git/my config".into(),
+                    "new file".into(),
+                ],
+            }
+        );
+        assert!(status.is_dirty());
+        assert!(status.has_changes(Path::new("nvim")));
+        assert!(!status.has_changes(Path::new("nvim/init.vim")));
+        assert_eq!(
+            RepoStatus::parse("# branch.oid 0123abc\0# branch.head (detached)\0"),
+            RepoStatus::default()
+        );
+    }
+
+    #[test]
+    fn git_submodule_parse() {
+        let output = indoc! {"
+             0123abc vim/pack/plugins (v1.0-2-g0123abc)
+            -4567def team config
+            +89abcde zsh/plugins (heads/main)
+            U0000000 nested/sub (odd (name))
+        "};
+        let submodule = |path: &str, state| Submodule {
+            path: path.into(),
+            state,
+        };
+        assert_eq!(
+            Submodule::parse(output),
+            vec![
+                submodule("vim/pack/plugins", SubmoduleState::Current),
+                submodule("team config", SubmoduleState::Uninitialized),
+                submodule("zsh/plugins", SubmoduleState::Modified),
+                submodule("nested/sub (odd", SubmoduleState::Conflicted),
+            ]
+        );
+    }
+
+    #[test]
+    fn git_submodules_uninitialized() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        git(root, &["init", "--quiet"]).unwrap();
+        let no_submodules = submodules(root);
+        fs::write(
+            root.join(".gitmodules"),
+            "[submodule \"vim\"]\n\tpath = vim\n\turl = https://example.com/vim.git\n",
+        )
+        .unwrap();
+        // Record a submodule without cloning it, like a fresh clone without
+        // `--recurse-submodules`.
+        git(
+            root,
+            &[
+                "update-index",
+                "--add",
+                "--cacheinfo",
+                "160000,0123456789abcdef0123456789abcdef01234567,vim",
+            ],
+        )
+        .unwrap();
+        let found = submodules(root);
+
+        assert_eq!(no_submodules.unwrap(), vec![]);
+        assert_eq!(
+            found.unwrap(),
+            vec![Submodule {
+                path: "vim".into(),
+                state: SubmoduleState::Uninitialized,
+            }]
+        );
+    }
+
+    #[test]
+    fn git_auto_commit() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        git(root, &["init", "--quiet"]).unwrap();
+        git(root, &["config", "user.name", "me"]).unwrap();
+        git(root, &["config", "user.email", "me@example.com"]).unwrap();
+        fs::write(root.join(".bashrc"), "bashrc").unwrap();
+        fs::write(root.join("unrelated"), "").unwrap();
+        let opts = GitOptions {
+            auto_commit: true,
+            commit_message: Some("Add {paths} ({action})".into()),
+        };
+        let bashrc = Path::new(".bashrc");
+
+        let disabled = auto_commit(root, &GitOptions::default(), "add", &[bashrc]);
+        let committed = auto_commit(root, &opts, "add", &[bashrc]);
+        let unchanged = auto_commit(root, &opts, "add", &[bashrc]);
+        let log = git(root, &["log", "--format=%s"]);
+        let untracked = git(root, &["status", "--porcelain"]);
+
+        assert!(!disabled.unwrap());
+        assert!(committed.unwrap());
+        assert!(!unchanged.unwrap());
+        assert_eq!(log.unwrap(), "Add .bashrc (add)\n");
+        assert_eq!(untracked.unwrap(), "?? unrelated\n");
+        assert_eq!(
+            GitOptions::default().commit_message("remove", &[bashrc, Path::new("nvim")]),
+            "dfm remove: .bashrc nvim"
+        );
+    }
+
+    #[test]
+    fn git_clone() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        git(&origin, &["init", "--quiet"]).unwrap();
+        fs::write(origin.join(".bashrc"), "bashrc").unwrap();
+        git(&origin, &["add", ".bashrc"]).unwrap();
+        git(
+            &origin,
+            &[
+                "-c",
+                "user.name=me",
+                "-c",
+                "user.email=me@example.com",
+                "commit",
+                "--quiet",
+                "--message=dotfiles",
+            ],
+        )
+        .unwrap();
+
+        let dest = root.join("dotfiles");
+        let cloned = clone(origin.to_str().unwrap(), &dest);
+        let bashrc = fs::read_to_string(dest.join(".bashrc"));
+        let again = clone(origin.to_str().unwrap(), &dest);
+        fs::write(dest.join(".bashrc"), "changed").unwrap();
+        let cloned_status = status(&dest);
+        let not_a_repo = status(root);
+        let missing = git(root, &["--git-dir=nowhere", "status"]);
+
+        cloned.unwrap();
+        assert_eq!(bashrc.unwrap(), "bashrc");
+        assert!(matches!(again, Err(GitError::Failed { .. })));
+        let cloned_status = cloned_status.unwrap().unwrap();
+        assert_eq!(
+            cloned_status.upstream,
+            cloned_status
+                .branch
+                .as_ref()
+                .map(|branch| format!("origin/{}", branch))
+        );
+        assert_eq!((cloned_status.ahead, cloned_status.behind), (0, 0));
+        assert_eq!(cloned_status.changed, vec![PathBuf::from(".bashrc")]);
+        assert_eq!(not_a_repo.unwrap(), None);
+        assert!(matches!(missing, Err(GitError::Failed { .. })));
+    }
+}
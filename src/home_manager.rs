@@ -0,0 +1,232 @@
+//! Interoperation with [home-manager](https://github.com/nix-community/home-manager).
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::dotfile::{AbsDotfile, Dotfile};
+use crate::facts::Facts;
+use crate::nix;
+use crate::nix::{nix_string, NixEvalError, NixOptions};
+use crate::template;
+use crate::template::TemplateError;
+use crate::util::make_abs;
+
+/// A home-manager module which installs `dotfiles` with `home.file` and
+/// `xdg.configFile`. Templates are rendered with `facts` and included as
+/// text; dotfiles installed outside of `home` can't be managed by
+/// home-manager, so they're left out with a comment.
+pub fn export(
+    dotfiles: &[AbsDotfile],
+    home: &Path,
+    facts: &Facts,
+) -> Result<String, TemplateError> {
+    let config_home = home.join(".config");
+    let mut module = String::from("# Generated by dotfile-manager.\n{ ... }:\n{\n");
+    for d in dotfiles {
+        let (option, target) = if let Ok(target) = d.installed.strip_prefix(&config_home) {
+            ("xdg.configFile", target)
+        } else if let Ok(target) = d.installed.strip_prefix(home) {
+            ("home.file", target)
+        } else {
+            writeln!(
+                module,
+                "  # Skipped {}: it's outside the home directory.",
+                d.installed.display()
+            )
+            .unwrap();
+            continue;
+        };
+        let value = if d.template {
+            format!(
+                "text = {}",
+                nix_string(&template::render(&fs::read_to_string(&d.repo)?, facts)?)
+            )
+        } else {
+            format!("source = {}", nix_string(&d.repo.to_string_lossy()))
+        };
+        writeln!(
+            module,
+            "  {}.{}.{};",
+            option,
+            nix_string(&target.to_string_lossy()),
+            value
+        )
+        .unwrap();
+    }
+    module.push_str("}\n");
+    Ok(module)
+}
+
+/// An entry in home-manager's `home.file`, which `xdg.configFile` entries
+/// are merged into.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct HomeFile {
+    /// The installed path, relative to the home directory.
+    target: PathBuf,
+    /// The file to link, unless it's defined with `text`.
+    source: Option<PathBuf>,
+    text: Option<String>,
+}
+
+/// A dotfiles list converted from a home-manager configuration.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Imported {
+    pub dotfiles: Vec<Dotfile>,
+    /// The targets of files defined with `text` rather than `source`; their
+    /// contents need to be copied into the dotfile repository by hand.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Evaluate the home-manager configuration at `config` (e.g.
+/// `~/.config/nixpkgs/home.nix`) and convert its `home.file` and
+/// `xdg.configFile` entries to a dotfiles list. Sources inside
+/// `dotfile_repo` are made relative to it. This needs `<nixpkgs>` and
+/// `<home-manager>` in the Nix search path.
+pub fn import(
+    config: &Path,
+    dotfile_repo: &Path,
+    opts: &NixOptions,
+) -> Result<Imported, NixEvalError> {
+    let config = make_abs(&env::current_dir()?, config);
+    let expr = format!(
+        "let \
+           pkgs = import <nixpkgs> {{}}; \
+           hm = import <home-manager/modules> {{ configuration = /. + {}; inherit pkgs; }}; \
+         in map \
+           (f: {{ \
+             inherit (f) target text; \
+             source = if f.text == null then toString f.source else null; \
+           }}) \
+           (builtins.filter (f: f.enable) (builtins.attrValues hm.config.home.file))",
+        nix_string(&config.to_string_lossy())
+    );
+    Ok(to_dotfiles(
+        nix::eval_expr::<Vec<HomeFile>>(&expr, opts)?.value,
+        dotfile_repo,
+    ))
+}
+
+fn to_dotfiles(files: Vec<HomeFile>, dotfile_repo: &Path) -> Imported {
+    let mut imported = Imported::default();
+    for file in files {
+        match file.source {
+            Some(source) if file.text.is_none() => {
+                let repo = source
+                    .strip_prefix(dotfile_repo)
+                    .map(Path::to_path_buf)
+                    .unwrap_or(source);
+                imported.dotfiles.push(Dotfile {
+                    installed: if repo == file.target {
+                        None
+                    } else {
+                        Some(file.target)
+                    },
+                    repo,
+                    ..Default::default()
+                });
+            }
+            _ => imported.skipped.push(file.target),
+        }
+    }
+    imported
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn export_module() {
+        let cwd = env::current_dir().unwrap();
+        let dotfile = |repo: &str, installed: &str| AbsDotfile {
+            repo: repo.into(),
+            installed: installed.into(),
+            ..Default::default()
+        };
+        let facts = Facts::new().with_vars(
+            &vec![("email".to_string(), "me@example.com".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        let template = cwd.join("test-data/template.txt");
+        assert_eq!(
+            export(
+                &[
+                    dotfile("/dotfiles/bashrc", "/home/me/.bashrc"),
+                    dotfile("/dotfiles/nvim", "/home/me/.config/nvim"),
+                    dotfile("/dotfiles/hosts", "/etc/hosts"),
+                    AbsDotfile {
+                        template: true,
+                        ..dotfile(template.to_str().unwrap(), "/home/me/.gitconfig")
+                    },
+                ],
+                Path::new("/home/me"),
+                &facts,
+            )
+            .unwrap(),
+            indoc!(
+                r#"
+                # Generated by dotfile-manager.
+                { ... }:
+                {
+                  home.file.".bashrc".source = "/dotfiles/bashrc";
+                  xdg.configFile."nvim".source = "/dotfiles/nvim";
+                  # Skipped /etc/hosts: it's outside the home directory.
+                  home.file.".gitconfig".text = "[user]
+                  email = me@example.com
+                ";
+                }
+                "#
+            )
+            .trim_start()
+        );
+    }
+
+    #[test]
+    fn import_home_files() {
+        let file = |target: &str, source: Option<&str>, text: Option<&str>| HomeFile {
+            target: target.into(),
+            source: source.map(PathBuf::from),
+            text: text.map(String::from),
+        };
+        assert_eq!(
+            to_dotfiles(
+                vec![
+                    file(".bashrc", Some("/home/me/dotfiles/.bashrc"), None),
+                    file(".config/nvim", Some("/home/me/dotfiles/nvim"), None),
+                    file(".npmrc", Some("/nix/store/abc-npmrc"), None),
+                    file(".gitconfig", None, Some("[user]\n")),
+                ],
+                Path::new("/home/me/dotfiles"),
+            ),
+            Imported {
+                dotfiles: vec![
+                    Dotfile {
+                        repo: ".bashrc".into(),
+                        ..Default::default()
+                    },
+                    Dotfile {
+                        repo: "nvim".into(),
+                        installed: Some(".config/nvim".into()),
+                        ..Default::default()
+                    },
+                    Dotfile {
+                        repo: "/nix/store/abc-npmrc".into(),
+                        installed: Some(".npmrc".into()),
+                        ..Default::default()
+                    },
+                ],
+                skipped: vec![".gitconfig".into()],
+            }
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! A parser for KDL documents (<https://kdl.dev>), for dotfiles lists like
+//!
+//! ```kdl
+//! dotfile ".bashrc"
+//! dotfile "fishfile" installed=".config/fish/fishfile" template=true {
+//!     when os="linux" hostname="work-*"
+//! }
+//! ```
+//!
+//! Documents are parsed with the `kdl` crate, which reads KDL 1, so
+//! keywords are written `true`, not KDL 2's `#true`. It doesn't implement
+//! serde, so nodes are turned into JSON here (see `Node::to_json`). Type
+//! annotations are ignored.
+
+use std::collections::BTreeMap;
+
+use kdl::{KdlDocument, KdlValue};
+use serde_json::{Map, Number, Value};
+use thiserror::Error;
+
+/// An error while parsing a KDL document.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("invalid KDL at line {line}: {message}")]
+pub struct KdlError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// A KDL node: a name, then any number of arguments and properties, then
+/// optionally a block of children.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Node {
+    /// The line the node starts on, counting from 1.
+    pub line: usize,
+    pub name: String,
+    pub args: Vec<Value>,
+    pub props: BTreeMap<String, Value>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    /// This node as a JSON value: its only argument if it has nothing else,
+    /// a list of its arguments if it has several, and otherwise an object of
+    /// its properties and children (see `fields_to_json`).
+    pub fn to_json(&self) -> Result<Value, String> {
+        match (
+            self.args.len(),
+            self.props.is_empty() && self.children.is_empty(),
+        ) {
+            (1, true) => Ok(self.args[0].clone()),
+            (_, true) => Ok(Value::Array(self.args.clone())),
+            (0, false) => self.fields_to_json(),
+            (_, false) => Err(format!(
+                "`{}` has both arguments and properties or children",
+                self.name
+            )),
+        }
+    }
+
+    /// This node's properties and children as a JSON object; a child named
+    /// `x` becomes the field `x`, whose value is the child's `to_json`.
+    pub fn fields_to_json(&self) -> Result<Value, String> {
+        let mut fields: Map<String, Value> = self
+            .props
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        for child in &self.children {
+            if fields.contains_key(&child.name) {
+                return Err(format!("`{}` is given more than once", child.name));
+            }
+            fields.insert(child.name.clone(), child.to_json()?);
+        }
+        Ok(Value::Object(fields))
+    }
+}
+
+/// Parse a KDL document into its top-level nodes.
+pub fn parse(src: &str) -> Result<Vec<Node>, KdlError> {
+    let doc = src.parse::<KdlDocument>().map_err(|err| KdlError {
+        line: line(src, err.span.offset()),
+        message: match err.help {
+            Some(help) => format!("{} {}", err, help),
+            None => err.to_string(),
+        },
+    })?;
+    Ok(nodes(src, &doc))
+}
+
+fn nodes(src: &str, doc: &KdlDocument) -> Vec<Node> {
+    doc.nodes()
+        .iter()
+        .map(|node| {
+            let mut args = Vec::new();
+            let mut props = BTreeMap::new();
+            for entry in node.entries() {
+                let value = value(entry.value());
+                match entry.name() {
+                    // Later properties replace earlier ones of the same name.
+                    Some(name) => {
+                        props.insert(name.value().to_string(), value);
+                    }
+                    None => args.push(value),
+                }
+            }
+            Node {
+                line: line(src, node.span().offset()),
+                name: node.name().value().to_string(),
+                args,
+                props,
+                children: node
+                    .children()
+                    .map(|children| nodes(src, children))
+                    .unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+/// The line `offset` is on in `src`, counting from 1.
+fn line(src: &str, offset: usize) -> usize {
+    src.bytes().take(offset).filter(|&b| b == b'\n').count() + 1
+}
+
+fn value(value: &KdlValue) -> Value {
+    match value {
+        KdlValue::RawString(s) | KdlValue::String(s) => Value::String(s.clone()),
+        KdlValue::Base2(n) | KdlValue::Base8(n) | KdlValue::Base10(n) | KdlValue::Base16(n) => {
+            (*n).into()
+        }
+        KdlValue::Base10Float(n) => Number::from_f64(*n).map_or(Value::Null, Value::Number),
+        KdlValue::Bool(b) => Value::Bool(*b),
+        KdlValue::Null => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn kdl_parse() {
+        let nodes = parse(indoc!(
+            r##"
+            // A comment.
+            dotfile ".bashrc"; dotfile r#"C:\Users"# r"raw" // Another.
+            /-dotfile ".vimrc"
+            dotfile "fishfile" \
+                installed=".config/fish/fishfile" /* inline */ template=true {
+                when os="linux" hostname=null /-ignored=1
+                /- privileged true
+            }
+            (u8)numbers 1 -2 0x1f 1_000 2.5 1e3
+            "quoted name" "tab\there" "\u{1F980}"
+            "##
+        ))
+        .unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    line: 2,
+                    name: "dotfile".into(),
+                    args: vec![json!(".bashrc")],
+                    ..Default::default()
+                },
+                Node {
+                    line: 2,
+                    name: "dotfile".into(),
+                    args: vec![json!(r"C:\Users"), json!("raw")],
+                    ..Default::default()
+                },
+                Node {
+                    line: 4,
+                    name: "dotfile".into(),
+                    args: vec![json!("fishfile")],
+                    props: vec![
+                        ("installed".into(), json!(".config/fish/fishfile")),
+                        ("template".into(), json!(true)),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    children: vec![Node {
+                        line: 6,
+                        name: "when".into(),
+                        props: vec![
+                            ("os".into(), json!("linux")),
+                            ("hostname".into(), json!(null)),
+                        ]
+                        .into_iter()
+                        .collect(),
+                        ..Default::default()
+                    }],
+                },
+                Node {
+                    line: 9,
+                    name: "numbers".into(),
+                    args: vec![
+                        json!(1),
+                        json!(-2),
+                        json!(31),
+                        json!(1000),
+                        json!(2.5),
+                        json!(1000.0)
+                    ],
+                    ..Default::default()
+                },
+                Node {
+                    line: 10,
+                    name: "quoted name".into(),
+                    args: vec![json!("tab\there"), json!("🦀")],
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn kdl_errors() {
+        let err = |src| parse(src).unwrap_err();
+        assert_eq!(
+            err("a {\n  b\n").to_string(),
+            "invalid KDL at line 1: Expected closing '}' in node children block."
+        );
+        assert_eq!(err("a\n}").line, 2);
+        assert_eq!(
+            err("a 1x").to_string(),
+            "invalid KDL at line 1: Expected valid node terminator. Nodes can only be \
+             terminated by `;` or a valid line ending."
+        );
+        for src in ["a \"b", "a 1=2", "a /* b", "a #true"] {
+            assert!(parse(src).is_err(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn kdl_to_json() {
+        let nodes = parse(indoc!(
+            r#"
+            a 1
+            b 1 2
+            c x=1 {
+                d "e"
+            }
+            f 1 x=1
+            g { h; h; }
+            "#
+        ))
+        .unwrap();
+        let json: Vec<_> = nodes.iter().map(Node::to_json).collect();
+        assert_eq!(
+            json,
+            vec![
+                Ok(json!(1)),
+                Ok(json!([1, 2])),
+                Ok(json!({ "x": 1, "d": "e" })),
+                Err("`f` has both arguments and properties or children".into()),
+                Err("`h` is given more than once".into()),
+            ]
+        );
+    }
+}
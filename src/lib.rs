@@ -1,4 +1,58 @@
+pub use error::Error;
+
+pub mod ansible;
+pub mod archive;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod bare_git;
+pub mod condition;
 pub mod config;
+pub mod cron;
+pub mod defaults;
+pub mod deploy;
+pub mod diagnostic;
+pub mod diff;
+pub mod discover;
+pub mod dotbot;
 pub mod dotfile;
+pub mod engine;
+pub mod error;
+pub mod facts;
+pub mod git;
+#[cfg(feature = "nix")]
+pub mod home_manager;
+pub mod kdl;
+pub mod loader;
+pub mod log;
+#[cfg(feature = "lua")]
+pub mod lua;
+pub mod manager;
+#[cfg(feature = "nix")]
 pub mod nix;
+pub mod notify;
+pub mod observer;
+pub mod packages;
+pub mod pager;
+pub mod plugin;
+pub mod porcelain;
+pub mod prompt;
+pub mod provider;
+pub mod report;
+#[cfg(feature = "rhai")]
+pub mod script;
+pub mod select;
+pub mod shell;
+pub mod short_status;
+pub mod spinner;
+#[cfg(feature = "starlark")]
+pub mod starlark;
+pub mod stow;
+pub mod strategy;
+pub mod subnet;
+pub mod systemd;
+pub mod table;
+pub mod template;
 pub mod util;
+pub mod version;
+pub mod vscode;
+pub mod warning;
@@ -0,0 +1,766 @@
+//! Parsers for dotfiles list files, chosen by file extension.
+//!
+//! Lists written in plain text, JSON, JSON5, TOML, YAML, KDL, Dhall, and CUE
+//! (and Jsonnet, Lua, Rhai, and Starlark, with the `jsonnet`, `lua`, `rhai`,
+//! and `starlark` features) are read by the loaders in
+//! `ListLoaders::default`; library users can support other formats by
+//! implementing `ListLoader` and adding it to `Config::list_loaders`. Lists
+//! written in the Nix expression language are evaluated rather than parsed,
+//! so `Config` handles them itself, and they take precedence over every
+//! loader; they need the `nix` feature.
+
+#[cfg(feature = "jsonnet")]
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "jsonnet")]
+use jrsonnet_evaluator::{EvaluationState, FileImportResolver, Val};
+use serde::Deserialize;
+
+use crate::config::DotfilesReadError;
+use crate::dotfile::{Dotfile, SerdeDotfile};
+use crate::facts::Facts;
+#[cfg(feature = "jsonnet")]
+use crate::facts::FactsModel;
+use crate::kdl;
+use crate::kdl::KdlError;
+use crate::util::output_with_input;
+
+/// How long programs which convert dotfiles lists to JSON, like
+/// `dhall-to-json`, may run.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A wrapper struct for use when deserializing a dotfile list.
+#[derive(Deserialize)]
+pub(crate) struct SerdeDotfileList {
+    /// Allow a `$schema` identifier for formats/programs that support it (mostly
+    /// JSON).
+    #[allow(dead_code)]
+    #[serde(rename = "$schema")]
+    schema: Option<String>,
+    dotfiles: Vec<SerdeDotfile>,
+}
+
+impl From<Vec<SerdeDotfile>> for SerdeDotfileList {
+    fn from(v: Vec<SerdeDotfile>) -> Self {
+        Self {
+            schema: None,
+            dotfiles: v,
+        }
+    }
+}
+
+impl SerdeDotfileList {
+    pub(crate) fn dotfiles(&self) -> Vec<Dotfile> {
+        self.dotfiles.iter().cloned().map(Into::into).collect()
+    }
+}
+
+/// A value read from a dotfiles list, along with anything printed while
+/// reading it, like Nix's `builtins.trace` output and deprecation warnings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluated<T> {
+    pub value: T,
+    /// Warnings, one line per entry.
+    pub warnings: Vec<String>,
+}
+
+impl<T> Evaluated<T> {
+    /// Transform the value, keeping the warnings.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Evaluated<U> {
+        Evaluated {
+            value: f(self.value),
+            warnings: self.warnings,
+        }
+    }
+}
+
+/// A dotfiles list file format.
+pub trait ListLoader: fmt::Debug + Send + Sync {
+    /// A short name for the format, e.g. `yaml`.
+    fn name(&self) -> &str;
+
+    /// The extensions files in this format may have, without the leading
+    /// `.`, in the order they're looked for.
+    fn extensions(&self) -> &[&str];
+
+    /// Parse a dotfiles list.
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError>;
+
+    /// Read the dotfiles list at `path`; by default, this parses the file's
+    /// contents, but formats with relative imports should read the file
+    /// itself. Scripted formats may make `facts` available to the list.
+    fn load(&self, path: &Path, _facts: &Facts) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        self.parse(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+/// Reads `{"dotfiles": [...]}` documents as JSON.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonLoader;
+
+impl ListLoader for JsonLoader {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["json"]
+    }
+
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        Ok(serde_json::from_reader::<_, SerdeDotfileList>(reader)?.dotfiles)
+    }
+}
+
+/// Reads `{dotfiles: [...]}` documents as JSON5, which allows comments,
+/// trailing commas, and unquoted keys; `.jsonc` files (JSON with comments)
+/// are read this way too.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json5Loader;
+
+impl ListLoader for Json5Loader {
+    fn name(&self) -> &str {
+        "json5"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["json5", "jsonc"]
+    }
+
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let mut json5 = String::new();
+        reader.read_to_string(&mut json5)?;
+        Ok(json5::from_str::<SerdeDotfileList>(&json5)?.dotfiles)
+    }
+}
+
+/// Reads `dotfiles = [...]` documents as TOML.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TomlLoader;
+
+impl ListLoader for TomlLoader {
+    fn name(&self) -> &str {
+        "toml"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["toml"]
+    }
+
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let mut toml = String::new();
+        reader.read_to_string(&mut toml)?;
+        Ok(toml::from_str::<SerdeDotfileList>(&toml)?.dotfiles)
+    }
+}
+
+/// Reads `dotfiles: [...]` documents as YAML.
+#[cfg(feature = "yaml")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct YamlLoader;
+
+#[cfg(feature = "yaml")]
+impl ListLoader for YamlLoader {
+    fn name(&self) -> &str {
+        "yaml"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["yaml", "yml"]
+    }
+
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        Ok(serde_yaml::from_reader::<_, SerdeDotfileList>(reader)?.dotfiles)
+    }
+}
+
+/// Reads plain text files with a dotfile's `repo` path on each line. Blank
+/// lines and lines starting with `#` are ignored, as is whitespace around
+/// each path.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextLoader;
+
+impl ListLoader for TextLoader {
+    fn name(&self) -> &str {
+        "text"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["txt"]
+    }
+
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        Ok(text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| SerdeDotfile::Path(line.into()))
+            .collect())
+    }
+}
+
+/// Reads KDL documents with a `dotfile` node for each dotfile; its argument
+/// is the `repo` path, and the other fields are given as properties or
+/// children (see `kdl::Node::fields_to_json`), e.g.
+///
+/// ```kdl
+/// dotfile ".bashrc"
+/// dotfile "nvim" installed=".config/nvim" {
+///     when os="linux"
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KdlLoader;
+
+impl ListLoader for KdlLoader {
+    fn name(&self) -> &str {
+        "kdl"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["kdl"]
+    }
+
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let mut kdl = String::new();
+        reader.read_to_string(&mut kdl)?;
+        kdl::parse(&kdl)?
+            .into_iter()
+            .map(|node| {
+                let error = |message| KdlError {
+                    line: node.line,
+                    message,
+                };
+                if node.name != "dotfile" {
+                    return Err(error(format!("expected `dotfile`, found `{}`", node.name)));
+                }
+                let repo = match node.args.as_slice() {
+                    [repo @ serde_json::Value::String(_)] => repo.clone(),
+                    _ => return Err(error("`dotfile` takes one path".to_string())),
+                };
+                let mut fields = node.fields_to_json().map_err(error)?;
+                fields["repo"] = repo;
+                serde_json::from_value(fields).map_err(|err| error(err.to_string()))
+            })
+            .collect::<Result<_, _>>()
+            .map_err(Into::into)
+    }
+}
+
+/// Reads `{ dotfiles = [...] }` Dhall records by converting them to JSON with
+/// `dhall-to-json`, which must be installed; serde_dhall can't be built
+/// alongside heim. Optional fields which are `None`
+/// are treated as unset, but Dhall lists have to be homogeneous, so every
+/// dotfile needs the same fields; a helper function which fills in defaults
+/// keeps that short.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DhallLoader;
+
+impl ListLoader for DhallLoader {
+    fn name(&self) -> &str {
+        "dhall"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["dhall"]
+    }
+
+    /// Relative imports are resolved relative to the current directory; use
+    /// `load` to resolve them relative to the list.
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let json = command_output(&mut Command::new("dhall-to-json"), Some(read_all(reader)?))?;
+        JsonLoader.parse(&mut json.as_slice())
+    }
+
+    fn load(&self, path: &Path, _facts: &Facts) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let json = command_output(Command::new("dhall-to-json").arg("--file").arg(path), None)?;
+        JsonLoader.parse(&mut json.as_slice())
+    }
+}
+
+/// Reads CUE files with a `dotfiles` field by exporting them to JSON with
+/// `cue export`, which must be installed; the list can then be checked
+/// against a CUE schema, e.g. `dotfiles: [...string | #Dotfile]`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CueLoader;
+
+impl ListLoader for CueLoader {
+    fn name(&self) -> &str {
+        "cue"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["cue"]
+    }
+
+    /// Imports are resolved relative to the current directory; use `load` to
+    /// resolve them relative to the list.
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        // `-` is standard input.
+        cue_export(OsStr::new("-"), Some(read_all(reader)?))
+    }
+
+    fn load(&self, path: &Path, _facts: &Facts) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        cue_export(path.as_os_str(), None)
+    }
+}
+
+fn cue_export(
+    file: &OsStr,
+    input: Option<Vec<u8>>,
+) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+    let json = command_output(
+        Command::new("cue")
+            .args(["export", "--out", "json"])
+            .arg(file),
+        input,
+    )?;
+    JsonLoader.parse(&mut json.as_slice())
+}
+
+/// Reads Jsonnet files which evaluate to `{ dotfiles: [...] }` with
+/// jrsonnet, which is built in; this needs the `jsonnet` feature. Imports
+/// are resolved relative to the importing file and then the directory
+/// containing the list, so shared `.libsonnet` files can live anywhere in
+/// the dotfile repository. Facts are available as `std.extVar("facts")`, like
+/// Nix's `facts` argument.
+#[cfg(feature = "jsonnet")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonnetLoader;
+
+#[cfg(feature = "jsonnet")]
+impl ListLoader for JsonnetLoader {
+    fn name(&self) -> &str {
+        "jsonnet"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["jsonnet"]
+    }
+
+    /// Lists only see the built-in facts, and imports are resolved relative
+    /// to the current directory; use `load` to give them user-defined facts
+    /// and resolve imports relative to the list.
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let mut code = String::new();
+        reader.read_to_string(&mut code)?;
+        let state = jsonnet_state(Path::new("."), &Facts::new())?;
+        let name = Path::new("dotfiles.jsonnet").into();
+        jsonnet_dotfiles(&state, |state| {
+            state.evaluate_snippet_raw(name, code.into())
+        })
+    }
+
+    fn load(&self, path: &Path, facts: &Facts) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let state = jsonnet_state(path.parent().unwrap_or_else(|| Path::new(".")), facts)?;
+        jsonnet_dotfiles(&state, |state| state.evaluate_file_raw(path))
+    }
+}
+
+/// A Jsonnet evaluator for a list in `dir`, with `facts` available as
+/// `std.extVar("facts")`.
+#[cfg(feature = "jsonnet")]
+fn jsonnet_state(dir: &Path, facts: &Facts) -> Result<EvaluationState, DotfilesReadError> {
+    let model = FactsModel {
+        env: Default::default(),
+        ..facts.model()?
+    };
+    let state = EvaluationState::default();
+    state.with_stdlib();
+    state.set_import_resolver(Box::new(FileImportResolver {
+        library_paths: vec![dir.to_path_buf()],
+    }));
+    state.add_ext_var(
+        "facts".into(),
+        Val::from(&serde_json::to_value(&model).expect("facts are always serializable")),
+    );
+    Ok(state)
+}
+
+/// Evaluate a Jsonnet dotfiles list with `evaluate`.
+#[cfg(feature = "jsonnet")]
+fn jsonnet_dotfiles(
+    state: &EvaluationState,
+    evaluate: impl FnOnce(&EvaluationState) -> jrsonnet_evaluator::error::Result<Val>,
+) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+    // Converting the result to JSON forces its lazy fields, which needs the
+    // evaluator's state.
+    let json = state
+        .run_in_state(|| evaluate(state).and_then(|value| serde_json::Value::try_from(&value)))
+        .map_err(|err| DotfilesReadError::Jsonnet(state.stringify_err(&err)))?;
+    Ok(serde_json::from_value::<SerdeDotfileList>(json)?.dotfiles)
+}
+
+fn read_all(reader: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    Ok(input)
+}
+
+/// Run a program which reads a dotfiles list, like `dhall-to-json`, with
+/// `input`, if any, on its standard input, and return its standard output.
+fn command_output(
+    command: &mut Command,
+    input: Option<Vec<u8>>,
+) -> Result<Vec<u8>, DotfilesReadError> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = match output_with_input(command, input, COMMAND_TIMEOUT) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(DotfilesReadError::ProgramNotFound(program))
+        }
+        Err(err) => return Err(err.into()),
+        Ok(None) => {
+            return Err(DotfilesReadError::ProgramFailed {
+                program,
+                stderr: format!("timed out after {} seconds", COMMAND_TIMEOUT.as_secs()),
+            })
+        }
+        Ok(Some(output)) => output,
+    };
+    if !output.status.success() {
+        return Err(DotfilesReadError::ProgramFailed {
+            program,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(output.stdout)
+}
+
+/// The loaders to read dotfiles lists with, in the order their extensions are
+/// looked for.
+#[derive(Debug, Clone)]
+pub struct ListLoaders(Vec<Arc<dyn ListLoader>>);
+
+impl Default for ListLoaders {
+    /// The built-in loaders, for JSON, JSON5, TOML, KDL, Dhall, CUE, plain
+    /// text, and YAML, Jsonnet, Lua, Rhai, and Starlark if they're enabled.
+    fn default() -> Self {
+        #[allow(unused_mut)]
+        let mut loaders: Vec<Arc<dyn ListLoader>> = vec![
+            Arc::new(JsonLoader),
+            Arc::new(Json5Loader),
+            Arc::new(TomlLoader),
+            #[cfg(feature = "yaml")]
+            Arc::new(YamlLoader),
+            Arc::new(KdlLoader),
+            Arc::new(DhallLoader),
+            Arc::new(CueLoader),
+            #[cfg(feature = "jsonnet")]
+            Arc::new(JsonnetLoader),
+            Arc::new(TextLoader),
+        ];
+        #[cfg(feature = "lua")]
+        loaders.push(Arc::new(crate::lua::LuaLoader));
+        #[cfg(feature = "rhai")]
+        loaders.push(Arc::new(crate::script::RhaiLoader));
+        #[cfg(feature = "starlark")]
+        loaders.push(Arc::new(crate::starlark::StarlarkLoader));
+        Self(loaders)
+    }
+}
+
+impl PartialEq for ListLoaders {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(other.0.iter())
+                .all(|(l, r)| l.name() == r.name())
+    }
+}
+
+impl ListLoaders {
+    /// No loaders at all, so only Nix dotfiles lists are read.
+    pub fn empty() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Add a loader, replacing any loader with the same name in place;
+    /// otherwise, it's looked for after the existing loaders.
+    pub fn register(&mut self, loader: impl ListLoader + 'static) {
+        let loader: Arc<dyn ListLoader> = Arc::new(loader);
+        match self.0.iter_mut().find(|l| l.name() == loader.name()) {
+            Some(existing) => *existing = loader,
+            None => self.0.push(loader),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn ListLoader>> {
+        self.0.iter()
+    }
+
+    /// The loader named `name`.
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn ListLoader>> {
+        self.0.iter().find(|l| l.name() == name)
+    }
+
+    /// The first loader for files with the extension `ext`.
+    pub fn for_extension(&self, ext: &str) -> Option<&Arc<dyn ListLoader>> {
+        self.0.iter().find(|l| l.extensions().contains(&ext))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::condition::{Condition, Pattern};
+
+    #[test]
+    fn serde_dotfile_list() {
+        let dotfiles: SerdeDotfileList = serde_json::from_str(
+            r#"
+                {
+                    "$schema": "...",
+                    "dotfiles": [
+                        "ok",
+                        {
+                            "repo": "repo-path",
+                            "installed": "installed-path"
+                        },
+                        "great"
+                    ]
+                }
+                "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            dotfiles.dotfiles,
+            vec![
+                SerdeDotfile::Path("ok".into()),
+                SerdeDotfile::Advanced(Dotfile {
+                    repo: "repo-path".into(),
+                    installed: Some("installed-path".into()),
+                    ..Default::default()
+                }),
+                SerdeDotfile::Path("great".into()),
+            ]
+        );
+
+        assert_eq!(
+            dotfiles.dotfiles(),
+            vec![
+                Dotfile {
+                    repo: "ok".into(),
+                    installed: None,
+                    ..Default::default()
+                },
+                Dotfile {
+                    repo: "repo-path".into(),
+                    installed: Some("installed-path".into()),
+                    ..Default::default()
+                },
+                Dotfile {
+                    repo: "great".into(),
+                    installed: None,
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    /// One path per line; it's named `yaml` to replace the built-in YAML
+    /// loader.
+    #[derive(Debug)]
+    struct LinesLoader;
+
+    impl ListLoader for LinesLoader {
+        fn name(&self) -> &str {
+            "yaml"
+        }
+
+        fn extensions(&self) -> &[&str] {
+            &["lines"]
+        }
+
+        fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+            let mut lines = String::new();
+            reader.read_to_string(&mut lines)?;
+            Ok(lines
+                .lines()
+                .map(|line| SerdeDotfile::Path(line.into()))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn list_loaders() {
+        let mut loaders = ListLoaders::default();
+        let parse = |loaders: &ListLoaders, ext: &str, list: &str| {
+            loaders
+                .for_extension(ext)
+                .unwrap()
+                .parse(&mut list.as_bytes())
+                .unwrap()
+        };
+        #[cfg(feature = "yaml")]
+        assert_eq!(
+            parse(&loaders, "yml", "dotfiles: [.bashrc]"),
+            vec![SerdeDotfile::Path(".bashrc".into())]
+        );
+        assert_eq!(
+            parse(
+                &loaders,
+                "jsonc",
+                "{\"dotfiles\": [\".bashrc\", /* .vimrc */]}"
+            ),
+            vec![SerdeDotfile::Path(".bashrc".into())]
+        );
+        assert_eq!(
+            parse(&loaders, "txt", "# Shell\n  .bashrc \n\n.config/fish\n"),
+            vec![
+                SerdeDotfile::Path(".bashrc".into()),
+                SerdeDotfile::Path(".config/fish".into())
+            ]
+        );
+        assert_eq!(
+            parse(&loaders, "toml", "dotfiles = ['.bashrc']"),
+            vec![SerdeDotfile::Path(".bashrc".into())]
+        );
+        assert!(loaders.for_extension("lines").is_none());
+
+        loaders.register(LinesLoader);
+        let mut extensions = vec![&["json"][..], &["json5", "jsonc"], &["toml"]];
+        // `LinesLoader` replaces the YAML loader in place, or comes last
+        // without one.
+        #[cfg(feature = "yaml")]
+        extensions.push(&["lines"]);
+        extensions.extend([&["kdl"][..], &["dhall"], &["cue"]]);
+        #[cfg(feature = "jsonnet")]
+        extensions.push(&["jsonnet"]);
+        extensions.push(&["txt"]);
+        #[cfg(feature = "lua")]
+        extensions.push(&["lua"]);
+        #[cfg(feature = "rhai")]
+        extensions.push(&["rhai"]);
+        #[cfg(feature = "starlark")]
+        extensions.push(&["star"]);
+        #[cfg(not(feature = "yaml"))]
+        extensions.push(&["lines"]);
+        assert_eq!(
+            loaders.iter().map(|l| l.extensions()).collect::<Vec<_>>(),
+            extensions
+        );
+        assert!(loaders.for_extension("yml").is_none());
+        assert_eq!(
+            parse(&loaders, "lines", ".bashrc\n.vimrc\n"),
+            vec![
+                SerdeDotfile::Path(".bashrc".into()),
+                SerdeDotfile::Path(".vimrc".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn dhall_loader() {
+        match DhallLoader.load(Path::new("test-data/dotfiles-dhall.dhall"), &Facts::new()) {
+            Err(DotfilesReadError::ProgramNotFound(program)) => {
+                assert_eq!(program, "dhall-to-json")
+            }
+            res => {
+                let dotfiles = SerdeDotfileList::from(res.unwrap()).dotfiles();
+                assert_eq!(dotfiles.len(), 4);
+                assert_eq!(
+                    dotfiles[3],
+                    Dotfile {
+                        repo: ".config/fisher_local/fishfile".into(),
+                        installed: Some(".config/fish/fishfile".into()),
+                        ..Default::default()
+                    }
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn kdl_loader() {
+        let parse = |list: &str| KdlLoader.parse(&mut list.as_bytes());
+        assert_eq!(
+            parse("dotfile \"nvim\" installed=\".config/nvim\" {\n  when os=\"linux\"\n}").unwrap(),
+            vec![SerdeDotfile::Advanced(Dotfile {
+                repo: "nvim".into(),
+                installed: Some(".config/nvim".into()),
+                when: Some(Condition::Table(
+                    vec![("os".into(), Pattern::String("linux".into()))]
+                        .into_iter()
+                        .collect()
+                )),
+                ..Default::default()
+            })]
+        );
+        assert_eq!(
+            parse("dotfile \".bashrc\"\nfile \".vimrc\"")
+                .unwrap_err()
+                .to_string(),
+            "failed to parse as KDL / incorrect schema"
+        );
+        assert!(matches!(
+            parse("dotfile \".bashrc\"\ndotfile \".vimrc\" template=\"yes\""),
+            Err(DotfilesReadError::KDL(KdlError { line: 2, .. }))
+        ));
+    }
+
+    #[test]
+    fn cue_loader() {
+        match CueLoader.load(Path::new("test-data/dotfiles-cue.cue"), &Facts::new()) {
+            Err(DotfilesReadError::ProgramNotFound(program)) => assert_eq!(program, "cue"),
+            res => assert_eq!(
+                SerdeDotfileList::from(res.unwrap()).dotfiles(),
+                vec![
+                    Dotfile::from(PathBuf::from(".bashrc")),
+                    Dotfile {
+                        repo: ".config/fisher_local/fishfile".into(),
+                        installed: Some(".config/fish/fishfile".into()),
+                        ..Default::default()
+                    },
+                ]
+            ),
+        }
+    }
+
+    #[cfg(feature = "jsonnet")]
+    #[test]
+    fn jsonnet_loader() {
+        let facts = Facts::new().with_vars(
+            &vec![("email".to_string(), "me@example.com".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(
+            SerdeDotfileList::from(
+                JsonnetLoader
+                    .load(Path::new("test-data/jsonnet/dotfiles.jsonnet"), &facts)
+                    .unwrap()
+            )
+            .dotfiles(),
+            vec![
+                Dotfile::from(PathBuf::from(".bashrc")),
+                Dotfile {
+                    repo: "gitconfig-me@example.com".into(),
+                    installed: Some(".gitconfig".into()),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert!(matches!(
+            JsonnetLoader.parse(&mut "{ dotfiles: error 'oops' }".as_bytes()),
+            Err(DotfilesReadError::Jsonnet(_))
+        ));
+    }
+}
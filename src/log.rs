@@ -0,0 +1,173 @@
+//! The `log_file`: a record of what each run did, so unattended runs (e.g.
+//! `dfm sync` from cron) can be checked on later.
+//!
+//! Each line is a JSON object with the `time` (in UTC, RFC 3339), an ID for
+//! the `run` shared by its records, the `event`, and fields depending on
+//! the event; null fields are left out. For example:
+//!
+//! ```text
+//! {"args":["dfm","link"],"event":"start","run":"1709262000-4242","time":"2024-03-01T03:00:00Z"}
+//! {"event":"link","installed":"/home/me/.bashrc","ok":true,"repo":"/home/me/.dotfiles/bashrc","run":"1709262000-4242","time":"2024-03-01T03:00:00Z"}
+//! {"event":"finish","ok":true,"run":"1709262000-4242","time":"2024-03-01T03:00:01Z"}
+//! ```
+//!
+//! When the log grows past its maximum size, it's moved aside to the same
+//! path with `.1` appended, replacing the previous one.
+
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Map, Value};
+
+/// The default `log_file_max_size`: 1 MiB.
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+
+/// An open log file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Log {
+    path: PathBuf,
+    run: String,
+}
+
+impl Log {
+    /// Start a run's records in the log at `path`, first moving the log
+    /// aside if it's larger than `max_size` bytes, so a run's records stay
+    /// in one file.
+    pub fn open(path: &Path, max_size: u64) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match fs::metadata(path) {
+            Ok(meta) if meta.len() > max_size => fs::rename(path, rotated(path))?,
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Self {
+            path: path.to_path_buf(),
+            run: format!("{}-{}", now.as_secs(), process::id()),
+        })
+    }
+
+    /// Append a record of `event` with `fields`, which should be a JSON
+    /// object; null fields are skipped.
+    pub fn record(&self, event: &str, fields: Value) -> io::Result<()> {
+        let mut record = Map::new();
+        record.insert("time".into(), timestamp(SystemTime::now()).into());
+        record.insert("run".into(), self.run.clone().into());
+        record.insert("event".into(), event.into());
+        if let Value::Object(fields) = fields {
+            record.extend(fields.into_iter().filter(|(_, value)| !value.is_null()));
+        }
+        let mut line = Value::Object(record).to_string();
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+}
+
+/// Where the log at `path` is moved to when it's rotated.
+fn rotated(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    rotated.into()
+}
+
+/// `time` in UTC in RFC 3339 format, e.g. `2024-03-01T03:00:00Z`.
+pub fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Days since 1970-01-01 to a date; see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn log_timestamp() {
+        let at = |secs| timestamp(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_709_262_000), "2024-03-01T03:00:00Z");
+        assert_eq!(at(4_102_444_799), "2099-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn log_record_and_rotate() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("state").join("log.jsonl");
+        let log = Log::open(&path, 100).unwrap();
+        log.record("start", json!({ "args": ["dfm", "link"] }))
+            .unwrap();
+        log.record("finish", json!({ "ok": true, "error": null }))
+            .unwrap();
+        let first = fs::read_to_string(&path).unwrap();
+        // The log is now over 100 bytes, so it's rotated.
+        Log::open(&path, 100)
+            .unwrap()
+            .record("start", json!({}))
+            .unwrap();
+        let second = fs::read_to_string(&path).unwrap();
+        let rotated = fs::read_to_string(rotated(&path)).unwrap();
+
+        let records = first
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["event"], "start");
+        assert_eq!(records[0]["args"], json!(["dfm", "link"]));
+        assert_eq!(records[1]["ok"], true);
+        assert_eq!(records[1].get("error"), None);
+        assert_eq!(records[0]["run"], records[1]["run"]);
+        assert!(records[0]["time"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(rotated, first);
+        assert_eq!(second.lines().count(), 1);
+    }
+}
@@ -0,0 +1,171 @@
+//! Dotfiles lists written in Lua (<https://www.lua.org>), run with an
+//! embedded Lua 5.4 interpreter. The script returns the list of dotfiles,
+//! e.g.
+//!
+//! ```lua
+//! local dotfiles = { ".bashrc" }
+//! if facts.os == "linux" then
+//!   table.insert(dotfiles, { repo = "i3", installed = ".config/i3" })
+//! end
+//! return dotfiles
+//! ```
+//!
+//! Besides Lua's standard library, scripts can use:
+//!
+//! - `facts`: every fact, as printed by `dotfile-manager facts --json`, but
+//!   without `env`.
+//! - `env(name)`: the environment variable `name`, or `nil` if it's unset.
+//! - `path.home` and `path.repo`: the home directory and the directory
+//!   containing the script.
+//! - `path.join(...)`: its arguments joined with `/`.
+//! - `path.exists(p)`: whether `p`, relative to `path.repo`, exists.
+//!
+//! Scripts are stopped if they run for too long.
+//!
+//! This needs the `lua` feature.
+
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use mlua::{HookTriggers, Lua, LuaSerdeExt, SerializeOptions, Variadic};
+
+use crate::config::DotfilesReadError;
+use crate::dotfile::SerdeDotfile;
+use crate::facts::{Facts, FactsModel};
+use crate::loader::ListLoader;
+use crate::util::home_dir;
+
+/// How many instructions a script may run before it's stopped.
+const MAX_INSTRUCTIONS: u32 = 100_000_000;
+
+/// Runs Lua dotfiles lists; see the module documentation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LuaLoader;
+
+impl ListLoader for LuaLoader {
+    fn name(&self) -> &str {
+        "lua"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["lua"]
+    }
+
+    /// Scripts see the current directory as `path.repo`, and only see the
+    /// built-in facts; use `load` to give them user-defined facts too.
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let mut script = String::new();
+        reader.read_to_string(&mut script)?;
+        eval_list(&script, "dotfiles.lua", &Facts::new(), Path::new("."))
+    }
+
+    fn load(&self, path: &Path, facts: &Facts) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let script = fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        eval_list(&script, &name, facts, dir)
+    }
+}
+
+/// Run `script`, called `name` in error messages, which returns a dotfiles
+/// list; `dir` is the directory containing it.
+fn eval_list(
+    script: &str,
+    name: &str,
+    facts: &Facts,
+    dir: &Path,
+) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+    let model = FactsModel {
+        env: Default::default(),
+        ..facts.model()?
+    };
+    let lua = lua(&model, &home_dir()?, dir)?;
+    let list = lua.load(script).set_name(name)?.eval()?;
+    Ok(lua.from_value(list)?)
+}
+
+/// A Lua interpreter with the API described in the module documentation.
+fn lua(facts: &FactsModel, home: &Path, dir: &Path) -> mlua::Result<Lua> {
+    let lua = Lua::new();
+    let triggers = HookTriggers {
+        every_nth_instruction: Some(MAX_INSTRUCTIONS),
+        ..Default::default()
+    };
+    lua.set_hook(triggers, |_, _| {
+        Err(mlua::Error::RuntimeError(
+            "the script ran for too long".to_string(),
+        ))
+    })?;
+    let globals = lua.globals();
+    // Leave out missing facts, rather than making them truthy `null`s.
+    let options = SerializeOptions::new()
+        .serialize_none_to_null(false)
+        .serialize_unit_to_null(false);
+    globals.set("facts", lua.to_value_with(facts, options)?)?;
+    globals.set(
+        "env",
+        lua.create_function(|_, name: String| Ok(env::var(name).ok()))?,
+    )?;
+    let path = lua.create_table()?;
+    path.set("home", home.to_string_lossy())?;
+    path.set("repo", dir.to_string_lossy())?;
+    path.set(
+        "join",
+        lua.create_function(|_, parts: Variadic<String>| Ok(parts.join("/")))?,
+    )?;
+    let repo = dir.to_path_buf();
+    path.set(
+        "exists",
+        lua.create_function(move |_, p: String| Ok(repo.join(p).exists()))?,
+    )?;
+    globals.set("path", path)?;
+    drop(globals);
+    Ok(lua)
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::dotfile::Dotfile;
+
+    #[test]
+    fn lua_loader() {
+        let facts = Facts::new().with_vars(
+            &vec![("email".to_string(), "me@example.com".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(
+            LuaLoader
+                .load(Path::new("test-data/dotfiles-lua.lua"), &facts)
+                .unwrap(),
+            vec![
+                SerdeDotfile::Path(".bashrc".into()),
+                SerdeDotfile::Advanced(Dotfile {
+                    repo: "gitconfig-me@example.com".into(),
+                    installed: Some(".config/git/config".into()),
+                    ..Default::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn lua_api() {
+        let parse = |script: &str| LuaLoader.parse(&mut script.as_bytes());
+        assert_eq!(
+            parse(r#"return { env("DFM_SURELY_UNSET") or "unset", path.exists("Cargo.toml") and "exists" or "missing" }"#)
+                .unwrap(),
+            vec![
+                SerdeDotfile::Path("unset".into()),
+                SerdeDotfile::Path("exists".into())
+            ]
+        );
+        assert!(parse("return facts.os").is_err());
+        assert!(parse("while true do end").is_err());
+    }
+}
@@ -1,44 +1,1736 @@
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
+use std::env;
+use std::fmt::Write;
+use std::fs;
 use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
 
-use thiserror::Error;
+#[cfg(feature = "interactive")]
+use dialoguer::{Checkboxes, Confirmation};
+use once_cell::sync::{Lazy, OnceCell};
+use serde_json::{json, Value};
+use structopt::StructOpt;
 
+use dotfile_manager::ansible;
+use dotfile_manager::archive;
+use dotfile_manager::archive::ArchiveFormat;
+use dotfile_manager::bare_git::BareRepo;
 use dotfile_manager::config;
-use dotfile_manager::config::{Config, ConfigReadError, DotfilesReadError};
-use dotfile_manager::dotfile::AbsDotfile;
+use dotfile_manager::config::{Config, ConfigReadError};
+use dotfile_manager::cron;
+use dotfile_manager::defaults;
+use dotfile_manager::deploy;
+use dotfile_manager::diagnostic::Diagnose;
+use dotfile_manager::diff;
+use dotfile_manager::diff::ColorChoice;
+use dotfile_manager::discover;
+use dotfile_manager::dotbot;
+use dotfile_manager::dotfile::{AbsDotfile, Dotfile, InstallStatus};
+use dotfile_manager::engine;
+use dotfile_manager::engine::Action;
+use dotfile_manager::facts::Facts;
+use dotfile_manager::git;
+use dotfile_manager::git::{RepoStatus, SubmoduleState};
+#[cfg(feature = "nix")]
+use dotfile_manager::home_manager;
+use dotfile_manager::log::Log;
+#[cfg(feature = "nix")]
+use dotfile_manager::nix;
+use dotfile_manager::notify;
+use dotfile_manager::observer::{ExecutionObserver, JsonLines, Outcome};
+use dotfile_manager::packages;
+use dotfile_manager::packages::Packages;
+use dotfile_manager::pager;
+use dotfile_manager::plugin;
+use dotfile_manager::plugin::Plugins;
+use dotfile_manager::porcelain;
+use dotfile_manager::porcelain::PorcelainVersion;
+#[cfg(feature = "interactive")]
+use dotfile_manager::prompt;
+use dotfile_manager::prompt::{ConflictAction, ConflictPrompt};
+use dotfile_manager::report::LinkReport;
+#[cfg(feature = "interactive")]
+use dotfile_manager::select;
+use dotfile_manager::shell;
+use dotfile_manager::short_status;
+use dotfile_manager::spinner;
+use dotfile_manager::spinner::Spinner;
+use dotfile_manager::stow::Stow;
+use dotfile_manager::systemd;
+use dotfile_manager::systemd::SystemdUnit;
+use dotfile_manager::table::Table;
+use dotfile_manager::template;
+use dotfile_manager::util;
+use dotfile_manager::util::{home_dir, make_abs};
+use dotfile_manager::vscode;
+use dotfile_manager::warning::{Warning, WarningKind, Warnings};
+use dotfile_manager::Error;
 
-#[derive(Debug, Error)]
-enum MainError {
-    #[error("{0}")]
-    Io(#[from] io::Error),
+/// Manage dotfiles across multiple computers.
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Collect system facts again rather than using cached facts.
+    #[structopt(long)]
+    refresh_facts: bool,
 
-    #[error("{0}")]
-    ConfigRead(#[from] ConfigReadError),
+    /// Pretend a fact has a value, e.g. `--fact os=windows`. May be given
+    /// multiple times.
+    #[structopt(
+        long = "fact",
+        value_name = "name=value",
+        number_of_values = 1,
+        parse(try_from_str = parse_fact_override)
+    )]
+    facts: Vec<(String, String)>,
 
-    #[error("{0}")]
-    DotfilesRead(#[from] DotfilesReadError),
+    /// Pass the Nix expression `expr` as the argument `name` to a dotfiles
+    /// list written in Nix. May be given multiple times.
+    #[cfg(feature = "nix")]
+    #[structopt(long, number_of_values = 2, value_names = &["name", "expr"])]
+    arg: Vec<String>,
+
+    /// Pass the string `value` as the argument `name` to a dotfiles list
+    /// written in Nix. May be given multiple times.
+    #[cfg(feature = "nix")]
+    #[structopt(long, number_of_values = 2, value_names = &["name", "value"])]
+    argstr: Vec<String>,
+
+    /// Read the dotfiles list from this file, relative to the dotfile
+    /// repository, rather than looking for one; `-` reads standard input.
+    #[structopt(long, parse(from_os_str))]
+    dotfiles: Option<PathBuf>,
+
+    /// The format the dotfiles list is written in, e.g. `yaml` or `nix`,
+    /// regardless of its extension.
+    #[structopt(long)]
+    format: Option<String>,
+
+    /// Evaluate a dotfiles list written in Nix even if it hasn't changed
+    /// since it was last evaluated.
+    #[cfg(feature = "nix")]
+    #[structopt(long)]
+    no_cache: bool,
+
+    /// Print more details about what's going on, like the environment Nix
+    /// is evaluated in.
+    #[structopt(short, long)]
+    verbose: bool,
+
+    /// Only print problems, like conflicts, warnings and errors; successful
+    /// operations print nothing, so dfm can run quietly from a login
+    /// script.
+    #[structopt(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Send a desktop notification when dotfiles are installed or something
+    /// goes wrong, so runs in the background, like `dfm sync` from cron,
+    /// don't fail silently.
+    #[structopt(long)]
+    notify: bool,
+
+    /// Don't show long output, like `dfm diff`, in `$PAGER`.
+    #[structopt(long)]
+    no_pager: bool,
+
+    /// Print what would be done without changing anything.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// When to color output: `always`, `never` or `auto`, which colors
+    /// output to a terminal unless `NO_COLOR` is set.
+    #[structopt(long, value_name = "when", default_value = "auto")]
+    color: ColorChoice,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// List the dotfiles which apply to this machine. This is the default.
+    Status {
+        /// Print only the dotfiles, for scripts to read: one per line, with
+        /// its status, installed path, path in the dotfile repository, and
+        /// tags separated by tabs. The value is the version of the format:
+        /// `v1`, the default, or `v2`, which shows copies and every
+        /// strategy.
+        #[structopt(long, value_name = "version", require_equals = true)]
+        porcelain: Option<Option<PorcelainVersion>>,
+
+        /// Print a summary for a shell prompt: `dfm:N!` if N dotfiles
+        /// aren't installed, or nothing. It's quick, and shows the last
+        /// status it checked if checking takes too long.
+        #[structopt(long, conflicts_with = "porcelain")]
+        short: bool,
+    },
+
+    /// Install the dotfiles which apply to this machine.
+    Link {
+        /// Choose which of the dotfiles to install, searching for them by
+        /// path.
+        #[structopt(short, long)]
+        interactive: bool,
+
+        /// Print a line of JSON to standard output for each step as it
+        /// happens, e.g. for another program to show progress.
+        #[structopt(long)]
+        json: bool,
+    },
+
+    /// Show how the installed dotfiles differ from what `dfm link` would
+    /// install: rendered templates, and files in the way of links.
+    Diff {
+        /// Show the two versions in columns side by side rather than as a
+        /// unified diff.
+        #[structopt(long)]
+        side_by_side: bool,
+
+        /// Choose which of the dotfiles to compare, searching for them by
+        /// path.
+        #[structopt(short, long)]
+        interactive: bool,
+    },
+
+    /// Set up a new machine: clone a dotfile repository into
+    /// `dotfile_repo`, install the configuration file it contains (if
+    /// there's none yet), and install the dotfiles.
+    Init {
+        /// The repository to clone, e.g.
+        /// `git@github.com:me/dotfiles.git`, or an archive of it to download
+        /// and unpack, e.g.
+        /// `https://github.com/me/dotfiles/archive/main.tar.gz`; default
+        /// `dotfile_repo_url`.
+        #[structopt(long)]
+        from: Option<String>,
+
+        /// The SHA-256 checksum the archive must have; default
+        /// `dotfile_repo_sha256` if `--from` isn't given.
+        #[structopt(long)]
+        sha256: Option<String>,
+    },
+
+    /// Install the dotfiles on another machine over SSH: copy the dotfile
+    /// repository (without `.git`) to the same path in the remote home
+    /// directory, then link the dotfiles there. Without `--remote-dfm`, the
+    /// remote machine's facts aren't collected: dotfiles with `when`
+    /// conditions or templates are refused unless its facts are given with
+    /// `--fact`, which are used for all of them.
+    Deploy {
+        /// The machine to install on, e.g. `me@server`; anything `ssh`
+        /// accepts.
+        host: String,
+
+        /// Run this dfm command on the remote machine to link the dotfiles,
+        /// e.g. `dfm`, rather than a generated shell script.
+        #[structopt(long)]
+        remote_dfm: Option<String>,
+    },
+
+    /// Initialize and update the dotfile repository's git submodules, e.g.
+    /// a shared configuration repository or editor plugins.
+    Sync {
+        /// Update each submodule to the latest commit of its remote branch
+        /// rather than the commit the dotfile repository records, and commit
+        /// the new commits if `git.auto_commit` is set.
+        #[structopt(long)]
+        remote: bool,
+    },
+
+    /// List well-known application configuration files in the home
+    /// directory which aren't managed yet.
+    Discover {
+        /// Choose files to manage and print them as a dotfiles list, as
+        /// JSON.
+        #[structopt(long)]
+        select: bool,
+    },
+
+    /// Check the configuration and environment for problems.
+    Doctor,
+
+    /// Print the dotfiles which apply to this machine in another tool's
+    /// format.
+    Export {
+        #[structopt(subcommand)]
+        format: ExportFormat,
+    },
+
+    /// Print a dotfiles list, as JSON, converted from another tool's
+    /// configuration.
+    Import {
+        #[structopt(subcommand)]
+        source: ImportSource,
+    },
+
+    /// Debug a dotfiles list written in Nix.
+    #[cfg(feature = "nix")]
+    Nix {
+        #[structopt(subcommand)]
+        cmd: NixCommand,
+    },
+
+    /// Install the packages declared in `packages.toml` in the dotfile
+    /// repository.
+    Packages {
+        #[structopt(subcommand)]
+        cmd: PackagesCommand,
+    },
+
+    /// Install the taps, formulae and casks in the `brewfile` with `brew
+    /// bundle`.
+    Brew {
+        #[structopt(subcommand)]
+        cmd: PackagesCommand,
+    },
+
+    /// Print the facts available to conditions and templates.
+    Facts {
+        /// Print facts as JSON, including environment variables.
+        #[structopt(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum ExportFormat {
+    /// A home-manager module setting `home.file` and `xdg.configFile`.
+    #[cfg(feature = "nix")]
+    HomeManager,
+
+    /// A dotbot `install.conf.yaml` linking each dotfile, to be saved in the
+    /// dotfile repository.
+    Dotbot,
+
+    /// An Ansible tasks file linking each dotfile and writing rendered
+    /// templates.
+    Ansible,
+}
+
+#[derive(Debug, StructOpt)]
+enum PackagesCommand {
+    /// List the declared packages which aren't installed.
+    Status,
+
+    /// Install the declared packages which aren't installed.
+    Install,
+}
+
+#[cfg(feature = "nix")]
+#[derive(Debug, StructOpt)]
+enum NixCommand {
+    /// Evaluate the dotfiles list, print the result as JSON, and report
+    /// which elements aren't valid dotfiles.
+    Check,
+}
+
+#[derive(Debug, StructOpt)]
+enum ImportSource {
+    /// The `home.file` and `xdg.configFile` entries of a home-manager
+    /// configuration.
+    #[cfg(feature = "nix")]
+    HomeManager {
+        /// The configuration file, e.g. `~/.config/nixpkgs/home.nix`.
+        #[structopt(parse(from_os_str))]
+        config: PathBuf,
+    },
+
+    /// The files tracked by a bare git repository whose work tree is the
+    /// home directory, e.g. one used with `alias config='git
+    /// --git-dir=$HOME/.cfg --work-tree=$HOME'`. The files are moved into
+    /// the dotfile repository and linked back, and the bare repository
+    /// becomes the dotfile repository's `.git`.
+    BareGit {
+        /// The bare repository, e.g. `~/.cfg`.
+        #[structopt(parse(from_os_str))]
+        git_dir: PathBuf,
+
+        /// The repository's work tree; default the home directory.
+        #[structopt(long, parse(from_os_str))]
+        work_tree: Option<PathBuf>,
+    },
+
+    /// The `link` directives of a dotbot configuration.
+    Dotbot {
+        /// The configuration file, e.g. `install.conf.yaml`.
+        #[structopt(parse(from_os_str))]
+        config: PathBuf,
+    },
+
+    /// The packages in a GNU Stow directory, including directories stow has
+    /// folded into one link.
+    Stow {
+        /// The stow directory, e.g. `~/dotfiles`.
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+
+        /// The directory the packages are linked to; default the stow
+        /// directory's parent, like stow.
+        #[structopt(short, long, parse(from_os_str))]
+        target: Option<PathBuf>,
+
+        /// Replace a `dot-` prefix on files in packages with `.`, like
+        /// `stow --dotfiles`.
+        #[structopt(long)]
+        dotfiles: bool,
+    },
+}
+
+/// Whether `--quiet` was given; see `note!`.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Print a note about what dfm is doing to standard error, unless
+/// `--quiet` was given. Problems should be printed with `eprintln!`
+/// instead.
+macro_rules! note {
+    ($($arg:tt)*) => {
+        if !quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Whether `--notify` was given; see `notify`.
+static NOTIFY: AtomicBool = AtomicBool::new(false);
+
+/// Send a desktop notification, if `--notify` was given.
+fn notify(title: &str, body: &str) {
+    if NOTIFY.load(Ordering::Relaxed) {
+        if let Err(err) = notify::send(title, body) {
+            eprintln!("Couldn't send a notification: {}", err);
+        }
+    }
+}
+
+/// Warnings collected during the run; see `warn`.
+static WARNINGS: Lazy<Mutex<Warnings>> = Lazy::new(Default::default);
+
+/// Record a warning, to be printed with the others at the end of the run.
+fn warn(kind: WarningKind, message: String) {
+    WARNINGS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(Warning::new(kind, message));
+}
+
+/// The `log_file`, if there is one; see `log_event`.
+static LOG: OnceCell<Log> = OnceCell::new();
+
+/// The plugins on the `PATH`, unless `plugins = false`; see `load_plugins`.
+static PLUGINS: OnceCell<Plugins> = OnceCell::new();
+
+/// Find and describe the plugins on the `PATH`, and add what they
+/// contribute to `cfg`.
+fn load_plugins(cfg: &mut Config, warnings: &mut Warnings) -> Plugins {
+    let plugins = Plugins::describe(plugin::discover(), warnings);
+    plugins.apply(cfg, warnings);
+    plugins
+}
+
+/// Record `event` in the `log_file`, if there is one. The log is
+/// best-effort; the run doesn't fail if it can't be written.
+fn log_event(event: &str, fields: Value) {
+    if let Some(log) = LOG.get() {
+        let _ = log.record(event, fields);
+    }
+}
+
+fn parse_fact_override(s: &str) -> Result<(String, String), String> {
+    match s.find('=') {
+        Some(i) if i > 0 => Ok((s[..i].to_string(), s[i + 1..].to_string())),
+        _ => Err(format!("expected `name=value` but found `{}`", s)),
+    }
 }
 
 fn main() {
-    let main_ret = main_inner();
-    if let Err(err) = main_ret {
-        println!("Error: {}", err);
-        println!("{:?}", err)
-    }
-}
-
-fn main_inner() -> Result<(), MainError> {
-    let cfg =
-        Config::try_from(dbg!(config::config_file())?.as_path()).or_else(|err| match err {
-            ConfigReadError::NotFound(_) => Config::try_default(),
-            err => Err(err),
-        })?;
-    println!("Configuration: {:?}", cfg);
-    let abs_dotfiles = cfg
-        .dotfiles()?
+    let res = main_inner();
+    let warnings = WARNINGS.lock().unwrap_or_else(|err| err.into_inner());
+    eprint!("{}", warnings.render(console::colors_enabled()));
+    if let Err(err) = res {
+        eprintln!("{}", err.diagnostic().render(console::colors_enabled()));
+        notify("dfm failed", &err.to_string());
+        process::exit(1);
+    }
+}
+
+fn main_inner() -> Result<(), Error> {
+    let opt = Opt::from_args();
+    console::set_colors_enabled(opt.color.enabled());
+    QUIET.store(opt.quiet, Ordering::Relaxed);
+    NOTIFY.store(opt.notify, Ordering::Relaxed);
+    // Output for other programs shouldn't come with a spinner.
+    let machine_output = matches!(
+        opt.cmd,
+        Some(Command::Facts { json: true })
+            | Some(Command::Status {
+                porcelain: Some(_),
+                ..
+            })
+            | Some(Command::Status { short: true, .. })
+            | Some(Command::Link { json: true, .. })
+            | Some(Command::Export { .. })
+            | Some(Command::Import { .. })
+    );
+    #[cfg(feature = "nix")]
+    let machine_output = machine_output || matches!(opt.cmd, Some(Command::Nix { .. }));
+    spinner::set_enabled(!opt.quiet && !machine_output);
+    let config_file = config::config_file()?;
+    if opt.verbose {
+        eprintln!("Configuration file: {}", config_file.display());
+    }
+    let mut cfg = Config::try_from(config_file.as_path()).or_else(|err| match err {
+        ConfigReadError::NotFound(_) => Config::try_default(),
+        err => Err(err),
+    })?;
+    #[cfg(feature = "nix")]
+    for pair in opt.arg.chunks(2) {
+        cfg.nix.args.insert(pair[0].clone(), pair[1].clone());
+    }
+    #[cfg(feature = "nix")]
+    for pair in opt.argstr.chunks(2) {
+        cfg.nix.argstrs.insert(pair[0].clone(), pair[1].clone());
+    }
+    #[cfg(feature = "nix")]
+    if opt.no_cache {
+        cfg.nix.no_cache = true;
+    }
+    if opt.dotfiles.is_some() {
+        cfg.dotfiles_list = opt.dotfiles;
+        cfg.dotfiles_flake = None;
+    }
+    if opt.format.is_some() {
+        cfg.dotfiles_format = opt.format;
+    }
+    #[cfg(feature = "nix")]
+    if opt.verbose {
+        eprintln!("Nix environment:");
+        for (name, value) in cfg.nix.environment() {
+            eprintln!("    {}={}", name, value);
+        }
+    }
+    let ttl = if opt.refresh_facts {
+        Duration::from_secs(0)
+    } else {
+        cfg.facts_cache_ttl
+    };
+    let facts_cache = config::facts_cache_file()?;
+    let overrides = opt.facts.into_iter().collect();
+    if let Some(Command::Status { short: true, .. }) = opt.cmd {
+        short_status(cfg, facts_cache, ttl, overrides);
+    }
+    if cfg.plugins {
+        let plugins = load_plugins(
+            &mut cfg,
+            &mut WARNINGS.lock().unwrap_or_else(|err| err.into_inner()),
+        );
+        let _ = PLUGINS.set(plugins);
+    }
+    let facts_for = |cfg: &Config| facts_with(cfg, &facts_cache, ttl, &overrides);
+    if let Some(log_file) = &cfg.log_file {
+        match Log::open(&make_abs(&home_dir()?, log_file), cfg.log_file_max_size) {
+            Ok(log) => {
+                let _ = LOG.set(log);
+            }
+            Err(err) => eprintln!("Couldn't open {}: {}", log_file.display(), err),
+        }
+    }
+    log_event(
+        "start",
+        json!({ "args": env::args().collect::<Vec<_>>(), "dry_run": opt.dry_run }),
+    );
+    let facts = facts_for(&cfg);
+    let res = match opt.cmd {
+        Some(Command::Facts { json }) => print_facts(&facts, json),
+        Some(Command::Discover { select }) => discover(&cfg, &facts, select),
+        Some(Command::Doctor) => doctor(&cfg, &facts_cache),
+        Some(Command::Export { format }) => export(&cfg, &facts, format),
+        Some(Command::Import { source }) => import(&cfg, source, opt.dry_run),
+        #[cfg(feature = "nix")]
+        Some(Command::Nix {
+            cmd: NixCommand::Check,
+        }) => nix_check(&cfg, &facts),
+        Some(Command::Link { interactive, json }) => {
+            link_dotfiles(&cfg, &facts, interactive, json, opt.dry_run)
+        }
+        Some(Command::Diff {
+            side_by_side,
+            interactive,
+        }) => diff(&cfg, &facts, side_by_side, interactive, !opt.no_pager),
+        Some(Command::Deploy { host, remote_dfm }) => deploy(
+            &cfg,
+            &facts,
+            !overrides.is_empty(),
+            &host,
+            remote_dfm,
+            opt.dry_run,
+        ),
+        Some(Command::Brew { cmd }) => brew(&cfg, cmd, opt.dry_run),
+        Some(Command::Packages { cmd }) => packages(&cfg, cmd, opt.dry_run),
+        Some(Command::Sync { remote }) => sync(&cfg, remote, opt.dry_run),
+        Some(Command::Init { from, sha256 }) => match init(&cfg, from, sha256, opt.dry_run) {
+            Ok(Some(cfg)) => link_dotfiles(&cfg, &facts_for(&cfg), false, false, opt.dry_run),
+            res => res.map(|_| ()),
+        },
+        Some(Command::Status {
+            porcelain: Some(version),
+            ..
+        }) => print_porcelain(&cfg, &facts, version.unwrap_or_default()),
+        Some(Command::Status {
+            porcelain: None, ..
+        })
+        | None => print_dotfiles(&cfg, &facts, opt.verbose, !opt.no_pager),
+    };
+    facts.write_cache(&facts_cache);
+    log_event(
+        "finish",
+        json!({ "ok": res.is_ok(), "error": res.as_ref().err().map(ToString::to_string) }),
+    );
+    res
+}
+
+/// System facts, from the cache at `facts_cache` if it's younger than
+/// `ttl`. Facts are collected lazily, so this is cheap if nothing needs
+/// them.
+fn facts_with(
+    cfg: &Config,
+    facts_cache: &Path,
+    ttl: Duration,
+    overrides: &BTreeMap<String, String>,
+) -> Facts {
+    Facts::cached(facts_cache, ttl)
+        .with_commands(&cfg.facts, cfg.fact_command_timeout)
+        .with_vars(&cfg.vars)
+        .with_overrides(overrides)
+}
+
+fn print_dotfiles(cfg: &Config, facts: &Facts, verbose: bool, pager: bool) -> Result<(), Error> {
+    let mut out = String::new();
+    if verbose {
+        writeln!(out, "Configuration: {:?}", cfg).unwrap();
+    }
+    let dotfiles = enabled_dotfiles(cfg, facts)?;
+    let home = home_dir()?;
+    let dotfile_repo = make_abs(&home, &cfg.dotfile_repo);
+    // Paths are shown relative to the dotfile repository and the home
+    // directory where possible, to keep the table narrow.
+    let mut table = Table::new(&["REPO", "INSTALLED", "STATUS", "TAGS"]);
+    for d in &dotfiles {
+        let status = match d.status() {
+            Ok(
+                status @ InstallStatus::Linked
+                | status @ InstallStatus::Rendered
+                | status @ InstallStatus::Copied,
+            ) => console::style(status.to_string()).green(),
+            Ok(status @ InstallStatus::Missing) => console::style(status.to_string()).yellow(),
+            Ok(status @ InstallStatus::Conflict) => console::style(status.to_string()).red(),
+            Err(err) => console::style(format!("unknown: {}", err)).red(),
+        };
+        table.push(vec![
+            relative(&d.repo, &dotfile_repo, ""),
+            relative(&d.installed, &home, "~/"),
+            status.to_string(),
+            d.tags().join(", "),
+        ]);
+    }
+    if table.is_empty() {
+        writeln!(out, "No dotfiles apply to this machine").unwrap();
+    } else {
+        out.push_str(&table.render(diff::terminal_width()));
+    }
+    let units = dotfiles
         .iter()
-        .map(|d| AbsDotfile::new(d, &cfg))
-        .collect::<Result<Vec<_>, _>>()?;
-    println!("Dotfiles: {:?}", abs_dotfiles);
+        .filter(|d| d.systemd.is_some())
+        .map(unit_name)
+        .collect::<Vec<_>>();
+    if !units.is_empty() {
+        writeln!(out, "Systemd units:").unwrap();
+        for unit in units {
+            match systemd::status(&unit) {
+                Ok(status) => {
+                    writeln!(out, "    {}: {}, {}", unit, status.active, status.enabled).unwrap()
+                }
+                Err(err) => writeln!(out, "    {}: couldn't check: {}", unit, err).unwrap(),
+            }
+        }
+    }
+    if !cfg.vscode.extensions.is_empty() && which::which("code").is_ok() {
+        let missing = cfg
+            .vscode
+            .missing_extensions(&vscode::installed_extensions()?);
+        writeln!(
+            out,
+            "VS Code extensions: {} of {} installed",
+            cfg.vscode.extensions.len() - missing.len(),
+            cfg.vscode.extensions.len()
+        )
+        .unwrap();
+        for id in missing {
+            writeln!(out, "    missing {}", id).unwrap();
+        }
+    }
+    if !cfg.defaults.is_empty() && which::which("defaults").is_ok() {
+        let differences = defaults::diff(&cfg.defaults)?;
+        if !differences.is_empty() {
+            writeln!(out, "Preferences which differ from `defaults`:").unwrap();
+        }
+        for difference in differences {
+            writeln!(
+                out,
+                "    {} {}: {}, not {}",
+                difference.domain,
+                difference.key,
+                difference.current.as_deref().unwrap_or("unset"),
+                difference.declared
+            )
+            .unwrap();
+        }
+    }
+    match git::status(&dotfile_repo) {
+        Ok(Some(status)) => write_git_status(&mut out, &status, &dotfiles, &dotfile_repo),
+        Ok(None) => {}
+        Err(err) => eprintln!(
+            "Couldn't check the dotfile repository's git status: {}",
+            err
+        ),
+    }
+    pager::page(&out, pager)?;
     Ok(())
 }
+
+/// Print the short status for `dfm status --short`, then exit straight
+/// away, rather than waiting on a check which ran out of time, and without
+/// printing warnings, which have nowhere to go in a prompt. Commands the check
+/// is still waiting on, like fact commands, plugins, and Nix, are killed
+/// first, so they don't keep running after dfm exits.
+fn short_status(
+    mut cfg: Config,
+    facts_cache: PathBuf,
+    ttl: Duration,
+    overrides: BTreeMap<String, String>,
+) -> ! {
+    let cache = config::short_status_cache_file().ok();
+    let checked = short_status::within(short_status::BUDGET, move || {
+        // Plugins count against the budget too, and there's nowhere to show
+        // their warnings.
+        if cfg.plugins {
+            load_plugins(&mut cfg, &mut Warnings::default());
+        }
+        let facts = facts_with(&cfg, &facts_cache, ttl, &overrides);
+        let drifted = enabled_dotfiles(&cfg, &facts).ok().map(|dotfiles| {
+            dotfiles
+                .iter()
+                .filter(|d| {
+                    !matches!(
+                        d.status(),
+                        Ok(InstallStatus::Linked | InstallStatus::Rendered | InstallStatus::Copied)
+                    )
+                })
+                .count()
+        });
+        facts.write_cache(&facts_cache);
+        short_status::render(drifted)
+    });
+    let status = match (checked, &cache) {
+        (Some(status), Some(cache)) => {
+            short_status::write_cache(cache, &status);
+            status
+        }
+        (Some(status), None) => status,
+        (None, Some(cache)) => {
+            short_status::read_cache(cache).unwrap_or_else(|| short_status::render(None))
+        }
+        (None, None) => short_status::render(None),
+    };
+    print!("{}", status);
+    let _ = io::Write::flush(&mut io::stdout());
+    util::kill_running();
+    process::exit(0)
+}
+
+/// Print the dotfiles in the porcelain format; see the `porcelain` module.
+fn print_porcelain(cfg: &Config, facts: &Facts, version: PorcelainVersion) -> Result<(), Error> {
+    for d in enabled_dotfiles(cfg, facts)? {
+        print!(
+            "{}",
+            porcelain::record(&[
+                &version.status(d.status().ok()),
+                &d.installed.to_string_lossy(),
+                &d.repo.to_string_lossy(),
+                &version.tags(&d).join(","),
+            ])
+        );
+    }
+    Ok(())
+}
+
+/// `path` relative to `base` with `prefix`, e.g. `~/` for the home
+/// directory, or all of `path` if it isn't in `base`.
+fn relative(path: &Path, base: &Path, prefix: &str) -> String {
+    match path.strip_prefix(base) {
+        Ok(rel) => format!("{}{}", prefix, rel.display()),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+/// Ask which of `dotfiles` to use, for `--interactive`.
+#[cfg(feature = "interactive")]
+fn choose_dotfiles(
+    cfg: &Config,
+    dotfiles: Vec<AbsDotfile>,
+    prompt: &str,
+) -> Result<Vec<AbsDotfile>, Error> {
+    let home = home_dir()?;
+    let dotfile_repo = make_abs(&home, &cfg.dotfile_repo);
+    let items = dotfiles
+        .iter()
+        .map(|d| {
+            format!(
+                "{} ({})",
+                relative(&d.installed, &home, "~/"),
+                relative(&d.repo, &dotfile_repo, "")
+            )
+        })
+        .collect::<Vec<_>>();
+    let chosen = select::choose(cfg.prompts.theme().as_ref(), prompt, &items)?;
+    Ok(dotfiles
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| chosen.contains(i))
+        .map(|(_, d)| d)
+        .collect())
+}
+
+#[cfg(not(feature = "interactive"))]
+fn choose_dotfiles(
+    _cfg: &Config,
+    _dotfiles: Vec<AbsDotfile>,
+    _prompt: &str,
+) -> Result<Vec<AbsDotfile>, Error> {
+    Err(Error::NotInteractive)
+}
+
+fn write_git_status(
+    out: &mut String,
+    status: &RepoStatus,
+    dotfiles: &[AbsDotfile],
+    dotfile_repo: &Path,
+) {
+    let branch = status.branch.as_deref().unwrap_or("detached HEAD");
+    let tracking = match (&status.upstream, status.ahead, status.behind) {
+        (None, _, _) => "no upstream".to_string(),
+        (Some(upstream), 0, 0) => format!("up to date with {}", upstream),
+        (Some(upstream), ahead, 0) => format!("{} ahead of {}", ahead, upstream),
+        (Some(upstream), 0, behind) => format!("{} behind {}", behind, upstream),
+        (Some(upstream), ahead, behind) => {
+            format!("{} ahead of and {} behind {}", ahead, behind, upstream)
+        }
+    };
+    let state = if status.is_dirty() {
+        "uncommitted changes"
+    } else {
+        "clean"
+    };
+    writeln!(out, "Git: {}, {}, {}", branch, tracking, state).unwrap();
+    let changed = dotfiles
+        .iter()
+        .filter_map(|d| d.repo.strip_prefix(dotfile_repo).ok())
+        .filter(|repo| status.has_changes(repo))
+        .collect::<Vec<_>>();
+    if !changed.is_empty() {
+        writeln!(out, "Dotfiles with uncommitted changes:").unwrap();
+        for repo in changed {
+            writeln!(out, "    {}", repo.display()).unwrap();
+        }
+    }
+}
+
+/// Clone `url` into the dotfile repository (or download and unpack it, if
+/// it's an archive) and return the configuration to install its dotfiles
+/// with: the one in the repository, if there is one.
+fn init(
+    cfg: &Config,
+    url: Option<String>,
+    sha256: Option<String>,
+    dry_run: bool,
+) -> Result<Option<Config>, Error> {
+    let (url, sha256) = match url {
+        Some(url) => (url, sha256),
+        None => (
+            cfg.dotfile_repo_url.clone().ok_or(Error::NoRepoUrl)?,
+            sha256.or_else(|| cfg.dotfile_repo_sha256.clone()),
+        ),
+    };
+    let home = home_dir()?;
+    let dotfile_repo = make_abs(&home, &cfg.dotfile_repo);
+    let is_archive = ArchiveFormat::of(&url).is_some();
+    if is_archive && dotfile_repo.exists() {
+        // Don't download it again, so this works offline once it's been
+        // fetched; remove the repository to fetch it again.
+        note!(
+            "{} already exists; using it rather than downloading {}",
+            dotfile_repo.display(),
+            url
+        );
+    } else if dry_run {
+        let verb = if is_archive { "download" } else { "clone" };
+        println!("Would {} {} into {}", verb, url, dotfile_repo.display());
+        return Ok(None);
+    } else if is_archive {
+        archive::fetch(&url, sha256.as_deref(), &dotfile_repo)?;
+    } else {
+        git::clone(&url, &dotfile_repo)?;
+    }
+    let dotfile_repo = dotfile_repo.canonicalize()?;
+    let repo_config = config::repo_config_file(&dotfile_repo);
+    if !repo_config.exists() {
+        return Ok(Some(cfg.clone()));
+    }
+    let mut repo_cfg = Config::try_from(repo_config.as_path())?;
+    let config_file = config::config_file()?;
+    if config_file.exists() {
+        eprintln!(
+            "Using {} rather than {} this time; merge them to keep using it",
+            repo_config.display(),
+            config_file.display()
+        );
+    } else if make_abs(&home, &repo_cfg.dotfile_repo) != dotfile_repo {
+        eprintln!(
+            "Using {} this time, but not installing it: its `dotfile_repo` isn't {}",
+            repo_config.display(),
+            dotfile_repo.display()
+        );
+    } else {
+        if let Some(parent) = config_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&repo_config, &config_file)?;
+        note!(
+            "Installed {} as {}",
+            repo_config.display(),
+            config_file.display()
+        );
+    }
+    repo_cfg.dotfile_repo = dotfile_repo;
+    Ok(Some(repo_cfg))
+}
+
+fn link_dotfiles(
+    cfg: &Config,
+    facts: &Facts,
+    interactive: bool,
+    json: bool,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let dotfile_repo = make_abs(&home_dir()?, &cfg.dotfile_repo);
+    let uninitialized = git::submodules(&dotfile_repo)?
+        .into_iter()
+        .filter(|submodule| submodule.state == SubmoduleState::Uninitialized)
+        .map(|submodule| dotfile_repo.join(submodule.path))
+        .collect::<Vec<_>>();
+    let mut units = Vec::new();
+    let mut report = LinkReport::default();
+    let mut conflicts = ConflictPrompt::new(&cfg.prompts);
+    let enabled = enabled_dotfiles_counted(cfg, facts, &mut report)?;
+    let dotfiles = if interactive {
+        choose_dotfiles(cfg, enabled.clone(), "Dotfiles to install")?
+    } else {
+        enabled.clone()
+    };
+    log_event(
+        "plan",
+        json!({
+            "dotfiles": dotfiles
+                .iter()
+                .map(|d| json!({ "action": verb(d), "repo": d.repo, "installed": d.installed }))
+                .collect::<Vec<_>>()
+        }),
+    );
+    let mut installing = Vec::new();
+    for d in &dotfiles {
+        if let Some(submodule) = uninitialized.iter().find(|dir| d.repo.starts_with(dir)) {
+            warn(
+                WarningKind::Skipped,
+                format!(
+                    "{}: the submodule {} isn't initialized; run `dfm sync` first",
+                    d.installed.display(),
+                    submodule.display()
+                ),
+            );
+            report.skipped_submodules += 1;
+            continue;
+        }
+        installing.push(d.clone());
+    }
+    let mut observers: Vec<Box<dyn ExecutionObserver>> = vec![Box::new(CliObserver {
+        spinner: None,
+        print_dry_run: !json,
+    })];
+    if json {
+        observers.push(Box::new(JsonLines(io::stdout())));
+    }
+    let mut warnings = Warnings::default();
+    let res = engine::install_all(
+        &installing,
+        facts,
+        &mut conflicts,
+        dry_run,
+        &mut report,
+        &mut warnings,
+        &mut observers,
+    );
+    for warning in warnings.iter() {
+        warn(warning.kind, warning.message.clone());
+    }
+    res?;
+    for d in &installing {
+        if let Some(unit) = d.systemd {
+            units.push((unit_name(d), unit));
+        }
+    }
+    if !units.is_empty() {
+        if dry_run {
+            println!("Would run `systemctl --user daemon-reload`");
+        } else {
+            systemd::daemon_reload()?;
+        }
+        for (name, _) in units
+            .iter()
+            .filter(|(_, unit)| *unit == SystemdUnit::Enabled)
+        {
+            if dry_run {
+                println!("Would run `systemctl --user enable --now {}`", name);
+            } else {
+                systemd::enable_now(name)?;
+            }
+        }
+    }
+    // Cron entries and shell snippets belong to every enabled dotfile, even
+    // ones which weren't chosen this time.
+    update_crontab(&enabled, dry_run)?;
+    let has_snippets = engine::has_shell_snippets(&enabled);
+    for (rc, contents) in shell::rc_updates(&home_dir()?, has_snippets)? {
+        let action = if has_snippets {
+            "source"
+        } else {
+            "stop sourcing"
+        };
+        if dry_run {
+            println!("Would make {} {} shell snippets", rc.display(), action);
+        } else {
+            fs::write(&rc, contents)?;
+        }
+    }
+    if !cfg.vscode.extensions.is_empty() {
+        install_vscode_extensions(cfg, dry_run)?;
+    }
+    if !cfg.defaults.is_empty() {
+        apply_defaults(cfg, dry_run)?;
+    }
+    if cfg.brewfile.is_some() {
+        if which::which("brew").is_ok() {
+            brew(cfg, PackagesCommand::Install, dry_run)?;
+        } else {
+            note!("Skipping the Brewfile: Homebrew isn't installed");
+        }
+    }
+    if let Some(plugins) = PLUGINS.get().filter(|_| !dry_run) {
+        plugins.post_link(
+            &dotfiles,
+            &report,
+            &mut WARNINGS.lock().unwrap_or_else(|err| err.into_inner()),
+        );
+    }
+    if let Some(action) = conflicts.answered_all() {
+        offer_on_conflict(cfg, action)?;
+    }
+    log_event(
+        "summary",
+        json!({ "report": report, "summary": report.to_string() }),
+    );
+    if !quiet() || !report.is_success() {
+        let prefix = if dry_run { "Dry run: " } else { "" };
+        eprintln!("{}{}", prefix, report);
+    }
+    if report.conflicts > 0 {
+        return Err(Error::Conflicts(report.conflicts));
+    }
+    if !dry_run && report.linked + report.rendered > 0 {
+        notify("dfm installed dotfiles", &report.to_string());
+    }
+    Ok(())
+}
+
+/// Print the differences between each dotfile's installed file and what
+/// `link_dotfiles` would install, unless it's already linked.
+fn diff(
+    cfg: &Config,
+    facts: &Facts,
+    side_by_side: bool,
+    interactive: bool,
+    pager: bool,
+) -> Result<(), Error> {
+    let mut out = String::new();
+    let color = console::colors_enabled();
+    let width = diff::terminal_width();
+    let mut dotfiles = enabled_dotfiles(cfg, facts)?;
+    if interactive {
+        dotfiles = choose_dotfiles(cfg, dotfiles, "Dotfiles to compare")?;
+    }
+    for d in dotfiles {
+        if !d.template && fs::read_link(&d.installed).is_ok_and(|target| target == d.repo) {
+            continue;
+        }
+        if d.repo.is_dir() || d.installed.is_dir() {
+            continue;
+        }
+        let installed = match fs::read_to_string(&d.installed) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+            installed => installed,
+        };
+        let (installed, expected) = match (installed, fs::read_to_string(&d.repo)) {
+            (Ok(installed), Ok(repo)) if d.template => (installed, template::render(&repo, facts)?),
+            (Ok(installed), Ok(repo)) => (installed, repo),
+            (Err(err), _) | (_, Err(err)) if err.kind() == io::ErrorKind::InvalidData => {
+                writeln!(
+                    out,
+                    "Binary files {} and {} differ",
+                    d.installed.display(),
+                    d.repo.display()
+                )
+                .unwrap();
+                continue;
+            }
+            (Err(err), _) | (_, Err(err)) => return Err(err.into()),
+        };
+        let rendered = if side_by_side {
+            diff::side_by_side(&installed, &expected, width, color)
+        } else {
+            diff::unified(&installed, &expected, color)
+        };
+        if rendered.is_empty() {
+            continue;
+        }
+        writeln!(
+            out,
+            "{}",
+            console::style(format!("--- {}", d.installed.display()))
+                .bold()
+                .force_styling(color)
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "{}",
+            console::style(format!("+++ {}", d.repo.display()))
+                .bold()
+                .force_styling(color)
+        )
+        .unwrap();
+        out.push_str(&rendered);
+    }
+    pager::page(&out, pager)?;
+    Ok(())
+}
+
+/// Offer to save an "all" answer to what to do with files in dotfiles'
+/// places as `on_conflict`, so it's the default from now on.
+#[cfg(feature = "interactive")]
+fn offer_on_conflict(cfg: &Config, action: ConflictAction) -> Result<(), Error> {
+    let config_file = config::config_file()?;
+    let save = Confirmation::with_theme(cfg.prompts.theme().as_ref())
+        .with_text(&format!(
+            "Do the same from now on? This sets `on_conflict = \"{}\"` in {}",
+            action,
+            config_file.display()
+        ))
+        .default(false)
+        .interact()?;
+    if save {
+        let config = match fs::read_to_string(&config_file) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            config => config?,
+        };
+        if let Some(parent) = config_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&config_file, prompt::with_on_conflict(&config, action)?)?;
+    }
+    Ok(())
+}
+
+/// Without the `interactive` feature, there are never answers to save.
+#[cfg(not(feature = "interactive"))]
+fn offer_on_conflict(_cfg: &Config, _action: ConflictAction) -> Result<(), Error> {
+    Ok(())
+}
+
+/// What installing `d` is called, e.g. `copy`; see `LinkStrategy::verb`.
+fn verb(d: &AbsDotfile) -> &str {
+    d.strategy.verb()
+}
+
+/// How `dfm link` shows what it's doing: a spinner while a dotfile is
+/// installed, a line for each dotfile it would install in a dry run, and
+/// an event in the log file for each dotfile it tries to install.
+struct CliObserver {
+    spinner: Option<Spinner>,
+    print_dry_run: bool,
+}
+
+impl ExecutionObserver for CliObserver {
+    fn entry_started(&mut self, d: &AbsDotfile, action: Action, index: usize, total: usize) {
+        if action != Action::AlreadyOk {
+            self.spinner = Some(Spinner::start(&format!(
+                "Installing {} ({}/{})",
+                d.installed.display(),
+                index + 1,
+                total
+            )));
+        }
+    }
+
+    fn conflict_encountered(&mut self, _d: &AbsDotfile) {
+        // The question needs the terminal.
+        self.spinner = None;
+    }
+
+    fn entry_finished(&mut self, d: &AbsDotfile, outcome: &Outcome) {
+        self.spinner = None;
+        let error = match outcome {
+            Outcome::AlreadyOk => return,
+            Outcome::DryRun => {
+                if self.print_dry_run {
+                    println!(
+                        "Would {} {} to {}",
+                        verb(d),
+                        d.repo.display(),
+                        d.installed.display()
+                    );
+                }
+                return;
+            }
+            Outcome::Installed => None,
+            Outcome::Conflict => Some("something else is there and wasn't overwritten"),
+            Outcome::Failed(err) => Some(err.as_str()),
+        };
+        log_event(
+            verb(d),
+            json!({
+                "repo": d.repo,
+                "installed": d.installed,
+                "ok": error.is_none(),
+                "error": error,
+            }),
+        );
+    }
+}
+
+/// Replace dfm's block of the user's crontab with the `cron` entries of
+/// `dotfiles`.
+fn update_crontab(enabled: &[AbsDotfile], dry_run: bool) -> Result<(), Error> {
+    let entries = engine::cron_entries(enabled);
+    if which::which("crontab").is_err() {
+        if !entries.is_empty() {
+            note!("Skipping cron entries: `crontab` isn't installed");
+        }
+        return Ok(());
+    }
+    let crontab = cron::read()?;
+    let updated = cron::with_block(&crontab, &entries);
+    if updated == crontab {
+        return Ok(());
+    }
+    if dry_run {
+        println!("Would update the crontab's dotfile-manager entries to:");
+        for entry in &entries {
+            println!("    {}", entry);
+        }
+    } else {
+        cron::write(&updated)?;
+    }
+    Ok(())
+}
+
+fn install_vscode_extensions(cfg: &Config, dry_run: bool) -> Result<(), Error> {
+    if which::which("code").is_err() {
+        note!("Skipping VS Code extensions: `code` isn't installed");
+        return Ok(());
+    }
+    for id in cfg
+        .vscode
+        .missing_extensions(&vscode::installed_extensions()?)
+    {
+        if dry_run {
+            println!("Would install the VS Code extension {}", id);
+        } else {
+            vscode::install_extension(&id)?;
+        }
+    }
+    Ok(())
+}
+
+/// Set the preferences in `defaults` which aren't set yet.
+fn apply_defaults(cfg: &Config, dry_run: bool) -> Result<(), Error> {
+    if which::which("defaults").is_err() {
+        note!("Skipping `defaults`: this isn't macOS");
+        return Ok(());
+    }
+    for difference in defaults::diff(&cfg.defaults)? {
+        if dry_run {
+            println!(
+                "Would set {} {} to {}",
+                difference.domain, difference.key, difference.declared
+            );
+        } else {
+            defaults::write(&difference)?;
+        }
+    }
+    Ok(())
+}
+
+/// The name of the systemd unit `d` is installed as, e.g. `backup.timer`.
+fn unit_name(d: &AbsDotfile) -> String {
+    d.installed
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn deploy(
+    cfg: &Config,
+    facts: &Facts,
+    facts_given: bool,
+    host: &str,
+    remote_dfm: Option<String>,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let home = home_dir()?;
+    let dotfile_repo = make_abs(&home, &cfg.dotfile_repo);
+    let remote_repo = deploy::remote_repo(&dotfile_repo, &home);
+    let script = match &remote_dfm {
+        Some(_) => None,
+        None => Some({
+            deploy::check_facts(&cfg.load_dotfiles(facts)?.value, facts_given)?;
+            deploy::link_script(&enabled_dotfiles(cfg, facts)?, &home, facts)?
+        }),
+    };
+    if dry_run {
+        println!(
+            "Would copy {} to {}:{}",
+            dotfile_repo.display(),
+            host,
+            remote_repo.display()
+        );
+        match (&remote_dfm, &script) {
+            (Some(dfm), _) => println!("Would run `{} link` on {}", dfm, host),
+            (None, Some(script)) => {
+                println!("Would run this script on {}:", host);
+                print!("{}", script);
+            }
+            (None, None) => {}
+        }
+        return Ok(());
+    }
+    deploy::copy_repo(&dotfile_repo, host, &remote_repo)?;
+    match (remote_dfm, script) {
+        (Some(dfm), _) => deploy::run_command(host, &format!("{} link", dfm))?,
+        (None, Some(script)) => deploy::run_script(host, &script)?,
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+fn packages(cfg: &Config, cmd: PackagesCommand, dry_run: bool) -> Result<(), Error> {
+    let dotfile_repo = make_abs(&home_dir()?, &cfg.dotfile_repo);
+    let declared = Packages::read(&dotfile_repo)?;
+    if declared.by_manager().is_empty() {
+        note!(
+            "No packages declared in {}",
+            dotfile_repo.join(packages::FILE_NAME).display()
+        );
+        return Ok(());
+    }
+    for (manager, names) in declared.by_manager() {
+        if !manager.is_available() {
+            note!(
+                "{}: not installed; skipping {} packages",
+                manager,
+                names.len()
+            );
+            continue;
+        }
+        let status = packages::Status::new(manager, names, &manager.installed()?);
+        match cmd {
+            PackagesCommand::Status => {
+                println!(
+                    "{}: {} of {} installed",
+                    manager,
+                    status.installed.len(),
+                    names.len()
+                );
+                for name in &status.missing {
+                    println!("    missing {}", name);
+                }
+            }
+            PackagesCommand::Install if status.missing.is_empty() => {}
+            PackagesCommand::Install if dry_run => println!(
+                "Would install with {}: {}",
+                manager,
+                status.missing.join(" ")
+            ),
+            PackagesCommand::Install => manager.install(&status.missing)?,
+        }
+    }
+    Ok(())
+}
+
+fn brew(cfg: &Config, cmd: PackagesCommand, dry_run: bool) -> Result<(), Error> {
+    let brewfile = match &cfg.brewfile {
+        Some(brewfile) => make_abs(&make_abs(&home_dir()?, &cfg.dotfile_repo), brewfile),
+        None => return Err(Error::NoBrewfile),
+    };
+    match cmd {
+        PackagesCommand::Status => {
+            if packages::brew_bundle(&brewfile, true, false)? && !quiet() {
+                println!("Everything in {} is installed", brewfile.display());
+            }
+        }
+        // `brew bundle check` lists what `install` would install.
+        PackagesCommand::Install if dry_run => {
+            packages::brew_bundle(&brewfile, true, false)?;
+        }
+        PackagesCommand::Install => {
+            packages::brew_bundle(&brewfile, false, quiet())?;
+        }
+    }
+    Ok(())
+}
+
+fn sync(cfg: &Config, remote: bool, dry_run: bool) -> Result<(), Error> {
+    let dotfile_repo = make_abs(&home_dir()?, &cfg.dotfile_repo);
+    let submodules = git::submodules(&dotfile_repo)?;
+    if submodules.is_empty() {
+        note!("{} has no submodules", dotfile_repo.display());
+    } else if dry_run {
+        for submodule in &submodules {
+            println!("Would update {}", submodule.path.display());
+        }
+    } else {
+        log_event(
+            "plan",
+            json!({ "submodules": submodules.iter().map(|s| &s.path).collect::<Vec<_>>() }),
+        );
+        git::update_submodules(&dotfile_repo, remote)?;
+        if remote {
+            // Nested submodules are committed in their parents, not here.
+            let paths = submodules
+                .iter()
+                .map(|s| s.path.as_path())
+                .filter(|path| {
+                    !submodules
+                        .iter()
+                        .any(|other| other.path != *path && path.starts_with(&other.path))
+                })
+                .collect::<Vec<_>>();
+            if git::auto_commit(&dotfile_repo, &cfg.git, "sync", &paths)? {
+                note!("Committed the updated submodules");
+            }
+        }
+        let noun = if submodules.len() == 1 {
+            "submodule"
+        } else {
+            "submodules"
+        };
+        note!("{} {} updated", submodules.len(), noun);
+    }
+    Ok(())
+}
+
+/// The dotfiles whose conditions are true on this machine.
+fn enabled_dotfiles(cfg: &Config, facts: &Facts) -> Result<Vec<AbsDotfile>, Error> {
+    enabled_dotfiles_counted(cfg, facts, &mut LinkReport::default())
+}
+
+/// Like `enabled_dotfiles`, counting the dotfiles which are skipped in
+/// `report`.
+fn enabled_dotfiles_counted(
+    cfg: &Config,
+    facts: &Facts,
+    report: &mut LinkReport,
+) -> Result<Vec<AbsDotfile>, Error> {
+    let mut warnings = Warnings::default();
+    let dotfiles = engine::enabled_dotfiles(cfg, facts, report, &mut warnings);
+    for warning in warnings.iter() {
+        warn(warning.kind, warning.message.clone());
+    }
+    dotfiles
+}
+
+fn export(cfg: &Config, facts: &Facts, format: ExportFormat) -> Result<(), Error> {
+    let dotfiles = enabled_dotfiles(cfg, facts)?;
+    match format {
+        #[cfg(feature = "nix")]
+        ExportFormat::HomeManager => {
+            print!("{}", home_manager::export(&dotfiles, &home_dir()?, facts)?)
+        }
+        ExportFormat::Dotbot => {
+            let home = home_dir()?;
+            let dotfile_repo = make_abs(&home, &cfg.dotfile_repo);
+            print!("{}", dotbot::export(&dotfiles, &dotfile_repo, &home))
+        }
+        ExportFormat::Ansible => print!("{}", ansible::export(&dotfiles, &home_dir()?, facts)?),
+    }
+    Ok(())
+}
+
+#[cfg(feature = "nix")]
+fn nix_check(cfg: &Config, facts: &Facts) -> Result<(), Error> {
+    let list = cfg.eval_nix_dotfiles(facts)?;
+    for warning in &list.warnings {
+        eprintln!("Nix: {}", warning);
+    }
+    println!("{}", serde_json::to_string_pretty(&list.value)?);
+    let problems = config::check_dotfiles_list(&list.value);
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidList(problems.len()))
+    }
+}
+
+fn import(cfg: &Config, source: ImportSource, dry_run: bool) -> Result<(), Error> {
+    match source {
+        ImportSource::BareGit { git_dir, work_tree } => {
+            let cwd = env::current_dir()?;
+            let home = home_dir()?;
+            let repo = BareRepo {
+                git_dir: make_abs(&cwd, &git_dir),
+                work_tree: work_tree.map_or_else(|| home.clone(), |dir| make_abs(&cwd, &dir)),
+            };
+            let dotfile_repo = make_abs(&home, &cfg.dotfile_repo);
+            let (moves, dotfiles) = repo.plan(&dotfile_repo)?;
+            if dry_run {
+                for m in &moves {
+                    eprintln!("Would move {} to {}", m.from.display(), m.to.display());
+                }
+            } else {
+                repo.migrate(&moves, &dotfile_repo)?;
+            }
+            println!("{}", serde_json::to_string_pretty(&dotfiles)?);
+        }
+        #[cfg(feature = "nix")]
+        ImportSource::HomeManager { config } => {
+            let imported = home_manager::import(&config, &cfg.dotfile_repo, &cfg.nix_options())?;
+            for target in &imported.skipped {
+                eprintln!(
+                    "Skipped {}: it's defined with `text`; copy its contents into the dotfile repository",
+                    target.display()
+                );
+            }
+            println!("{}", serde_json::to_string_pretty(&imported.dotfiles)?);
+        }
+        ImportSource::Dotbot { config } => {
+            let imported = dotbot::import(&config, &cfg.dotfile_repo, &home_dir()?)?;
+            for note in &imported.notes {
+                eprintln!("{}", note);
+            }
+            if !imported.facts.is_empty() {
+                eprintln!("Add these facts to your configuration file for the `if` commands:");
+                eprintln!("[facts]");
+                for (name, command) in &imported.facts {
+                    eprintln!("{} = {}", name, toml::Value::from(command.as_str()));
+                }
+            }
+            println!("{}", serde_json::to_string_pretty(&imported.dotfiles)?);
+        }
+        ImportSource::Stow {
+            dir,
+            target,
+            dotfiles,
+        } => {
+            let mut stow = Stow::new(&dir)?;
+            if let Some(target) = target {
+                stow.target = make_abs(&env::current_dir()?, &target);
+            }
+            stow.dot_prefix = dotfiles;
+            let dotfiles = stow.import(&cfg.dotfile_repo, &home_dir()?)?;
+            println!("{}", serde_json::to_string_pretty(&dotfiles)?);
+        }
+    }
+    Ok(())
+}
+
+fn discover(cfg: &Config, facts: &Facts, select: bool) -> Result<(), Error> {
+    let home = home_dir()?;
+    let dotfile_repo = make_abs(&home, &cfg.dotfile_repo);
+    // There may be no dotfiles list yet on a machine being set up.
+    let managed = if cfg.dotfiles_flake.is_some() || cfg.dotfiles_file().is_some() {
+        enabled_dotfiles(cfg, facts)?
+            .into_iter()
+            .map(|d| d.installed)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let found = discover::discover(&discover::apps(&cfg.apps), &home, &dotfile_repo, &managed);
+    if found.is_empty() {
+        eprintln!("No unmanaged application configuration files found");
+        return Ok(());
+    }
+    let items = found
+        .iter()
+        .map(|f| format!("{}: ~/{}", f.app, f.path.display()))
+        .collect::<Vec<_>>();
+    if !select {
+        for item in items {
+            println!("{}", item);
+        }
+        return Ok(());
+    }
+    let chosen = check_off(cfg, "Files to manage", &items)?;
+    let dotfiles = chosen
+        .into_iter()
+        .map(|i| Dotfile::from(found[i].path.clone()))
+        .collect::<Vec<_>>();
+    eprintln!(
+        "Move these files into {} at the same paths and add them to the dotfiles list:",
+        dotfile_repo.display()
+    );
+    println!("{}", serde_json::to_string_pretty(&dotfiles)?);
+    Ok(())
+}
+
+/// Ask which of `items` to use, for `dfm discover --select`.
+#[cfg(feature = "interactive")]
+fn check_off(cfg: &Config, prompt: &str, items: &[String]) -> Result<Vec<usize>, Error> {
+    Ok(Checkboxes::with_theme(cfg.prompts.theme().as_ref())
+        .with_prompt(prompt)
+        .items(items)
+        .interact()?)
+}
+
+#[cfg(not(feature = "interactive"))]
+fn check_off(_cfg: &Config, _prompt: &str, _items: &[String]) -> Result<Vec<usize>, Error> {
+    Err(Error::NotInteractive)
+}
+
+fn doctor(cfg: &Config, facts_cache: &Path) -> Result<(), Error> {
+    let config_file = config::config_file()?;
+    println!(
+        "Configuration file: {}{}",
+        config_file.display(),
+        if config_file.exists() {
+            ""
+        } else {
+            " (not found; using defaults)"
+        }
+    );
+    println!("Dotfile repository: {}", cfg.dotfile_repo.display());
+    let dotfile_repo = make_abs(&home_dir()?, &cfg.dotfile_repo);
+    match git::submodules(&dotfile_repo) {
+        Ok(submodules) => {
+            for submodule in submodules {
+                let problem = match submodule.state {
+                    SubmoduleState::Current => continue,
+                    SubmoduleState::Uninitialized => "not initialized; run `dfm sync`",
+                    SubmoduleState::Modified => {
+                        "not at the recorded commit; run `dfm sync` or commit it"
+                    }
+                    SubmoduleState::Conflicted => "has merge conflicts",
+                };
+                println!("Submodule {}: {}", submodule.path.display(), problem);
+            }
+        }
+        Err(err) => println!("Submodules: couldn't check: {}", err),
+    }
+    match (&cfg.dotfiles_flake, cfg.dotfiles_file()) {
+        (Some(flake_ref), _) => println!("Dotfiles list: flake output {}", flake_ref),
+        (None, Some(path)) => println!("Dotfiles list: {}", path.display()),
+        (None, None) => println!("Dotfiles list: not found"),
+    }
+    #[cfg(feature = "nix")]
+    match (&cfg.nix.evaluator_command, nix::nix_version()) {
+        (Some(command), _) => println!(
+            "Nix: {} ({})",
+            command.join(" "),
+            cfg.nix.select_evaluator()
+        ),
+        (None, Some(version)) => println!("Nix: {} ({})", version, cfg.nix.select_evaluator()),
+        (None, None) => match which::which("nix-instantiate") {
+            Ok(_) => println!("Nix: {}", nix::Evaluator::NixInstantiate),
+            Err(_) => println!("Nix: not found"),
+        },
+    }
+    println!("Facts cache: {}", facts_cache.display());
+    if let Some(plugins) = PLUGINS.get() {
+        for (plugin, manifest) in plugins.iter() {
+            println!(
+                "Plugin {}: {}{}",
+                plugin.name,
+                plugin.path.display(),
+                if manifest.kinds.is_empty() {
+                    String::new()
+                } else {
+                    format!(" (entry kinds: {})", manifest.kinds.join(", "))
+                }
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_facts(facts: &Facts, json: bool) -> Result<(), Error> {
+    let mut context = facts.template_context()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&context)?);
+    } else {
+        // Environment variables are noisy and available elsewhere.
+        if let Value::Object(map) = &mut context {
+            map.remove("env");
+        }
+        print_fact_value("", &context);
+    }
+    Ok(())
+}
+
+/// Print a fact value as `name: value` lines; nested objects are flattened
+/// into dotted names.
+fn print_fact_value(name: &str, value: &Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                if name.is_empty() {
+                    print_fact_value(key, value);
+                } else {
+                    print_fact_value(&format!("{}.{}", name, key), value);
+                }
+            }
+        }
+        Value::Array(values) => println!(
+            "{}: {}",
+            name,
+            values
+                .iter()
+                .map(|v| v
+                    .as_str()
+                    .map(String::from)
+                    .unwrap_or_else(|| v.to_string()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::String(s) => println!("{}: {}", name, s),
+        value => println!("{}: {}", name, value),
+    }
+}
@@ -0,0 +1,266 @@
+//! `DotfileManager`, for using dfm from other programs without
+//! reimplementing the glue in `main.rs`:
+//!
+//! ```no_run
+//! use dotfile_manager::config::Config;
+//! use dotfile_manager::manager::DotfileManager;
+//! use dotfile_manager::prompt::ConflictPrompt;
+//!
+//! let manager = DotfileManager::new(Config::try_default()?);
+//! for (dotfile, status) in manager.status()? {
+//!     println!("{}: {:?}", dotfile.installed.display(), status);
+//! }
+//! let report = manager.link(&mut ConflictPrompt::new(&manager.config().prompts))?;
+//! println!("{}", report);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Warnings, e.g. about unknown fields in the dotfiles list, are collected
+//! rather than printed; see `DotfileManager::take_warnings`. To follow
+//! along as dotfiles are installed, e.g. to show progress, see
+//! `DotfileManager::with_observer`.
+
+use std::cell::RefCell;
+use std::fmt;
+
+use crate::config::Config;
+use crate::dotfile::{AbsDotfile, DotfileError, InstallStatus};
+use crate::engine;
+use crate::engine::Action;
+use crate::error::Error;
+use crate::facts::Facts;
+use crate::observer::ExecutionObserver;
+use crate::prompt::ConflictPrompt;
+use crate::report::LinkReport;
+use crate::warning::Warnings;
+
+/// A dotfile and whether it's installed; see `DotfileManager::status`.
+pub type DotfileStatus = (AbsDotfile, Result<InstallStatus, DotfileError>);
+
+/// The dotfiles for one configuration and machine.
+pub struct DotfileManager {
+    config: Config,
+    facts: Facts,
+    warnings: RefCell<Warnings>,
+    observers: RefCell<Vec<Box<dyn ExecutionObserver>>>,
+}
+
+impl fmt::Debug for DotfileManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DotfileManager")
+            .field("config", &self.config)
+            .field("facts", &self.facts)
+            .field("warnings", &self.warnings)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DotfileManager {
+    /// A manager for `config`'s dotfiles on this machine.
+    pub fn new(config: Config) -> Self {
+        let facts = Facts::new()
+            .with_commands(&config.facts, config.fact_command_timeout)
+            .with_vars(&config.vars);
+        Self {
+            config,
+            facts,
+            warnings: Default::default(),
+            observers: Default::default(),
+        }
+    }
+
+    /// Use `facts` rather than collecting them, e.g. facts from a cache or
+    /// with overrides.
+    pub fn with_facts(mut self, facts: Facts) -> Self {
+        self.facts = facts;
+        self
+    }
+
+    /// Tell `observer` what happens to each dotfile as `link` installs it.
+    pub fn with_observer(self, observer: impl ExecutionObserver + 'static) -> Self {
+        self.observers.borrow_mut().push(Box::new(observer));
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn facts(&self) -> &Facts {
+        &self.facts
+    }
+
+    /// The warnings collected so far, leaving none.
+    pub fn take_warnings(&self) -> Warnings {
+        self.warnings.take()
+    }
+
+    /// The dotfiles which apply to this machine.
+    pub fn list(&self) -> Result<Vec<AbsDotfile>, Error> {
+        engine::enabled_dotfiles(
+            &self.config,
+            &self.facts,
+            &mut LinkReport::default(),
+            &mut self.warnings.borrow_mut(),
+        )
+    }
+
+    /// The dotfiles which apply to this machine and whether they're
+    /// installed.
+    pub fn status(&self) -> Result<Vec<DotfileStatus>, Error> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .map(|d| {
+                let status = d.status();
+                (d, status)
+            })
+            .collect())
+    }
+
+    /// What `link` would do with each dotfile which applies to this
+    /// machine.
+    pub fn plan(&self) -> Result<Vec<(AbsDotfile, Action)>, Error> {
+        engine::plan(self.list()?, &self.facts)
+    }
+
+    /// Link or render the dotfiles which apply to this machine, asking
+    /// `conflicts` what to do with files in their places. Unlike `dfm link`,
+    /// this doesn't update systemd units, cron jobs, shell snippets or
+    /// anything else outside the dotfiles list.
+    pub fn link(&self, conflicts: &mut ConflictPrompt) -> Result<LinkReport, Error> {
+        let mut report = LinkReport::default();
+        let dotfiles = engine::enabled_dotfiles(
+            &self.config,
+            &self.facts,
+            &mut report,
+            &mut self.warnings.borrow_mut(),
+        )?;
+        engine::install_all(
+            &dotfiles,
+            &self.facts,
+            conflicts,
+            false,
+            &mut report,
+            &mut self.warnings.borrow_mut(),
+            &mut *self.observers.borrow_mut(),
+        )?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::rc::Rc;
+
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::observer::Outcome;
+
+    #[test]
+    fn manager_plan() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let installed = format!(".dfm-manager-test-{}", std::process::id());
+        fs::write(
+            root.join("dotfiles.json"),
+            json!({ "dotfiles": [
+                { "repo": "bashrc", "installed": format!("{}-bashrc", installed), "tempalte": true },
+                { "repo": "profile", "installed": format!("{}-profile", installed), "template": true },
+                { "repo": "inputrc", "when": "env('DFM_MANAGER_TEST_UNSET') != null" },
+            ]})
+            .to_string(),
+        )
+        .unwrap();
+        fs::write(root.join("bashrc"), "").unwrap();
+        fs::write(root.join("profile"), "").unwrap();
+        let manager = DotfileManager::new(Config::builder().dotfile_repo(root).build().unwrap());
+        let plan = manager.plan();
+
+        assert_eq!(
+            plan.unwrap()
+                .into_iter()
+                .map(|(d, action)| (d.repo, action))
+                .collect::<Vec<_>>(),
+            vec![
+                (root.join("bashrc"), Action::Link),
+                (root.join("profile"), Action::Render),
+            ]
+        );
+        assert_eq!(
+            manager.take_warnings().render(false),
+            "warning: unknown fields in the dotfiles list, which are ignored\n    bashrc: `tempalte`\n"
+        );
+        assert!(manager.take_warnings().is_empty());
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl ExecutionObserver for Recorder {
+        fn entry_started(&mut self, d: &AbsDotfile, action: Action, index: usize, total: usize) {
+            self.0.borrow_mut().push(format!(
+                "started {} {:?} {}/{}",
+                d.repo.file_name().unwrap().to_string_lossy(),
+                action,
+                index,
+                total
+            ));
+        }
+
+        fn action_performed(&mut self, _d: &AbsDotfile, action: Action) {
+            self.0.borrow_mut().push(format!("performed {:?}", action));
+        }
+
+        fn entry_finished(&mut self, _d: &AbsDotfile, outcome: &Outcome) {
+            self.0.borrow_mut().push(format!("finished {:?}", outcome));
+        }
+    }
+
+    #[test]
+    fn manager_link_observer() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let installed = root.join("installed");
+        fs::write(
+            root.join("dotfiles.json"),
+            json!({ "dotfiles": [
+                { "repo": "bashrc", "installed": installed.join("bashrc") },
+                { "repo": "profile", "installed": installed.join("profile"), "link": "copy" },
+            ]})
+            .to_string(),
+        )
+        .unwrap();
+        fs::create_dir_all(&installed).unwrap();
+        fs::write(root.join("bashrc"), "").unwrap();
+        fs::write(root.join("profile"), "").unwrap();
+        symlink::symlink_file(root.join("bashrc"), installed.join("bashrc")).unwrap();
+        let recorder = Recorder::default();
+        let manager = DotfileManager::new(Config::builder().dotfile_repo(root).build().unwrap())
+            .with_observer(recorder.clone());
+        let report = manager.link(&mut ConflictPrompt::new(&Default::default()));
+
+        assert_eq!(
+            report.unwrap(),
+            LinkReport {
+                linked: 1,
+                already_ok: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            *recorder.0.borrow(),
+            vec![
+                "started bashrc AlreadyOk 0/2",
+                "finished AlreadyOk",
+                "started profile Link 1/2",
+                "performed Link",
+                "finished Installed",
+            ]
+        );
+    }
+}
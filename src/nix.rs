@@ -1,69 +1,1300 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
 use std::io;
-use std::path::Path;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 
+use lazy_static::lazy_static;
+use regex::Regex;
 use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::diagnostic::{Diagnose, Diagnostic};
+pub use crate::loader::Evaluated;
+use crate::spinner::Spinner;
+use crate::util::{make_abs, read_with_timeout, sha256_hex, write_cache};
+use crate::version;
+
 #[derive(Error, Debug)]
 pub enum NixEvalError {
-    #[error("nix-instantiate binary not found: {0}")]
+    #[error("Nix binary not found: {0}")]
     NoNix(io::Error),
-    #[error("executing nix-instantiate failed: {0}")]
+    #[error("executing Nix failed: {0}")]
     CommandFailed(#[from] io::Error),
-    #[error("Nix evaluation failed: {0:?}")]
-    EvalFailed(String),
+    #[error("Nix evaluation failed: {0}")]
+    EvalFailed(EvalFailure),
+    #[error("Nix evaluation timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("Nix evaluation produced more than {0} bytes of output; raise `nix.max_output_size` if that's expected")]
+    OutputTooLarge(u64),
     #[error("{0}")]
     SerdeJSON(#[from] serde_json::Error),
 }
 
-pub fn eval_file<T: DeserializeOwned>(path: &Path) -> Result<T, NixEvalError> {
-    let output_res = Command::new("nix-instantiate")
-        .args(&["--strict", "--json", "--eval"])
-        .arg(path)
-        .output();
+/// The environment variables the evaluator sees by default: what Nix needs to
+/// find its store, daemon, configuration, and certificates, and what programs
+/// need to run at all.
+pub const DEFAULT_ENV_ALLOWLIST: &[&str] = &[
+    "HOME",
+    "NIX_CONF_DIR",
+    "NIX_PATH",
+    "NIX_REMOTE",
+    "NIX_SSL_CERT_FILE",
+    "NIX_STATE_DIR",
+    "NIX_USER_CONF_FILES",
+    "PATH",
+    "SSL_CERT_FILE",
+    "SYSTEMROOT",
+    "TMPDIR",
+    "USER",
+    "XDG_CACHE_HOME",
+    "XDG_CONFIG_HOME",
+    "XDG_DATA_HOME",
+];
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_BUILD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_MAX_OUTPUT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A location in a Nix file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub file: PathBuf,
+    /// The 1-based line number.
+    pub line: usize,
+    /// The 1-based column number.
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.column)
+    }
+}
+
+/// A failed evaluation, parsed from Nix's error output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalFailure {
+    /// The error message, e.g. `undefined variable 'x'`.
+    pub message: String,
+    /// Where the error happened, if Nix said.
+    pub position: Option<Position>,
+    /// Everything Nix printed to stderr.
+    pub stderr: String,
+}
+
+impl EvalFailure {
+    /// Parse Nix's stderr output. Both the single-line errors of Nix 2.3
+    /// (`error: undefined variable 'x' at /a.nix:2:3`) and the multi-line
+    /// errors of later versions are understood; the last error in a trace
+    /// is the one reported.
+    pub fn parse(stderr: &str) -> Self {
+        lazy_static! {
+            static ref POSITION: Regex = Regex::new(r",? at (\S+?):(\d+):(\d+)").unwrap();
+        }
+        let (message_start, message) = stderr
+            .lines()
+            .scan(0, |offset, line| {
+                let start = *offset;
+                *offset += line.len() + 1;
+                Some((start, line))
+            })
+            .filter_map(|(start, line)| {
+                let message = line.trim().strip_prefix("error:")?.trim();
+                if message.is_empty() {
+                    None
+                } else {
+                    Some((start, message))
+                }
+            })
+            .last()
+            .unwrap_or((0, stderr.trim()));
+        let position = POSITION
+            .captures(&stderr[message_start..])
+            .or_else(|| POSITION.captures(stderr))
+            .map(|captures| Position {
+                file: PathBuf::from(&captures[1]),
+                line: captures[2].parse().unwrap_or(0),
+                column: captures[3].parse().unwrap_or(0),
+            });
+        // Nix 2.3 puts the position on the same line as the message.
+        let message = match POSITION.find(message) {
+            Some(m) => &message[..m.start()],
+            None => message,
+        };
+        Self {
+            message: message.to_string(),
+            position,
+            stderr: stderr.to_string(),
+        }
+    }
+
+    /// A diagnostic pointing at the error's position, with the offending
+    /// line quoted if the file can be read:
+    ///
+    /// ```text
+    /// error: undefined variable 'x'
+    ///   --> /home/me/.dotfiles/dotfiles.nix:2:9
+    ///    |
+    ///  2 |   foo = x;
+    ///    |         ^
+    /// ```
+    pub fn diagnostic(&self) -> String {
+        self.to_diagnostic().render(false)
+    }
+
+    fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::new(self.message.clone());
+        match &self.position {
+            Some(position) => diagnostic
+                .in_file(&position.file)
+                .at(position.line, position.column),
+            None => diagnostic,
+        }
+    }
+}
+
+impl Diagnose for NixEvalError {
+    fn diagnostic(&self) -> Diagnostic {
+        match self {
+            NixEvalError::EvalFailed(failure) => failure.to_diagnostic(),
+            NixEvalError::NoNix(_) => Diagnostic::from_error(self).help(
+                "install Nix, or write the dotfiles list in another format, like TOML or YAML",
+            ),
+            NixEvalError::Timeout(_) => Diagnostic::from_error(self).help(
+                "raise `nix.timeout`, or `nix.build_timeout` for builds, in the configuration file",
+            ),
+            _ => Diagnostic::from_error(self),
+        }
+    }
+}
+
+impl fmt::Display for EvalFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.position {
+            Some(position) => write!(f, "{}: {}", position, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Options for evaluating Nix: which evaluator to run, the arguments to pass
+/// it, and how long it may run and where its results are cached. This is the
+/// `[nix]` table in the config file; use `Config::nix_options` to get them with
+/// relative paths and the cache directory filled in, so evaluation behaves the
+/// same everywhere.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NixOptions {
+    /// Arguments whose values are Nix expressions, passed with `--arg`.
+    pub args: BTreeMap<String, String>,
+    /// Arguments whose values are strings, passed with `--argstr`.
+    pub argstrs: BTreeMap<String, String>,
+    /// Forbid evaluation from reading anything that isn't content-addressed,
+    /// like local files outside the Nix store, environment variables, or
+    /// `builtins.currentSystem`. This is mostly useful with flakes, which are
+    /// evaluated purely by default anyway.
+    pub pure_eval: bool,
+    /// Forbid evaluation from reading files outside the Nix search path (and
+    /// the directory containing the evaluated file) or fetching anything
+    /// from the network.
+    pub restrict_eval: bool,
+    /// Replace `NIX_PATH` with this value during evaluation; set it to the
+    /// empty string to make `<nixpkgs>`-style lookups fail. If unset,
+    /// `NIX_PATH` is inherited.
+    ///
+    /// The evaluator only sees the environment variables in
+    /// `DEFAULT_ENV_ALLOWLIST` and `env_allowlist`, unless `inherit_env` is
+    /// set; see `environment`.
+    pub nix_path: Option<String>,
+    /// Environment variables to pass to the evaluator, in addition to
+    /// `DEFAULT_ENV_ALLOWLIST`.
+    pub env_allowlist: Vec<String>,
+    /// Pass the whole environment to the evaluator, rather than just the
+    /// allowed variables, so results may depend on e.g. `builtins.getEnv`.
+    pub inherit_env: bool,
+    /// Entries to add to the Nix search path with `-I`, searched before
+    /// `nix_path`: either `prefix=path`, e.g. `mylib=./lib` so that
+    /// `<mylib/helpers.nix>` refers to `./lib/helpers.nix`, or a directory
+    /// to look up every `<...>` path in. See `with_include_base` for relative
+    /// paths.
+    pub include: Vec<String>,
+    /// How many seconds evaluation may take before it's killed; default 60.
+    pub timeout: Option<u64>,
+    /// How many bytes of JSON evaluation may produce before it's stopped;
+    /// default 64 MiB.
+    pub max_output_size: Option<u64>,
+    /// How many seconds building a dotfile with `build` may take before it's
+    /// killed; default an hour.
+    pub build_timeout: Option<u64>,
+    /// Always evaluate files rather than reusing cached results; see
+    /// `eval_file_cached`.
+    pub no_cache: bool,
+    /// Where `eval_file_cached` stores evaluation results; if unset, nothing
+    /// is cached. `Config::nix_options` defaults it to `config::nix_cache_dir`.
+    pub cache_dir: Option<PathBuf>,
+    /// The attribute path to select from the evaluated file, e.g.
+    /// `hosts.laptop`; set from the top-level `dotfiles_attr` option. If the
+    /// file evaluates to a function, it's called before the attribute is
+    /// selected.
+    #[serde(skip)]
+    pub attr: Option<String>,
+    /// Which evaluator CLI to use. If unset, it's guessed from the name of
+    /// `evaluator_command`'s program, or `nix eval` is used if the installed
+    /// Nix supports it.
+    pub evaluator: Option<Evaluator>,
+    /// The program to evaluate with, and any arguments to pass before the
+    /// usual ones, instead of `nix` or `nix-instantiate` from `PATH`; e.g. a
+    /// Nix pinned to a store path or a wrapper script. It must accept the
+    /// same arguments as `evaluator`, so other evaluators like `tvix-eval`
+    /// need a wrapper.
+    pub evaluator_command: Option<Vec<String>>,
+}
+
+impl NixOptions {
+    /// How long evaluation may take; see `timeout`.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TIMEOUT)
+    }
+
+    /// How much output evaluation may produce; see `max_output_size`.
+    pub fn max_output_size(&self) -> u64 {
+        self.max_output_size.unwrap_or(DEFAULT_MAX_OUTPUT_SIZE)
+    }
+
+    /// How long building may take; see `build_timeout`.
+    pub fn build_timeout(&self) -> Duration {
+        self.build_timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_BUILD_TIMEOUT)
+    }
+
+    /// Pass the Nix expression `expr` as the argument `name`.
+    pub fn arg(mut self, name: impl Into<String>, expr: impl Into<String>) -> Self {
+        self.args.insert(name.into(), expr.into());
+        self
+    }
+
+    /// Pass the string `value` as the argument `name`.
+    pub fn argstr(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.argstrs.insert(name.into(), value.into());
+        self
+    }
+
+    /// Pass `defaults` as string arguments, unless an argument of the same
+    /// name is already given; e.g. `opts.with_default_argstrs(facts.nix_args()?)`.
+    pub fn with_default_argstrs(mut self, defaults: BTreeMap<String, String>) -> Self {
+        for (name, value) in defaults {
+            if !self.args.contains_key(&name) {
+                self.argstrs.entry(name).or_insert(value);
+            }
+        }
+        self
+    }
+
+    /// Make relative paths in `include` relative to `dir` rather than the
+    /// working directory; URLs like `nixpkgs=https://...` and `flake:` or
+    /// `channel:` references are left alone.
+    pub fn with_include_base(mut self, dir: &Path) -> Self {
+        for entry in &mut self.include {
+            let (prefix, path) = match entry.find('=') {
+                Some(i) => entry.split_at(i + 1),
+                None => ("", entry.as_str()),
+            };
+            let is_relative = Path::new(path).is_relative()
+                && !path.contains("://")
+                && !path.starts_with("flake:")
+                && !path.starts_with("channel:");
+            if is_relative {
+                *entry = format!("{}{}", prefix, make_abs(dir, Path::new(path)).display());
+            }
+        }
+        self
+    }
+
+    /// The evaluator CLI to use; see `evaluator`.
+    pub fn select_evaluator(&self) -> Evaluator {
+        match (self.evaluator, &self.evaluator_command) {
+            (Some(evaluator), _) => evaluator,
+            (None, Some(command)) => match command.first().map(Path::new) {
+                Some(program) if program.file_stem() == Some(OsStr::new("nix")) => {
+                    Evaluator::NixEval
+                }
+                _ => Evaluator::NixInstantiate,
+            },
+            (None, None) => Evaluator::detect(),
+        }
+    }
+
+    /// A command which runs `program` (e.g. `nix`), or `evaluator_command` if
+    /// it's set.
+    fn program_command(&self, program: &str) -> Command {
+        match self.evaluator_command.as_deref() {
+            Some([program, args @ ..]) => {
+                let mut command = Command::new(program);
+                command.args(args);
+                command
+            }
+            _ => Command::new(program),
+        }
+    }
+
+    /// A command for evaluating something strictly to JSON with the selected
+    /// evaluator.
+    fn command(&self) -> (Evaluator, Command) {
+        let evaluator = self.select_evaluator();
+        let mut command = self.program_command(evaluator.program());
+        command.args(evaluator.args());
+        (evaluator, command)
+    }
+
+    /// The environment the evaluator runs in; see `nix_path` and
+    /// `env_allowlist`.
+    pub fn environment(&self) -> BTreeMap<String, String> {
+        self.environment_from(env::vars())
+    }
+
+    fn environment_from(
+        &self,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> BTreeMap<String, String> {
+        let mut environment: BTreeMap<String, String> = vars
+            .filter(|(name, _)| {
+                self.inherit_env
+                    || DEFAULT_ENV_ALLOWLIST.contains(&name.as_str())
+                    || self.env_allowlist.contains(name)
+            })
+            .collect();
+        if let Some(nix_path) = &self.nix_path {
+            environment.insert("NIX_PATH".to_string(), nix_path.clone());
+        }
+        environment
+    }
+
+    /// Run `command` in `environment()`.
+    fn apply_env(&self, command: &mut Command) {
+        command.env_clear().envs(self.environment());
+    }
+
+    fn apply(&self, command: &mut Command) {
+        if self.pure_eval {
+            command.args(["--option", "pure-eval", "true"]);
+        }
+        if self.restrict_eval {
+            command.args(["--option", "restrict-eval", "true"]);
+        }
+        for entry in &self.include {
+            command.arg("-I").arg(entry);
+        }
+        for (name, expr) in &self.args {
+            command.arg("--arg").arg(name).arg(expr);
+        }
+        for (name, value) in &self.argstrs {
+            command.arg("--argstr").arg(name).arg(value);
+        }
+    }
+}
+
+/// A program for evaluating Nix files.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Evaluator {
+    /// `nix eval`, from Nix 2.4 and later.
+    NixEval,
+    /// `nix-instantiate --eval`, which older versions of Nix have.
+    NixInstantiate,
+}
+
+impl Evaluator {
+    /// Prefer `nix eval` if the installed Nix is new enough, and fall back to
+    /// `nix-instantiate` otherwise.
+    pub fn detect() -> Self {
+        match nix_version() {
+            Some(version) if version::compare(&version, "2.4") != Ordering::Less => {
+                Evaluator::NixEval
+            }
+            _ => Evaluator::NixInstantiate,
+        }
+    }
+
+    /// The program providing this CLI in a standard Nix installation.
+    fn program(self) -> &'static str {
+        match self {
+            Evaluator::NixEval => "nix",
+            Evaluator::NixInstantiate => "nix-instantiate",
+        }
+    }
+
+    /// Arguments for evaluating something strictly to JSON.
+    fn args(self) -> &'static [&'static str] {
+        match self {
+            Evaluator::NixEval => &[
+                "--extra-experimental-features",
+                "nix-command",
+                "eval",
+                "--json",
+            ],
+            Evaluator::NixInstantiate => &["--strict", "--json", "--eval"],
+        }
+    }
+}
+
+impl fmt::Display for Evaluator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Evaluator::NixEval => write!(f, "nix eval"),
+            Evaluator::NixInstantiate => write!(f, "nix-instantiate"),
+        }
+    }
+}
+
+/// The version of the installed `nix` binary, e.g. `2.18.1`, if there is one.
+pub fn nix_version() -> Option<String> {
+    let output = Command::new("nix").arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // `nix (Nix) 2.18.1`
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .last()
+        .map(String::from)
+}
+
+/// Evaluate the Nix file at `path` with the `Evaluator` selected by `opts`
+/// and deserialize the result. If the file evaluates to a function, it's
+/// called with the arguments in `opts`.
+pub fn eval_file<T: DeserializeOwned>(
+    path: &Path,
+    opts: &NixOptions,
+) -> Result<Evaluated<T>, NixEvalError> {
+    let mut command = file_command(path, opts)?;
+    opts.apply_env(&mut command);
+    run(&mut command, opts.timeout(), opts.max_output_size())
+}
+
+fn file_command(path: &Path, opts: &NixOptions) -> io::Result<Command> {
+    let (evaluator, mut command) = opts.command();
+    opts.apply(&mut command);
+    if opts.restrict_eval {
+        // Otherwise, the file couldn't even read itself, let alone files next
+        // to it.
+        if let Some(dir) = path.parent() {
+            command.arg("-I").arg(make_abs(&env::current_dir()?, dir));
+        }
+    }
+    match evaluator {
+        Evaluator::NixEval => {
+            command.arg("--file").arg(path);
+            command.args(&opts.attr);
+        }
+        Evaluator::NixInstantiate => {
+            if let Some(attr) = &opts.attr {
+                command.arg("--attr").arg(attr);
+            }
+            command.arg(path);
+        }
+    }
+    Ok(command)
+}
+
+/// Like `eval_file`, but reuse the result of an earlier evaluation stored in
+/// `opts.cache_dir` if the file, the files it imports, and the relevant
+/// options haven't changed since. Only imports written as relative path
+/// literals (`./lib.nix`) are tracked, so a file which reads from `<nixpkgs>`,
+/// the environment, or the network may see stale results; set `no_cache` to
+/// always evaluate.
+pub fn eval_file_cached<T: DeserializeOwned>(
+    path: &Path,
+    opts: &NixOptions,
+) -> Result<Evaluated<T>, NixEvalError> {
+    let cache_dir = match &opts.cache_dir {
+        Some(cache_dir) if !opts.no_cache => cache_dir,
+        _ => return eval_file(path, opts),
+    };
+    let cache_file = cache_dir.join(format!("{}.json", cache_key(path, opts)?));
+    if let Some(cached) = fs::read(&cache_file)
+        .ok()
+        .and_then(|json| serde_json::from_slice::<CachedEval>(&json).ok())
+    {
+        return Ok(Evaluated {
+            value: serde_json::from_value(cached.value)?,
+            warnings: cached.warnings,
+        });
+    }
+    let evaluated = eval_file::<serde_json::Value>(path, opts)?;
+    write_cache(
+        &cache_file,
+        &serde_json::to_vec(&CachedEval {
+            value: evaluated.value.clone(),
+            warnings: evaluated.warnings.clone(),
+        })?,
+    );
+    Ok(Evaluated {
+        value: serde_json::from_value(evaluated.value)?,
+        warnings: evaluated.warnings,
+    })
+}
+
+/// The on-disk format of a cached evaluation.
+#[derive(Serialize, Deserialize)]
+struct CachedEval {
+    value: serde_json::Value,
+    warnings: Vec<String>,
+}
+
+/// A SHA-256 hash of everything which affects the result of evaluating
+/// `path`, as far as we can tell, which stays the same between builds of dfm.
+fn cache_key(path: &Path, opts: &NixOptions) -> io::Result<String> {
+    let files = nix_imports(path)?
+        .into_iter()
+        .map(|file| Ok((file.to_string_lossy().into_owned(), sha256_hex(&fs::read(&file)?))))
+        .collect::<io::Result<Vec<_>>>()?;
+    let key = serde_json::json!({
+        "files": files,
+        "args": opts.args,
+        "argstrs": opts.argstrs,
+        "pure_eval": opts.pure_eval,
+        "restrict_eval": opts.restrict_eval,
+        "include": opts.include,
+        "attr": opts.attr,
+        "environment": opts.environment(),
+        "evaluator": opts.evaluator,
+        "evaluator_command": opts.evaluator_command,
+    });
+    Ok(sha256_hex(key.to_string().as_bytes()))
+}
+
+/// `path` and the files it refers to with relative path literals, and the
+/// files they refer to, and so on; directories are resolved to their
+/// `default.nix`. Paths which don't exist are skipped, because they're often
+/// in comments or strings rather than real imports.
+fn nix_imports(path: &Path) -> io::Result<BTreeSet<PathBuf>> {
+    lazy_static! {
+        static ref PATH_LITERAL: Regex =
+            Regex::new(r"(?:^|[^A-Za-z0-9._+\-/])(\.\.?/[A-Za-z0-9._+\-/]+)").unwrap();
+    }
+    let mut files = BTreeSet::new();
+    let mut queue = vec![make_abs(&env::current_dir()?, path)];
+    while let Some(file) = queue.pop() {
+        if !files.insert(file.clone()) || file.extension() != Some(OsStr::new("nix")) {
+            continue;
+        }
+        let dir = file.parent().unwrap_or_else(|| Path::new("/"));
+        for literal in PATH_LITERAL.captures_iter(&fs::read_to_string(&file)?) {
+            let mut import = make_abs(dir, Path::new(&literal[1]));
+            if import.is_dir() {
+                import.push("default.nix");
+            }
+            if import.is_file() {
+                queue.push(import);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Evaluate the Nix expression `expr` and deserialize the result. If it
+/// evaluates to a function, it's called with the arguments in `opts`.
+/// Relative paths in `expr` are relative to the working directory, and
+/// `opts.attr` is ignored; select attributes in the expression instead.
+pub fn eval_expr<T: DeserializeOwned>(
+    expr: &str,
+    opts: &NixOptions,
+) -> Result<Evaluated<T>, NixEvalError> {
+    let (evaluator, mut command) = opts.command();
+    opts.apply(&mut command);
+    // Unlike files, `nix eval --expr` is evaluated purely by default.
+    if evaluator == Evaluator::NixEval && !opts.pure_eval {
+        command.arg("--impure");
+    }
+    command.arg("--expr").arg(expr);
+    opts.apply_env(&mut command);
+    run(&mut command, opts.timeout(), opts.max_output_size())
+}
+
+/// Evaluate the flake output `flake_ref` (e.g. `github:me/dotfiles#dotfiles`)
+/// with `nix eval` and deserialize the result. If the output is a function,
+/// it's called with the arguments in `opts` which it accepts.
+///
+/// `nix-instantiate` can't evaluate flakes, so `opts.evaluator_command` is
+/// only used if it's a `nix eval` evaluator.
+pub fn eval_flake<T: DeserializeOwned>(
+    flake_ref: &str,
+    opts: &NixOptions,
+) -> Result<Evaluated<T>, NixEvalError> {
+    let mut command = if opts.select_evaluator() == Evaluator::NixEval {
+        opts.program_command("nix")
+    } else {
+        Command::new("nix")
+    };
+    command
+        .args(["--extra-experimental-features", "nix-command flakes"])
+        .args(["eval", "--json", flake_ref])
+        .arg("--apply")
+        .arg(apply_args_expr(opts));
+    opts.apply_env(&mut command);
+    run(&mut command, opts.timeout(), opts.max_output_size())
+}
+
+/// An element of `nix build --json`'s output.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct BuildResult {
+    outputs: BTreeMap<String, PathBuf>,
+}
+
+/// Build the flake installable `installable` (e.g. `.#nvim-config`) with
+/// `nix build` and return its default output's store path. If `out_link` is
+/// given, a symlink to the output is created there, which keeps it from
+/// being garbage-collected. Nix only rebuilds when the derivation changes, so
+/// this is cheap when nothing has.
+pub fn build(
+    installable: &str,
+    out_link: Option<&Path>,
+    opts: &NixOptions,
+) -> Result<PathBuf, NixEvalError> {
+    let mut command = build_command(installable, out_link, opts);
+    opts.apply_env(&mut command);
+    let results: Vec<BuildResult> =
+        run(&mut command, opts.build_timeout(), opts.max_output_size())?.value;
+    results
+        .into_iter()
+        .next()
+        .and_then(|mut result| result.outputs.remove("out"))
+        .ok_or_else(|| {
+            NixEvalError::EvalFailed(EvalFailure {
+                message: format!("`nix build {}` didn't produce an output", installable),
+                position: None,
+                stderr: String::new(),
+            })
+        })
+}
+
+fn build_command(installable: &str, out_link: Option<&Path>, opts: &NixOptions) -> Command {
+    let mut command = if opts.select_evaluator() == Evaluator::NixEval {
+        opts.program_command("nix")
+    } else {
+        Command::new("nix")
+    };
+    command
+        .args(["--extra-experimental-features", "nix-command flakes"])
+        .args(["build", "--json"]);
+    match out_link {
+        Some(out_link) => command.arg("--out-link").arg(out_link),
+        None => command.arg("--no-link"),
+    };
+    command.arg(installable);
+    command
+}
+
+/// `nix eval` only passes `--arg`s to functions from files, not flake
+/// outputs, so we apply a function which calls the value with the arguments
+/// it takes.
+fn apply_args_expr(opts: &NixOptions) -> String {
+    let mut args = String::new();
+    for (name, expr) in &opts.args {
+        args.push_str(&format!("{} = ({}); ", nix_string(name), expr));
+    }
+    for (name, value) in &opts.argstrs {
+        if !opts.args.contains_key(name) {
+            args.push_str(&format!("{} = {}; ", nix_string(name), nix_string(value)));
+        }
+    }
+    format!(
+        "f: if builtins.isFunction f \
+         then f (builtins.intersectAttrs (builtins.functionArgs f) {{ {}}}) \
+         else f",
+        args
+    )
+}
+
+/// Quote `s` as a Nix string literal.
+pub(crate) fn nix_string(s: &str) -> String {
+    format!(
+        "\"{}\"",
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace("${", "\\${")
+    )
+}
+
+/// Run a Nix command and deserialize its JSON output. Nix prints warnings to
+/// stderr too, so only a non-zero exit status means evaluation failed.
+///
+/// The output is parsed as it's read, so huge lists don't need to be held in
+/// memory twice; evaluation is stopped if it produces more than `max_size`
+/// bytes.
+fn run<T: DeserializeOwned>(
+    command: &mut Command,
+    timeout: Duration,
+    max_size: u64,
+) -> Result<Evaluated<T>, NixEvalError> {
+    let _spinner = Spinner::start("Evaluating Nix");
+    let output_res = read_with_timeout(command, timeout, |stdout| {
+        let mut reader = LimitReader {
+            inner: BufReader::new(stdout),
+            remaining: max_size,
+            exceeded: false,
+        };
+        let value = serde_json::from_reader::<_, T>(&mut reader);
+        (value, reader.exceeded)
+    });
     match output_res {
         Err(err) => match err.kind() {
             io::ErrorKind::NotFound => Err(NixEvalError::NoNix(err)),
             _ => Err(err.into()),
         },
-        Ok(output) => {
-            if !output.stderr.is_empty() {
-                Err(NixEvalError::EvalFailed(
-                    String::from_utf8_lossy(&output.stderr).to_string(),
-                ))
+        Ok((None, _, _)) => Err(NixEvalError::Timeout(timeout)),
+        Ok((_, (_, true), _)) => Err(NixEvalError::OutputTooLarge(max_size)),
+        Ok((Some(status), (value, false), stderr)) => {
+            let stderr = String::from_utf8_lossy(&stderr);
+            if !status.success() {
+                Err(NixEvalError::EvalFailed(EvalFailure::parse(&stderr)))
             } else {
-                Ok(serde_json::from_reader(&output.stdout[..])?)
+                Ok(Evaluated {
+                    value: value?,
+                    warnings: stderr
+                        .lines()
+                        .filter(|line| !line.trim().is_empty())
+                        .map(String::from)
+                        .collect(),
+                })
             }
         }
     }
 }
 
+/// A reader which fails after reading `remaining` bytes.
+struct LimitReader<R> {
+    inner: R,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<R: Read> Read for LimitReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Read one byte more than allowed, so we can tell the difference
+        // between output of exactly `remaining` bytes and too much output.
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining + 1).unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..max])?;
+        if n as u64 > self.remaining {
+            self.exceeded = true;
+            return Err(io::Error::other("output too large"));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use indoc::indoc;
     use pretty_assertions::assert_eq;
-    use std::path::PathBuf;
+    use serde_json::Value;
+    use tempfile::TempDir;
 
     use super::*;
+    use crate::util::shell_command;
+
+    #[test]
+    fn evaluator() {
+        let evaluator = Evaluator::detect();
+        match nix_version() {
+            Some(version) if version.starts_with("2.3") => {
+                assert_eq!(evaluator, Evaluator::NixInstantiate)
+            }
+            Some(_) => assert_eq!(evaluator, Evaluator::NixEval),
+            None => assert_eq!(evaluator, Evaluator::NixInstantiate),
+        }
+
+        let opts = |evaluator, command: Option<&[&str]>| NixOptions {
+            evaluator,
+            evaluator_command: command.map(|c| c.iter().map(|s| s.to_string()).collect()),
+            ..Default::default()
+        };
+        let command = |opts: NixOptions| format!("{:?}", opts.command().1);
+        assert_eq!(
+            command(opts(Some(Evaluator::NixEval), None)),
+            r#""nix" "--extra-experimental-features" "nix-command" "eval" "--json""#
+        );
+        assert_eq!(
+            command(opts(None, Some(&["/nix/store/abc-nix-2.18/bin/nix"]))),
+            r#""/nix/store/abc-nix-2.18/bin/nix" "--extra-experimental-features" "nix-command" "eval" "--json""#
+        );
+        assert_eq!(
+            command(opts(None, Some(&["nix-wrapper", "--quiet"]))),
+            r#""nix-wrapper" "--quiet" "--strict" "--json" "--eval""#
+        );
+        assert_eq!(
+            opts(Some(Evaluator::NixInstantiate), Some(&["/bin/nix"])).select_evaluator(),
+            Evaluator::NixInstantiate
+        );
+    }
 
     #[test]
     fn nix_eval_file() {
-        let res = dbg!(eval_file::<Vec<String>>(&PathBuf::from("test-data/string-list.nix")));
+        let res = dbg!(eval_file::<Vec<String>>(
+            &PathBuf::from("test-data/string-list.nix"),
+            &NixOptions::default()
+        ));
+        // Allow tests to pass on systems without Nix installed.
+        match res {
+            Err(NixEvalError::NoNix(_)) => {}
+            res => assert_eq!(res.unwrap().value, vec!["foo", "bar", "baz"]),
+        }
+    }
+
+    #[test]
+    fn nix_eval_file_argstrs() {
+        let path = PathBuf::from("test-data/string-function.nix");
+        let res = dbg!(eval_file::<Vec<String>>(&path, &NixOptions::default()));
+        // Allow tests to pass on systems without Nix installed.
+        match res {
+            Err(NixEvalError::NoNix(_)) => {}
+            res => {
+                assert_eq!(res.unwrap().value, vec!["foo", "default"]);
+            assert_eq!(
+                eval_file::<Vec<String>>(&path, &NixOptions::default().argstr("name", "bar"))
+                    .unwrap()
+                    .value,
+                vec!["foo", "bar"]
+            );
+            assert_eq!(
+                eval_file::<Vec<String>>(
+                    &path,
+                    &NixOptions::default().arg("name", r#""baz" + "quux""#)
+                )
+                .unwrap()
+                .value,
+                vec!["foo", "bazquux"]
+            );
+            }
+        }
+    }
+
+    #[test]
+    fn nix_eval_expr() {
+        let res = dbg!(eval_expr::<Vec<String>>(
+            r#"{ name ? "default" }: [ "foo" name ]"#,
+            &NixOptions::default().argstr("name", "bar")
+        ));
+        // Allow tests to pass on systems without Nix installed.
+        match res {
+            Err(NixEvalError::NoNix(_)) => {}
+            res => assert_eq!(res.unwrap().value, vec!["foo", "bar"]),
+        }
+    }
+
+    #[test]
+    fn eval_options() {
+        let opts = NixOptions::default()
+            .arg("pkgs", "import <nixpkgs> {}")
+            .argstr("hostname", "work")
+            .with_default_argstrs(
+                vec![
+                    ("hostname".to_string(), "laptop".to_string()),
+                    ("pkgs".to_string(), "shadowed".to_string()),
+                    ("os".to_string(), "linux".to_string()),
+                ]
+                .into_iter()
+                .collect(),
+            );
+        let mut command = Command::new("nix-instantiate");
+        opts.apply(&mut command);
+        assert_eq!(
+            format!("{:?}", command),
+            r#""nix-instantiate" "--arg" "pkgs" "import <nixpkgs> {}" "--argstr" "hostname" "work" "--argstr" "os" "linux""#
+        );
+
+        let opts = NixOptions {
+            pure_eval: true,
+            restrict_eval: true,
+            nix_path: Some("".into()),
+            ..Default::default()
+        };
+        let mut command = Command::new("nix-instantiate");
+        opts.apply(&mut command);
+        assert_eq!(
+            format!("{:?}", command),
+            r#""nix-instantiate" "--option" "pure-eval" "true" "--option" "restrict-eval" "true""#
+        );
+
+        let opts = NixOptions {
+            include: vec![
+                "mylib=./lib".into(),
+                "nixpkgs=https://example.com/nixpkgs.tar.gz".into(),
+                "flake:nixpkgs".into(),
+                "/etc/nix/path".into(),
+                "overlays".into(),
+            ],
+            ..Default::default()
+        }
+        .with_include_base(Path::new("/home/me/.dotfiles"));
+        let mut command = Command::new("nix-instantiate");
+        opts.apply(&mut command);
+        assert_eq!(
+            format!("{:?}", command),
+            r#""nix-instantiate" "-I" "mylib=/home/me/.dotfiles/./lib" "-I" "nixpkgs=https://example.com/nixpkgs.tar.gz" "-I" "flake:nixpkgs" "-I" "/etc/nix/path" "-I" "/home/me/.dotfiles/overlays""#
+        );
+    }
+
+    #[test]
+    fn environment() {
+        let vars = || {
+            vec![
+                ("PATH", "/usr/bin"),
+                (
+                    "NIX_PATH",
+                    "nixpkgs=/nix/var/nix/profiles/per-user/root/channels/nixpkgs",
+                ),
+                ("EDITOR", "vim"),
+                ("AWS_SECRET_ACCESS_KEY", "hunter2"),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        };
+        let environment = |opts: NixOptions| {
+            opts.environment_from(vars())
+                .into_iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            environment(NixOptions::default()),
+            vec![
+                "NIX_PATH=nixpkgs=/nix/var/nix/profiles/per-user/root/channels/nixpkgs",
+                "PATH=/usr/bin",
+            ]
+        );
+        assert_eq!(
+            environment(NixOptions {
+                env_allowlist: vec!["EDITOR".into()],
+                nix_path: Some("".into()),
+                ..Default::default()
+            }),
+            vec!["EDITOR=vim", "NIX_PATH=", "PATH=/usr/bin"]
+        );
+        assert_eq!(
+            environment(NixOptions {
+                inherit_env: true,
+                ..Default::default()
+            })
+            .len(),
+            4
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nix_eval_timeout() {
+        let timeout = Duration::from_millis(100);
+        match run::<Value>(
+            &mut shell_command("sleep 10"),
+            timeout,
+            DEFAULT_MAX_OUTPUT_SIZE,
+        ) {
+            Err(NixEvalError::Timeout(t)) => assert_eq!(t, timeout),
+            res => panic!("expected a timeout but got {:?}", res),
+        }
+        assert_eq!(NixOptions::default().timeout(), DEFAULT_TIMEOUT);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nix_eval_warnings() {
+        let timeout = Duration::from_secs(10);
+        assert_eq!(
+            run::<Vec<String>>(
+                &mut shell_command(r#"echo 'trace: hi' >&2; echo '["foo"]'"#),
+                timeout,
+                DEFAULT_MAX_OUTPUT_SIZE,
+            )
+            .unwrap(),
+            Evaluated {
+                value: vec!["foo".to_string()],
+                warnings: vec!["trace: hi".to_string()],
+            }
+        );
+        match run::<Value>(
+            &mut shell_command("echo 'error: oops' >&2; exit 1"),
+            timeout,
+            DEFAULT_MAX_OUTPUT_SIZE,
+        ) {
+            Err(NixEvalError::EvalFailed(err)) => {
+                assert_eq!(err.message, "oops");
+                assert_eq!(err.stderr, "error: oops\n");
+            }
+            res => panic!("expected an evaluation failure but got {:?}", res),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nix_eval_output_limit() {
+        let timeout = Duration::from_secs(10);
+        let mut command = shell_command(r#"echo '["foo", "bar"]'"#);
+        assert_eq!(
+            run::<Vec<String>>(&mut command, timeout, 15).unwrap().value,
+            vec!["foo", "bar"]
+        );
+        match run::<Vec<String>>(&mut command, timeout, 14) {
+            Err(NixEvalError::OutputTooLarge(14)) => {}
+            res => panic!("expected the output to be too large but got {:?}", res),
+        }
+        // The command is stopped rather than left to write forever.
+        match run::<Value>(&mut shell_command("echo '['; yes '1,'"), timeout, 1024) {
+            Err(NixEvalError::OutputTooLarge(1024)) => {}
+            res => panic!("expected the output to be too large but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn imports() {
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(
+            nix_imports(Path::new("test-data/nix-imports/main.nix")).unwrap(),
+            vec![
+                cwd.join("test-data/nix-imports/lib.nix"),
+                cwd.join("test-data/nix-imports/main.nix"),
+                cwd.join("test-data/string-list.nix"),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn nix_eval_cached() {
+        let path = Path::new("test-data/nix-imports/main.nix");
+        let dir = TempDir::new().unwrap();
+        let cache_dir = dir.path();
+        let opts = NixOptions {
+            cache_dir: Some(cache_dir.to_path_buf()),
+            ..Default::default()
+        }
+        .argstr("name", "cached");
+        let key = cache_key(path, &opts).unwrap();
+        assert_eq!(cache_key(path, &opts).unwrap(), key);
+        assert_ne!(
+            cache_key(path, &NixOptions::default().argstr("name", "other")).unwrap(),
+            key
+        );
+        let evaluator_command = NixOptions {
+            evaluator_command: Some(vec!["/nix/store/abc-nix/bin/nix".into()]),
+            ..opts.clone()
+        };
+        assert_ne!(cache_key(path, &evaluator_command).unwrap(), key);
+
+        // A cached result is used without evaluating anything, so this works
+        // without Nix installed.
+        fs::write(
+            cache_dir.join(format!("{}.json", key)),
+            r#"{"value": ["from", "cache"], "warnings": ["trace: hi"]}"#,
+        )
+        .unwrap();
+        let res = eval_file_cached::<Vec<String>>(path, &opts);
+        assert_eq!(
+            res.unwrap(),
+            Evaluated {
+                value: vec!["from".to_string(), "cache".to_string()],
+                warnings: vec!["trace: hi".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn flake_apply_expr() {
+        assert_eq!(nix_string(r#"a "b" \ ${c}"#), r#""a \"b\" \\ \${c}""#);
+        assert_eq!(
+            apply_args_expr(
+                &NixOptions::default()
+                    .arg("pkgs", "import <nixpkgs> {}")
+                    .argstr("hostname", "work")
+                    .argstr("pkgs", "shadowed")
+            ),
+            "f: if builtins.isFunction f \
+             then f (builtins.intersectAttrs (builtins.functionArgs f) \
+             { \"pkgs\" = (import <nixpkgs> {}); \"hostname\" = \"work\"; }) \
+             else f"
+        );
+    }
+
+    #[test]
+    fn nix_eval_flake() {
+        let res = dbg!(eval_flake::<Vec<String>>(
+            "path:test-data/flake#dotfiles",
+            &NixOptions::default().argstr("name", "bar")
+        ));
+        // Allow tests to pass on systems without Nix installed.
+        match res {
+            Err(NixEvalError::NoNix(_)) => {}
+            res => assert_eq!(res.unwrap().value, vec!["foo", "bar"]),
+        }
+    }
+
+    #[test]
+    fn nix_eval_file_restricted() {
+        let opts = NixOptions {
+            restrict_eval: true,
+            nix_path: Some("".into()),
+            ..Default::default()
+        };
+        let res = dbg!(eval_file::<Vec<String>>(
+            &PathBuf::from("test-data/string-list.nix"),
+            &opts
+        ));
         // Allow tests to pass on systems without Nix installed.
-        if res.is_err() {
-            assert!(matches!(res, Err(NixEvalError::NoNix(_))));
-        } else {
-            assert_eq!(res.unwrap(), vec!["foo", "bar", "baz"]);
+        match res {
+            Err(NixEvalError::NoNix(_)) => {}
+            res => assert_eq!(res.unwrap().value, vec!["foo", "bar", "baz"]),
         }
     }
 
+    #[test]
+    fn eval_file_attr() {
+        let path = Path::new("/home/me/dotfiles.nix");
+        let opts = NixOptions {
+            attr: Some("hosts.laptop".into()),
+            ..Default::default()
+        }
+        .argstr("os", "linux");
+        let command = |evaluator| {
+            format!(
+                "{:?}",
+                file_command(
+                    path,
+                    &NixOptions {
+                        evaluator: Some(evaluator),
+                        ..opts.clone()
+                    }
+                )
+                .unwrap()
+            )
+        };
+        assert_eq!(
+            command(Evaluator::NixEval),
+            r#""nix" "--extra-experimental-features" "nix-command" "eval" "--json" "--argstr" "os" "linux" "--file" "/home/me/dotfiles.nix" "hosts.laptop""#
+        );
+        assert_eq!(
+            command(Evaluator::NixInstantiate),
+            r#""nix-instantiate" "--strict" "--json" "--eval" "--argstr" "os" "linux" "--attr" "hosts.laptop" "/home/me/dotfiles.nix""#
+        );
+    }
+
+    #[test]
+    fn nix_build() {
+        assert_eq!(
+            format!(
+                "{:?}",
+                build_command(
+                    ".#nvim-config",
+                    Some(Path::new("/cache/gcroots/nvim-config")),
+                    &NixOptions::default()
+                )
+            ),
+            r#""nix" "--extra-experimental-features" "nix-command flakes" "build" "--json" "--out-link" "/cache/gcroots/nvim-config" ".#nvim-config""#
+        );
+        assert_eq!(
+            serde_json::from_str::<Vec<BuildResult>>(
+                r#"[{"drvPath": "/nix/store/abc-nvim-config.drv", "outputs": {"out": "/nix/store/def-nvim-config"}}]"#
+            )
+            .unwrap(),
+            vec![BuildResult {
+                outputs: vec![("out".to_string(), PathBuf::from("/nix/store/def-nvim-config"))]
+                    .into_iter()
+                    .collect()
+            }]
+        );
+    }
+
+    #[test]
+    fn eval_failure() {
+        let position = |file: &str, line, column| {
+            Some(Position {
+                file: file.into(),
+                line,
+                column,
+            })
+        };
+
+        let failure =
+            EvalFailure::parse("error: undefined variable 'x' at /home/me/dotfiles.nix:2:9\n");
+        assert_eq!(failure.message, "undefined variable 'x'");
+        assert_eq!(failure.position, position("/home/me/dotfiles.nix", 2, 9));
+
+        let failure = EvalFailure::parse(indoc!(
+            "
+            error:
+                   … while evaluating the file '/home/me/dotfiles.nix':
+
+                   … while calling a function
+
+                     at /home/me/lib.nix:1:1:
+
+                        1| x: x + 1
+                         | ^
+
+                   error: cannot add a string to an integer
+
+                   at /home/me/lib.nix:1:4:
+
+                        1| x: x + 1
+                         |    ^
+            "
+        ));
+        assert_eq!(failure.message, "cannot add a string to an integer");
+        assert_eq!(failure.position, position("/home/me/lib.nix", 1, 4));
+        assert_eq!(
+            failure.to_string(),
+            "/home/me/lib.nix:1:4: cannot add a string to an integer"
+        );
+
+        let failure = EvalFailure::parse("warning: something\nerror: oops\n");
+        assert_eq!(failure.message, "oops");
+        assert_eq!(failure.position, None);
+        assert_eq!(failure.diagnostic(), "error: oops");
+
+        let failure = EvalFailure {
+            message: "undefined variable 'nam'".into(),
+            position: position("test-data/string-function.nix", 1, 37),
+            stderr: String::new(),
+        };
+        assert_eq!(
+            failure.diagnostic(),
+            indoc!(
+                r#"
+                error: undefined variable 'nam'
+                  --> test-data/string-function.nix:1:37
+                   |
+                 1 | { name ? "default", ... }: [ "foo" name ]
+                   |                                     ^"#
+            )
+            .trim_start()
+        );
+    }
+
     #[test]
     fn nix_eval_missing_file() {
-        let res = dbg!(eval_file::<Vec<String>>(&PathBuf::from("test-data/doesnt-exist.sldgkjaslj")));
+        let res = dbg!(eval_file::<Vec<String>>(
+            &PathBuf::from("test-data/doesnt-exist.sldgkjaslj"),
+            &NixOptions::default()
+        ));
         assert!(matches!(res, Err(NixEvalError::EvalFailed(_))));
         if let NixEvalError::EvalFailed(err) = res.unwrap_err() {
-            assert!(err.starts_with("error: getting status of"));
-            assert!(err.ends_with(": No such file or directory\n"));
+            assert!(err.message.starts_with("getting status of"));
+            assert!(err.message.ends_with(": No such file or directory"));
         }
     }
 }
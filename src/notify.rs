@@ -0,0 +1,33 @@
+//! Desktop notifications, so runs nobody's watching, like `dfm sync` from
+//! cron, don't fail silently. They're shown with `notify-rust`, which talks
+//! to the notification server over D-Bus, or to Notification Center on
+//! macOS. Sending them needs the `notify` feature.
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum NotifyError {
+    #[cfg(feature = "notify")]
+    #[error("{0}")]
+    Notify(#[from] notify_rust::error::Error),
+
+    #[error("sending notifications needs the `notify` feature")]
+    NoNotify,
+}
+
+/// Show a desktop notification.
+#[cfg(feature = "notify")]
+pub fn send(title: &str, body: &str) -> Result<(), NotifyError> {
+    notify_rust::Notification::new()
+        .appname("dfm")
+        .summary(title)
+        .body(body)
+        .show()?;
+    Ok(())
+}
+
+/// Show a desktop notification.
+#[cfg(not(feature = "notify"))]
+pub fn send(_title: &str, _body: &str) -> Result<(), NotifyError> {
+    Err(NotifyError::NoNotify)
+}
@@ -0,0 +1,152 @@
+//! `ExecutionObserver`, for following along as dotfiles are installed,
+//! e.g. to show progress, without the code which installs them knowing
+//! anything about how it's shown. `dfm link` uses one to update its
+//! spinner and log file, and `dfm link --json` another to print each event
+//! as a line of JSON:
+//!
+//! ```text
+//! {"action":"link","event":"entry_started","index":0,"installed":"/home/me/.bashrc","repo":"/home/me/.dotfiles/bashrc","total":1}
+//! {"action":"link","event":"action_performed","installed":"/home/me/.bashrc","repo":"/home/me/.dotfiles/bashrc"}
+//! {"event":"entry_finished","installed":"/home/me/.bashrc","outcome":"installed","repo":"/home/me/.dotfiles/bashrc"}
+//! ```
+
+use std::io::Write;
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::dotfile::AbsDotfile;
+use crate::engine::Action;
+
+/// What happened to a dotfile; see `ExecutionObserver::entry_finished`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// It was already installed, so nothing was done.
+    AlreadyOk,
+    Installed,
+    /// It would have been installed, but this is a dry run.
+    DryRun,
+    /// Something else is in its place and wasn't overwritten.
+    Conflict,
+    /// Installing it failed, with this error; the run stops.
+    Failed(String),
+}
+
+/// Told what happens to each dotfile as it's installed; see
+/// `engine::install_all`. Every method does nothing by default.
+pub trait ExecutionObserver {
+    /// `d` is about to be installed with `action`; it's number `index`,
+    /// counting from 0, of `total`.
+    fn entry_started(&mut self, _d: &AbsDotfile, _action: Action, _index: usize, _total: usize) {}
+
+    /// `action` was performed on `d`. It isn't for dotfiles which are
+    /// already installed, or in a dry run.
+    fn action_performed(&mut self, _d: &AbsDotfile, _action: Action) {}
+
+    /// Something else is in `d`'s place, and the user is about to be asked
+    /// what to do with it.
+    fn conflict_encountered(&mut self, _d: &AbsDotfile) {}
+
+    /// `d` is done with.
+    fn entry_finished(&mut self, _d: &AbsDotfile, _outcome: &Outcome) {}
+}
+
+/// Ignores everything.
+impl ExecutionObserver for () {}
+
+/// Tells each observer in turn.
+impl ExecutionObserver for Vec<Box<dyn ExecutionObserver>> {
+    fn entry_started(&mut self, d: &AbsDotfile, action: Action, index: usize, total: usize) {
+        for observer in self {
+            observer.entry_started(d, action, index, total);
+        }
+    }
+
+    fn action_performed(&mut self, d: &AbsDotfile, action: Action) {
+        for observer in self {
+            observer.action_performed(d, action);
+        }
+    }
+
+    fn conflict_encountered(&mut self, d: &AbsDotfile) {
+        for observer in self {
+            observer.conflict_encountered(d);
+        }
+    }
+
+    fn entry_finished(&mut self, d: &AbsDotfile, outcome: &Outcome) {
+        for observer in self {
+            observer.entry_finished(d, outcome);
+        }
+    }
+}
+
+/// Writes each event as a line of JSON. Errors writing are ignored, so a
+/// closed pipe doesn't stop dotfiles being installed.
+#[derive(Debug)]
+pub struct JsonLines<W: Write>(pub W);
+
+impl<W: Write> JsonLines<W> {
+    fn write(&mut self, event: &str, d: &AbsDotfile, mut fields: serde_json::Value) {
+        fields["event"] = event.into();
+        fields["repo"] = json!(d.repo);
+        fields["installed"] = json!(d.installed);
+        let _ = writeln!(self.0, "{}", fields).and_then(|_| self.0.flush());
+    }
+}
+
+impl<W: Write> ExecutionObserver for JsonLines<W> {
+    fn entry_started(&mut self, d: &AbsDotfile, action: Action, index: usize, total: usize) {
+        self.write(
+            "entry_started",
+            d,
+            json!({ "action": action, "index": index, "total": total }),
+        );
+    }
+
+    fn action_performed(&mut self, d: &AbsDotfile, action: Action) {
+        self.write("action_performed", d, json!({ "action": action }));
+    }
+
+    fn conflict_encountered(&mut self, d: &AbsDotfile) {
+        self.write("conflict_encountered", d, json!({}));
+    }
+
+    fn entry_finished(&mut self, d: &AbsDotfile, outcome: &Outcome) {
+        self.write("entry_finished", d, json!({ "outcome": outcome }));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn json_lines() {
+        let d = AbsDotfile {
+            repo: "/dotfiles/bashrc".into(),
+            installed: "/home/me/.bashrc".into(),
+            ..Default::default()
+        };
+        let mut observer = JsonLines(Vec::new());
+        observer.entry_started(&d, Action::Link, 0, 2);
+        observer.conflict_encountered(&d);
+        observer.entry_finished(&d, &Outcome::Failed("no".into()));
+        observer.entry_finished(&d, &Outcome::AlreadyOk);
+        assert_eq!(
+            String::from_utf8(observer.0).unwrap(),
+            indoc!(
+                r#"
+                {"action":"link","event":"entry_started","index":0,"installed":"/home/me/.bashrc","repo":"/dotfiles/bashrc","total":2}
+                {"event":"conflict_encountered","installed":"/home/me/.bashrc","repo":"/dotfiles/bashrc"}
+                {"event":"entry_finished","installed":"/home/me/.bashrc","outcome":{"failed":"no"},"repo":"/dotfiles/bashrc"}
+                {"event":"entry_finished","installed":"/home/me/.bashrc","outcome":"already_ok","repo":"/dotfiles/bashrc"}
+                "#
+            )
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Packages to install alongside the dotfiles, declared in `packages.toml` in
+//! the dotfile repository, e.g.
+//!
+//! ```toml
+//! brew = ["ripgrep", "fd"]
+//! apt = ["ripgrep", "fd-find"]
+//! cargo = ["cargo-edit"]
+//! ```
+//!
+//! Package managers which aren't installed are skipped, so one file can
+//! declare packages for several operating systems.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// The name of the file packages are declared in, in the dotfile repository.
+pub const FILE_NAME: &str = "packages.toml";
+
+#[derive(Error, Debug)]
+pub enum PackagesError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("failed to parse {}: {source}", .path.display())]
+    Toml {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("`{command}` failed: {message}")]
+    Failed { command: String, message: String },
+}
+
+/// The package managers packages can be declared for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Manager {
+    Brew,
+    Apt,
+    Pacman,
+    Cargo,
+    Pipx,
+}
+
+impl fmt::Display for Manager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Manager::Brew => "brew",
+            Manager::Apt => "apt",
+            Manager::Pacman => "pacman",
+            Manager::Cargo => "cargo",
+            Manager::Pipx => "pipx",
+        })
+    }
+}
+
+impl Manager {
+    /// The command which lists installed packages, for `parse_installed`.
+    fn list_command(self) -> Vec<&'static str> {
+        match self {
+            Manager::Brew => vec!["brew", "list", "-1"],
+            Manager::Apt => vec!["dpkg-query", "-W", "-f", "${db:Status-Abbrev} ${Package}\n"],
+            Manager::Pacman => vec!["pacman", "-Qq"],
+            Manager::Cargo => vec!["cargo", "install", "--list"],
+            Manager::Pipx => vec!["pipx", "list", "--short"],
+        }
+    }
+
+    /// The command which installs packages, without the packages.
+    fn install_command(self) -> Vec<&'static str> {
+        match self {
+            Manager::Brew => vec!["brew", "install"],
+            Manager::Apt => vec!["sudo", "apt-get", "install"],
+            Manager::Pacman => vec!["sudo", "pacman", "-S", "--needed"],
+            Manager::Cargo => vec!["cargo", "install"],
+            Manager::Pipx => vec!["pipx", "install"],
+        }
+    }
+
+    /// Whether this package manager is installed.
+    pub fn is_available(self) -> bool {
+        which::which(self.list_command()[0]).is_ok()
+    }
+
+    /// The names of the installed packages in the output of `list_command`.
+    fn parse_installed(self, output: &str) -> BTreeSet<String> {
+        output
+            .lines()
+            .filter_map(|line| match self {
+                Manager::Brew | Manager::Pacman => Some(line.trim()),
+                // Only fully installed packages, not removed ones whose
+                // configuration files are left.
+                Manager::Apt => line.strip_prefix("ii ").map(str::trim),
+                // `name v1.0.0:` followed by indented binary names.
+                Manager::Cargo if !line.starts_with(char::is_whitespace) => line.split(' ').next(),
+                Manager::Cargo => None,
+                // `name 1.0.0`.
+                Manager::Pipx => line.split(' ').next(),
+            })
+            .filter(|name| !name.is_empty())
+            // apt's names may have an architecture, e.g. `libc6:amd64`.
+            .map(|name| name.split(':').next().unwrap_or(name).to_string())
+            .collect()
+    }
+
+    /// The installed packages.
+    pub fn installed(self) -> Result<BTreeSet<String>, PackagesError> {
+        let command = self.list_command();
+        let output = Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(PackagesError::Failed {
+                command: command.join(" "),
+                message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(self.parse_installed(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Install `packages`; the package manager's output and prompts go to
+    /// the terminal, on standard error.
+    pub fn install(self, packages: &[String]) -> Result<(), PackagesError> {
+        let command = self.install_command();
+        let status = Command::new(command[0])
+            .args(&command[1..])
+            .args(packages)
+            .stdout(io::stderr())
+            .status()?;
+        if !status.success() {
+            return Err(PackagesError::Failed {
+                command: format!("{} {}", command.join(" "), packages.join(" ")),
+                message: status.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The packages declared for each package manager.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Packages {
+    pub brew: Vec<String>,
+    pub apt: Vec<String>,
+    pub pacman: Vec<String>,
+    pub cargo: Vec<String>,
+    pub pipx: Vec<String>,
+}
+
+impl Packages {
+    /// Each package manager with packages declared for it, and its
+    /// packages.
+    pub fn by_manager(&self) -> Vec<(Manager, &[String])> {
+        vec![
+            (Manager::Brew, self.brew.as_slice()),
+            (Manager::Apt, &self.apt),
+            (Manager::Pacman, &self.pacman),
+            (Manager::Cargo, &self.cargo),
+            (Manager::Pipx, &self.pipx),
+        ]
+        .into_iter()
+        .filter(|(_, names)| !names.is_empty())
+        .collect()
+    }
+
+    /// The packages declared in `FILE_NAME` in `dotfile_repo`, or none if it
+    /// doesn't exist.
+    pub fn read(dotfile_repo: &Path) -> Result<Self, PackagesError> {
+        let path = dotfile_repo.join(FILE_NAME);
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                toml::from_str(&text).map_err(|source| PackagesError::Toml { path, source })
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Run `brew bundle` with `brewfile`, which installs the taps, formulae,
+/// casks, etc. it lists, or with `check` set, only lists the ones which
+/// aren't installed. Returns whether everything is installed. brew's
+/// output goes to standard error, except the list `check` prints, which
+/// goes to standard output unless `quiet` is set.
+pub fn brew_bundle(brewfile: &Path, check: bool, quiet: bool) -> Result<bool, PackagesError> {
+    let subcommand = if check { "check" } else { "install" };
+    let mut command = Command::new("brew");
+    command.args(["bundle", subcommand, "--file"]).arg(brewfile);
+    if check {
+        command.arg("--verbose");
+    }
+    if quiet {
+        command.stdout(Stdio::null());
+    } else if !check {
+        command.stdout(io::stderr());
+    }
+    let status = command.status()?;
+    if status.success() {
+        Ok(true)
+    } else if check {
+        // `check` fails if anything's missing.
+        Ok(false)
+    } else {
+        Err(PackagesError::Failed {
+            command: format!("brew bundle install --file {}", brewfile.display()),
+            message: status.to_string(),
+        })
+    }
+}
+
+/// A package manager's declared packages compared to its installed ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub manager: Manager,
+    /// Declared packages which are installed.
+    pub installed: Vec<String>,
+    /// Declared packages which aren't installed.
+    pub missing: Vec<String>,
+}
+
+impl Status {
+    /// Compare `declared` to `installed`.
+    pub fn new(manager: Manager, declared: &[String], installed: &BTreeSet<String>) -> Self {
+        let (installed, missing) = declared
+            .iter()
+            .cloned()
+            .partition(|name| installed.contains(name));
+        Self {
+            manager,
+            installed,
+            missing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn packages_parse_installed() {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        assert_eq!(
+            Manager::Brew.parse_installed("fd\nripgrep\n"),
+            names(&["fd", "ripgrep"])
+        );
+        assert_eq!(
+            Manager::Apt.parse_installed("ii  fd-find\nrc  old\nii  libc6:amd64\n"),
+            names(&["fd-find", "libc6"])
+        );
+        assert_eq!(
+            Manager::Cargo.parse_installed(indoc!(
+                "
+                cargo-edit v0.12.2:
+                    cargo-add
+                    cargo-rm
+                ripgrep v14.0.0 (/src/ripgrep):
+                    rg
+                "
+            )),
+            names(&["cargo-edit", "ripgrep"])
+        );
+        assert_eq!(
+            Manager::Pipx.parse_installed("black 23.1.0\nhttpie 3.2.1\n"),
+            names(&["black", "httpie"])
+        );
+    }
+
+    #[test]
+    fn packages_status() {
+        let packages: Packages = toml::from_str(indoc!(
+            r#"
+            brew = ["fd", "ripgrep"]
+            cargo = ["cargo-edit"]
+            "#
+        ))
+        .unwrap();
+        assert_eq!(
+            Status::new(
+                Manager::Brew,
+                &packages.brew,
+                &Manager::Brew.parse_installed("ripgrep\n")
+            ),
+            Status {
+                manager: Manager::Brew,
+                installed: vec!["ripgrep".into()],
+                missing: vec!["fd".into()],
+            }
+        );
+        assert_eq!(
+            packages.by_manager(),
+            vec![
+                (Manager::Brew, packages.brew.as_slice()),
+                (Manager::Cargo, packages.cargo.as_slice()),
+            ]
+        );
+        assert!(toml::from_str::<Packages>("npm = []").is_err());
+        assert_eq!(
+            Packages::read(Path::new("test-data/nonexistent")).unwrap(),
+            Packages::default()
+        );
+    }
+}
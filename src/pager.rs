@@ -0,0 +1,74 @@
+//! Showing long output, like `dfm diff`, in a pager when it's going to a
+//! terminal, like git does.
+//!
+//! The pager is `$PAGER`, or `less` if it isn't set; an empty `$PAGER` or
+//! `cat` turns paging off. Like git, `LESS` defaults to `FRX`, so `less`
+//! keeps colors, leaves the output on the screen when it quits, and quits
+//! straight away if the output fits on one screen.
+
+use std::env;
+use std::io;
+use std::io::Write;
+use std::process::Stdio;
+
+use console::Term;
+
+use crate::util;
+
+/// The pager to use, given the value of `$PAGER`, if any.
+fn pager(var: Option<String>) -> Option<String> {
+    match var {
+        None => Some("less".to_string()),
+        Some(pager) if pager.trim().is_empty() || pager.trim() == "cat" => None,
+        Some(pager) => Some(pager),
+    }
+}
+
+/// Print `text` to standard output, through the pager if `enabled` is true
+/// and standard output is a terminal.
+pub fn page(text: &str, enabled: bool) -> io::Result<()> {
+    let pager = match pager(env::var("PAGER").ok()) {
+        Some(pager) if enabled && Term::stdout().is_term() => pager,
+        _ => return print(text),
+    };
+    let mut command = util::shell_command(&pager);
+    if env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = match command.stdin(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        // Don't fail just because the pager isn't installed.
+        Err(_) => return print(text),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(text.as_bytes()) {
+            // The pager was quit before reading everything.
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+            res => res?,
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
+
+fn print(text: &str) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    stdout.write_all(text.as_bytes())?;
+    stdout.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn pager_from_env() {
+        assert_eq!(pager(None), Some("less".to_string()));
+        assert_eq!(pager(Some("most -s".into())), Some("most -s".to_string()));
+        assert_eq!(pager(Some("".into())), None);
+        assert_eq!(pager(Some("cat".into())), None);
+    }
+}
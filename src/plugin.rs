@@ -0,0 +1,470 @@
+//! Plugins, for integrations too niche to live in dfm itself. A plugin is
+//! an executable on the `PATH` named `dfm-plugin-<name>`, e.g.
+//! `dfm-plugin-keychain`, which can contribute:
+//!
+//! - Entry kinds, which dotfiles choose with their `link` field like the
+//!   built-in strategies; see the `strategy` module.
+//! - Facts, which are available to conditions and templates like `vars`;
+//!   the configuration's `vars` take precedence.
+//! - A post-link action, run at the end of `dfm link`.
+//!
+//! Set `plugins = false` in the configuration file to ignore plugins.
+//!
+//! dfm runs a plugin once per request, writing the request to its standard
+//! input as a JSON object and reading its response from its standard
+//! output. Requests have a `version` (currently 1) and a `method`:
+//!
+//! - `describe`, when dfm starts: the response says what the plugin
+//!   contributes, e.g. `{"kinds": ["keychain"], "facts": {"gpu": "nvidia"},
+//!   "post_link": true}`; each field is optional.
+//! - `install`, with `kind`, `repo` and `installed` paths: install the
+//!   dotfile. Nothing is at the installed path. The response is `{}`.
+//! - `status`, with `kind`, `repo` and `installed`: whether the dotfile is
+//!   installed, e.g. `{"status": "linked"}`; `conflict` if something else
+//!   is at the installed path.
+//! - `post_link`, with the `dotfiles` which apply to this machine (each
+//!   with `repo`, `installed` and `link`) and dfm's `report` of what it did:
+//!   the response is `{}`.
+//!
+//! To fail, a plugin can exit unsuccessfully, with a message on standard
+//! error, or respond with `{"error": "message"}`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::config::Config;
+use crate::dotfile::{AbsDotfile, InstallStatus};
+use crate::error::Error;
+use crate::facts::Facts;
+use crate::report::LinkReport;
+use crate::strategy::LinkStrategy;
+use crate::util::output_with_input;
+use crate::warning::{Warning, WarningKind, Warnings};
+
+/// What plugins' file names start with.
+pub const PREFIX: &str = "dfm-plugin-";
+
+/// The version of the protocol dfm speaks to plugins.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How long a plugin may take to respond.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Error, Debug)]
+pub enum PluginError {
+    #[error("couldn't run plugin `{plugin}`")]
+    Run {
+        plugin: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("plugin `{plugin}` didn't respond within {} seconds", TIMEOUT.as_secs())]
+    TimedOut { plugin: String },
+
+    /// The plugin exited unsuccessfully.
+    #[error("plugin `{plugin}` failed: {stderr}")]
+    Failed { plugin: String, stderr: String },
+
+    #[error("plugin `{plugin}` sent an invalid response")]
+    Response {
+        plugin: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// The plugin responded with an error.
+    #[error("plugin `{plugin}`: {message}")]
+    Error { plugin: String, message: String },
+}
+
+/// What a plugin contributes; its response to `describe`.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    /// Entry kinds, which dotfiles choose with their `link` field.
+    #[serde(default)]
+    pub kinds: Vec<String>,
+    /// Facts, by name.
+    #[serde(default)]
+    pub facts: BTreeMap<String, String>,
+    /// Whether the plugin wants a `post_link` request at the end of `dfm
+    /// link`.
+    #[serde(default)]
+    pub post_link: bool,
+}
+
+#[derive(Deserialize)]
+struct StatusResponse {
+    status: InstallStatus,
+}
+
+/// A plugin executable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plugin {
+    /// The plugin's name, e.g. `keychain` for `dfm-plugin-keychain`.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Plugin {
+    /// Send `request` to the plugin and read its response.
+    fn request<T: DeserializeOwned>(&self, mut request: Value) -> Result<T, PluginError> {
+        request["version"] = PROTOCOL_VERSION.into();
+        let output = output_with_input(
+            &mut Command::new(&self.path),
+            Some(request.to_string().into_bytes()),
+            TIMEOUT,
+        )
+        .map_err(|source| PluginError::Run {
+            plugin: self.name.clone(),
+            source,
+        })?
+        .ok_or_else(|| PluginError::TimedOut {
+            plugin: self.name.clone(),
+        })?;
+        if !output.status.success() {
+            return Err(PluginError::Failed {
+                plugin: self.name.clone(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        let response_error = |source| PluginError::Response {
+            plugin: self.name.clone(),
+            source,
+        };
+        let response: Value = serde_json::from_slice(&output.stdout).map_err(response_error)?;
+        if let Some(message) = response.get("error") {
+            return Err(PluginError::Error {
+                plugin: self.name.clone(),
+                message: message
+                    .as_str()
+                    .map(ToString::to_string)
+                    .unwrap_or_else(|| message.to_string()),
+            });
+        }
+        serde_json::from_value(response).map_err(response_error)
+    }
+
+    /// What the plugin contributes.
+    pub fn describe(&self) -> Result<Manifest, PluginError> {
+        self.request(json!({ "method": "describe" }))
+    }
+
+    /// Install `d`, a dotfile of the plugin's entry kind `kind`.
+    pub fn install(&self, kind: &str, d: &AbsDotfile) -> Result<(), PluginError> {
+        self.request::<Value>(json!({
+            "method": "install",
+            "kind": kind,
+            "repo": d.repo,
+            "installed": d.installed,
+        }))
+        .map(|_| ())
+    }
+
+    /// Whether `d`, a dotfile of the plugin's entry kind `kind`, is
+    /// installed.
+    pub fn status(&self, kind: &str, d: &AbsDotfile) -> Result<InstallStatus, PluginError> {
+        self.request::<StatusResponse>(json!({
+            "method": "status",
+            "kind": kind,
+            "repo": d.repo,
+            "installed": d.installed,
+        }))
+        .map(|response| response.status)
+    }
+
+    /// Tell the plugin `dfm link` has finished.
+    pub fn post_link(
+        &self,
+        dotfiles: &[AbsDotfile],
+        report: &LinkReport,
+    ) -> Result<(), PluginError> {
+        self.request::<Value>(json!({
+            "method": "post_link",
+            "dotfiles": dotfiles
+                .iter()
+                .map(|d| json!({
+                    "repo": d.repo,
+                    "installed": d.installed,
+                    "link": d.strategy.name(),
+                }))
+                .collect::<Vec<_>>(),
+            "report": report,
+        }))
+        .map(|_| ())
+    }
+}
+
+/// The plugin at `path`, if it is one.
+fn plugin_at(path: &Path) -> Option<Plugin> {
+    let file_name = path.file_name()?.to_str()?;
+    let name = if cfg!(windows) {
+        match file_name.rsplit_once('.') {
+            Some((stem, ext)) if ["exe", "cmd", "bat"].contains(&ext.to_lowercase().as_str()) => {
+                stem
+            }
+            _ => return None,
+        }
+    } else {
+        file_name
+    }
+    .strip_prefix(PREFIX)?;
+    let metadata = fs::metadata(path).ok()?;
+    #[cfg(unix)]
+    let executable = {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o111 != 0
+    };
+    #[cfg(not(unix))]
+    let executable = true;
+    if name.is_empty() || !metadata.is_file() || !executable {
+        return None;
+    }
+    Some(Plugin {
+        name: name.to_string(),
+        path: path.to_path_buf(),
+    })
+}
+
+/// The plugins in `dirs`, by name. If two have the same name, the one in
+/// the earlier directory wins, like commands on the `PATH`.
+fn discover_in(dirs: impl IntoIterator<Item = PathBuf>) -> Vec<Plugin> {
+    let mut plugins = BTreeMap::new();
+    for dir in dirs {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut found = entries
+            .filter_map(|entry| plugin_at(&entry.ok()?.path()))
+            .collect::<Vec<_>>();
+        found.sort_by(|l, r| l.path.cmp(&r.path));
+        for plugin in found {
+            plugins.entry(plugin.name.clone()).or_insert(plugin);
+        }
+    }
+    plugins.into_values().collect()
+}
+
+/// The plugins on the `PATH`, by name.
+pub fn discover() -> Vec<Plugin> {
+    env::var_os("PATH")
+        .map(|path| discover_in(env::split_paths(&path)))
+        .unwrap_or_default()
+}
+
+/// An entry kind contributed by a plugin.
+#[derive(Debug, Clone)]
+pub struct PluginStrategy {
+    pub plugin: Plugin,
+    pub kind: String,
+}
+
+impl LinkStrategy for PluginStrategy {
+    fn name(&self) -> &str {
+        &self.kind
+    }
+
+    fn install(&self, d: &AbsDotfile, _facts: &Facts) -> Result<(), Error> {
+        Ok(self.plugin.install(&self.kind, d)?)
+    }
+
+    fn status(&self, d: &AbsDotfile, _metadata: &fs::Metadata) -> io::Result<InstallStatus> {
+        self.plugin
+            .status(&self.kind, d)
+            .map_err(|err| io::Error::other(err.to_string()))
+    }
+}
+
+/// The plugins which described themselves, and what they contribute.
+#[derive(Debug, Clone, Default)]
+pub struct Plugins(Vec<(Plugin, Manifest)>);
+
+impl Plugins {
+    /// Describe each of `plugins`. Plugins which can't are left out, with
+    /// a warning in `warnings`.
+    pub fn describe(plugins: Vec<Plugin>, warnings: &mut Warnings) -> Self {
+        Self(
+            plugins
+                .into_iter()
+                .filter_map(|plugin| match plugin.describe() {
+                    Ok(manifest) => Some((plugin, manifest)),
+                    Err(err) => {
+                        warnings.push(Warning::new(WarningKind::Plugin, err.to_string()));
+                        None
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Plugin, Manifest)> {
+        self.0.iter()
+    }
+
+    /// Add the plugins' entry kinds to `config`'s strategies and their
+    /// facts to its `vars`. A kind which is already a strategy or a fact
+    /// which is already a variable is left as it is, with a warning in
+    /// `warnings`.
+    pub fn apply(&self, config: &mut Config, warnings: &mut Warnings) {
+        for (plugin, manifest) in &self.0 {
+            for kind in &manifest.kinds {
+                if config.link_strategies.get(kind).is_some() {
+                    warnings.push(Warning::new(
+                        WarningKind::Plugin,
+                        format!(
+                            "plugin `{}`: the entry kind `{}` already exists",
+                            plugin.name, kind
+                        ),
+                    ));
+                    continue;
+                }
+                config.link_strategies.register(PluginStrategy {
+                    plugin: plugin.clone(),
+                    kind: kind.clone(),
+                });
+            }
+            for (name, value) in &manifest.facts {
+                if config.vars.contains_key(name) || config.facts.contains_key(name) {
+                    warnings.push(Warning::new(
+                        WarningKind::Plugin,
+                        format!(
+                            "plugin `{}`: the fact `{}` is already set in the configuration file",
+                            plugin.name, name
+                        ),
+                    ));
+                    continue;
+                }
+                config.vars.insert(name.clone(), value.clone());
+            }
+        }
+    }
+
+    /// Tell the plugins which want to know that `dfm link` has finished.
+    /// Plugins which fail are recorded in `warnings`.
+    pub fn post_link(&self, dotfiles: &[AbsDotfile], report: &LinkReport, warnings: &mut Warnings) {
+        for (plugin, _) in self.0.iter().filter(|(_, manifest)| manifest.post_link) {
+            if let Err(err) = plugin.post_link(dotfiles, report) {
+                warnings.push(Warning::new(WarningKind::Plugin, err.to_string()));
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn plugins() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("bin")).unwrap();
+        fs::create_dir_all(root.join("other")).unwrap();
+        let plugin = root.join("bin/dfm-plugin-keychain");
+        fs::write(
+            &plugin,
+            indoc!(
+                r#"
+                #!/bin/sh
+                request=$(cat)
+                case "$request" in
+                    *'"method":"describe"'*)
+                        echo '{"kinds": ["keychain"], "facts": {"gpu": "none"}, "post_link": true}' ;;
+                    *'"method":"install"'*)
+                        echo "$request" > "$(dirname "$0")/../installed.json"
+                        echo '{}' ;;
+                    *'"method":"status"'*)
+                        echo '{"status": "linked"}' ;;
+                    *)
+                        echo '{"error": "unknown method"}' ;;
+                esac
+                "#
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&plugin, fs::Permissions::from_mode(0o755)).unwrap();
+        // Not executable:
+        fs::write(root.join("bin/dfm-plugin-readme"), "").unwrap();
+        // Shadowed by the first:
+        fs::write(root.join("other/dfm-plugin-keychain"), "").unwrap();
+        fs::set_permissions(
+            root.join("other/dfm-plugin-keychain"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+
+        let discovered = discover_in(vec![root.join("bin"), root.join("other")]);
+        let mut warnings = Warnings::default();
+        let plugins = Plugins::describe(discovered.clone(), &mut warnings);
+        let mut config = Config::builder()
+            .dotfile_repo(root)
+            .var("gpu", "nvidia")
+            .build()
+            .unwrap();
+        plugins.apply(&mut config, &mut warnings);
+        let d = AbsDotfile {
+            repo: root.join("login.keychain"),
+            installed: root.join("Library/login.keychain"),
+            strategy: config.link_strategies.get("keychain").unwrap().clone(),
+            ..Default::default()
+        };
+        let installed = d.install(&Facts::new());
+        let request = fs::read_to_string(root.join("installed.json"));
+        let status = d.strategy.status(&d, &fs::metadata(root).unwrap());
+        plugins.post_link(
+            std::slice::from_ref(&d),
+            &LinkReport::default(),
+            &mut warnings,
+        );
+
+        assert_eq!(
+            discovered,
+            vec![Plugin {
+                name: "keychain".into(),
+                path: plugin,
+            }]
+        );
+        assert_eq!(config.vars["gpu"], "nvidia");
+        assert!(installed.is_ok());
+        let request: Value = serde_json::from_str(&request.unwrap()).unwrap();
+        assert_eq!(
+            request,
+            json!({
+                "version": 1,
+                "method": "install",
+                "kind": "keychain",
+                "repo": root.join("login.keychain"),
+                "installed": root.join("Library/login.keychain"),
+            })
+        );
+        assert_eq!(status.unwrap(), InstallStatus::Linked);
+        assert_eq!(
+            warnings.render(false),
+            indoc!(
+                "
+                warning: problems with plugins
+                    plugin `keychain`: the fact `gpu` is already set in the configuration file
+                    plugin `keychain`: unknown method
+                "
+            )
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Output for scripts, like `dfm status --porcelain`, in the spirit of `git
+//! status --porcelain`: one record per line, with fields separated by tabs,
+//! so it can be read without a JSON parser, e.g.
+//!
+//! ```sh
+//! dfm status --porcelain | while IFS="$(printf '\t')" read -r status installed repo tags; do
+//!     ...
+//! done
+//! ```
+//!
+//! Each version of a format stays the same once it's released; new fields
+//! mean a new version. Tabs, newlines and backslashes in fields are escaped
+//! as `\t`, `\n` and `\\`.
+
+use std::str::FromStr;
+
+use crate::dotfile::{AbsDotfile, InstallStatus};
+
+/// A version of the porcelain format; the value of `--porcelain`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum PorcelainVersion {
+    /// For `dfm status`, each dotfile's status (`linked`, `rendered`,
+    /// `missing`, `conflict` or `unknown`), absolute installed path,
+    /// absolute path in the dotfile repository, and comma-separated tags
+    /// (`template`, `systemd`, `cron` and `shell`).
+    #[default]
+    V1,
+    /// Like `V1`, with the status `copied` for dotfiles installed as copies,
+    /// and the name of the dotfile's strategy as a tag unless it's
+    /// `symlink`, e.g. `copy` or `hardlink`, rather than only `template`.
+    V2,
+}
+
+impl PorcelainVersion {
+    /// `status` as this version writes it, or `unknown` if it couldn't be
+    /// found. Copies are `linked` in v1, which predates them: both mean the
+    /// dotfile is installed.
+    pub fn status(self, status: Option<InstallStatus>) -> String {
+        match (self, status) {
+            (_, None) => "unknown".into(),
+            (PorcelainVersion::V1, Some(InstallStatus::Copied)) => "linked".into(),
+            (_, Some(status)) => status.to_string(),
+        }
+    }
+
+    /// `d`'s tags as this version writes them; see `AbsDotfile::tags`.
+    pub fn tags(self, d: &AbsDotfile) -> Vec<&str> {
+        match self {
+            PorcelainVersion::V1 => {
+                let strategy = d.strategy.name();
+                d.tags()
+                    .into_iter()
+                    .filter(|&tag| tag != strategy || tag == "template")
+                    .collect()
+            }
+            PorcelainVersion::V2 => d.tags(),
+        }
+    }
+}
+
+impl FromStr for PorcelainVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" | "1" => Ok(PorcelainVersion::V1),
+            "v2" | "2" => Ok(PorcelainVersion::V2),
+            _ => Err(format!("expected `v1` or `v2` but found `{}`", s)),
+        }
+    }
+}
+
+/// `field` with tabs, newlines and backslashes escaped.
+pub fn escape(field: &str) -> String {
+    field
+        .replace('\\', r"\\")
+        .replace('\t', r"\t")
+        .replace('\n', r"\n")
+}
+
+/// A record of `fields`, escaped and separated by tabs, ending in a newline.
+pub fn record(fields: &[&str]) -> String {
+    let mut record = fields
+        .iter()
+        .map(|field| escape(field))
+        .collect::<Vec<_>>()
+        .join("\t");
+    record.push('\n');
+    record
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn porcelain_record() {
+        assert_eq!(
+            record(&[
+                "linked",
+                "/home/me/.bashrc",
+                "/home/me/.dotfiles/bashrc",
+                ""
+            ]),
+            "linked\t/home/me/.bashrc\t/home/me/.dotfiles/bashrc\t\n"
+        );
+        assert_eq!(
+            record(&["missing", "/home/me/odd\tname\n", r"C:\dotfiles"]),
+            "missing\t/home/me/odd\\tname\\n\tC:\\\\dotfiles\n"
+        );
+        assert_eq!("1".parse(), Ok(PorcelainVersion::V1));
+        assert_eq!("v2".parse(), Ok(PorcelainVersion::V2));
+        assert_eq!(
+            "v3".parse::<PorcelainVersion>(),
+            Err("expected `v1` or `v2` but found `v3`".to_string())
+        );
+    }
+
+    #[test]
+    fn porcelain_versions() {
+        use crate::strategy::{FileCopy, Strategy, Template};
+
+        let copy = AbsDotfile {
+            strategy: Strategy::new(FileCopy),
+            shell: true,
+            ..Default::default()
+        };
+        let template = AbsDotfile {
+            strategy: Strategy::new(Template),
+            ..Default::default()
+        };
+        let v1 = PorcelainVersion::V1;
+        let v2 = PorcelainVersion::V2;
+        assert_eq!(v1.status(Some(InstallStatus::Copied)), "linked");
+        assert_eq!(v2.status(Some(InstallStatus::Copied)), "copied");
+        assert_eq!(v1.status(Some(InstallStatus::Conflict)), "conflict");
+        assert_eq!(v2.status(None), "unknown");
+        assert_eq!(v1.tags(&copy), vec!["shell"]);
+        assert_eq!(v2.tags(&copy), vec!["copy", "shell"]);
+        assert_eq!(v1.tags(&template), vec!["template"]);
+        assert_eq!(v2.tags(&template), vec!["template"]);
+    }
+}
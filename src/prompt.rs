@@ -0,0 +1,411 @@
+//! How interactive prompts look and what they default to; the `[prompts]`
+//! table in the config file, e.g.
+//!
+//! ```toml
+//! [prompts]
+//! # Don't overwrite files when Enter is pressed.
+//! destructive_default = false
+//! # Move files in dotfiles' places aside without asking.
+//! on_conflict = "backup"
+//!
+//! [prompts.styles]
+//! # Brighter than the default `dim` on a dark terminal.
+//! defaults = "white"
+//! values = "yellow.bold"
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+use console::Style;
+#[cfg(feature = "interactive")]
+use dialoguer::theme::{ColorfulTheme, SimpleTheme, Theme};
+#[cfg(feature = "interactive")]
+use dialoguer::Select;
+use serde::Deserialize;
+use toml_edit::{Document, Item, Table};
+
+use crate::config::ConfigReadError;
+
+/// Options for interactive prompts.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptOptions {
+    /// `colorful`, the default, or `plain`, which doesn't style prompts at
+    /// all.
+    pub theme: PromptTheme,
+    /// Styles for the parts of the `colorful` theme.
+    pub styles: PromptStyles,
+    /// The answer to questions which replace or delete files, like whether
+    /// to overwrite a file in a dotfile's place, when Enter is pressed;
+    /// default `true`.
+    pub destructive_default: Option<bool>,
+    /// What to do with files in dotfiles' places; default `ask`.
+    pub on_conflict: ConflictAction,
+}
+
+impl PromptOptions {
+    /// The theme to show prompts with.
+    #[cfg(feature = "interactive")]
+    pub fn theme(&self) -> Box<dyn Theme> {
+        match self.theme {
+            PromptTheme::Colorful => Box::new(self.styles.colorful_theme()),
+            PromptTheme::Plain => Box::new(SimpleTheme),
+        }
+    }
+
+    /// The default answer to questions which replace or delete files.
+    pub fn destructive_default(&self) -> bool {
+        self.destructive_default.unwrap_or(true)
+    }
+}
+
+/// What to do with a file in a dotfile's place.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictAction {
+    #[default]
+    Ask,
+    Overwrite,
+    /// Move the file aside, then overwrite it; see
+    /// `AbsDotfile::back_up`.
+    Backup,
+    Skip,
+}
+
+impl fmt::Display for ConflictAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictAction::Ask => write!(f, "ask"),
+            ConflictAction::Overwrite => write!(f, "overwrite"),
+            ConflictAction::Backup => write!(f, "backup"),
+            ConflictAction::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+/// Asks what to do with files in dotfiles' places during a run, and
+/// remembers answers like "overwrite all" for the rest of it.
+pub struct ConflictPrompt {
+    #[cfg_attr(not(feature = "interactive"), allow(dead_code))]
+    options: PromptOptions,
+    /// The answer for every conflict, from `on_conflict` or an "all"
+    /// answer.
+    all: Option<ConflictAction>,
+    /// Whether `all` was answered during this run.
+    answered_all: bool,
+}
+
+impl ConflictPrompt {
+    pub fn new(options: &PromptOptions) -> Self {
+        Self {
+            options: options.clone(),
+            all: match options.on_conflict {
+                ConflictAction::Ask => None,
+                action => Some(action),
+            },
+            answered_all: false,
+        }
+    }
+
+    /// What to do about the conflict in `question`, e.g. `Overwrite
+    /// ~/.bashrc?`. Never `Ask`; `Skip` if there isn't a terminal to ask on,
+    /// or this build can't ask.
+    pub fn ask(&mut self, question: &str) -> io::Result<ConflictAction> {
+        if let Some(action) = self.all {
+            return Ok(action);
+        }
+        // There's nobody to ask, e.g. in a script or a cron job.
+        if !io::stdin().is_terminal() || !console::Term::stderr().is_term() {
+            return Ok(ConflictAction::Skip);
+        }
+        let (action, all) = self.choose(question)?;
+        if all {
+            self.all = Some(action);
+            self.answered_all = true;
+        }
+        Ok(action)
+    }
+
+    /// Ask `question`; the answer, and whether it's for all the remaining
+    /// conflicts.
+    #[cfg(feature = "interactive")]
+    fn choose(&self, question: &str) -> io::Result<(ConflictAction, bool)> {
+        let choices = [
+            (ConflictAction::Overwrite, false, "Overwrite"),
+            (ConflictAction::Backup, false, "Back up, then overwrite"),
+            (ConflictAction::Skip, false, "Skip"),
+            (ConflictAction::Overwrite, true, "Overwrite all"),
+            (ConflictAction::Backup, true, "Back up all"),
+            (ConflictAction::Skip, true, "Skip all remaining"),
+        ];
+        let labels = choices
+            .iter()
+            .map(|(_, _, label)| *label)
+            .collect::<Vec<_>>();
+        let default = if self.options.destructive_default() {
+            0
+        } else {
+            2
+        };
+        let (action, all, _) = choices[Select::with_theme(self.options.theme().as_ref())
+            .with_prompt(question)
+            .items(&labels)
+            .default(default)
+            .interact()?];
+        Ok((action, all))
+    }
+
+    #[cfg(not(feature = "interactive"))]
+    fn choose(&self, _question: &str) -> io::Result<(ConflictAction, bool)> {
+        Ok((ConflictAction::Skip, false))
+    }
+
+    /// The "all" answer given during this run, if any, to offer to save as
+    /// `on_conflict`.
+    pub fn answered_all(&self) -> Option<ConflictAction> {
+        self.all.filter(|_| self.answered_all)
+    }
+}
+
+/// The text of a config file, `config`, with `prompts.on_conflict` set to
+/// `action`, however `prompts` is written; comments and the rest of the file
+/// are left alone.
+pub fn with_on_conflict(config: &str, action: ConflictAction) -> Result<String, ConfigReadError> {
+    let mut doc = config.parse::<Document>()?;
+    let action = action.to_string();
+    match doc.get_mut("prompts").and_then(Item::as_table_like_mut) {
+        Some(prompts) => match prompts.get_mut("on_conflict").and_then(Item::as_value_mut) {
+            // Keep the comment after the old value, if there is one.
+            Some(value) => {
+                let decor = value.decor().clone();
+                *value = action.into();
+                *value.decor_mut() = decor;
+            }
+            None => {
+                prompts.insert("on_conflict", toml_edit::value(action));
+            }
+        },
+        None => {
+            let mut prompts = Table::new();
+            prompts["on_conflict"] = toml_edit::value(action);
+            if !config.trim().is_empty() {
+                prompts.decor_mut().set_prefix("\n");
+            }
+            doc["prompts"] = Item::Table(prompts);
+        }
+    }
+    // Inline tables can't hold comments, so there's nothing to lose by
+    // tidying up the spacing around the new value.
+    if let Some(prompts) = doc["prompts"].as_inline_table_mut() {
+        prompts.fmt();
+    }
+    Ok(doc.to_string())
+}
+
+/// The prompts' look.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PromptTheme {
+    #[default]
+    Colorful,
+    Plain,
+}
+
+/// Styles for the parts of the `colorful` theme, written like
+/// `yellow.bold` or `black.on_white`: colors, `on_` background colors,
+/// `bold`, `dim`, `underlined`, `blink`, `reverse` and `hidden`, separated
+/// by dots. Unset parts keep the default style.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptStyles {
+    /// Default answers; default `dim`.
+    pub defaults: Option<PromptStyle>,
+    /// Errors; default `red`.
+    pub error: Option<PromptStyle>,
+    /// The cursor and checkmarks; default `cyan.bold`.
+    pub indicator: Option<PromptStyle>,
+    /// Items which aren't selected; default `dim`.
+    pub inactive: Option<PromptStyle>,
+    /// The selected item; default unstyled.
+    pub active: Option<PromptStyle>,
+    /// "Yes" answers; default `green`.
+    pub yes: Option<PromptStyle>,
+    /// "No" answers; default `green`.
+    pub no: Option<PromptStyle>,
+    /// Answers and other values; default `cyan`.
+    pub values: Option<PromptStyle>,
+}
+
+impl PromptStyles {
+    #[cfg(feature = "interactive")]
+    fn colorful_theme(&self) -> ColorfulTheme {
+        let default = ColorfulTheme::default();
+        let style = |style: &Option<PromptStyle>, default: Style| {
+            style.as_ref().map_or(default, |style| style.0.clone())
+        };
+        ColorfulTheme {
+            defaults_style: style(&self.defaults, default.defaults_style),
+            error_style: style(&self.error, default.error_style),
+            indicator_style: style(&self.indicator, default.indicator_style),
+            inactive_style: style(&self.inactive, default.inactive_style),
+            active_style: style(&self.active, default.active_style),
+            yes_style: style(&self.yes, default.yes_style),
+            no_style: style(&self.no, default.no_style),
+            values_style: style(&self.values, default.values_style),
+        }
+    }
+}
+
+/// A style in a `PromptStyles`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub struct PromptStyle(Style);
+
+// `Style` isn't `PartialEq`, so styles are compared by how they render.
+impl PartialEq for PromptStyle {
+    fn eq(&self, other: &Self) -> bool {
+        let render = |style: &Style| style.apply_to("x").force_styling(true).to_string();
+        render(&self.0) == render(&other.0)
+    }
+}
+
+impl FromStr for PromptStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // `Style::from_dotted_str` skips words it doesn't know.
+        let known = |part: &str| {
+            let color = part.strip_prefix("on_").unwrap_or(part);
+            [
+                "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+            ]
+            .contains(&color)
+                || ["bold", "dim", "underlined", "blink", "reverse", "hidden"].contains(&part)
+        };
+        match s.split('.').find(|part| !known(part)) {
+            Some(part) => Err(format!("unknown style `{}` in `{}`", part, s)),
+            None => Ok(PromptStyle(Style::from_dotted_str(s))),
+        }
+    }
+}
+
+impl TryFrom<String> for PromptStyle {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn prompt_options() {
+        let opts: PromptOptions = toml::from_str(indoc!(
+            r#"
+            theme = "plain"
+            destructive_default = false
+
+            [styles]
+            values = "yellow.bold"
+            "#
+        ))
+        .unwrap();
+        assert_eq!(opts.theme, PromptTheme::Plain);
+        assert!(!opts.destructive_default());
+        assert_eq!(opts.styles.values, Some("bold.yellow".parse().unwrap()));
+        assert_ne!(opts.styles.values, Some("yellow".parse().unwrap()));
+        assert!(PromptOptions::default().destructive_default());
+
+        assert_eq!(
+            "yellow.shiny".parse::<PromptStyle>(),
+            Err("unknown style `shiny` in `yellow.shiny`".to_string())
+        );
+        assert!(toml::from_str::<PromptOptions>("styles = { values = \"purple\" }").is_err());
+    }
+
+    #[test]
+    fn prompt_conflicts() {
+        let mut prompt = ConflictPrompt::new(&PromptOptions {
+            on_conflict: ConflictAction::Skip,
+            ..Default::default()
+        });
+        assert_eq!(
+            prompt.ask("Overwrite ~/.bashrc?").unwrap(),
+            ConflictAction::Skip
+        );
+        // Only answers given during the run are offered to be saved.
+        assert_eq!(prompt.answered_all(), None);
+    }
+
+    #[test]
+    fn prompt_with_on_conflict() {
+        let with = |config: &str, action| with_on_conflict(config, action).unwrap();
+        assert_eq!(
+            with("", ConflictAction::Skip),
+            "[prompts]\non_conflict = \"skip\"\n"
+        );
+        assert_eq!(
+            with(
+                indoc!(
+                    r#"
+                    dotfile_repo = ".dotfiles"
+                    "#
+                ),
+                ConflictAction::Backup
+            ),
+            indoc!(
+                r#"
+                dotfile_repo = ".dotfiles"
+
+                [prompts]
+                on_conflict = "backup"
+                "#
+            )
+        );
+        let config = indoc!(
+            r#"
+            [prompts]  # How dfm asks.
+            theme = "plain"
+            on_conflict = "ask"
+            on_conflict_note = "not this one"
+
+            [prompts.styles]
+            on_conflict = "not this one either"
+            "#
+        );
+        assert_eq!(
+            with(config, ConflictAction::Overwrite),
+            config.replace(r#"on_conflict = "ask""#, r#"on_conflict = "overwrite""#)
+        );
+        assert_eq!(
+            with("[prompts]\ntheme = \"plain\"\n", ConflictAction::Skip),
+            "[prompts]\ntheme = \"plain\"\non_conflict = \"skip\"\n"
+        );
+        assert_eq!(
+            with("prompts.theme = \"plain\"\n", ConflictAction::Skip),
+            "prompts.theme = \"plain\"\nprompts.on_conflict = \"skip\"\n"
+        );
+        assert_eq!(
+            with(
+                "prompts.on_conflict = \"ask\" # Not yet.\n",
+                ConflictAction::Skip
+            ),
+            "prompts.on_conflict = \"skip\" # Not yet.\n"
+        );
+        assert_eq!(
+            with("prompts = { theme = \"plain\" }\n", ConflictAction::Skip),
+            "prompts = { theme = \"plain\", on_conflict = \"skip\" }\n"
+        );
+        assert!(with_on_conflict("[prompts", ConflictAction::Skip).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+//! Backends for collecting the system facts in `Facts`.
+//!
+//! The default `StdProvider` only needs the standard library, a couple of
+//! small crates, and the ubiquitous `uname` and `who` commands. With the
+//! `heim-facts` feature, `HeimProvider` is used instead; it doesn't shell out,
+//! but it pulls in a large async stack.
+//!
+//! Each category of facts can be collected on its own, when it's first
+//! needed, or all of them at once with `FactsProvider::collect`, which
+//! collects them concurrently.
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::process::Command;
+use std::thread;
+
+use thiserror::Error;
+
+use crate::facts::{Nic, PlatformInfo, User};
+
+/// An error while collecting system facts.
+#[derive(Error, Debug)]
+pub enum ProviderError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("`{command}` failed: {stderr}")]
+    CommandFailed { command: String, stderr: String },
+
+    #[cfg(feature = "heim-facts")]
+    #[error("{0}")]
+    Heim(#[from] heim::Error),
+}
+
+/// Every category of system facts: users, networks, and platform
+/// information.
+pub type Collected = (HashMap<String, User>, HashMap<String, Nic>, PlatformInfo);
+
+/// A source of system facts; see `Facts::with_provider`.
+pub trait FactsProvider: fmt::Debug + Send + Sync {
+    /// The users logged in to the system, keyed by username.
+    fn users(&self) -> Result<HashMap<String, User>, ProviderError>;
+
+    /// The system's network interfaces, keyed by name.
+    fn networks(&self) -> Result<HashMap<String, Nic>, ProviderError>;
+
+    /// Information about the operating system.
+    fn platform(&self) -> Result<PlatformInfo, ProviderError>;
+
+    /// Every category of facts, collected concurrently; see
+    /// `Facts::collect`. By default, users and networks are each collected
+    /// on their own thread while the platform is collected on this one.
+    fn collect(&self) -> Result<Collected, ProviderError> {
+        thread::scope(|scope| {
+            let users = scope.spawn(|| self.users());
+            let networks = scope.spawn(|| self.networks());
+            let platform = self.platform();
+            Ok((join(users)?, join(networks)?, platform?))
+        })
+    }
+}
+
+/// The result of the thread `handle`, passing on its panic if it panicked.
+fn join<T>(handle: thread::ScopedJoinHandle<'_, T>) -> T {
+    handle
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+/// The provider selected by this build's features.
+pub fn default_provider() -> Box<dyn FactsProvider> {
+    #[cfg(feature = "heim-facts")]
+    return Box::new(HeimProvider);
+    #[cfg(not(feature = "heim-facts"))]
+    return Box::new(StdProvider);
+}
+
+/// A provider built on the standard library.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdProvider;
+
+impl FactsProvider for StdProvider {
+    fn users(&self) -> Result<HashMap<String, User>, ProviderError> {
+        let mut users: HashMap<String, User> = if cfg!(unix) {
+            parse_who(&command_output("who", &[])?)
+                .into_iter()
+                .map(|username| (username.to_string(), User::from(username)))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        // `who` reads utmp, which is often empty in containers and over some
+        // remote sessions, but we know at least one user is logged in.
+        if users.is_empty() {
+            let username = whoami::username();
+            users.insert(username.clone(), username.as_str().into());
+        }
+        Ok(users)
+    }
+
+    fn networks(&self) -> Result<HashMap<String, Nic>, ProviderError> {
+        let mut networks: HashMap<String, Nic> = HashMap::new();
+        // `get_if_addrs` yields one `Interface` per address, so merge them
+        // by interface name.
+        for iface in if_addrs::get_if_addrs()? {
+            match networks.get_mut(&iface.name) {
+                Some(nic) => nic.addresses.push(iface.ip()),
+                None => {
+                    networks.insert(
+                        iface.name.clone(),
+                        Nic {
+                            is_up: interface_is_up(&iface.name),
+                            is_loopback: iface.is_loopback(),
+                            addresses: vec![iface.ip()],
+                            name: iface.name,
+                        },
+                    );
+                }
+            }
+        }
+        Ok(networks)
+    }
+
+    fn platform(&self) -> Result<PlatformInfo, ProviderError> {
+        let (system, release, version) = if cfg!(unix) {
+            (
+                command_output("uname", &["-s"])?,
+                command_output("uname", &["-r"])?,
+                command_output("uname", &["-v"])?,
+            )
+        } else {
+            ("Windows".to_string(), String::new(), whoami::os())
+        };
+        Ok(PlatformInfo {
+            system,
+            release,
+            version,
+            hostname: whoami::hostname(),
+            arch: env::consts::ARCH.to_string(),
+        })
+    }
+}
+
+/// The trimmed standard output of a successful command.
+fn command_output(program: &str, args: &[&str]) -> Result<String, ProviderError> {
+    let output = Command::new(program).args(args).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(ProviderError::CommandFailed {
+            command: std::iter::once(program)
+                .chain(args.iter().copied())
+                .collect::<Vec<_>>()
+                .join(" "),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
+    }
+}
+
+/// The usernames in the output of `who`, deduplicated.
+fn parse_who(who: &str) -> Vec<&str> {
+    let mut users: Vec<&str> = who
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+    users.sort_unstable();
+    users.dedup();
+    users
+}
+
+/// Whether the interface named `name` is up. `getifaddrs` doesn't tell us, so
+/// this reads the interface flags from sysfs where it's available and assumes
+/// interfaces with addresses are up everywhere else.
+fn interface_is_up(name: &str) -> bool {
+    const IFF_UP: u32 = 0x1;
+    fs::read_to_string(format!("/sys/class/net/{}/flags", name))
+        .ok()
+        .and_then(|flags| u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok())
+        .map(|flags| flags & IFF_UP != 0)
+        .unwrap_or(true)
+}
+
+#[cfg(feature = "heim-facts")]
+pub use self::heim_provider::HeimProvider;
+
+#[cfg(feature = "heim-facts")]
+mod heim_provider {
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+
+    use futures::executor::block_on;
+    use futures::stream::TryStreamExt;
+    use heim::net::Address;
+
+    use super::{Collected, FactsProvider, ProviderError};
+    use crate::facts::{Nic, PlatformInfo, User};
+
+    /// A provider built on heim.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct HeimProvider;
+
+    impl FactsProvider for HeimProvider {
+        fn users(&self) -> Result<HashMap<String, User>, ProviderError> {
+            block_on(users())
+        }
+
+        fn networks(&self) -> Result<HashMap<String, Nic>, ProviderError> {
+            block_on(networks())
+        }
+
+        fn platform(&self) -> Result<PlatformInfo, ProviderError> {
+            block_on(platform())
+        }
+
+        /// heim is async, so there's no need for threads: every category
+        /// is collected at once on this one.
+        fn collect(&self) -> Result<Collected, ProviderError> {
+            block_on(async { futures::try_join!(users(), networks(), platform()) })
+        }
+    }
+
+    async fn users() -> Result<HashMap<String, User>, ProviderError> {
+        Ok(heim::host::users()
+            .map_ok(|u| (u.username().to_string(), u.username().into()))
+            .try_collect()
+            .await?)
+    }
+
+    async fn networks() -> Result<HashMap<String, Nic>, ProviderError> {
+        let mut networks: HashMap<String, Nic> = HashMap::new();
+        let nics = heim::net::nic();
+        futures::pin_mut!(nics);
+        // heim yields one `Nic` per address, so merge them by interface name.
+        while let Some(nic) = nics.try_next().await? {
+            let addresses: Vec<IpAddr> = ip_addr(nic.address()).into_iter().collect();
+            match networks.get_mut(nic.name()) {
+                Some(existing) => existing.addresses.extend(addresses),
+                None => {
+                    networks.insert(
+                        nic.name().to_string(),
+                        Nic {
+                            name: nic.name().to_string(),
+                            addresses,
+                            is_up: nic.is_up(),
+                            is_loopback: nic.is_loopback(),
+                        },
+                    );
+                }
+            }
+        }
+        Ok(networks)
+    }
+
+    async fn platform() -> Result<PlatformInfo, ProviderError> {
+        let p = heim::host::platform().await?;
+        Ok(PlatformInfo {
+            system: p.system().to_string(),
+            release: p.release().to_string(),
+            version: p.version().to_string(),
+            hostname: p.hostname().to_string(),
+            arch: p.architecture().as_str().to_string(),
+        })
+    }
+
+    fn ip_addr(address: Address) -> Option<IpAddr> {
+        match address {
+            Address::Inet(addr) | Address::Inet6(addr) => Some(addr.ip()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn who() {
+        assert_eq!(
+            parse_who(
+                "becca    tty7         2020-02-20 09:41 (:0)\n\
+                 root     pts/1        2020-02-20 10:02 (10.0.0.2)\n\
+                 becca    pts/0        2020-02-20 09:42 (:0)\n"
+            ),
+            vec!["becca", "root"]
+        );
+        assert_eq!(parse_who(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn std_provider() {
+        let platform = StdProvider.platform().unwrap();
+        assert!(!platform.system.is_empty());
+        assert_eq!(platform.arch, env::consts::ARCH);
+        assert!(!StdProvider.users().unwrap().is_empty());
+        StdProvider.networks().unwrap();
+
+        let (users, _, collected) = StdProvider.collect().unwrap();
+        assert!(!users.is_empty());
+        assert_eq!(collected.hostname, platform.hostname);
+    }
+}
@@ -0,0 +1,102 @@
+//! What `dfm link` did, summarized at the end of the run, e.g. `12 linked,
+//! 3 already ok, 2 skipped (conditions), 1 conflict unresolved`.
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// Counts of what happened to each dotfile during `dfm link`.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkReport {
+    /// Dotfiles which were linked.
+    pub linked: usize,
+    /// Templates which were rendered.
+    pub rendered: usize,
+    /// Dotfiles which were already linked, or templates whose rendered
+    /// output hadn't changed.
+    pub already_ok: usize,
+    /// Dotfiles whose `when` conditions are false on this machine.
+    pub skipped_conditions: usize,
+    /// Dotfiles which need administrative privileges we don't have.
+    pub skipped_privileged: usize,
+    /// Dotfiles in submodules which aren't initialized.
+    pub skipped_submodules: usize,
+    /// Dotfiles which weren't linked because something else is in the way
+    /// and it wasn't overwritten.
+    pub conflicts: usize,
+}
+
+impl LinkReport {
+    /// Whether every dotfile which applies to this machine is installed.
+    pub fn is_success(&self) -> bool {
+        self.conflicts == 0 && self.skipped_submodules == 0
+    }
+}
+
+impl fmt::Display for LinkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let conflicts = if self.conflicts == 1 {
+            "conflict unresolved"
+        } else {
+            "conflicts unresolved"
+        };
+        let parts = [
+            (self.linked, "linked"),
+            (self.rendered, "rendered"),
+            (self.already_ok, "already ok"),
+            (self.skipped_conditions, "skipped (conditions)"),
+            (self.skipped_privileged, "skipped (privileges)"),
+            (self.skipped_submodules, "skipped (submodules)"),
+            (self.conflicts, conflicts),
+        ]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, what)| format!("{} {}", count, what))
+        .collect::<Vec<_>>();
+        if parts.is_empty() {
+            write!(f, "no dotfiles")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn link_report_summary() {
+        assert_eq!(LinkReport::default().to_string(), "no dotfiles");
+        let report = LinkReport {
+            linked: 12,
+            already_ok: 3,
+            skipped_conditions: 2,
+            conflicts: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            report.to_string(),
+            "12 linked, 3 already ok, 2 skipped (conditions), 1 conflict unresolved"
+        );
+        assert!(!report.is_success());
+        let report = LinkReport {
+            rendered: 1,
+            skipped_privileged: 2,
+            conflicts: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            report.to_string(),
+            "1 rendered, 2 skipped (privileges), 2 conflicts unresolved"
+        );
+        assert!(LinkReport {
+            linked: 1,
+            skipped_conditions: 1,
+            ..Default::default()
+        }
+        .is_success());
+    }
+}
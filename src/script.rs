@@ -0,0 +1,139 @@
+//! Rhai scripts (<https://rhai.rs>), for dotfiles lists written in Rhai and
+//! for `script:` conditions, e.g.
+//!
+//! ```toml
+//! when = "script: facts.os == \"linux\" && facts.users.len() > 1"
+//! ```
+//!
+//! Scripts see every fact as `facts`, like Nix files do, and can read
+//! environment variables with `env(name)`, which returns `()` if `name` is
+//! unset. They can't touch the filesystem or run commands, and they're
+//! stopped if they run for too long.
+//!
+//! This needs the `rhai` feature.
+
+use std::env;
+use std::io::Read;
+use std::path::Path;
+
+use rhai::{Dynamic, Engine, EvalAltResult, Scope};
+use thiserror::Error;
+
+use crate::config::DotfilesReadError;
+use crate::dotfile::SerdeDotfile;
+use crate::facts::{Facts, FactsError, FactsModel};
+use crate::loader::ListLoader;
+
+/// How many operations a script may perform before it's stopped.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// An error while running a Rhai script.
+#[derive(Error, Debug)]
+pub enum ScriptError {
+    #[error("{0}")]
+    Rhai(#[from] Box<EvalAltResult>),
+
+    #[error("{0}")]
+    Facts(#[from] FactsError),
+}
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.register_fn("env", |name: &str| -> Dynamic {
+        env::var(name).map(Dynamic::from).unwrap_or(Dynamic::UNIT)
+    });
+    engine
+}
+
+fn scope(facts: &Facts) -> Result<Scope<'static>, ScriptError> {
+    let model = FactsModel {
+        env: Default::default(),
+        ..facts.model()?
+    };
+    let mut scope = Scope::new();
+    scope.push_constant("facts", rhai::serde::to_dynamic(&model)?);
+    Ok(scope)
+}
+
+/// Evaluate a `script:` condition, without the `script:` prefix.
+pub fn eval_condition(script: &str, facts: &Facts) -> Result<bool, ScriptError> {
+    Ok(engine().eval_with_scope::<bool>(&mut scope(facts)?, script)?)
+}
+
+/// Evaluate a script which returns a dotfiles list.
+pub fn eval_list(script: &str, facts: &Facts) -> Result<Vec<SerdeDotfile>, ScriptError> {
+    let list: Dynamic = engine().eval_with_scope(&mut scope(facts)?, script)?;
+    Ok(rhai::serde::from_dynamic(&list)?)
+}
+
+/// Runs Rhai dotfiles lists, which return an array of paths and object maps,
+/// e.g. `[".bashrc", #{ repo: "nvim", installed: ".config/nvim" }]`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RhaiLoader;
+
+impl ListLoader for RhaiLoader {
+    fn name(&self) -> &str {
+        "rhai"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["rhai"]
+    }
+
+    /// Scripts only see the built-in facts; use `load` to give them
+    /// user-defined facts too.
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let mut script = String::new();
+        reader.read_to_string(&mut script)?;
+        Ok(eval_list(&script, &Facts::new())?)
+    }
+
+    fn load(&self, path: &Path, facts: &Facts) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let mut script = String::new();
+        std::fs::File::open(path)?.read_to_string(&mut script)?;
+        Ok(eval_list(&script, facts)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::dotfile::Dotfile;
+
+    fn facts() -> Facts {
+        Facts::new().with_vars(
+            &vec![("email".to_string(), "me@example.com".to_string())]
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn rhai_condition() {
+        assert!(eval_condition(r#"facts.vars.email.ends_with("example.com")"#, &facts()).unwrap());
+        assert!(!eval_condition(r#"env("DFM_SURELY_UNSET") != ()"#, &facts()).unwrap());
+        assert!(eval_condition("1 + 1", &facts()).is_err());
+        assert!(eval_condition("loop {}", &facts()).is_err());
+    }
+
+    #[test]
+    fn rhai_list() {
+        assert_eq!(
+            RhaiLoader
+                .load(Path::new("test-data/dotfiles-rhai.rhai"), &facts())
+                .unwrap(),
+            vec![
+                SerdeDotfile::Path(".bashrc".into()),
+                SerdeDotfile::Advanced(Dotfile {
+                    repo: "gitconfig".into(),
+                    installed: Some(".gitconfig".into()),
+                    template: true,
+                    ..Default::default()
+                }),
+            ]
+        );
+    }
+}
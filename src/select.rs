@@ -0,0 +1,118 @@
+//! Choosing some of a list of items interactively, like the dotfiles to
+//! install with `dfm link --interactive`: a search narrows the list down,
+//! then the items to use are checked off.
+//!
+//! The search is fuzzy: it matches items containing its characters in
+//! order, so `nvinit` finds `~/.config/nvim/init.lua`. Asking needs the
+//! `interactive` feature.
+
+#[cfg(feature = "interactive")]
+use std::io;
+
+#[cfg(feature = "interactive")]
+use dialoguer::{theme::Theme, Checkboxes, Input};
+
+/// How well `query` matches `item`, if it does; lower is better.
+///
+/// The query matches if its characters (other than whitespace) appear in
+/// the item in order, ignoring case. Each character skipped between the
+/// first and last matched characters makes the match worse.
+pub fn fuzzy_score(query: &str, item: &str) -> Option<usize> {
+    let mut item = item.chars().flat_map(char::to_lowercase);
+    let mut score = 0;
+    let mut started = false;
+    for wanted in query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+    {
+        loop {
+            let c = item.next()?;
+            if c == wanted {
+                break;
+            }
+            if started {
+                score += 1;
+            }
+        }
+        started = true;
+    }
+    Some(score)
+}
+
+/// The indices of the `items` which match `query`, best match first; an
+/// empty query matches everything.
+pub fn filter(query: &str, items: &[String]) -> Vec<usize> {
+    let mut matches = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| fuzzy_score(query, item).map(|score| (score, i)))
+        .collect::<Vec<_>>();
+    matches.sort();
+    matches.into_iter().map(|(_, i)| i).collect()
+}
+
+/// Ask which of the `items` to use: first for a search to narrow them down,
+/// then to check off the ones to use. Returns their indices, in order.
+#[cfg(feature = "interactive")]
+pub fn choose(theme: &dyn Theme, prompt: &str, items: &[String]) -> io::Result<Vec<usize>> {
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+    let matches = loop {
+        let query = Input::<String>::with_theme(theme)
+            .with_prompt("Search (empty for all)")
+            .allow_empty(true)
+            .interact()?;
+        let matches = filter(&query, items);
+        if !matches.is_empty() {
+            break matches;
+        }
+        eprintln!("Nothing matches `{}`", query);
+    };
+    let shown = matches.iter().map(|&i| &items[i]).collect::<Vec<_>>();
+    let mut chosen = Checkboxes::with_theme(theme)
+        .with_prompt(prompt)
+        .items(&shown)
+        .interact()?
+        .into_iter()
+        .map(|i| matches[i])
+        .collect::<Vec<_>>();
+    chosen.sort_unstable();
+    Ok(chosen)
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn select_fuzzy_score() {
+        assert_eq!(fuzzy_score("", "~/.bashrc"), Some(0));
+        assert_eq!(fuzzy_score("bashrc", "~/.bashrc"), Some(0));
+        assert_eq!(fuzzy_score("BashRC", "~/.bashrc"), Some(0));
+        assert_eq!(fuzzy_score("nv init", "~/.config/nvim/init.lua"), Some(8));
+        assert_eq!(fuzzy_score("bashrc", "~/.bash_profile"), None);
+        assert_eq!(fuzzy_score("rcbash", "~/.bashrc"), None);
+    }
+
+    #[test]
+    fn select_filter() {
+        let items = [
+            "~/.bash_profile (bash_profile)",
+            "~/.bashrc (bashrc)",
+            "~/.config/nvim/init.lua (nvim/init.lua)",
+            "~/.gitconfig (gitconfig)",
+        ]
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(filter("", &items), vec![0, 1, 2, 3]);
+        assert_eq!(filter("bashrc", &items), vec![1]);
+        assert_eq!(filter("git", &items), vec![3, 2]);
+        assert_eq!(filter("config", &items), vec![2, 3]);
+        assert!(filter("zsh", &items).is_empty());
+    }
+}
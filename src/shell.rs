@@ -0,0 +1,139 @@
+//! Shell startup snippets; see `Dotfile::shell`.
+//!
+//! Snippets are installed as numbered files in `SNIPPET_DIR`, and a block
+//! which sources them in order is kept in `.bashrc` and `.zshrc`, so
+//! several parts of the dotfiles can add to the shell's startup without
+//! editing the same file. If an rc file is itself a dotfile, the block is
+//! written to it in the dotfile repository.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::util;
+
+/// Where snippets are installed, relative to the home directory.
+pub const SNIPPET_DIR: &str = ".config/dfm/shell.d";
+
+const BEGIN: &str = "# BEGIN dotfile-manager: changes between these lines are overwritten";
+const END: &str = "# END dotfile-manager";
+
+/// The rc files the block is kept in, relative to the home directory, and
+/// the extensions of the snippets they source, besides `.sh`.
+const RC_FILES: &[(&str, &str)] = &[(".bashrc", "bash"), (".zshrc", "zsh")];
+
+/// Where a snippet `repo` with the position `order` is installed, relative
+/// to the home directory, e.g. `.config/dfm/shell.d/10-aliases.sh`.
+pub fn snippet_path(repo: &Path, order: u32) -> PathBuf {
+    Path::new(SNIPPET_DIR).join(format!(
+        "{:02}-{}",
+        order,
+        repo.file_name().unwrap_or_default().to_string_lossy()
+    ))
+}
+
+/// The lines of the block sourcing the snippets for the shell whose
+/// snippets have the extension `extension`.
+fn source_block(extension: &str) -> Vec<String> {
+    vec![
+        format!("for dfm_snippet in \"$HOME\"/{}/*; do", SNIPPET_DIR),
+        "    case \"$dfm_snippet\" in".into(),
+        format!("        *.sh|*.{}) . \"$dfm_snippet\" ;;", extension),
+        "    esac".into(),
+        "done".into(),
+        "unset dfm_snippet".into(),
+    ]
+}
+
+/// `rc` with the block sourcing snippets added, or removed if
+/// `has_snippets` is false.
+fn rc_with_block(rc: &str, extension: &str, has_snippets: bool) -> String {
+    let lines = if has_snippets {
+        source_block(extension)
+    } else {
+        Vec::new()
+    };
+    util::with_block(rc, BEGIN, END, &lines)
+}
+
+/// The rc files in `home` which need the block added (or removed, if
+/// `has_snippets` is false), and their new contents. rc files which don't
+/// exist are left alone.
+pub fn rc_updates(home: &Path, has_snippets: bool) -> io::Result<Vec<(PathBuf, String)>> {
+    let mut updates = Vec::new();
+    for (name, extension) in RC_FILES {
+        let path = home.join(name);
+        let rc = match fs::read_to_string(&path) {
+            Ok(rc) => rc,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        let updated = rc_with_block(&rc, extension, has_snippets);
+        if updated != rc {
+            updates.push((path, updated));
+        }
+    }
+    Ok(updates)
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::process::Command;
+
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn shell_snippets() {
+        assert_eq!(
+            snippet_path(Path::new("shell/aliases.sh"), 5),
+            Path::new(".config/dfm/shell.d/05-aliases.sh")
+        );
+
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let home = root.join("home");
+        let snippets = home.join(SNIPPET_DIR);
+        fs::create_dir_all(&snippets).unwrap();
+        fs::write(snippets.join("10-path.sh"), "order=\"$order path\"\n").unwrap();
+        fs::write(snippets.join("05-env.bash"), "order=\"$order env\"\n").unwrap();
+        fs::write(snippets.join("20-prompt.zsh"), "order=\"$order prompt\"\n").unwrap();
+        fs::write(home.join(".bashrc"), "export EDITOR=vim\n").unwrap();
+
+        let updates = rc_updates(&home, true).unwrap();
+        assert_eq!(updates.len(), 1);
+        let (path, bashrc) = &updates[0];
+        assert_eq!(path, &home.join(".bashrc"));
+        fs::write(path, bashrc).unwrap();
+        let order = Command::new("sh")
+            .arg("-c")
+            .arg(format!(". {}; echo $order", path.display()))
+            .env("HOME", &home)
+            .output()
+            .unwrap();
+        let unchanged = rc_updates(&home, true).unwrap();
+        let removed = rc_updates(&home, false).unwrap();
+
+        assert!(bashrc.starts_with("export EDITOR=vim\n# BEGIN dotfile-manager"));
+        assert_eq!(String::from_utf8_lossy(&order.stdout), "env path\n");
+        assert_eq!(unchanged, vec![]);
+        assert_eq!(
+            removed,
+            vec![(home.join(".bashrc"), "export EDITOR=vim\n".to_string())]
+        );
+    }
+
+    #[test]
+    fn shell_rc_missing_end() {
+        // The `END` line was deleted, so the lines after `BEGIN` are the
+        // user's, and must survive.
+        let rc = format!("{}\nalias ll='ls -l'\nexport EDITOR=vim\n", BEGIN);
+        let updated = rc_with_block(&rc, "bash", true);
+        assert!(updated.starts_with(&rc));
+        assert!(updated.ends_with(&format!("{}\n", END)));
+        assert_eq!(rc_with_block(&updated, "bash", true), updated);
+        assert_eq!(rc_with_block(&rc, "bash", false), rc);
+    }
+}
@@ -0,0 +1,76 @@
+//! `dfm status --short`, a summary of the dotfiles' status small enough to
+//! show in a shell prompt, e.g. `dfm:2!` when two dotfiles are missing or
+//! have something else in their place, or nothing when they're all
+//! installed:
+//!
+//! ```sh
+//! PS1='$(dfm status --short) \$ '
+//! ```
+//!
+//! A prompt can't wait on dfm, so checking has a hard time budget. If it
+//! runs out, the last status which was checked in time is shown instead,
+//! from a cache file; if there isn't one, `dfm:?`.
+
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::util;
+
+/// How long checking may take.
+pub const BUDGET: Duration = Duration::from_millis(200);
+
+/// The short status, given how many dotfiles aren't installed, if that's
+/// known.
+pub fn render(drifted: Option<usize>) -> String {
+    match drifted {
+        Some(0) => String::new(),
+        Some(drifted) => format!("dfm:{}!", drifted),
+        None => "dfm:?".to_string(),
+    }
+}
+
+/// Run `f` on another thread, giving up on it after `budget`. The thread
+/// keeps running in the background, but the process doesn't wait for it
+/// before exiting; see `util::kill_running` for the commands it started.
+pub fn within<T: Send + 'static>(
+    budget: Duration,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Option<T> {
+    let (send, recv) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = send.send(f());
+    });
+    recv.recv_timeout(budget).ok()
+}
+
+/// The short status saved in the cache file at `path`, if any.
+pub fn read_cache(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok()
+}
+
+/// Save `status` in the cache file at `path`; see `util::write_cache`.
+pub fn write_cache(path: &Path, status: &str) {
+    util::write_cache(path, status.as_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn short_status() {
+        assert_eq!(render(Some(0)), "");
+        assert_eq!(render(Some(2)), "dfm:2!");
+        assert_eq!(render(None), "dfm:?");
+        assert_eq!(within(BUDGET, || 1 + 1), Some(2));
+        assert_eq!(
+            within(Duration::from_millis(10), || thread::sleep(BUDGET)),
+            None
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! A spinner on standard error while dfm waits on something slow, like
+//! collecting system facts or evaluating Nix, so it doesn't look like it's
+//! hung.
+//!
+//! Spinners are off until `set_enabled` turns them on, and are only shown
+//! when standard error is a terminal and the step takes long enough to
+//! notice. Only one is shown at a time: spinners started while another is
+//! running aren't shown.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use console::Term;
+
+const FRAMES: &[&str] = &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+/// How long to wait before showing a spinner, so quick steps don't flicker.
+const DELAY: Duration = Duration::from_millis(250);
+
+const INTERVAL: Duration = Duration::from_millis(80);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether a spinner is running.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Turn spinners on or off, e.g. off for `--quiet`.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// A spinner, shown until it's dropped.
+#[derive(Debug)]
+pub struct Spinner {
+    running: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl Spinner {
+    /// Start a spinner describing the current step, e.g. `Collecting system
+    /// facts`.
+    pub fn start(step: &str) -> Self {
+        let term = Term::stderr();
+        if !ENABLED.load(Ordering::Relaxed)
+            || !term.is_term()
+            || ACTIVE.swap(true, Ordering::SeqCst)
+        {
+            return Self { running: None };
+        }
+        let (stop, stopped) = mpsc::channel();
+        let step = step.to_string();
+        let handle = thread::spawn(move || {
+            if stopped.recv_timeout(DELAY) != Err(RecvTimeoutError::Timeout) {
+                return;
+            }
+            for frame in FRAMES.iter().cycle() {
+                let _ = term.clear_line();
+                let _ = term.write_str(&format!("{} {}…", frame, step));
+                if stopped.recv_timeout(INTERVAL) != Err(RecvTimeoutError::Timeout) {
+                    break;
+                }
+            }
+            let _ = term.clear_line();
+        });
+        Self {
+            running: Some((stop, handle)),
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if let Some((stop, handle)) = self.running.take() {
+            let _ = stop.send(());
+            let _ = handle.join();
+            ACTIVE.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spinner_disabled() {
+        // Spinners are off by default, e.g. in tests and library use.
+        let spinner = Spinner::start("Collecting system facts");
+        assert!(spinner.running.is_none());
+        assert!(!ACTIVE.load(Ordering::SeqCst));
+    }
+}
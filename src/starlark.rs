@@ -0,0 +1,223 @@
+//! Dotfiles lists written in Starlark (<https://github.com/bazelbuild/starlark>),
+//! the Python-like language Bazel is configured in. The script sets
+//! `dotfiles` to the list of dotfiles. Like in Bazel's `.bzl` files, `if`
+//! and `for` statements are only allowed in functions, e.g.
+//!
+//! ```python
+//! def linux_dotfiles():
+//!     if facts["os"] != "linux":
+//!         return []
+//!     return [{"repo": "i3", "installed": ".config/i3"}]
+//!
+//! dotfiles = [".bashrc"] + linux_dotfiles()
+//! ```
+//!
+//! Scripts see every fact, as printed by `dotfile-manager facts --json` but
+//! without `env`, as the dictionary `facts`, and nothing else: they can't
+//! read files or environment variables or `load` other scripts, so the same
+//! facts always give the same list. Starlark has no `while` loops or
+//! recursion, so scripts always finish.
+//!
+//! This needs the `starlark` feature.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use codemap::CodeMap;
+use codemap_diagnostic::{Diagnostic, Emitter};
+use starlark::eval::noload;
+use starlark::stdlib::global_environment_with_extensions;
+use starlark::syntax::dialect::Dialect;
+use starlark::values::dict::Dictionary;
+use starlark::values::none::NoneType;
+use starlark::values::Value;
+
+use crate::config::DotfilesReadError;
+use crate::dotfile::SerdeDotfile;
+use crate::facts::{Facts, FactsModel};
+use crate::loader::ListLoader;
+
+/// Runs Starlark dotfiles lists; see the module documentation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StarlarkLoader;
+
+impl ListLoader for StarlarkLoader {
+    fn name(&self) -> &str {
+        "starlark"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["star"]
+    }
+
+    /// Scripts only see the built-in facts; use `load` to give them
+    /// user-defined facts too.
+    fn parse(&self, reader: &mut dyn Read) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let mut script = String::new();
+        reader.read_to_string(&mut script)?;
+        eval_list(&script, "dotfiles.star", &Facts::new())
+    }
+
+    fn load(&self, path: &Path, facts: &Facts) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+        let script = fs::read_to_string(path)?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        eval_list(&script, &name, facts)
+    }
+}
+
+/// Run `script`, called `name` in error messages, and read the dotfiles list
+/// it sets `dotfiles` to.
+fn eval_list(
+    script: &str,
+    name: &str,
+    facts: &Facts,
+) -> Result<Vec<SerdeDotfile>, DotfilesReadError> {
+    let model = FactsModel {
+        env: Default::default(),
+        ..facts.model()?
+    };
+    let facts = to_starlark(&serde_json::to_value(&model)?);
+    let (globals, type_values) = global_environment_with_extensions();
+    globals.freeze();
+    let mut env = globals.child(name);
+    env.set("facts", facts)
+        .map_err(|err| error(format!("{:?}", err)))?;
+    let map = Arc::new(Mutex::new(CodeMap::new()));
+    noload::eval(&map, name, script, Dialect::Bzl, &mut env, &type_values)
+        .map_err(|diagnostic| error(emit(&map, diagnostic)))?;
+    let dotfiles = env
+        .get("dotfiles")
+        .map_err(|_| error(format!("{} doesn't set `dotfiles`", name)))?;
+    Ok(serde_json::from_value(to_json(&dotfiles)?)?)
+}
+
+fn error(message: String) -> DotfilesReadError {
+    DotfilesReadError::Starlark(message)
+}
+
+/// `diagnostic`, with the code it points to.
+fn emit(map: &Mutex<CodeMap>, diagnostic: Diagnostic) -> String {
+    let map = map.lock().unwrap_or_else(|err| err.into_inner());
+    let mut out = Vec::new();
+    Emitter::vec(&mut out, Some(&map)).emit(&[diagnostic]);
+    String::from_utf8_lossy(&out).trim_end().to_string()
+}
+
+/// `json` as a Starlark value. Objects' `null` fields, e.g. facts which
+/// couldn't be collected, are left out, so they can be tested for with
+/// `in` or `get`.
+fn to_starlark(json: &serde_json::Value) -> Value {
+    match json {
+        serde_json::Value::Null => Value::new(NoneType::None),
+        serde_json::Value::Bool(b) => Value::new(*b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(n) => Value::new(n),
+            // Starlark has no floats; no fact is fractional.
+            None => Value::from(n.to_string()),
+        },
+        serde_json::Value::String(s) => Value::from(s.clone()),
+        serde_json::Value::Array(values) => {
+            Value::from(values.iter().map(to_starlark).collect::<Vec<_>>())
+        }
+        serde_json::Value::Object(fields) => {
+            let dict = Value::new(Dictionary::default());
+            {
+                let mut d = dict
+                    .downcast_mut::<Dictionary>()
+                    .expect("a new dictionary isn't frozen")
+                    .expect("it's a dictionary");
+                for (name, value) in fields.iter().filter(|(_, value)| !value.is_null()) {
+                    d.insert(Value::from(name.clone()), to_starlark(value))
+                        .expect("strings are hashable");
+                }
+            }
+            dict
+        }
+    }
+}
+
+/// `value`, which must be made of lists, dictionaries with string keys,
+/// strings, integers, booleans and `None`, as JSON.
+fn to_json(value: &Value) -> Result<serde_json::Value, DotfilesReadError> {
+    let unsupported = || {
+        error(format!(
+            "`dotfiles` can't contain a {}: {}",
+            value.get_type(),
+            value.to_repr()
+        ))
+    };
+    Ok(match value.get_type() {
+        "NoneType" => serde_json::Value::Null,
+        "bool" => value.to_bool().into(),
+        "int" => value.to_int().map_err(|_| unsupported())?.into(),
+        "string" => value.to_str().into(),
+        "list" | "tuple" => serde_json::Value::Array(
+            value
+                .to_vec()
+                .map_err(|_| unsupported())?
+                .iter()
+                .map(to_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        "dict" => {
+            let dict = value.downcast_ref::<Dictionary>().ok_or_else(unsupported)?;
+            let mut fields = serde_json::Map::new();
+            for (name, value) in dict.items() {
+                if name.get_type() != "string" {
+                    return Err(unsupported());
+                }
+                fields.insert(name.to_str(), to_json(&value)?);
+            }
+            serde_json::Value::Object(fields)
+        }
+        _ => return Err(unsupported()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::dotfile::Dotfile;
+
+    #[test]
+    fn starlark_loader() {
+        let facts = Facts::new().with_vars(
+            &vec![("email".to_string(), "me@example.com".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(
+            StarlarkLoader
+                .load(Path::new("test-data/dotfiles-starlark.star"), &facts)
+                .unwrap(),
+            vec![
+                SerdeDotfile::Path(".bashrc".into()),
+                SerdeDotfile::Advanced(Dotfile {
+                    repo: "gitconfig-me@example.com".into(),
+                    installed: Some(".config/git/config".into()),
+                    ..Default::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn starlark_sandbox() {
+        let parse = |script: &str| StarlarkLoader.parse(&mut script.as_bytes());
+        assert_eq!(
+            parse("dotfiles = [d for d in ['.bashrc', '.vimrc'] if 'os' in facts]").unwrap(),
+            vec![
+                SerdeDotfile::Path(".bashrc".into()),
+                SerdeDotfile::Path(".vimrc".into())
+            ]
+        );
+        assert!(parse("dotfiles = facts['vars']").is_err());
+        assert!(parse("dotfiles = [len]").is_err());
+        assert!(parse("x = 1").is_err());
+        assert!(parse("load('other.star', 'dotfiles')").is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! Interoperation with [GNU Stow](https://www.gnu.org/software/stow/).
+//!
+//! A stow directory holds packages: directories which mirror the target
+//! directory, usually the stow directory's parent. Stow links each file in a
+//! package to the same path in the target directory; if a whole directory
+//! belongs to one package, it may link the directory instead ("tree
+//! folding"). Each linked file or folded directory becomes one dotfile.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use thiserror::Error;
+
+use crate::dotfile::Dotfile;
+
+/// Stow's built-in ignore list, used for packages without a
+/// `.stow-local-ignore` file.
+const DEFAULT_IGNORE: &str = r"
+RCS
+.+,v
+CVS
+\.\#.+       # CVS conflict files / emacs lock files
+\.cvsignore
+\.svn
+_darcs
+\.hg
+\.git
+\.gitignore
+\.gitmodules
+.+~          # emacs backup files
+\#.*\#       # emacs autosave files
+^/README.*
+^/LICENSE.*
+^/COPYING
+";
+
+/// The name of the file listing the files in a package which aren't linked.
+const LOCAL_IGNORE: &str = ".stow-local-ignore";
+
+#[derive(Error, Debug)]
+pub enum StowError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("invalid pattern in {}: {source}", .path.display())]
+    Ignore { path: PathBuf, source: regex::Error },
+}
+
+/// Where a stow directory's packages are linked to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stow {
+    /// The stow directory.
+    pub dir: PathBuf,
+    /// The target directory; stow's default is `dir`'s parent.
+    pub target: PathBuf,
+    /// Whether a `dot-` prefix on a file in a package is replaced with `.`
+    /// when it's linked, like `stow --dotfiles`.
+    pub dot_prefix: bool,
+}
+
+impl Stow {
+    /// A stow directory linked to its parent.
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        let dir = dir.canonicalize()?;
+        Ok(Self {
+            target: dir.parent().unwrap_or(&dir).to_path_buf(),
+            dir,
+            dot_prefix: false,
+        })
+    }
+
+    /// Convert every package to a dotfiles list. Paths in the packages are
+    /// made relative to `dotfile_repo` if they're inside it, and installed
+    /// paths relative to `home`.
+    pub fn import(&self, dotfile_repo: &Path, home: &Path) -> Result<Vec<Dotfile>, StowError> {
+        let dotfile_repo = dotfile_repo
+            .canonicalize()
+            .unwrap_or_else(|_| dotfile_repo.to_path_buf());
+        let mut dotfiles = Vec::new();
+        for package in sorted_entries(&self.dir)? {
+            let name = package.file_name().unwrap_or_default().to_string_lossy();
+            if !package.is_dir() || name.starts_with('.') {
+                continue;
+            }
+            let ignore = Ignore::for_package(&package)?;
+            self.import_tree(&package, Path::new(""), &ignore, &mut |source, target| {
+                let repo = source.strip_prefix(&dotfile_repo).unwrap_or(source);
+                let installed = target.strip_prefix(home).unwrap_or(target);
+                dotfiles.push(Dotfile {
+                    installed: if installed == repo {
+                        None
+                    } else {
+                        Some(installed.to_path_buf())
+                    },
+                    repo: repo.to_path_buf(),
+                    ..Default::default()
+                });
+            })?;
+        }
+        Ok(dotfiles)
+    }
+
+    /// Call `found` with the source and target paths of each file in
+    /// `package`'s subdirectory `rel` which is (or would be) linked, or of
+    /// the directory itself if stow has folded it.
+    fn import_tree(
+        &self,
+        package: &Path,
+        rel: &Path,
+        ignore: &Ignore,
+        found: &mut dyn FnMut(&Path, &Path),
+    ) -> io::Result<()> {
+        for source in sorted_entries(&package.join(rel))? {
+            let rel = rel.join(source.file_name().unwrap_or_default());
+            if ignore.is_ignored(&rel) {
+                continue;
+            }
+            let target = self.target.join(self.translate(&rel));
+            if source.is_dir() && !is_link_to(&target, &source) {
+                self.import_tree(package, &rel, ignore, found)?;
+            } else {
+                found(&source, &target);
+            }
+        }
+        Ok(())
+    }
+
+    /// The path a package's file `rel` is linked to, relative to `target`.
+    fn translate(&self, rel: &Path) -> PathBuf {
+        if !self.dot_prefix {
+            return rel.to_path_buf();
+        }
+        rel.iter()
+            .map(|component| match component.to_str() {
+                Some(name) if name.starts_with("dot-") => format!(".{}", &name[4..]).into(),
+                _ => component.to_os_string(),
+            })
+            .collect()
+    }
+}
+
+/// Whether `target` is a symlink to `source`, i.e. stow has folded the
+/// directory `source`.
+fn is_link_to(target: &Path, source: &Path) -> bool {
+    let is_link = fs::symlink_metadata(target)
+        .map(|metadata| metadata.file_type().is_symlink())
+        .unwrap_or(false);
+    is_link && target.canonicalize().ok().as_deref() == Some(source)
+}
+
+/// The entries of `dir`, sorted by name.
+fn sorted_entries(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+/// A package's ignore list. Patterns containing `/` are matched against the
+/// path from the package's root, starting with `/`; others are matched
+/// against file names.
+#[derive(Debug)]
+struct Ignore {
+    paths: Vec<Regex>,
+    names: Vec<Regex>,
+}
+
+impl Ignore {
+    fn for_package(package: &Path) -> Result<Self, StowError> {
+        let path = package.join(LOCAL_IGNORE);
+        match fs::read_to_string(&path) {
+            Ok(list) => Self::parse(&list).map_err(|source| StowError::Ignore { path, source }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::parse(DEFAULT_IGNORE).expect("the default ignore list is valid"))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn parse(list: &str) -> Result<Self, regex::Error> {
+        let mut ignore = Ignore {
+            paths: Vec::new(),
+            names: Vec::new(),
+        };
+        for line in list.lines() {
+            // Comments start with `#` at the start of a line or after
+            // whitespace; other `#`s are part of the pattern.
+            let end = line
+                .char_indices()
+                .find(|&(i, c)| c == '#' && (i == 0 || line[..i].ends_with(char::is_whitespace)))
+                .map_or(line.len(), |(i, _)| i);
+            let pattern = line[..end].trim();
+            if pattern.is_empty() {
+                continue;
+            }
+            let regex = Regex::new(&format!("^(?:{})$", pattern))?;
+            if pattern.contains('/') {
+                ignore.paths.push(regex);
+            } else {
+                ignore.names.push(regex);
+            }
+        }
+        ignore
+            .names
+            .push(Regex::new(&format!("^{}$", regex::escape(LOCAL_IGNORE)))?);
+        Ok(ignore)
+    }
+
+    fn is_ignored(&self, rel: &Path) -> bool {
+        let name = rel.file_name().unwrap_or_default().to_string_lossy();
+        let path = format!("/{}", rel.to_string_lossy());
+        self.names.iter().any(|regex| regex.is_match(&name))
+            || self.paths.iter().any(|regex| regex.is_match(&path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn stow_ignore() {
+        let ignore = Ignore::parse(DEFAULT_IGNORE).unwrap();
+        for ignored in &[
+            ".git",
+            "nvim/.git",
+            ".#init.vim",
+            "init.vim~",
+            "#init.vim#",
+            "README.md",
+            LOCAL_IGNORE,
+        ] {
+            assert!(ignore.is_ignored(Path::new(ignored)), "{}", ignored);
+        }
+        for linked in &[
+            ".bashrc",
+            ".config/nvim/README.md",
+            "git#hub",
+            "COPYING.txt",
+        ] {
+            assert!(!ignore.is_ignored(Path::new(linked)), "{}", linked);
+        }
+        assert!(Ignore::parse("(").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stow_import() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        // The temporary directory may be behind a symlink, e.g. on macOS.
+        let root = root.canonicalize().unwrap();
+        let home = root.join("home");
+        let stow_dir = home.join("dotfiles");
+        let files = [
+            "bash/dot-bashrc",
+            "bash/README.md",
+            "git/.config/git/config",
+            "nvim/.config/nvim/init.vim",
+            "nvim/.config/nvim/lua/plugins.lua",
+        ];
+        for file in &files {
+            let path = stow_dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "").unwrap();
+        }
+        fs::create_dir_all(home.join(".config")).unwrap();
+        std::os::unix::fs::symlink(
+            stow_dir.join("nvim/.config/nvim"),
+            home.join(".config/nvim"),
+        )
+        .unwrap();
+
+        let stow = Stow {
+            dot_prefix: true,
+            ..Stow::new(&stow_dir).unwrap()
+        };
+        let dotfiles = stow.import(&stow_dir, &home);
+        let dotfile = |repo: &str, installed: &str| Dotfile {
+            repo: repo.into(),
+            installed: Some(installed.into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            dotfiles.unwrap(),
+            vec![
+                dotfile("bash/dot-bashrc", ".bashrc"),
+                dotfile("git/.config/git/config", ".config/git/config"),
+                dotfile("nvim/.config/nvim", ".config/nvim"),
+            ]
+        );
+    }
+}
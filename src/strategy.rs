@@ -0,0 +1,515 @@
+//! How dotfiles are installed, chosen per dotfile with its `link` field:
+//!
+//! - `symlink`, the default: the installed path is a symbolic link to the
+//!   dotfile.
+//! - `hardlink`: the installed path is a hard link to the dotfile, for
+//!   programs which replace symbolic links with files when they save.
+//! - `copy`: the installed path is a copy of the dotfile, for programs
+//!   which won't read links at all.
+//! - `template`: the dotfile is rendered to the installed path; see the
+//!   `template` module. `template = true` is the same as `link =
+//!   "template"`.
+//! - `junction`: on Windows, the installed path is a directory junction,
+//!   which unlike a symbolic link doesn't need administrative privileges or
+//!   developer mode; elsewhere, this is the same as `symlink`.
+//!
+//! Library users can add other ways, like reflinks on filesystems which
+//! support them, by implementing `LinkStrategy` and adding it to
+//! `Config::link_strategies`.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config;
+use crate::dotfile::{AbsDotfile, DotfileAction, InstallStatus};
+use crate::error::Error;
+use crate::facts::Facts;
+use crate::template;
+
+/// A way of installing dotfiles.
+pub trait LinkStrategy: fmt::Debug + Send + Sync {
+    /// A short name for the strategy, e.g. `copy`, which dotfiles choose it
+    /// by.
+    fn name(&self) -> &str;
+
+    /// What installing a dotfile this way is called, as in "Would copy
+    /// ~/.dotfiles/bashrc to ~/.bashrc"; by default, `install`.
+    fn verb(&self) -> &str {
+        "install"
+    }
+
+    /// What installing `d` puts at its installed path, as in "Overwrite
+    /// ~/.bashrc with a copy of ~/.dotfiles/bashrc?".
+    fn describe(&self, d: &AbsDotfile) -> String {
+        format!("{} installed with `{}`", d.repo.display(), self.name())
+    }
+
+    /// Install `d` to its installed path. Unless the strategy `overwrites`,
+    /// nothing is there.
+    fn install(&self, d: &AbsDotfile, facts: &Facts) -> Result<(), Error>;
+
+    /// Whether `d` is installed, given the metadata of what's at its
+    /// installed path, without following links.
+    fn status(&self, d: &AbsDotfile, metadata: &fs::Metadata) -> io::Result<InstallStatus>;
+
+    /// Whether `d` is installed and up to date, so `dfm link` leaves it
+    /// alone; by default, whether its status is `Linked`.
+    fn is_current(&self, d: &AbsDotfile, _facts: &Facts) -> Result<bool, Error> {
+        Ok(d.status()? == InstallStatus::Linked)
+    }
+
+    /// Whether installing replaces what an earlier install left at the
+    /// installed path without asking, like re-rendering a template. Anything
+    /// else there, i.e. whose status is `Conflict`, is still a conflict.
+    fn overwrites(&self) -> bool {
+        false
+    }
+}
+
+/// Wrap an I/O error linking `d`.
+fn linking(d: &AbsDotfile) -> impl FnOnce(io::Error) -> Error + '_ {
+    move |err| d.error(DotfileAction::Linking)(err).into()
+}
+
+/// Whether `installed` is a symbolic link to `repo`.
+fn links_to(installed: &Path, repo: &Path, metadata: &fs::Metadata) -> io::Result<InstallStatus> {
+    if metadata.file_type().is_symlink() && fs::read_link(installed)? == repo {
+        Ok(InstallStatus::Linked)
+    } else {
+        Ok(InstallStatus::Conflict)
+    }
+}
+
+/// Installs dotfiles as symbolic links.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Symlink;
+
+impl LinkStrategy for Symlink {
+    fn name(&self) -> &str {
+        "symlink"
+    }
+
+    fn verb(&self) -> &str {
+        "link"
+    }
+
+    fn describe(&self, d: &AbsDotfile) -> String {
+        format!("a link to {}", d.repo.display())
+    }
+
+    fn install(&self, d: &AbsDotfile, _facts: &Facts) -> Result<(), Error> {
+        Ok(d.link()?)
+    }
+
+    fn status(&self, d: &AbsDotfile, metadata: &fs::Metadata) -> io::Result<InstallStatus> {
+        links_to(&d.installed, &d.repo, metadata)
+    }
+}
+
+/// Installs dotfiles as hard links, so they must be files on the same
+/// filesystem as the dotfile repository.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hardlink;
+
+impl LinkStrategy for Hardlink {
+    fn name(&self) -> &str {
+        "hardlink"
+    }
+
+    fn verb(&self) -> &str {
+        "hardlink"
+    }
+
+    fn describe(&self, d: &AbsDotfile) -> String {
+        format!("a hard link to {}", d.repo.display())
+    }
+
+    fn install(&self, d: &AbsDotfile, _facts: &Facts) -> Result<(), Error> {
+        fs::hard_link(&d.repo, &d.installed).map_err(linking(d))
+    }
+
+    fn status(&self, d: &AbsDotfile, metadata: &fs::Metadata) -> io::Result<InstallStatus> {
+        if !metadata.is_file() {
+            return Ok(InstallStatus::Conflict);
+        }
+        #[cfg(unix)]
+        let same = {
+            use std::os::unix::fs::MetadataExt;
+            let repo = fs::metadata(&d.repo)?;
+            (metadata.dev(), metadata.ino()) == (repo.dev(), repo.ino())
+        };
+        // Telling hard links apart needs file IDs, which the standard
+        // library doesn't expose elsewhere; the same contents will do.
+        #[cfg(not(unix))]
+        let same = fs::read(&d.installed)? == fs::read(&d.repo)?;
+        if same {
+            Ok(InstallStatus::Linked)
+        } else {
+            Ok(InstallStatus::Conflict)
+        }
+    }
+}
+
+/// Installs dotfiles as copies. A copy whose contents differ from the
+/// dotfile's is a conflict, so it's only overwritten once the user's seen
+/// how.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileCopy;
+
+/// Copy the file or directory at `from` to `to`.
+fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+impl LinkStrategy for FileCopy {
+    fn name(&self) -> &str {
+        "copy"
+    }
+
+    fn verb(&self) -> &str {
+        "copy"
+    }
+
+    fn describe(&self, d: &AbsDotfile) -> String {
+        format!("a copy of {}", d.repo.display())
+    }
+
+    fn install(&self, d: &AbsDotfile, _facts: &Facts) -> Result<(), Error> {
+        copy_all(&d.repo, &d.installed).map_err(linking(d))
+    }
+
+    /// A copied directory counts as installed even if its contents are out
+    /// of date, like a rendered template.
+    fn status(&self, d: &AbsDotfile, metadata: &fs::Metadata) -> io::Result<InstallStatus> {
+        let copied = if metadata.is_dir() {
+            d.repo.is_dir()
+        } else {
+            metadata.is_file() && fs::read(&d.installed)? == fs::read(&d.repo)?
+        };
+        if copied {
+            Ok(InstallStatus::Copied)
+        } else {
+            Ok(InstallStatus::Conflict)
+        }
+    }
+
+    fn is_current(&self, d: &AbsDotfile, _facts: &Facts) -> Result<bool, Error> {
+        Ok(d.status()? == InstallStatus::Copied)
+    }
+}
+
+/// Renders dotfiles as templates; see the `template` module. A file at the
+/// installed path which dfm didn't render there is a conflict; renders are
+/// recorded in `config::rendered_dir` to tell them apart.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Template;
+
+/// Whether the file at `installed` is what dfm last rendered there.
+fn is_rendered(installed: &Path) -> io::Result<bool> {
+    template::is_rendered(&config::rendered_dir()?, installed)
+}
+
+impl LinkStrategy for Template {
+    fn name(&self) -> &str {
+        "template"
+    }
+
+    fn verb(&self) -> &str {
+        "render"
+    }
+
+    fn describe(&self, d: &AbsDotfile) -> String {
+        format!("{} rendered", d.repo.display())
+    }
+
+    fn install(&self, d: &AbsDotfile, facts: &Facts) -> Result<(), Error> {
+        d.render(facts)?;
+        template::record_render(&config::rendered_dir()?, &d.installed).map_err(linking(d))
+    }
+
+    /// A rendered template counts as installed even if it's out of date;
+    /// see `dfm diff`. A symbolic link to the template, from before the
+    /// dotfile was one, is `Linked`, and replaced without asking.
+    fn status(&self, d: &AbsDotfile, metadata: &fs::Metadata) -> io::Result<InstallStatus> {
+        if metadata.file_type().is_symlink() {
+            links_to(&d.installed, &d.repo, metadata)
+        } else if metadata.is_file() && is_rendered(&d.installed)? {
+            Ok(InstallStatus::Rendered)
+        } else {
+            Ok(InstallStatus::Conflict)
+        }
+    }
+
+    /// Whether rendering the template would leave the installed file as it
+    /// is. If it would, but there's no record of rendering it, e.g. because
+    /// it was rendered before renders were recorded, it's recorded now.
+    fn is_current(&self, d: &AbsDotfile, facts: &Facts) -> Result<bool, Error> {
+        let rendered = template::render(&fs::read_to_string(&d.repo)?, facts)?;
+        let current = fs::symlink_metadata(&d.installed).is_ok_and(|m| m.is_file())
+            && fs::read_to_string(&d.installed).is_ok_and(|installed| installed == rendered);
+        if current && !is_rendered(&d.installed).unwrap_or(false) {
+            let _ = template::record_render(&config::rendered_dir()?, &d.installed);
+        }
+        Ok(current)
+    }
+
+    fn overwrites(&self) -> bool {
+        true
+    }
+}
+
+/// Installs dotfiles as directory junctions on Windows, and as symbolic
+/// links elsewhere.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Junction;
+
+impl LinkStrategy for Junction {
+    fn name(&self) -> &str {
+        "junction"
+    }
+
+    fn verb(&self) -> &str {
+        "junction"
+    }
+
+    fn describe(&self, d: &AbsDotfile) -> String {
+        if cfg!(windows) {
+            format!("a junction to {}", d.repo.display())
+        } else {
+            format!("a link to {}", d.repo.display())
+        }
+    }
+
+    #[cfg(windows)]
+    fn install(&self, d: &AbsDotfile, _facts: &Facts) -> Result<(), Error> {
+        let output = std::process::Command::new("cmd")
+            .arg("/C")
+            .arg("mklink")
+            .arg("/J")
+            .arg(&d.installed)
+            .arg(&d.repo)
+            .output()
+            .map_err(linking(d))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(linking(d)(io::Error::other(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            )))
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn install(&self, d: &AbsDotfile, _facts: &Facts) -> Result<(), Error> {
+        Ok(d.link()?)
+    }
+
+    /// Junctions look like symbolic links to the standard library.
+    fn status(&self, d: &AbsDotfile, metadata: &fs::Metadata) -> io::Result<InstallStatus> {
+        links_to(&d.installed, &d.repo, metadata)
+    }
+}
+
+/// A `LinkStrategy`; strategies with the same name are equal.
+#[derive(Debug, Clone)]
+pub struct Strategy(Arc<dyn LinkStrategy>);
+
+impl Strategy {
+    pub fn new(strategy: impl LinkStrategy + 'static) -> Self {
+        Self(Arc::new(strategy))
+    }
+}
+
+impl Default for Strategy {
+    /// `Symlink`.
+    fn default() -> Self {
+        Self::new(Symlink)
+    }
+}
+
+impl PartialEq for Strategy {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Deref for Strategy {
+    type Target = dyn LinkStrategy;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+/// The ways dotfiles may be installed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkStrategies(Vec<Strategy>);
+
+impl Default for LinkStrategies {
+    /// The built-in strategies: symlink, hardlink, copy, template, and
+    /// junction.
+    fn default() -> Self {
+        Self(vec![
+            Strategy::new(Symlink),
+            Strategy::new(Hardlink),
+            Strategy::new(FileCopy),
+            Strategy::new(Template),
+            Strategy::new(Junction),
+        ])
+    }
+}
+
+impl LinkStrategies {
+    /// Add a strategy, replacing any strategy with the same name.
+    pub fn register(&mut self, strategy: impl LinkStrategy + 'static) {
+        let strategy = Strategy::new(strategy);
+        match self.0.iter_mut().find(|s| s.name() == strategy.name()) {
+            Some(existing) => *existing = strategy,
+            None => self.0.push(strategy),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Strategy> {
+        self.0.iter()
+    }
+
+    /// The strategy named `name`.
+    pub fn get(&self, name: &str) -> Option<&Strategy> {
+        self.0.iter().find(|s| s.name() == name)
+    }
+
+    /// The strategies' names.
+    pub fn names(&self) -> Vec<String> {
+        self.0.iter().map(|s| s.name().to_string()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct Reflink;
+
+    impl LinkStrategy for Reflink {
+        fn name(&self) -> &str {
+            "reflink"
+        }
+
+        fn install(&self, _d: &AbsDotfile, _facts: &Facts) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn status(&self, _d: &AbsDotfile, _metadata: &fs::Metadata) -> io::Result<InstallStatus> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn link_strategies() {
+        let mut strategies = LinkStrategies::default();
+        assert_eq!(strategies.get("reflink"), None);
+        strategies.register(Reflink);
+        strategies.register(FileCopy);
+        assert_eq!(
+            strategies.names(),
+            vec!["symlink", "hardlink", "copy", "template", "junction", "reflink"]
+        );
+        assert_eq!(strategies.get("reflink"), Some(&Strategy::new(Reflink)));
+        assert_eq!(
+            strategies.iter().map(|s| s.verb()).collect::<Vec<_>>(),
+            vec!["link", "hardlink", "copy", "render", "junction", "install"]
+        );
+        let d = AbsDotfile {
+            repo: "/dotfiles/bashrc".into(),
+            ..Default::default()
+        };
+        assert_eq!(FileCopy.describe(&d), "a copy of /dotfiles/bashrc");
+        assert_eq!(
+            Reflink.describe(&d),
+            "/dotfiles/bashrc installed with `reflink`"
+        );
+        assert_eq!(Strategy::default(), Strategy::new(Symlink));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn link_strategies_install() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("vim")).unwrap();
+        fs::write(root.join("bashrc"), "hi").unwrap();
+        fs::write(root.join("vim/vimrc"), "set nu").unwrap();
+        let facts = Facts::new();
+        let install = |repo: &str, installed: &str, strategy: Strategy| {
+            let d = AbsDotfile {
+                repo: root.join(repo),
+                installed: root.join(installed),
+                strategy,
+                ..Default::default()
+            };
+            let before = d.strategy.is_current(&d, &facts).unwrap();
+            d.strategy.install(&d, &facts).unwrap();
+            (
+                before,
+                d.status().unwrap(),
+                d.strategy.is_current(&d, &facts).unwrap(),
+            )
+        };
+        let results = vec![
+            install("bashrc", ".bashrc", Strategy::new(Symlink)),
+            install("bashrc", ".bashrc-hard", Strategy::new(Hardlink)),
+            install("bashrc", ".bashrc-copy", Strategy::new(FileCopy)),
+            install("vim", ".vim", Strategy::new(FileCopy)),
+            install("vim", ".vim-junction", Strategy::new(Junction)),
+        ];
+        fs::write(root.join(".bashrc-other"), "hi").unwrap();
+        let copy_status = AbsDotfile {
+            repo: root.join("bashrc"),
+            installed: root.join(".bashrc-other"),
+            strategy: Strategy::new(FileCopy),
+            ..Default::default()
+        }
+        .status()
+        .unwrap();
+        let hardlink_status = AbsDotfile {
+            repo: root.join("bashrc"),
+            installed: root.join(".bashrc-other"),
+            strategy: Strategy::new(Hardlink),
+            ..Default::default()
+        }
+        .status()
+        .unwrap();
+        let copied = fs::read_to_string(root.join(".vim/vimrc"));
+
+        assert_eq!(
+            results,
+            vec![
+                (false, InstallStatus::Linked, true),
+                (false, InstallStatus::Linked, true),
+                (false, InstallStatus::Copied, true),
+                (false, InstallStatus::Copied, true),
+                (false, InstallStatus::Linked, true),
+            ]
+        );
+        assert_eq!(copied.unwrap(), "set nu");
+        assert_eq!(copy_status, InstallStatus::Copied);
+        assert_eq!(hardlink_status, InstallStatus::Conflict);
+    }
+}
@@ -0,0 +1,116 @@
+//! IP subnets in CIDR notation, e.g. `10.42.0.0/16` or `fd00::/8`.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("invalid subnet `{0}`; expected an address and prefix length like `10.0.0.0/8`")]
+pub struct SubnetParseError(String);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Subnet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Subnet {
+    /// Whether `addr` is in this subnet; IPv4 addresses are never in IPv6
+    /// subnets and vice versa.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                masked(u32::from(net).into(), 32, self.prefix_len)
+                    == masked(u32::from(*addr).into(), 32, self.prefix_len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                masked(net.into(), 128, self.prefix_len)
+                    == masked((*addr).into(), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The first `prefix_len` bits of the `bits`-bit address `addr`.
+fn masked(addr: u128, bits: u8, prefix_len: u8) -> u128 {
+    if prefix_len == 0 {
+        0
+    } else {
+        addr >> (bits - prefix_len)
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = SubnetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || SubnetParseError(s.to_string());
+        let (addr, prefix_len) = match s.find('/') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| err())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        // A bare address is a subnet containing just that address.
+        let prefix_len = match prefix_len {
+            Some(len) => len.parse().map_err(|_| err())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(err());
+        }
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn contains(subnet: &str, addr: &str) -> bool {
+        subnet
+            .parse::<Subnet>()
+            .unwrap()
+            .contains(&addr.parse().unwrap())
+    }
+
+    #[test]
+    fn subnet_contains() {
+        assert!(contains("10.42.0.0/16", "10.42.7.1"));
+        assert!(!contains("10.42.0.0/16", "10.43.0.1"));
+        assert!(contains("10.42.0.0/15", "10.43.0.1"));
+        assert!(contains("0.0.0.0/0", "192.168.1.1"));
+        assert!(contains("192.168.1.1", "192.168.1.1"));
+        assert!(!contains("192.168.1.1", "192.168.1.2"));
+        assert!(contains("fd00::/8", "fd12:3456::1"));
+        assert!(!contains("fd00::/8", "fe80::1"));
+        assert!(!contains("::/0", "10.0.0.1"));
+    }
+
+    #[test]
+    fn subnet_parse() {
+        assert_eq!(
+            "10.0.0.0/8".parse::<Subnet>().unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert_eq!(
+            "fe80::1".parse::<Subnet>().unwrap().to_string(),
+            "fe80::1/128"
+        );
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+        assert!("10.0.0/8".parse::<Subnet>().is_err());
+        assert!("10.0.0.0/".parse::<Subnet>().is_err());
+        assert!("wg0".parse::<Subnet>().is_err());
+    }
+}
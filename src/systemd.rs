@@ -0,0 +1,76 @@
+//! Managing systemd user units installed as dotfiles; see
+//! `Dotfile::systemd`.
+
+use std::io;
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Where systemd looks for user units, relative to the home directory.
+pub const USER_UNIT_DIR: &str = ".config/systemd/user";
+
+#[derive(Error, Debug)]
+pub enum SystemdError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("`systemctl --user {args}` failed: {message}")]
+    Failed { args: String, message: String },
+}
+
+/// What to do with a dotfile which is a systemd user unit once it's linked.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SystemdUnit {
+    /// Only make systemd aware of it.
+    Linked,
+    /// Also enable and start it.
+    Enabled,
+}
+
+/// A user unit's state, as reported by `systemctl --user is-active` and
+/// `is-enabled`, e.g. `active` and `enabled`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitStatus {
+    pub active: String,
+    pub enabled: String,
+}
+
+/// Run `systemctl --user` with `args` and return its standard output.
+/// `is-active` and `is-enabled` print the state even when they fail, so
+/// their output is returned regardless.
+fn systemctl(args: &[&str]) -> Result<String, SystemdError> {
+    let output = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() || (args[0].starts_with("is-") && !stdout.is_empty()) {
+        Ok(stdout)
+    } else {
+        Err(SystemdError::Failed {
+            args: args.join(" "),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
+    }
+}
+
+/// Make systemd load new or changed unit files.
+pub fn daemon_reload() -> Result<(), SystemdError> {
+    systemctl(&["daemon-reload"]).map(|_| ())
+}
+
+/// Enable and start `unit`, e.g. `backup.timer`.
+pub fn enable_now(unit: &str) -> Result<(), SystemdError> {
+    systemctl(&["enable", "--now", unit]).map(|_| ())
+}
+
+/// `unit`'s state.
+pub fn status(unit: &str) -> Result<UnitStatus, SystemdError> {
+    Ok(UnitStatus {
+        active: systemctl(&["is-active", unit])?,
+        enabled: systemctl(&["is-enabled", unit])?,
+    })
+}
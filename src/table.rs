@@ -0,0 +1,146 @@
+//! Printing aligned tables, like `dfm status`'s list of dotfiles.
+
+use console::{measure_text_width, pad_str, truncate_str, Alignment};
+
+/// Spaces between columns.
+const GAP: usize = 2;
+
+/// Columns aren't truncated to narrower than this, or their header.
+const MIN_WIDTH: usize = 8;
+
+/// `text` padded or truncated to `width` columns, marking cut off text with
+/// `…`.
+pub fn fit(text: &str, width: usize) -> String {
+    // `truncate_str` makes room for the `…` even if `text` fits exactly.
+    if measure_text_width(text) > width {
+        truncate_str(text, width, "…").into_owned()
+    } else {
+        pad_str(text, width, Alignment::Left, None).into_owned()
+    }
+}
+
+/// The `i`th cell of `row`, or an empty one.
+fn cell(row: &[String], i: usize) -> &str {
+    row.get(i).map(|cell| cell.as_str()).unwrap_or_default()
+}
+
+/// A table of text; cells may be styled with `console::style`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Self {
+            headers: headers.iter().map(|header| header.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Add a row; missing cells are empty and extra cells are ignored.
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The table as lines of aligned columns, at most `width` columns wide
+    /// if possible. The widest columns are truncated first, marking cut
+    /// off text with `…`.
+    pub fn render(&self, width: usize) -> String {
+        let mut widths = self
+            .headers
+            .iter()
+            .enumerate()
+            .map(|(i, header)| {
+                self.rows
+                    .iter()
+                    .map(|row| measure_text_width(cell(row, i)))
+                    .chain(Some(measure_text_width(header)))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+        let min_widths = self
+            .headers
+            .iter()
+            .map(|header| measure_text_width(header).max(MIN_WIDTH))
+            .collect::<Vec<_>>();
+        let gaps = GAP * widths.len().saturating_sub(1);
+        while widths.iter().sum::<usize>() + gaps > width {
+            let widest = (0..widths.len())
+                .filter(|&i| widths[i] > min_widths[i])
+                .max_by_key(|&i| widths[i]);
+            match widest {
+                Some(i) => widths[i] -= 1,
+                None => break,
+            }
+        }
+
+        let mut table = String::new();
+        let headers = self.headers.clone();
+        for row in Some(&headers).into_iter().chain(&self.rows) {
+            let line = widths
+                .iter()
+                .enumerate()
+                .map(|(i, &width)| fit(cell(row, i), width))
+                .collect::<Vec<_>>()
+                .join(&" ".repeat(GAP));
+            table.push_str(line.trim_end());
+            table.push('\n');
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn table_render() {
+        let mut table = Table::new(&["REPO", "INSTALLED", "STATUS"]);
+        assert!(table.is_empty());
+        table.push(vec!["bashrc".into(), "~/.bashrc".into(), "linked".into()]);
+        table.push(vec![
+            "nvim/init.lua".into(),
+            "~/.config/nvim/init.lua".into(),
+            "missing".into(),
+        ]);
+        table.push(vec!["gitconfig".into()]);
+
+        assert_eq!(
+            table.render(80),
+            [
+                "REPO           INSTALLED                STATUS",
+                "bashrc         ~/.bashrc                linked",
+                "nvim/init.lua  ~/.config/nvim/init.lua  missing",
+                "gitconfig",
+                "",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            table.render(40),
+            [
+                "REPO           INSTALLED         STATUS",
+                "bashrc         ~/.bashrc         linked",
+                "nvim/init.lua  ~/.config/nvim/…  missing",
+                "gitconfig",
+                "",
+            ]
+            .join("\n")
+        );
+        // Columns aren't truncated past their minimum width.
+        assert_eq!(
+            table.render(10).lines().nth(2),
+            Some("nvim/in…  ~/.confi…  missing")
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Rendering templated dotfiles with [handlebars](https://handlebarsjs.com/).
+//!
+//! Templates are rendered with a `facts` object in scope, so a template can
+//! contain e.g. `{{ facts.hostname }}` or `{{ facts.vars.email }}`; see
+//! `Facts::template_context` for the available fields.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use handlebars::{Handlebars, TemplateRenderError};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::facts::{Facts, FactsError};
+use crate::util;
+
+/// An error while rendering a template.
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("failed to read or write template: {0}")]
+    Io(#[from] io::Error),
+
+    // Boxed because `TemplateRenderError` is large.
+    #[error("failed to render template: {0}")]
+    Render(Box<TemplateRenderError>),
+
+    #[error("{0}")]
+    Facts(#[from] FactsError),
+}
+
+/// Render `template` with `facts` in scope.
+pub fn render(template: &str, facts: &Facts) -> Result<String, TemplateError> {
+    let mut handlebars = Handlebars::new();
+    // Dotfiles aren't HTML.
+    handlebars.register_escape_fn(handlebars::no_escape);
+    // Referring to a fact which doesn't exist is almost certainly a mistake.
+    handlebars.set_strict_mode(true);
+    handlebars
+        .render_template(template, &json!({ "facts": facts.template_context()? }))
+        .map_err(|err| TemplateError::Render(Box::new(err)))
+}
+
+/// Render the template at `src` and write the result to `dest`. A symlink at
+/// `dest`, e.g. from before the dotfile was a template, is replaced rather
+/// than written through, which could overwrite the template itself.
+pub fn render_file(src: &Path, dest: &Path, facts: &Facts) -> Result<(), TemplateError> {
+    let rendered = render(&fs::read_to_string(src)?, facts)?;
+    if fs::symlink_metadata(dest).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        fs::remove_file(dest)?;
+    }
+    fs::write(dest, rendered)?;
+    Ok(())
+}
+
+/// The file in `records` holding the hash of what was last rendered to
+/// `dest`.
+fn record_file(records: &Path, dest: &Path) -> PathBuf {
+    records.join(util::sha256_hex(dest.to_string_lossy().as_bytes()))
+}
+
+/// Note in `records` that the file at `dest` was just rendered; see
+/// `is_rendered`.
+pub fn record_render(records: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(records)?;
+    fs::write(
+        record_file(records, dest),
+        util::sha256_hex(&fs::read(dest)?),
+    )
+}
+
+/// Whether the file at `dest` is what was last rendered there, according
+/// to `records`, rather than something else, like a file the user wrote. If
+/// the record's gone, e.g. because the cache was cleared, it isn't.
+pub fn is_rendered(records: &Path, dest: &Path) -> io::Result<bool> {
+    let recorded = match fs::read_to_string(record_file(records, dest)) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        recorded => recorded?,
+    };
+    Ok(recorded == util::sha256_hex(&fs::read(dest)?))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn render_facts() {
+        let vars: BTreeMap<_, _> = vec![("email".to_string(), "me@example.com".to_string())]
+            .into_iter()
+            .collect();
+        let facts = Facts::new().with_vars(&vars);
+        assert_eq!(
+            render(
+                "[user]\n\temail = {{ facts.vars.email }}\n# on {{ facts.hostname }}",
+                &facts
+            )
+            .unwrap(),
+            format!(
+                "[user]\n\temail = me@example.com\n# on {}",
+                facts.hostname().unwrap()
+            )
+        );
+
+        // No HTML escaping.
+        assert_eq!(
+            render("{{ facts.vars.email }} <&>", &facts).unwrap(),
+            "me@example.com <&>"
+        );
+
+        assert!(matches!(
+            render("{{ facts.nonexistent }}", &facts),
+            Err(TemplateError::Render(_))
+        ));
+    }
+
+    #[test]
+    fn render_file_to_dest() {
+        let dir = TempDir::new().unwrap();
+        let dest = dir.path().join("rendered.txt");
+        render_file(
+            Path::new("test-data/fixed-size-file.txt"),
+            &dest,
+            &Facts::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&dest).unwrap(),
+            fs::read_to_string("test-data/fixed-size-file.txt").unwrap()
+        );
+    }
+
+    #[test]
+    fn render_file_over_symlink() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let src = root.join("gitconfig");
+        let dest = root.join(".gitconfig");
+        fs::write(&src, "# on {{ facts.os }}\n").unwrap();
+        symlink::symlink_file(&src, &dest).unwrap();
+        let facts = Facts::new();
+        let res = render_file(&src, &dest, &facts);
+        let template = fs::read_to_string(&src);
+        let rendered = fs::read_to_string(&dest);
+        let is_symlink = fs::symlink_metadata(&dest).map(|m| m.file_type().is_symlink());
+
+        res.unwrap();
+        assert_eq!(template.unwrap(), "# on {{ facts.os }}\n");
+        assert_eq!(rendered.unwrap(), format!("# on {}\n", facts.os().unwrap()));
+        assert!(!is_symlink.unwrap());
+    }
+
+    #[test]
+    fn render_records() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let records = root.join("rendered");
+        let dest = root.join(".gitconfig");
+        fs::write(&dest, "rendered\n").unwrap();
+        let unrecorded = is_rendered(&records, &dest).unwrap();
+        record_render(&records, &dest).unwrap();
+        let recorded = is_rendered(&records, &dest).unwrap();
+        fs::write(&dest, "edited\n").unwrap();
+        let edited = is_rendered(&records, &dest).unwrap();
+
+        assert!(!unrecorded);
+        assert!(recorded);
+        assert!(!edited);
+    }
+}
@@ -2,8 +2,15 @@ use std::convert::TryInto;
 use std::fs;
 use std::fs::{File, Metadata};
 use std::io;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::process::{ChildStdout, Command, ExitStatus, Output, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use wait_timeout::ChildExt;
 
 pub fn make_abs(base: &Path, p: &Path) -> PathBuf {
     let abs = base.join(p);
@@ -47,6 +54,213 @@ pub fn file_to_string(file: &mut File) -> io::Result<String> {
     Ok(s)
 }
 
+/// Run `command` to completion and capture its output, like
+/// `Command::output`, but kill it and return `None` if it runs for longer than
+/// `timeout`. On Unix, the command runs in its own process group, and the
+/// whole group is killed, so subprocesses it started don't outlive it.
+pub fn output_with_timeout(command: &mut Command, timeout: Duration) -> io::Result<Option<Output>> {
+    output_with_input(command, None, timeout)
+}
+
+/// Write `contents` to the cache file at `path`, creating its directory.
+/// Caches are best-effort, so this never fails: if the file can't be
+/// written, the next run does the work again.
+pub fn write_cache(path: &Path, contents: &[u8]) {
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let _ = fs::write(path, contents);
+}
+
+/// Like `output_with_timeout`, but write `input`, if any, to the command's
+/// standard input and then close it.
+pub fn output_with_input(
+    command: &mut Command,
+    input: Option<Vec<u8>>,
+    timeout: Duration,
+) -> io::Result<Option<Output>> {
+    let (status, stdout, stderr) = run_with_timeout(command, input, timeout, |mut stdout| {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    })?;
+    match status {
+        Some(status) => Ok(Some(Output {
+            status,
+            stdout: stdout?,
+            stderr,
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Run `command` like `output_with_timeout`, but pass its standard output to
+/// `read` as it's produced rather than buffering all of it. Returns the exit
+/// status (or `None` if the command timed out), the result of `read`, and
+/// the command's standard error. If `read` returns before reaching the end of
+/// the output, the command's standard output is closed, so it'll probably
+/// exit with an error.
+pub fn read_with_timeout<R>(
+    command: &mut Command,
+    timeout: Duration,
+    read: impl FnOnce(ChildStdout) -> R,
+) -> io::Result<(Option<ExitStatus>, R, Vec<u8>)> {
+    run_with_timeout(command, None, timeout, read)
+}
+
+fn run_with_timeout<R>(
+    command: &mut Command,
+    input: Option<Vec<u8>>,
+    timeout: Duration,
+    read: impl FnOnce(ChildStdout) -> R,
+) -> io::Result<(Option<ExitStatus>, R, Vec<u8>)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let pid = child.id();
+    RUNNING.lock().unwrap().push(pid);
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        // Write on another thread so a child which doesn't read all of its
+        // input can't block us. If it exits without reading it, that's its
+        // business.
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+    let stdout = child.stdout.take().expect("stdout is piped");
+    // Read stderr on another thread so a chatty child can't fill the pipe up
+    // and block forever.
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stderr.read_to_end(&mut buf).map(|_| buf)
+    });
+    // Waiting on another thread lets us kill the child while `read` is
+    // blocked on its output.
+    let waiter = thread::spawn(move || {
+        let status = match child.wait_timeout(timeout) {
+            Ok(Some(status)) => Ok(Some(status)),
+            Ok(None) => kill_process_group(child.id())
+                .or_else(|_| child.kill())
+                .and_then(|()| child.wait())
+                .map(|_| None),
+            Err(err) => Err(err),
+        };
+        RUNNING.lock().unwrap().retain(|&running| running != pid);
+        status
+    });
+    let value = read(stdout);
+    let status = waiter.join().expect("waiter panicked")?;
+    Ok((
+        status,
+        value,
+        stderr.join().expect("stderr reader panicked")?,
+    ))
+}
+
+/// The PIDs of the commands started by `run_with_timeout` which haven't been
+/// waited on yet.
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Kill every command started by `output_with_timeout` and friends which is
+/// still running, along with its subprocesses. Exiting doesn't kill them,
+/// so call this before exiting while another thread might be waiting on one.
+/// Only supported on Unix; elsewhere, this does nothing.
+pub fn kill_running() {
+    for &pid in RUNNING.lock().unwrap().iter() {
+        let _ = kill_process_group(pid);
+    }
+}
+
+#[cfg(unix)]
+fn kill_process_group(pid: u32) -> io::Result<()> {
+    // The child is its process group's leader, so the group ID is its PID.
+    if unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_pid: u32) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// The SHA-256 hash of `bytes`, in hexadecimal. Unlike `DefaultHasher`'s,
+/// it's the same in every build, so it can be saved between runs.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// A double-quoted YAML string; JSON strings are valid YAML.
+pub fn yaml_string(s: &str) -> String {
+    serde_json::to_string(s).expect("strings are always serializable")
+}
+
+/// A `Command` which runs `script` with the system shell.
+pub fn shell_command(script: &str) -> Command {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    command.arg(script);
+    command
+}
+
+/// `text` with the lines between `begin` and `end` (a block of a file
+/// which dfm manages, like part of the crontab) replaced by `lines`, or
+/// removed, with `begin` and `end`, if there are none. A new block is
+/// appended.
+///
+/// A block only ends at an `end` line, so a `begin` without one after it,
+/// e.g. because the user deleted the `end`, is left alone along with
+/// everything after it, and a new block is appended after them.
+pub fn with_block(text: &str, begin: &str, end: &str, lines: &[String]) -> String {
+    let mut kept = Vec::new();
+    // Where the last `begin` without an `end` yet is in `kept`.
+    let mut block_start = None;
+    for line in text.lines() {
+        if line == begin {
+            block_start = Some(kept.len());
+            kept.push(line);
+        } else if let (true, Some(start)) = (line == end, block_start) {
+            kept.truncate(start);
+            block_start = None;
+        } else {
+            kept.push(line);
+        }
+    }
+    if !lines.is_empty() {
+        kept.push(begin);
+        kept.extend(lines.iter().map(String::as_str));
+        kept.push(end);
+    }
+    let mut text = kept.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
 #[cfg(test)]
 mod test {
     use std::env;
@@ -108,6 +322,14 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
     #[test]
     fn test_home_dir() {
         let dir = home_dir().unwrap();
@@ -125,4 +347,33 @@ mod test {
             )
         );
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_with_timeout() {
+        let output = output_with_timeout(
+            &mut shell_command("echo hello; echo oops >&2"),
+            Duration::from_secs(10),
+        )
+        .unwrap()
+        .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello\n");
+        assert_eq!(output.stderr, b"oops\n");
+
+        assert!(
+            output_with_timeout(&mut shell_command("sleep 10"), Duration::from_millis(100))
+                .unwrap()
+                .is_none()
+        );
+
+        // The backgrounded `sleep` holds the pipes open, so this would hang
+        // if only `sh` were killed.
+        assert!(output_with_timeout(
+            &mut shell_command("sleep 10 & wait"),
+            Duration::from_millis(100)
+        )
+        .unwrap()
+        .is_none());
+    }
 }
@@ -0,0 +1,105 @@
+//! Loose version-string comparisons, for things like `os_version >= '10.15'`.
+//!
+//! Versions are split into runs of digits and runs of letters; anything else
+//! (`.`, `-`, `+`, ...) is a separator. Runs are compared pairwise: digits
+//! numerically, letters lexicographically, and digits sort after letters, so
+//! `1.2.1 > 1.2rc1`. When one version runs out of runs, it's padded with
+//! zeroes, so `13 == 13.0`, but a trailing letter run marks a pre-release, so
+//! `1.2rc1 < 1.2`.
+
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Part<'a> {
+    Number(&'a str),
+    Word(&'a str),
+}
+
+impl Ord for Part<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            // Compare by length first so arbitrarily long numbers don't
+            // overflow.
+            (Part::Number(l), Part::Number(r)) => l.len().cmp(&r.len()).then_with(|| l.cmp(r)),
+            (Part::Word(l), Part::Word(r)) => l.cmp(r),
+            (Part::Number(_), Part::Word(_)) => Ordering::Greater,
+            (Part::Word(_), Part::Number(_)) => Ordering::Less,
+        }
+    }
+}
+
+impl PartialOrd for Part<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Eq for Part<'_> {}
+
+fn parts(version: &str) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    let mut rest = version;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric()) {
+        rest = &rest[start..];
+        let is_digit = rest.starts_with(|c: char| c.is_ascii_digit());
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() || c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (part, tail) = rest.split_at(end);
+        parts.push(if is_digit {
+            Part::Number(part.trim_start_matches('0'))
+        } else {
+            Part::Word(part)
+        });
+        rest = tail;
+    }
+    parts
+}
+
+/// Compare two version strings; see the module documentation for details.
+pub fn compare(l: &str, r: &str) -> Ordering {
+    let (l, r) = (parts(l), parts(r));
+    for i in 0..l.len().max(r.len()) {
+        let ordering = match (l.get(i), r.get(i)) {
+            (Some(l), Some(r)) => l.cmp(r),
+            (Some(Part::Word(_)), None) => Ordering::Less,
+            (None, Some(Part::Word(_))) => Ordering::Greater,
+            (Some(l), None) => l.cmp(&Part::Number("")),
+            (None, Some(r)) => Part::Number("").cmp(r),
+            (None, None) => unreachable!(),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn version_compare() {
+        use Ordering::*;
+        for (l, r, want) in &[
+            ("13.0", "13.0", Equal),
+            ("13", "13.0.0", Equal),
+            ("10.15.7", "11.0", Less),
+            ("10.9", "10.10", Less),
+            ("007", "7", Equal),
+            ("20.04", "18.04", Greater),
+            ("1.2rc1", "1.2", Less),
+            ("1.2rc1", "1.2rc2", Less),
+            ("1.2.1", "1.2rc1", Greater),
+            ("5.4.0-42-generic", "5.4.0-42", Less),
+            ("5.10.0-42-generic", "5.4.0", Greater),
+            ("99999999999999999999999", "1", Greater),
+        ] {
+            assert_eq!(compare(l, r), *want, "{} vs {}", l, r);
+            assert_eq!(compare(r, l), want.reverse(), "{} vs {}", r, l);
+        }
+    }
+}
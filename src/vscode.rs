@@ -0,0 +1,129 @@
+//! Visual Studio Code's settings and extensions; the `[vscode]` table in the
+//! config file.
+
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::dotfile::Dotfile;
+
+#[derive(Error, Debug)]
+pub enum VsCodeError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("`code {args}` failed: {message}")]
+    Failed { args: String, message: String },
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VsCodeOptions {
+    /// The `settings.json` to install, relative to the dotfile repository.
+    pub settings: Option<PathBuf>,
+    /// The `keybindings.json` to install, relative to the dotfile
+    /// repository.
+    pub keybindings: Option<PathBuf>,
+    /// The IDs of extensions to install, e.g. `rust-lang.rust-analyzer`.
+    pub extensions: Vec<String>,
+}
+
+impl VsCodeOptions {
+    /// Dotfiles installing `settings` and `keybindings` into `user_dir`.
+    pub fn dotfiles(&self, user_dir: &Path) -> Vec<Dotfile> {
+        [
+            (&self.settings, "settings.json"),
+            (&self.keybindings, "keybindings.json"),
+        ]
+        .iter()
+        .filter_map(|(repo, name)| {
+            Some(Dotfile {
+                repo: repo.as_ref()?.clone(),
+                installed: Some(user_dir.join(name)),
+                ..Default::default()
+            })
+        })
+        .collect()
+    }
+
+    /// The declared extensions which aren't in `installed`. Extension IDs
+    /// aren't case-sensitive.
+    pub fn missing_extensions(&self, installed: &BTreeSet<String>) -> Vec<String> {
+        self.extensions
+            .iter()
+            .filter(|id| !installed.contains(&id.to_lowercase()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// VS Code's user settings directory: `Code/User` in the platform's
+/// configuration directory, e.g. `~/.config` on Linux and `~/Library/Application
+/// Support` on macOS.
+pub fn user_dir() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("Code").join("User"))
+}
+
+fn code(args: &[&str]) -> Result<String, VsCodeError> {
+    let output = Command::new("code")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(VsCodeError::Failed {
+            args: args.join(" "),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The IDs of the installed extensions, in lowercase.
+pub fn installed_extensions() -> Result<BTreeSet<String>, VsCodeError> {
+    Ok(code(&["--list-extensions"])?
+        .lines()
+        .map(|id| id.trim().to_lowercase())
+        .filter(|id| !id.is_empty())
+        .collect())
+}
+
+/// Install the extension `id`.
+pub fn install_extension(id: &str) -> Result<(), VsCodeError> {
+    code(&["--install-extension", id]).map(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn vscode_options() {
+        let opts: VsCodeOptions = toml::from_str(
+            r#"
+            settings = "vscode/settings.json"
+            extensions = ["rust-lang.rust-analyzer", "vscodevim.vim"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            opts.dotfiles(Path::new("/home/me/.config/Code/User")),
+            vec![Dotfile {
+                repo: "vscode/settings.json".into(),
+                installed: Some("/home/me/.config/Code/User/settings.json".into()),
+                ..Default::default()
+            }]
+        );
+        let installed = vec!["vscodevim.vim".to_string()].into_iter().collect();
+        assert_eq!(
+            opts.missing_extensions(&installed),
+            vec!["rust-lang.rust-analyzer".to_string()]
+        );
+        assert!(user_dir().unwrap().ends_with("Code/User"));
+    }
+}
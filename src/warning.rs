@@ -0,0 +1,139 @@
+//! Problems which don't stop dfm, like a misspelled field in the dotfiles
+//! list or a dotfile it had to skip. They're collected while dfm reads its
+//! configuration and plans what to do, then printed together, grouped by
+//! kind, at the end of the run, e.g.
+//!
+//! ```text
+//! warning: unknown fields in the dotfiles list, which are ignored
+//!     bashrc: `tempalte`
+//! warning: skipped dotfiles
+//!     /etc/hosts: it needs administrative privileges; rerun as root to install it
+//! ```
+
+use std::fmt;
+
+use console::style;
+
+/// What a `Warning` is about; warnings are grouped by kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WarningKind {
+    /// More than one dotfiles list file exists; see
+    /// `Config::ambiguous_dotfiles`.
+    AmbiguousList,
+    /// A dotfile in the dotfiles list has fields dfm doesn't know.
+    UnknownField,
+    /// Nix printed something while evaluating the dotfiles list, like
+    /// `builtins.trace` output or a deprecation warning.
+    Nix,
+    /// A dotfile which applies to this machine wasn't installed.
+    Skipped,
+    /// A plugin failed or contributed something which already exists; see
+    /// the `plugin` module.
+    Plugin,
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarningKind::AmbiguousList => write!(f, "more than one dotfiles list"),
+            WarningKind::UnknownField => {
+                write!(f, "unknown fields in the dotfiles list, which are ignored")
+            }
+            WarningKind::Nix => write!(f, "Nix printed messages"),
+            WarningKind::Skipped => write!(f, "skipped dotfiles"),
+            WarningKind::Plugin => write!(f, "problems with plugins"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(kind: WarningKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+/// The warnings collected during a run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Warnings(Vec<Warning>);
+
+impl Warnings {
+    /// Add `warning`, unless it's already been added; e.g. the dotfiles list
+    /// may be read more than once.
+    pub fn push(&mut self, warning: Warning) {
+        if !self.0.contains(&warning) {
+            self.0.push(warning);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Warning> {
+        self.0.iter()
+    }
+
+    /// The warnings, grouped by kind, colored if `color` is true; empty if
+    /// there are none.
+    pub fn render(&self, color: bool) -> String {
+        let mut kinds = self
+            .0
+            .iter()
+            .map(|warning| warning.kind)
+            .collect::<Vec<_>>();
+        kinds.sort();
+        kinds.dedup();
+        let mut rendered = String::new();
+        for kind in kinds {
+            rendered.push_str(&format!(
+                "{} {}\n",
+                style("warning:").yellow().bold().force_styling(color),
+                kind
+            ));
+            for warning in self.0.iter().filter(|warning| warning.kind == kind) {
+                rendered.push_str(&format!("    {}\n", warning.message));
+            }
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn warnings_render() {
+        let mut warnings = Warnings::default();
+        assert!(warnings.is_empty());
+        assert_eq!(warnings.render(false), "");
+        warnings.push(Warning::new(WarningKind::Skipped, "/etc/hosts: no"));
+        warnings.push(Warning::new(WarningKind::Nix, "trace: hi"));
+        warnings.push(Warning::new(WarningKind::Skipped, "/etc/motd: no"));
+        warnings.push(Warning::new(WarningKind::Nix, "trace: hi"));
+        assert_eq!(
+            warnings.render(false),
+            indoc!(
+                "
+                warning: Nix printed messages
+                    trace: hi
+                warning: skipped dotfiles
+                    /etc/hosts: no
+                    /etc/motd: no
+                "
+            )
+        );
+    }
+}
@@ -0,0 +1,14 @@
+#Dotfile: {
+	repo:       string
+	installed?: string
+	template?:  bool
+}
+
+dotfiles: [...string | #Dotfile]
+dotfiles: [
+	".bashrc",
+	{
+		repo:      ".config/fisher_local/fishfile"
+		installed: ".config/fish/fishfile"
+	},
+]
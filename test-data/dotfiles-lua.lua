@@ -0,0 +1,8 @@
+local dotfiles = { ".bashrc" }
+if facts.vars.email then
+  table.insert(dotfiles, {
+    repo = "gitconfig-" .. facts.vars.email,
+    installed = path.join(".config", "git", "config"),
+  })
+end
+return dotfiles
@@ -0,0 +1,5 @@
+{
+  outputs = { self }: {
+    dotfiles = { name ? "default", ... }: [ "foo" name ];
+  };
+}
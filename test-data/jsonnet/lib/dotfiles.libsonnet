@@ -0,0 +1,3 @@
+{
+  dotfile(repo, installed):: { repo: repo, installed: installed },
+}
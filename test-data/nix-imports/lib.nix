@@ -0,0 +1,2 @@
+# Not a real import: ./comment.nix
+[ "lib" ]
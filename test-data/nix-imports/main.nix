@@ -0,0 +1,2 @@
+{ name ? "main" }:
+import ./lib.nix ++ import ../string-list.nix ++ [ name ]
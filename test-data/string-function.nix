@@ -0,0 +1 @@
+{ name ? "default", ... }: [ "foo" name ]